@@ -0,0 +1,139 @@
+//! Per-title content-complexity analysis for `--auto-quality`.
+//!
+//! A few short segments are test-encoded with libx264 at the planned CRF and
+//! the resulting bits per pixel per frame is used as a complexity measure:
+//! flat animation compresses to a fraction of what film grain needs. The
+//! measurement then adjusts the CRF and the bitrate targets of the plan.
+
+use crate::plan::VideoSettings;
+use crate::probe::VideoInfo;
+use crate::runner::{FfmpegRunner, Tool};
+use std::env;
+use std::fs;
+
+/// Seconds encoded per sample segment.
+const SAMPLE_SECS: f64 = 5.0;
+
+/// Positions of the sample segments as fractions of the duration.
+const SAMPLE_POSITIONS: &[f64] = &[0.2, 0.5, 0.8];
+
+/// Measured complexity of a title.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complexity {
+    /// Bits spent per pixel per frame by the test encodes
+    pub bits_per_pixel: f64,
+    /// Bitrate the test encodes needed at the planned CRF, in kbit/s
+    pub sample_kbps: u32,
+}
+
+impl Complexity {
+    /// Human-readable bucket for console output.
+    pub fn label(&self) -> &'static str {
+        if self.bits_per_pixel < 0.03 {
+            "simple"
+        } else if self.bits_per_pixel > 0.15 {
+            "grainy/complex"
+        } else {
+            "typical"
+        }
+    }
+
+    /// Adjusts `settings` for this content. Simple content gets a lower CRF
+    /// (it costs little); grainy content a higher one so grain doesn't balloon
+    /// the file. Bitrate targets follow what the test encodes needed, bounded
+    /// to 40%–150% of the resolution-tier bitrate.
+    pub fn apply(&self, settings: &mut VideoSettings) {
+        if self.bits_per_pixel < 0.03 {
+            settings.crf = settings.crf.saturating_sub(1);
+            settings.nvenc_cq = settings.nvenc_cq.saturating_sub(1);
+        } else if self.bits_per_pixel > 0.15 {
+            settings.crf += 2;
+            settings.nvenc_cq += 2;
+        }
+
+        let tier = settings.bitrate_kbps;
+        let target = (self.sample_kbps as f64 * 1.1) as u32;
+        let bitrate = target.clamp(tier * 2 / 5, tier * 3 / 2);
+        settings.max_bitrate_kbps = bitrate * 3 / 2;
+        settings.bitrate_kbps = bitrate;
+    }
+}
+
+/// Test-encodes sample segments of `info` at `crf` and measures complexity.
+pub fn analyze_complexity(
+    runner: &dyn FfmpegRunner,
+    info: &VideoInfo,
+    crf: u32,
+) -> Result<Complexity, String> {
+    if info.width == 0 || info.height == 0 {
+        return Err("unknown video dimensions".to_string());
+    }
+    let frame_rate = if info.frame_rate > 0.0 { info.frame_rate } else { 25.0 };
+
+    // Short sources get one segment from the start
+    let starts: Vec<f64> = if info.duration_secs > SAMPLE_SECS * 4.0 {
+        SAMPLE_POSITIONS.iter().map(|p| info.duration_secs * p).collect()
+    } else {
+        vec![0.0]
+    };
+
+    let sample_path = env::temp_dir().join(format!("tvcode-analyze-{}.mkv", std::process::id()));
+    let mut total_bytes = 0u64;
+    let mut total_secs = 0.0;
+
+    for start in starts {
+        let args: Vec<String> = vec![
+            "-hide_banner".to_string(),
+            "-loglevel".to_string(),
+            "error".to_string(),
+            "-ss".to_string(),
+            format!("{:.3}", start),
+            "-i".to_string(),
+            info.path.to_str().unwrap().to_string(),
+            "-t".to_string(),
+            SAMPLE_SECS.to_string(),
+            "-map".to_string(),
+            "0:v:0".to_string(),
+            "-an".to_string(),
+            "-sn".to_string(),
+            "-c:v".to_string(),
+            "libx264".to_string(),
+            "-preset".to_string(),
+            "veryfast".to_string(),
+            "-crf".to_string(),
+            crf.to_string(),
+            "-f".to_string(),
+            "matroska".to_string(),
+            "-y".to_string(),
+            sample_path.to_str().unwrap().to_string(),
+        ];
+        let output = runner
+            .capture(Tool::Ffmpeg, &args)
+            .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+        if !output.success() {
+            let _ = fs::remove_file(&sample_path);
+            return Err(format!(
+                "sample encode failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        total_bytes += fs::metadata(&sample_path).map(|m| m.len()).unwrap_or(0);
+        total_secs += if info.duration_secs > 0.0 {
+            SAMPLE_SECS.min(info.duration_secs - start)
+        } else {
+            SAMPLE_SECS
+        };
+    }
+    let _ = fs::remove_file(&sample_path);
+
+    if total_bytes == 0 || total_secs <= 0.0 {
+        return Err("sample encode produced no output".to_string());
+    }
+
+    let bits = total_bytes as f64 * 8.0;
+    let pixels = info.width as f64 * info.height as f64 * frame_rate * total_secs;
+    Ok(Complexity {
+        bits_per_pixel: bits / pixels,
+        sample_kbps: (bits / total_secs / 1000.0) as u32,
+    })
+}
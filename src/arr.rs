@@ -0,0 +1,103 @@
+//! `tvcode arr`: running as a Sonarr or Radarr custom script.
+//!
+//! Sonarr and Radarr describe the event in environment variables prefixed
+//! with `sonarr_` or `radarr_`; on import (`Download`) the path of the new
+//! file is among them.
+
+use std::path::PathBuf;
+
+/// The application that ran the script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrApp {
+    Sonarr,
+    Radarr,
+}
+
+impl ArrApp {
+    pub fn name(self) -> &'static str {
+        match self {
+            ArrApp::Sonarr => "Sonarr",
+            ArrApp::Radarr => "Radarr",
+        }
+    }
+}
+
+/// What Sonarr or Radarr asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArrEvent {
+    /// The "Test" button in the connection settings
+    Test(ArrApp),
+    /// A file was imported (or upgraded)
+    Import {
+        app: ArrApp,
+        path: PathBuf,
+        /// e.g. "Show Name S01E02" or "Movie Title (2021)"
+        title: Option<String>,
+    },
+    /// Any other event (grab, rename, delete, health...), which has nothing to convert
+    Other(ArrApp, String),
+}
+
+impl ArrEvent {
+    /// Reads the event from this process's environment.
+    pub fn from_env() -> Result<ArrEvent, String> {
+        ArrEvent::from_vars(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+    }
+
+    /// Reads the event through `var`, which looks up one variable.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<ArrEvent, String> {
+        let (app, event) = if let Some(event) = var("sonarr_eventtype") {
+            (ArrApp::Sonarr, event)
+        } else if let Some(event) = var("radarr_eventtype") {
+            (ArrApp::Radarr, event)
+        } else {
+            return Err("no sonarr_eventtype or radarr_eventtype in the environment; run this from Sonarr or Radarr \
+                        as a custom script"
+                .to_string());
+        };
+
+        match event.as_str() {
+            "Test" => Ok(ArrEvent::Test(app)),
+            "Download" => {
+                let (path_var, title) = match app {
+                    ArrApp::Sonarr => ("sonarr_episodefile_path", episode_title(&var)),
+                    ArrApp::Radarr => ("radarr_moviefile_path", movie_title(&var)),
+                };
+                let path = var(path_var).ok_or_else(|| format!("{} did not set {}", app.name(), path_var))?;
+                Ok(ArrEvent::Import {
+                    app,
+                    path: PathBuf::from(path),
+                    title,
+                })
+            }
+            _ => Ok(ArrEvent::Other(app, event)),
+        }
+    }
+}
+
+/// "Show S01E02" (or "S01E02E03" for multi-episode files).
+fn episode_title(var: &impl Fn(&str) -> Option<String>) -> Option<String> {
+    let series = var("sonarr_series_title")?;
+    let season = var("sonarr_episodefile_seasonnumber").and_then(|s| s.parse::<u32>().ok());
+    let episodes: Vec<u32> = var("sonarr_episodefile_episodenumbers")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|n| n.trim().parse().ok())
+        .collect();
+    match season {
+        Some(season) if !episodes.is_empty() => {
+            let numbers: String = episodes.iter().map(|e| format!("E{:02}", e)).collect();
+            Some(format!("{} S{:02}{}", series, season, numbers))
+        }
+        _ => Some(series),
+    }
+}
+
+/// "Movie (2021)".
+fn movie_title(var: &impl Fn(&str) -> Option<String>) -> Option<String> {
+    let title = var("radarr_movie_title")?;
+    match var("radarr_movie_year") {
+        Some(year) => Some(format!("{} ({})", title, year)),
+        None => Some(title),
+    }
+}
@@ -0,0 +1,98 @@
+//! On-disk cache of ffprobe output, so repeat runs over a large library do
+//! not probe every unchanged file again.
+//!
+//! Each entry is a JSON file named after a hash of the file's path, size and
+//! modification time, in `$XDG_CACHE_HOME/tvcode/probe` (falling back to
+//! `~/.cache/tvcode/probe`, `~/Library/Caches/tvcode/probe` on macOS or
+//! `%LOCALAPPDATA%\tvcode\probe` on Windows). A changed file gets a new key;
+//! stale entries are simply never read again.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    modified_nanos: u64,
+    probe: Value,
+}
+
+/// Cached ffprobe output. The default cache is disabled and stores nothing.
+#[derive(Debug, Clone, Default)]
+pub struct ProbeCache {
+    dir: Option<PathBuf>,
+}
+
+impl ProbeCache {
+    /// The per-user cache, or a disabled one if no location can be found.
+    pub fn open_default() -> ProbeCache {
+        ProbeCache { dir: default_dir() }
+    }
+
+    /// The cached ffprobe JSON for `path`, if it has not changed since.
+    pub fn get(&self, path: &Path) -> Option<Vec<u8>> {
+        let (file, key) = self.entry_file(path)?;
+        let entry: Entry = serde_json::from_slice(&fs::read(file).ok()?).ok()?;
+        if (&entry.path, entry.size, entry.modified_nanos) != (&key.0, key.1, key.2) {
+            return None;
+        }
+        // Entries from before chapters were probed
+        entry.probe.get("chapters")?;
+        serde_json::to_vec(&entry.probe).ok()
+    }
+
+    /// Stores ffprobe's `json` for `path`. Failures only mean a later miss.
+    pub fn put(&self, path: &Path, json: &[u8]) {
+        let Some((file, (path, size, modified_nanos))) = self.entry_file(path) else {
+            return;
+        };
+        let Ok(probe) = serde_json::from_slice::<Value>(json) else {
+            return;
+        };
+        let entry = Entry {
+            path,
+            size,
+            modified_nanos,
+            probe,
+        };
+        let Ok(text) = serde_json::to_string(&entry) else {
+            return;
+        };
+        let temp = file.with_extension("json.tmp");
+        let _ = fs::create_dir_all(file.parent().unwrap())
+            .and_then(|()| fs::write(&temp, text))
+            .and_then(|()| fs::rename(&temp, &file));
+    }
+
+    /// Where `path`'s entry lives and the key it must match: the absolute
+    /// path, size and modification time.
+    fn entry_file(&self, path: &Path) -> Option<(PathBuf, (PathBuf, u64, u64))> {
+        let dir = self.dir.as_ref()?;
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64;
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let mut hasher = DefaultHasher::new();
+        (&path, metadata.len(), modified).hash(&mut hasher);
+        Some((dir.join(format!("{:016x}.json", hasher.finish())), (path, metadata.len(), modified)))
+    }
+}
+
+fn default_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if let Some(dir) = env::var_os("XDG_CACHE_HOME") {
+        Some(PathBuf::from(dir))
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Caches"))
+    } else {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache"))
+    };
+    base.map(|dir| dir.join("tvcode").join("probe"))
+}
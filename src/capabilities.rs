@@ -0,0 +1,102 @@
+//! What the installed ffmpeg was built with. Checked once before a run, so a
+//! build without libx264 or libass fails up front with a clear message
+//! instead of partway through an encode.
+
+use crate::hwaccel::HwAccel;
+use crate::options::EncodeOptions;
+use crate::runner::{FfmpegRunner, Tool};
+
+/// Encoders and filters listed by `ffmpeg -encoders` and `ffmpeg -filters`.
+#[derive(Debug, Clone, Default)]
+pub struct FfmpegCapabilities {
+    encoders: Vec<String>,
+    filters: Vec<String>,
+}
+
+impl FfmpegCapabilities {
+    /// Asks ffmpeg through `runner`. A listing that cannot be read comes back
+    /// empty, so everything in it counts as missing.
+    pub fn probe(runner: &dyn FfmpegRunner) -> FfmpegCapabilities {
+        let list = |flag: &str| {
+            runner
+                .capture(Tool::Ffmpeg, &["-hide_banner".to_string(), flag.to_string()])
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+                .unwrap_or_default()
+        };
+        FfmpegCapabilities {
+            encoders: parse_encoders(&list("-encoders")),
+            filters: parse_filters(&list("-filters")),
+        }
+    }
+
+    pub fn has_encoder(&self, name: &str) -> bool {
+        self.encoders.iter().any(|encoder| encoder == name)
+    }
+
+    pub fn has_filter(&self, name: &str) -> bool {
+        self.filters.iter().any(|filter| filter == name)
+    }
+
+    /// What this build lacks for a run with `options` on `hw_accel` (`None`
+    /// for software), one message per missing piece. `burn_subtitles` is set
+    /// when text subtitles may be burned in.
+    pub fn missing_for(&self, hw_accel: Option<&HwAccel>, options: &EncodeOptions, burn_subtitles: bool) -> Vec<String> {
+        let mut missing = Vec::new();
+        match hw_accel {
+            Some(hw) => {
+                let encoder = format!("h264_{}", hw.family);
+                if !self.has_encoder(&encoder) {
+                    missing.push(format!("your ffmpeg lacks {}, the selected {} encoder", encoder, hw.family));
+                }
+            }
+            None if !self.has_encoder("libx264") => {
+                missing.push("your ffmpeg lacks libx264, needed for software H.264 encoding".to_string());
+            }
+            None => {}
+        }
+        if hw_accel.is_some() && options.auto_quality && !self.has_encoder("libx264") {
+            missing.push("your ffmpeg lacks libx264, which --auto-quality test-encodes with".to_string());
+        }
+        if !self.has_encoder("aac") {
+            missing.push("your ffmpeg lacks the aac encoder".to_string());
+        }
+        if burn_subtitles && !self.has_filter("subtitles") {
+            missing.push("your ffmpeg lacks libass, needed to burn text subtitles".to_string());
+        }
+        if let Some(metric) = options.quality_check {
+            if !self.has_filter(metric.filter()) {
+                missing.push(format!(
+                    "your ffmpeg lacks the {} filter, needed for --quality-check {}",
+                    metric.filter(),
+                    metric.label().to_lowercase()
+                ));
+            }
+        }
+        missing
+    }
+}
+
+/// Names from `ffmpeg -encoders`: the second column of every line after the
+/// `------` that ends the legend.
+fn parse_encoders(listing: &str) -> Vec<String> {
+    listing
+        .lines()
+        .skip_while(|line| line.trim() != "------")
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Names from `ffmpeg -filters`, whose entries look like
+/// ` TSC scale  V->V  Scale the input video size`.
+fn parse_filters(listing: &str) -> Vec<String> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let name = columns.nth(1)?;
+            columns.next()?.contains("->").then(|| name.to_string())
+        })
+        .collect()
+}
@@ -0,0 +1,701 @@
+//! Command-line definitions for the `tvcode` binary.
+
+use clap::{Args, Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use tvcode::device::Device;
+use tvcode::discover::FileFilter;
+use tvcode::hwaccel::ENCODER_FAMILIES;
+use tvcode::preset::{QualityPreset, Tune};
+use tvcode::config::Profile;
+use tvcode::options::{
+    parse_audio_delay, parse_duration, parse_sample_secs, parse_sample_start, parse_sharpen, parse_size, parse_watermark, Denoise, OutputSplit, SampleStart,
+    Watermark,
+};
+use tvcode::power::PowerPolicy;
+use tvcode::quality::QualityMetric;
+use tvcode::schedule::{parse_schedule, Schedule};
+use tvcode::subtitles::{parse_color, parse_delay, Color, SubtitlePosition, SubtitleStyle};
+use tvcode::library::MediaServers;
+use tvcode::notify::{Notifier, Webhook};
+use tvcode::thumbnails::Thumbnails;
+use tvcode::{Config, Container, EncodeOptions, ExistingOutput, OutputLayout};
+
+#[derive(Parser, Debug)]
+#[command(name = "tvcode")]
+#[command(version)]
+#[command(about = "Convert videos to Apple TV-compatible H.264/AAC format with subtitle burning")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    /// Options for the bare `tvcode` invocation, an alias for `tvcode convert`
+    #[command(flatten)]
+    pub convert: ConvertArgs,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+}
+
+impl Cli {
+    /// The subcommand to run, treating a bare invocation as `convert`.
+    pub fn into_command(self) -> (Commands, GlobalArgs) {
+        let command = self.command.unwrap_or(Commands::Convert(self.convert));
+        (command, self.global)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Report what would be converted without touching anything
+    Scan(ScanArgs),
+    /// Convert incompatible videos (the default)
+    Convert(ConvertArgs),
+    /// Keep converting new videos as they appear in a directory
+    Watch(WatchArgs),
+    /// Convert the file Sonarr or Radarr just imported (run as a custom script)
+    Arr(ArrArgs),
+    /// Show recent activity from the log file
+    History(HistoryArgs),
+    /// Download a static ffmpeg build for tvcode to use instead of the system one
+    SetupFfmpeg(SetupFfmpegArgs),
+    /// Extract the closed captions (EIA-608/708) in a video stream to an SRT file beside it
+    Captions(CaptionsArgs),
+    /// Encode one excerpt at several CRFs or bitrates and compare their size and quality
+    Sweep(SweepArgs),
+    /// Encode one file for a `--remote` coordinator, reading the job from stdin
+    #[command(hide = true)]
+    Worker(WorkerArgs),
+}
+
+/// Options shared by every subcommand.
+#[derive(Args, Debug)]
+pub struct GlobalArgs {
+    /// Increase output detail (-v streams ffmpeg output, -vv also prints ffmpeg commands)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Only print errors and the final summary
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Append structured JSON Lines records for every file to this path
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Read defaults from this config file instead of the per-user one
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Run ffmpeg at this CPU niceness (0-19, higher yields more to other programs)
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(i32).range(0..=19))]
+    pub nice: Option<i32>,
+
+    /// Give ffmpeg's disk I/O idle priority (Linux and macOS)
+    #[arg(long, global = true)]
+    pub ionice: bool,
+
+    /// Probe every file again instead of reusing cached ffprobe results
+    #[arg(long, global = true)]
+    pub no_probe_cache: bool,
+}
+
+/// Encoder tuning shared by the commands that transcode.
+#[derive(Args, Debug, Clone)]
+pub struct EncodeArgs {
+    /// Named settings from the config file's [profiles.<name>] tables
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Speed/quality trade-off for every encoder [default: balanced]
+    #[arg(long, value_enum)]
+    pub preset: Option<QualityPreset>,
+
+    /// libx264 content tuning (software encodes only)
+    #[arg(long, value_enum)]
+    pub tune: Option<Tune>,
+
+    /// Apple TV generation to target (resolution, frame rate, H.264 level, HEVC and audio support)
+    #[arg(long, value_enum, value_name = "MODEL")]
+    pub device: Option<Device>,
+
+    /// Two-pass average-bitrate encoding (libx264 and NVENC)
+    #[arg(long)]
+    pub two_pass: bool,
+
+    /// Test-encode a few segments per file to pick CRF/bitrate for its content
+    #[arg(long)]
+    pub auto_quality: bool,
+
+    /// Aim for this output size per file (e.g. 4GB, 700MB), encoding at the bitrate that fits
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with_all = ["hls", "auto_quality"])]
+    pub target_size: Option<u64>,
+
+    /// After encoding, compare sampled segments with the source using VMAF or SSIM
+    #[arg(long, value_enum, value_name = "METRIC", conflicts_with = "hls")]
+    pub quality_check: Option<QualityMetric>,
+
+    /// Lowest acceptable score for --quality-check [default: 90 for vmaf, 0.95 for ssim]
+    #[arg(long, value_name = "SCORE", requires = "quality_check")]
+    pub min_quality: Option<f64>,
+
+    /// Re-encode once at higher quality when --quality-check scores below the minimum
+    #[arg(long, requires = "quality_check")]
+    pub requality: bool,
+
+    /// Also write a poster thumbnail and/or preview sprite beside each output [default: all]
+    #[arg(
+        long,
+        value_enum,
+        value_name = "KIND",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "all",
+        conflicts_with = "hls"
+    )]
+    pub thumbnails: Option<Thumbnails>,
+
+    /// On a Mac laptop running on battery or throttling: pause between files, or use the fast preset
+    #[arg(long, value_enum, value_name = "ACTION")]
+    pub on_battery: Option<PowerPolicy>,
+
+    /// NVENC constant-quality level (lower is better, overrides the preset)
+    #[arg(long, value_name = "0-51", value_parser = clap::value_parser!(u32).range(0..=51))]
+    pub nvenc_cq: Option<u32>,
+
+    /// Encode NVENC at the fixed table bitrate instead of constant quality
+    #[arg(long, conflicts_with = "nvenc_cq")]
+    pub nvenc_bitrate: bool,
+
+    /// Output container; m4v suits iTunes/TV app imports [default: mp4]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub container: Option<Container>,
+
+    /// Where outputs go: beside the source, or in Plex's "Plex Versions/Optimized for TV" folder [default: beside]
+    #[arg(long, value_enum, value_name = "LAYOUT", conflicts_with = "hls")]
+    pub layout: Option<OutputLayout>,
+
+    /// Write an HLS package (1080p/720p/480p renditions plus master playlist) per file
+    #[arg(long, conflicts_with_all = ["container", "fragmented"])]
+    pub hls: bool,
+
+    /// Write fragmented MP4 (playable even when partially copied) instead of faststart
+    #[arg(long)]
+    pub fragmented: bool,
+
+    /// Split each output into numbered parts of at most this size (e.g. 4GB for FAT32 drives)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with_all = ["hls", "split_every", "quality_check", "thumbnails"])]
+    pub split_size: Option<u64>,
+
+    /// Split each output into numbered parts of this length (e.g. 1h, 45min)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with_all = ["hls", "quality_check", "thumbnails"])]
+    pub split_every: Option<f64>,
+
+    /// Write one output per chapter, named after the chapter titles, into a _chapters folder
+    #[arg(long, conflicts_with_all = ["hls", "sample", "split_size", "split_every", "quality_check", "thumbnails"])]
+    pub split_chapters: bool,
+
+    /// Encode only this many seconds with the planned settings, into a _sample file
+    #[arg(long, value_name = "SECS", value_parser = parse_sample_secs, conflicts_with = "hls")]
+    pub sample: Option<f64>,
+
+    /// Where the sample starts: start, middle, or a number of seconds
+    #[arg(long, value_name = "WHERE", value_parser = parse_sample_start, default_value = "start", requires = "sample")]
+    pub sample_from: SampleStart,
+
+    /// What to do when an output file already exists [default: overwrite]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub if_exists: Option<ExistingOutput>,
+
+    /// Encode into this local folder, then copy each finished output to its destination and read it back (for network shares)
+    #[arg(long, value_name = "DIR")]
+    pub stage_dir: Option<PathBuf>,
+
+    /// Keep only audio tracks in these languages (e.g. eng,swe), in this order, the first as default
+    #[arg(long = "keep-audio-langs", value_name = "LANGS", value_delimiter = ',')]
+    pub keep_audio_languages: Vec<String>,
+
+    /// Compress the audio's dynamic range so dialogue and explosions play at similar volumes
+    #[arg(long)]
+    pub night_mode: bool,
+
+    /// Re-encode audio that is not 48 kHz or has an unusual channel layout as 48 kHz stereo AAC
+    #[arg(long)]
+    pub resample_audio: bool,
+
+    /// Convert TrueHD and DTS-HD MA surround tracks to 5.1 E-AC3 instead of stereo AAC
+    #[arg(long = "surround-eac3")]
+    pub surround_eac3: bool,
+
+    /// Play the audio this many milliseconds later, or earlier when negative (e.g. 120, -80, 1.5s)
+    #[arg(long, value_name = "MS", value_parser = parse_audio_delay, allow_hyphen_values = true)]
+    pub audio_delay: Option<f64>,
+
+    /// Bitrate of converted audio in kbit/s [default: 96 per AAC channel, 640 for E-AC3]
+    #[arg(long, value_name = "KBPS")]
+    pub audio_bitrate: Option<u32>,
+
+    /// Filter out tape and sensor noise before encoding; much smaller files from grainy sources
+    #[arg(long, value_enum, value_name = "STRENGTH")]
+    pub denoise: Option<Denoise>,
+
+    /// Sharpen soft or upscaled sources after scaling, optionally with a strength up to 1.5 [default: 0.8]
+    #[arg(
+        long,
+        value_name = "STRENGTH",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0.8",
+        value_parser = parse_sharpen
+    )]
+    pub sharpen: Option<f64>,
+
+    /// Lay an image over the picture: IMAGE[:POSITION][:OPACITY], e.g. logo.png:top-left:0.5
+    #[arg(long, value_name = "IMAGE", value_parser = parse_watermark)]
+    pub watermark: Option<Watermark>,
+
+    /// Extra fonts for burning text subtitles (e.g. CJK fonts the system lacks)
+    #[arg(long, value_name = "DIR")]
+    pub fonts_dir: Option<PathBuf>,
+
+    /// Font family for burned subtitles (SRT, WebVTT; ASS keeps its own styles)
+    #[arg(long, value_name = "FAMILY", visible_alias = "subtitle-font")]
+    pub sub_font: Option<String>,
+
+    /// Font size for burned subtitles, in libass units (default 16 on a 288-line canvas)
+    #[arg(long, value_name = "SIZE")]
+    pub sub_size: Option<u32>,
+
+    /// Text color for burned subtitles: a name, #RRGGBB or #RRGGBBAA
+    #[arg(long, value_name = "COLOR", value_parser = parse_color)]
+    pub sub_color: Option<Color>,
+
+    /// Outline width for burned subtitles (0 for none)
+    #[arg(long, value_name = "WIDTH")]
+    pub sub_outline: Option<f64>,
+
+    /// Draw burned subtitles on a box of this color, e.g. '#00000080' for half-transparent black
+    #[arg(long, value_name = "COLOR", value_parser = parse_color)]
+    pub sub_background: Option<Color>,
+
+    /// Burn subtitles at the top or bottom of the picture [default: bottom]
+    #[arg(long, value_enum, value_name = "EDGE")]
+    pub sub_position: Option<SubtitlePosition>,
+
+    /// Distance of burned subtitles from that edge, in percent of the picture height
+    #[arg(long, value_name = "PERCENT")]
+    pub sub_margin: Option<f64>,
+
+    /// Show burned subtitles this much later, or earlier when negative (e.g. 1.5s, -400ms)
+    #[arg(long, value_name = "DELAY", value_parser = parse_delay, allow_hyphen_values = true)]
+    pub sub_delay: Option<f64>,
+
+    /// Add the chosen text subtitle track as a selectable track instead of burning it in
+    #[arg(long)]
+    pub soft_subs: bool,
+
+    /// Remove sound descriptions like [door creaks] and speaker labels from text subtitles
+    #[arg(long)]
+    pub strip_sdh: bool,
+
+    /// Burn only the forced subtitles of the chosen track, e.g. translations of foreign dialogue
+    #[arg(long)]
+    pub forced_only: bool,
+
+    /// Download subtitles in this language (e.g. en) from OpenSubtitles for files that have none
+    #[arg(long, value_name = "LANG")]
+    pub download_subs: Option<String>,
+
+    /// Character set of text subtitles that are not UTF-8, e.g. CP1250 (detected when not given)
+    #[arg(long, value_name = "CHARSET")]
+    pub sub_charenc: Option<String>,
+
+    /// Re-encode every file, even ones that already look Apple TV compatible
+    #[arg(long)]
+    pub force: bool,
+
+    /// Skip videos shorter than this (e.g. 10min, 90s, 1h30m)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub min_duration: Option<f64>,
+
+    /// Skip files smaller than this (e.g. 100MB)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Decode each source in full before encoding it, failing damaged files up front
+    #[arg(long)]
+    pub verify_input: bool,
+
+    /// Remux damaged sources (broken index, truncated file) into a clean copy and encode that instead of failing
+    #[arg(long)]
+    pub repair: bool,
+
+    /// Downscale videos taller than this many lines (e.g. 1080, 720)
+    #[arg(long, value_name = "LINES")]
+    pub max_height: Option<u32>,
+
+    /// Decode on the GPU too, keeping frames on-device for scaling
+    #[arg(long)]
+    pub hw_decode: bool,
+
+    /// VAAPI render node to encode on (default: first one that can encode H.264)
+    #[arg(long, value_name = "PATH")]
+    pub vaapi_device: Option<PathBuf>,
+
+    /// Split each long software encode into N keyframe-aligned parts encoded in parallel
+    #[arg(long, value_name = "N", conflicts_with_all = ["hls", "sample"])]
+    pub segments: Option<u32>,
+
+    /// Use at most this many CPU threads for decoding, filters and libx264 (default: all cores)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+
+    /// Encoders to try in order (e.g. qsv,vaapi,software); the first that passes a test encode is used
+    #[arg(
+        long = "encoder",
+        value_name = "NAME",
+        value_delimiter = ',',
+        value_parser = clap::builder::PossibleValuesParser::new(ENCODER_FAMILIES)
+    )]
+    pub encoders: Vec<String>,
+
+    /// GPU index to encode on; a list (e.g. 0,1) spreads parallel jobs across GPUs
+    #[arg(long = "gpu", value_name = "N", value_delimiter = ',')]
+    pub gpus: Vec<u32>,
+}
+
+impl EncodeArgs {
+    /// The options for this run. Flags win over the `--profile`, which wins
+    /// over the top-level config keys.
+    pub fn to_options(&self, config: &Config) -> Result<EncodeOptions, String> {
+        let profile = match &self.profile {
+            Some(name) => config.profile(name)?.clone(),
+            None => Profile::default(),
+        };
+        let keep_audio_languages = [&self.keep_audio_languages, &config.keep_audio_langs]
+            .into_iter()
+            .find(|list| !list.is_empty())
+            .cloned()
+            .unwrap_or_default();
+        let encoders = [&self.encoders, &profile.encoders, &config.encoders]
+            .into_iter()
+            .find(|list| !list.is_empty())
+            .cloned()
+            .unwrap_or_default();
+        Ok(EncodeOptions {
+            preset: self.preset.or(profile.preset).unwrap_or_default(),
+            tune: self.tune.or(profile.tune),
+            crf: profile.crf,
+            bitrate_kbps: profile.bitrate,
+            audio: profile.audio.unwrap_or_default(),
+            filters: profile.filters,
+            device: self.device.or(profile.device).or(config.device),
+            two_pass: self.two_pass,
+            auto_quality: self.auto_quality,
+            target_size: self.target_size,
+            quality_check: self.quality_check,
+            min_quality: self.min_quality,
+            requality: self.requality,
+            thumbnails: self.thumbnails,
+            on_battery: self.on_battery,
+            nvenc_cq: self.nvenc_cq,
+            nvenc_bitrate: self.nvenc_bitrate,
+            container: self.container.or(profile.container).or(config.container).unwrap_or_default(),
+            layout: self.layout.or(config.layout).unwrap_or_default(),
+            sample_secs: self.sample,
+            sample_from: self.sample_from,
+            hls: self.hls,
+            fragmented: self.fragmented,
+            split_chapters: self.split_chapters,
+            split: self.split_size.map(OutputSplit::Size).or(self.split_every.map(OutputSplit::Duration)),
+            if_exists: self.if_exists.or(config.if_exists).unwrap_or_default(),
+            stage_dir: self.stage_dir.clone().or_else(|| config.stage_dir.clone()),
+            keep_audio_languages,
+            night_mode: self.night_mode,
+            resample_audio: self.resample_audio,
+            surround_eac3: self.surround_eac3,
+            audio_delay_secs: self.audio_delay.unwrap_or(0.0),
+            audio_bitrate_kbps: self.audio_bitrate,
+            denoise: self.denoise.or(profile.denoise),
+            sharpen: self.sharpen.or(profile.sharpen),
+            watermark: self.watermark.clone(),
+            subtitle_style: SubtitleStyle {
+                fonts_dir: self.fonts_dir.clone().or_else(|| config.fonts_dir.clone()),
+                font: self.sub_font.clone().or_else(|| config.subtitle_font.clone()),
+                size: self.sub_size,
+                color: self.sub_color,
+                outline: self.sub_outline,
+                background: self.sub_background,
+                position: self.sub_position.unwrap_or_default(),
+                margin: self.sub_margin,
+                delay_secs: self.sub_delay.unwrap_or(0.0),
+                charenc: self.sub_charenc.clone(),
+            },
+            soft_subtitles: self.soft_subs,
+            strip_sdh: self.strip_sdh,
+            forced_subtitles: self.forced_only,
+            download_subtitles: self.download_subs.clone(),
+            force: self.force,
+            min_duration_secs: self.min_duration,
+            min_size: self.min_size,
+            verify_input: self.verify_input,
+            repair: self.repair,
+            max_height: self.max_height.or(profile.max_height),
+            hw_decode: self.hw_decode,
+            vaapi_device: self.vaapi_device.clone(),
+            segments: self.segments,
+            threads: self.threads,
+            encoders,
+            gpus: self.gpus.clone(),
+        })
+    }
+}
+
+/// Where to announce finished files and runs.
+#[derive(Args, Debug, Clone)]
+pub struct NotifyArgs {
+    /// POST a message here when each file and the whole run finish (Discord and Slack URLs get chat messages)
+    #[arg(long = "webhook", value_name = "URL")]
+    pub webhooks: Vec<String>,
+
+    /// Show a desktop notification when each file and the whole run finish
+    #[arg(long)]
+    pub notify: bool,
+}
+
+impl NotifyArgs {
+    /// Combines these flags with the config file's destinations.
+    pub fn to_notifier(&self, config: &Config) -> Notifier {
+        let webhooks = self.webhooks.iter().map(|url| Webhook::new(url));
+        Notifier::new(webhooks.chain(config.webhooks.iter().cloned()).collect())
+            .with_desktop(self.notify || config.desktop_notifications.unwrap_or(false))
+            .with_email(config.email.clone())
+            .with_library(MediaServers {
+                plex: config.plex.clone(),
+                jellyfin: config.jellyfin.clone(),
+            })
+    }
+}
+
+/// Narrowing which files discovery picks up.
+#[derive(Args, Debug, Clone)]
+pub struct DiscoverArgs {
+    /// Only take files whose names match this glob (e.g. '*.mkv'); repeatable
+    #[arg(long = "include", value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Leave out files whose names match this glob (e.g. '*trailer*'); repeatable
+    #[arg(long = "exclude", value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Also take samples, trailers and files in Extras/Featurettes folders, which are skipped by default
+    #[arg(long)]
+    pub keep_extras: bool,
+    /// Also look for videos in subfolders
+    #[arg(short, long)]
+    pub recursive: bool,
+}
+
+impl DiscoverArgs {
+    pub fn to_filter(&self) -> FileFilter {
+        FileFilter {
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            skip_extras: !self.keep_extras,
+            recursive: self.recursive,
+        }
+    }
+}
+
+/// Taking the files from a list instead of scanning a directory.
+#[derive(Args, Debug, Clone)]
+pub struct FileListArgs {
+    /// Read the files to process from this list, one path per line ("-" for stdin, also used when DIR is "-")
+    #[arg(long, value_name = "PATH")]
+    pub files_from: Option<PathBuf>,
+
+    /// The file list is separated by NUL bytes, as from `find -print0` or `fd -0`
+    #[arg(short = '0', long)]
+    pub null: bool,
+}
+
+impl FileListArgs {
+    /// Where to read the list from, if anywhere: `--files-from`, or stdin
+    /// when `dir` is `-`.
+    pub fn source(&self, dir: &Path) -> Option<PathBuf> {
+        self.files_from
+            .clone()
+            .or_else(|| (dir == Path::new("-")).then(|| PathBuf::from("-")))
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ScanArgs {
+    /// Directory to scan ("-" reads a file list from stdin)
+    #[arg(default_value = ".")]
+    pub dir: PathBuf,
+
+    #[command(flatten)]
+    pub discover: DiscoverArgs,
+
+    #[command(flatten)]
+    pub file_list: FileListArgs,
+
+    /// Print a compatibility report: a verdict per file and statistics for the whole library
+    #[arg(long)]
+    pub report: bool,
+
+    /// Write the per-file report to this file: CSV if it ends in .csv, JSON otherwise
+    #[arg(long, value_name = "PATH")]
+    pub report_file: Option<PathBuf>,
+
+    /// Settings to estimate output sizes with
+    #[command(flatten)]
+    pub encode: EncodeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// Directory to convert ("-" reads a file list from stdin)
+    #[arg(default_value = ".")]
+    pub dir: PathBuf,
+
+    #[command(flatten)]
+    pub discover: DiscoverArgs,
+
+    #[command(flatten)]
+    pub file_list: FileListArgs,
+
+    /// Enable subtitle burning mode (prompts for subtitle selection)
+    #[arg(short, long)]
+    pub subtitles: bool,
+
+    /// Show each file's plan and ask to encode it, change its subtitles or skip it
+    #[arg(long)]
+    pub interactive: bool,
+
+    /// Join multi-part films (CD1/CD2, part1/part2) into one file before converting them
+    #[arg(long)]
+    pub join_parts: bool,
+
+    /// Number of files to encode at the same time
+    #[arg(short, long, default_value_t = 1, value_name = "N")]
+    pub jobs: usize,
+
+    /// Also encode on these SSH hosts running tvcode (e.g. nas,me@desktop), one file at a time each
+    #[arg(long = "remote", value_name = "HOST", value_delimiter = ',')]
+    pub remotes: Vec<String>,
+
+    /// Start over instead of resuming an interrupted run's queue
+    #[arg(long)]
+    pub no_resume: bool,
+
+    /// Only start encodes between these local times, e.g. 23:00-07:00
+    #[arg(long, value_name = "HH:MM-HH:MM", value_parser = parse_schedule)]
+    pub schedule: Option<Schedule>,
+
+    /// Serve a web dashboard and /metrics on this address, e.g. 0.0.0.0:8080
+    #[arg(long, value_name = "ADDR")]
+    pub dashboard: Option<String>,
+
+    /// Full-screen progress view with keys to pause, skip and reorder files
+    #[arg(long, conflicts_with = "subtitles")]
+    pub tui: bool,
+
+    /// Write each file's result to this file when the run ends: CSV if it ends in .csv, JSON otherwise
+    #[arg(long, value_name = "PATH")]
+    pub report_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub notify: NotifyArgs,
+
+    #[command(flatten)]
+    pub encode: EncodeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Directory to watch
+    #[arg(default_value = ".")]
+    pub dir: PathBuf,
+
+    #[command(flatten)]
+    pub discover: DiscoverArgs,
+
+    /// Seconds between directory scans
+    #[arg(long, default_value_t = 30, value_name = "SECS")]
+    pub interval: u64,
+
+    /// Only start encodes between these local times, e.g. 23:00-07:00
+    #[arg(long, value_name = "HH:MM-HH:MM", value_parser = parse_schedule)]
+    pub schedule: Option<Schedule>,
+
+    /// Serve a web dashboard and /metrics on this address, e.g. 0.0.0.0:8080
+    #[arg(long, value_name = "ADDR")]
+    pub dashboard: Option<String>,
+
+    #[command(flatten)]
+    pub notify: NotifyArgs,
+
+    #[command(flatten)]
+    pub encode: EncodeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ArrArgs {
+    #[command(flatten)]
+    pub notify: NotifyArgs,
+
+    #[command(flatten)]
+    pub encode: EncodeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct WorkerArgs {
+    /// Input file copied over by the coordinator
+    pub input: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct SetupFfmpegArgs {
+    /// Delete the downloaded build and go back to the ffmpeg on PATH
+    #[arg(long)]
+    pub remove: bool,
+    /// SHA-256 the download must have, as published by its source; repeat for each archive, in order
+    #[arg(long, value_name = "HASH", conflicts_with = "remove")]
+    pub sha256: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct CaptionsArgs {
+    /// Videos whose captions to extract, e.g. TV recordings
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct SweepArgs {
+    /// Video to take the excerpt from, e.g. one episode of a season
+    pub file: PathBuf,
+
+    /// CRFs to try (libx264 CRF, NVENC CQ), e.g. 18,20,22 [default: five around the preset's]
+    #[arg(long, value_name = "LIST", value_delimiter = ',', value_parser = clap::value_parser!(u32).range(0..=51))]
+    pub crf: Vec<u32>,
+
+    /// Video bitrates in kbit/s to try, encoded in two passes, e.g. 3000,5000
+    #[arg(long, value_name = "KBPS", value_delimiter = ',')]
+    pub bitrate: Vec<u32>,
+
+    /// How to score each point against the source
+    #[arg(long, value_enum, default_value = "vmaf")]
+    pub metric: QualityMetric,
+
+    #[command(flatten)]
+    pub encode: EncodeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryArgs {
+    /// Number of most recent records to show
+    #[arg(short = 'n', long, default_value_t = 20)]
+    pub limit: usize,
+}
@@ -0,0 +1,157 @@
+//! User defaults read from `config.toml`.
+//!
+//! The file lives at `$XDG_CONFIG_HOME/tvcode/config.toml` (falling back to
+//! `~/.config/tvcode/config.toml`, or `%APPDATA%\tvcode\config.toml` on
+//! Windows) unless `--config` points elsewhere. Command-line flags always win
+//! over values from the file.
+
+use crate::device::Device;
+use crate::hwaccel::ENCODER_FAMILIES;
+use crate::library::{JellyfinSettings, PlexSettings};
+use crate::notify::{EmailSettings, Webhook};
+use crate::opensubtitles::OpenSubtitlesSettings;
+use crate::options::{AudioPolicy, Container, Denoise, ExistingOutput, OutputLayout};
+use crate::preset::{QualityPreset, Tune};
+use crate::transfer::TransferSettings;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings loaded from the config file. Every key is optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Encoder families to try in order, e.g. `["qsv", "vaapi", "software"]`
+    pub encoders: Vec<String>,
+    /// Default for `--if-exists`: `overwrite`, `skip`, `prompt` or `rename`
+    pub if_exists: Option<ExistingOutput>,
+    /// Default for `--container`: `mp4` or `m4v`
+    pub container: Option<Container>,
+    /// Default for `--layout`: `beside` or `plex-versions`
+    pub layout: Option<OutputLayout>,
+    /// Default for `--device`: `appletv3`, `appletvhd` or `appletv4k`
+    pub device: Option<Device>,
+    /// Default for `--keep-audio-langs`
+    pub keep_audio_langs: Vec<String>,
+    /// Default for `--fonts-dir`
+    pub fonts_dir: Option<PathBuf>,
+    /// Default for `--sub-font`
+    pub subtitle_font: Option<String>,
+    /// Default for `--stage-dir`
+    pub stage_dir: Option<PathBuf>,
+    /// Named `[profiles.<name>]` tables selected with `--profile`
+    pub profiles: BTreeMap<String, Profile>,
+    /// `[[webhooks]]` notified when files and batches finish
+    pub webhooks: Vec<Webhook>,
+    /// Default for `--notify`
+    pub desktop_notifications: Option<bool>,
+    /// `[email]` summary of each `convert` run
+    pub email: Option<EmailSettings>,
+    /// `[plex]` server to refresh when outputs land
+    pub plex: Option<PlexSettings>,
+    /// `[jellyfin]` server to refresh when outputs land
+    pub jellyfin: Option<JellyfinSettings>,
+    /// `[opensubtitles]` account for `--download-subs`
+    pub opensubtitles: Option<OpenSubtitlesSettings>,
+    /// `[transfer]` host that finished outputs are pushed to
+    pub transfer: Option<TransferSettings>,
+}
+
+/// A named set of encoding settings. Command-line flags override a profile;
+/// a profile overrides the top-level config keys.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Encoder preference list, as for `--encoder`
+    pub encoders: Vec<String>,
+    pub preset: Option<QualityPreset>,
+    /// libx264 `-tune`, as for `--tune`
+    pub tune: Option<Tune>,
+    /// libx264 CRF instead of the preset's
+    pub crf: Option<u32>,
+    /// Target video bitrate in kbit/s instead of the resolution table
+    pub bitrate: Option<u32>,
+    /// `auto` copies compatible audio, `aac` always re-encodes
+    pub audio: Option<AudioPolicy>,
+    /// Noise filter strength, as for `--denoise`
+    pub denoise: Option<Denoise>,
+    /// Sharpening strength, as for `--sharpen=STRENGTH`
+    pub sharpen: Option<f64>,
+    /// Extra ffmpeg video filters, applied after scaling
+    pub filters: Vec<String>,
+    pub container: Option<Container>,
+    pub device: Option<Device>,
+    pub max_height: Option<u32>,
+}
+
+impl Config {
+    /// The per-user config file location, if one can be determined.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        };
+        base.map(|dir| dir.join("tvcode").join("config.toml"))
+    }
+
+    /// Loads `path`, or the default location when `None`. A missing default
+    /// file yields an empty config; a missing explicit one is an error.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Config::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        if !required && !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        let config: Config =
+            toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        config.validate().map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    /// The profile called `name`.
+    pub fn profile(&self, name: &str) -> Result<&Profile, String> {
+        self.profiles.get(name).ok_or_else(|| {
+            if self.profiles.is_empty() {
+                format!("Unknown profile '{}': the config file defines no profiles", name)
+            } else {
+                let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                format!("Unknown profile '{}' (defined: {})", name, known.join(", "))
+            }
+        })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        validate_encoders(&self.encoders)?;
+        for (name, profile) in &self.profiles {
+            validate_encoders(&profile.encoders).map_err(|e| format!("profile '{}': {}", name, e))?;
+            if profile.crf.is_some_and(|crf| crf > 51) {
+                return Err(format!("profile '{}': crf must be 0-51", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_encoders(encoders: &[String]) -> Result<(), String> {
+    if let Some(unknown) = encoders.iter().find(|e| !ENCODER_FAMILIES.contains(&e.as_str())) {
+        return Err(format!(
+            "unknown encoder '{}' (expected one of {})",
+            unknown,
+            ENCODER_FAMILIES.join(", ")
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,458 @@
+//! `--dashboard`: a small web page for checking on a run from another device.
+//!
+//! A plain HTTP server, run as tasks on the [`runtime`], serves one page that polls
+//! `/api/status` for the file list, live progress and recent results, and
+//! `/api/log?id=N` for the ffmpeg output of one file. Progress comes from the
+//! `time=` field of ffmpeg's stats lines, which the [`SystemRunner`] passes to
+//! [`ffmpeg_line`] while the dashboard is running.
+//!
+//! `/metrics` exposes the same figures plus running totals in the Prometheus
+//! text format, for graphing a backlog in Grafana.
+//!
+//! There is no authentication; bind it to a trusted network.
+//!
+//! The same state backs `--tui`, which also reorders and skips pending files
+//! through it.
+//!
+//! [`SystemRunner`]: crate::runner::SystemRunner
+//! [`runtime`]: crate::runtime
+
+use crate::joblog::rfc3339_now;
+use crate::queue::FileStatus;
+use crate::runtime;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// ffmpeg lines kept per file.
+const LOG_LINES: usize = 200;
+
+/// Finished files listed under recent results.
+const HISTORY_LEN: usize = 50;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATE: Mutex<State> = Mutex::new(State {
+    files: Vec::new(),
+    history: VecDeque::new(),
+    totals: Totals {
+        done: 0,
+        skipped: 0,
+        failed: 0,
+        input_bytes: 0,
+        output_bytes: 0,
+    },
+});
+
+struct State {
+    files: Vec<FileState>,
+    history: VecDeque<HistoryItem>,
+    totals: Totals,
+}
+
+/// Running totals for `/metrics`.
+struct Totals {
+    done: u64,
+    skipped: u64,
+    failed: u64,
+    /// Sizes of the sources and outputs of transcoded files
+    input_bytes: u64,
+    output_bytes: u64,
+}
+
+struct FileState {
+    path: PathBuf,
+    status: FileStatus,
+    output: Option<PathBuf>,
+    duration_secs: f64,
+    encoded_secs: f64,
+    speed: Option<String>,
+    fps: f64,
+    started: Option<Instant>,
+    log: VecDeque<String>,
+}
+
+#[derive(Serialize, Clone)]
+struct HistoryItem {
+    name: String,
+    status: FileStatus,
+    finished: String,
+    elapsed_secs: Option<u64>,
+}
+
+/// One file as shown on the dashboard and in the TUI.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileView {
+    pub id: usize,
+    pub name: String,
+    pub status: FileStatus,
+    /// Fraction encoded, 0-1, while encoding with a known duration
+    pub progress: Option<f64>,
+    pub speed: Option<String>,
+    pub elapsed_secs: Option<u64>,
+    /// Estimated seconds left, from the progress and ffmpeg's speed
+    pub eta_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct StatusView {
+    files: Vec<FileView>,
+    history: Vec<HistoryItem>,
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned())
+}
+
+/// Whether the dashboard or TUI is running, so callers can skip work for it.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Starts tracking state without serving it, for `--tui`.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Adds `paths` to the file list as pending, ignoring ones already listed.
+pub fn files_queued(paths: &[PathBuf]) {
+    if !enabled() {
+        return;
+    }
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    for path in paths {
+        if !state.files.iter().any(|file| &file.path == path) {
+            state.files.push(FileState {
+                path: path.clone(),
+                status: FileStatus::Pending,
+                output: None,
+                duration_secs: 0.0,
+                encoded_secs: 0.0,
+                speed: None,
+                fps: 0.0,
+                started: None,
+                log: VecDeque::new(),
+            });
+        }
+    }
+}
+
+/// Records that `path` started encoding to `output`, which will be
+/// `duration_secs` long (for the progress bar).
+pub fn file_started(path: &Path, output: &Path, duration_secs: f64) {
+    if !enabled() {
+        return;
+    }
+    files_queued(&[path.to_path_buf()]);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = state.files.iter_mut().find(|file| file.path == path) {
+        file.status = FileStatus::Encoding;
+        file.output = Some(output.to_path_buf());
+        file.duration_secs = duration_secs;
+        file.encoded_secs = 0.0;
+        file.started = Some(Instant::now());
+    }
+}
+
+/// Records how `path` finished: done, skipped or failed.
+pub fn file_finished(path: &Path, status: FileStatus) {
+    if !enabled() {
+        return;
+    }
+    files_queued(&[path.to_path_buf()]);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let State { files, history, totals } = &mut *state;
+    let Some(file) = files.iter_mut().find(|file| file.path == path) else {
+        return;
+    };
+    file.status = status;
+    file.fps = 0.0;
+    match status {
+        FileStatus::Done => {
+            totals.done += 1;
+            let size = |path: &Path| fs::metadata(path).map_or(0, |m| m.len());
+            if let Some(output) = &file.output {
+                totals.input_bytes += size(&file.path);
+                totals.output_bytes += size(output);
+            }
+        }
+        FileStatus::Skipped => totals.skipped += 1,
+        FileStatus::Failed => totals.failed += 1,
+        FileStatus::Pending | FileStatus::Encoding => return,
+    }
+    history.push_front(HistoryItem {
+        name: file_name(&file.path),
+        status,
+        finished: rfc3339_now(),
+        elapsed_secs: file.started.map(|started| started.elapsed().as_secs()),
+    });
+    history.truncate(HISTORY_LEN);
+}
+
+/// Records how far work done in-process rather than by ffmpeg has got on
+/// `path`: `encoded_secs` of it, at `speed` times real time.
+pub fn file_progress(path: &Path, encoded_secs: f64, speed: f64) {
+    if !enabled() {
+        return;
+    }
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = state.files.iter_mut().find(|file| file.path == path && file.status == FileStatus::Encoding) {
+        file.encoded_secs = encoded_secs;
+        file.speed = Some(format!("{:.1}x", speed));
+    }
+}
+
+/// Takes one line of ffmpeg output from the job labelled `label` (which
+/// starts with the input's file name).
+pub fn ffmpeg_line(label: &str, line: &str) {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(file) = state
+        .files
+        .iter_mut()
+        .filter(|file| file.status == FileStatus::Encoding)
+        .find(|file| label.starts_with(&file_name(&file.path)))
+    else {
+        return;
+    };
+    if let Some(secs) = stats_field(line, "time=").and_then(parse_timestamp) {
+        file.encoded_secs = secs;
+        file.speed = stats_field(line, "speed=").map(str::to_string);
+        file.fps = stats_field(line, "fps=").and_then(|fps| fps.parse().ok()).unwrap_or(0.0);
+        return;
+    }
+    file.log.push_back(format!("[{}] {}", label, line));
+    if file.log.len() > LOG_LINES {
+        file.log.pop_front();
+    }
+}
+
+/// The value after `key` in a stats line such as `frame= 42 ... time=00:00:01.75 ...`.
+fn stats_field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let rest = &line[line.find(key)? + key.len()..];
+    rest.split_whitespace().next()
+}
+
+/// Seconds in an `HH:MM:SS.cc` timestamp.
+fn parse_timestamp(text: &str) -> Option<f64> {
+    let mut secs = 0.0;
+    for part in text.split(':') {
+        secs = secs * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(secs)
+}
+
+/// The file list in queue order.
+pub fn files() -> Vec<FileView> {
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state.files.iter().enumerate().map(|(id, file)| view(id, file)).collect()
+}
+
+fn view(id: usize, file: &FileState) -> FileView {
+    let encoding = file.status == FileStatus::Encoding;
+    let speed = file.speed.as_deref().and_then(|speed| speed.trim_end_matches('x').parse::<f64>().ok());
+    FileView {
+        id,
+        name: file_name(&file.path),
+        status: file.status,
+        progress: (encoding && file.duration_secs > 0.0).then(|| (file.encoded_secs / file.duration_secs).min(1.0)),
+        speed: file.speed.clone(),
+        elapsed_secs: if encoding { file.started.map(|started| started.elapsed().as_secs()) } else { None },
+        eta_secs: match speed {
+            Some(speed) if encoding && speed > 0.0 && file.duration_secs > 0.0 => {
+                Some(((file.duration_secs - file.encoded_secs).max(0.0) / speed) as u64)
+            }
+            _ => None,
+        },
+    }
+}
+
+/// Moves the pending file `id` one place earlier (`up`) or later among the
+/// pending files, so it is started sooner or later. Returns its new id.
+pub fn move_file(id: usize, up: bool) -> usize {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let pending: Vec<usize> = (0..state.files.len())
+        .filter(|&i| state.files[i].status == FileStatus::Pending)
+        .collect();
+    let Some(at) = pending.iter().position(|&i| i == id) else {
+        return id;
+    };
+    let other = if up { at.checked_sub(1) } else { Some(at + 1) };
+    match other.and_then(|other| pending.get(other)) {
+        Some(&other) => {
+            state.files.swap(id, other);
+            other
+        }
+        None => id,
+    }
+}
+
+/// Marks the pending file `id` as skipped. Files already started are left alone.
+pub fn skip_file(id: usize) -> bool {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    match state.files.get_mut(id) {
+        Some(file) if file.status == FileStatus::Pending => {
+            file.status = FileStatus::Skipped;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Whether `path` was skipped with [`skip_file`].
+pub fn is_skipped(path: &Path) -> bool {
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state.files.iter().any(|file| file.path == path && file.status == FileStatus::Skipped)
+}
+
+/// Pending files in their current order.
+pub fn pending_order() -> Vec<PathBuf> {
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state
+        .files
+        .iter()
+        .filter(|file| file.status == FileStatus::Pending)
+        .map(|file| file.path.clone())
+        .collect()
+}
+
+fn status_json() -> String {
+    let view = StatusView {
+        files: files(),
+        history: STATE.lock().unwrap_or_else(|e| e.into_inner()).history.iter().cloned().collect(),
+    };
+    serde_json::to_string(&view).unwrap_or_default()
+}
+
+/// Totals and current activity in the Prometheus text exposition format.
+fn metrics_text() -> String {
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let totals = &state.totals;
+    let count = |status| state.files.iter().filter(|file| file.status == status).count();
+    let mut text = String::new();
+    let _ = writeln!(text, "# HELP tvcode_files_total Files finished, by result.");
+    let _ = writeln!(text, "# TYPE tvcode_files_total counter");
+    for (result, value) in [("done", totals.done), ("skipped", totals.skipped), ("failed", totals.failed)] {
+        let _ = writeln!(text, "tvcode_files_total{{result=\"{}\"}} {}", result, value);
+    }
+    let metrics: [(&str, &str, &str, String); 7] = [
+        ("tvcode_failures_total", "counter", "Files that failed to transcode.", totals.failed.to_string()),
+        ("tvcode_input_bytes_total", "counter", "Size of the sources of transcoded files.", totals.input_bytes.to_string()),
+        ("tvcode_output_bytes_total", "counter", "Size of the outputs of transcoded files.", totals.output_bytes.to_string()),
+        (
+            "tvcode_bytes_saved",
+            "gauge",
+            "Source size minus output size over all transcoded files.",
+            (totals.input_bytes as i64 - totals.output_bytes as i64).to_string(),
+        ),
+        ("tvcode_queue_depth", "gauge", "Files waiting to be encoded.", count(FileStatus::Pending).to_string()),
+        ("tvcode_encodes_running", "gauge", "Files being encoded.", count(FileStatus::Encoding).to_string()),
+        (
+            "tvcode_encode_fps",
+            "gauge",
+            "Frames per second over all running encodes.",
+            state.files.iter().map(|file| file.fps).sum::<f64>().to_string(),
+        ),
+    ];
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(text, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+    }
+    text
+}
+
+fn log_text(id: usize) -> Option<String> {
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let file = state.files.get(id)?;
+    Some(file.log.iter().map(|line| format!("{}\n", line)).collect())
+}
+
+/// Starts serving the dashboard on `addr` (e.g. `0.0.0.0:8080`).
+pub fn serve(addr: &str) -> Result<(), String> {
+    let listener = runtime::block_on(TcpListener::bind(addr))
+        .map_err(|e| format!("Cannot serve the dashboard on {}: {}", addr, e))?;
+    ENABLED.store(true, Ordering::Relaxed);
+    runtime::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { continue };
+            runtime::spawn(async move {
+                let _ = handle(stream).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn handle(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&mut stream).read_line(&mut request_line).await?;
+    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let (status, content_type, body) = match path {
+        "/" => ("200 OK", "text/html; charset=utf-8", PAGE.to_string()),
+        "/api/status" => ("200 OK", "application/json", status_json()),
+        "/metrics" => ("200 OK", "text/plain; version=0.0.4", metrics_text()),
+        "/api/log" => {
+            let id = query.strip_prefix("id=").and_then(|id| id.parse().ok());
+            match id.and_then(log_text) {
+                Some(text) => ("200 OK", "text/plain; charset=utf-8", text),
+                None => ("404 Not Found", "text/plain", "No such file\n".to_string()),
+            }
+        }
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+const PAGE: &str = r##"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1">
+<title>tvcode</title>
+<style>
+body { font-family: -apple-system, system-ui, sans-serif; margin: 1em; color: #222; }
+table { border-collapse: collapse; width: 100%; }
+td, th { padding: .3em .5em; border-bottom: 1px solid #ddd; text-align: left; font-size: .95em; }
+.bar { background: #eee; border-radius: 4px; height: .8em; min-width: 6em; }
+.bar div { background: #3478f6; height: 100%; border-radius: 4px; }
+.done { color: #1a7f37; } .failed { color: #cf222e; } .encoding { color: #3478f6; } .skipped { color: #888; }
+pre { background: #f6f8fa; padding: .5em; overflow-x: auto; font-size: .8em; white-space: pre-wrap; }
+a { color: inherit; }
+</style></head><body>
+<h2>📺 tvcode</h2>
+<table><thead><tr><th>File</th><th>Status</th><th>Progress</th></tr></thead><tbody id="files"></tbody></table>
+<h3>Recent</h3>
+<table><tbody id="history"></tbody></table>
+<pre id="log" hidden></pre>
+<script>
+const esc = s => s.replace(/[&<>"]/g, c => ({'&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;'}[c]));
+const mins = s => s == null ? '' : Math.floor(s / 60) + 'm' + String(s % 60).padStart(2, '0') + 's';
+async function showLog(id) {
+  const log = document.getElementById('log');
+  log.textContent = await (await fetch('/api/log?id=' + id)).text() || '(no ffmpeg output yet)';
+  log.hidden = false;
+}
+async function refresh() {
+  const s = await (await fetch('/api/status')).json();
+  document.getElementById('files').innerHTML = s.files.map(f => `<tr>
+    <td><a href="#" onclick="showLog(${f.id}); return false">${esc(f.name)}</a></td>
+    <td class="${f.status}">${f.status}</td>
+    <td>${f.progress == null ? '' : `<div class="bar"><div style="width:${(f.progress * 100).toFixed(1)}%"></div></div>
+      ${(f.progress * 100).toFixed(1)}% ${f.speed || ''} ${mins(f.elapsed_secs)}`}</td></tr>`).join('');
+  document.getElementById('history').innerHTML = s.history.map(h => `<tr>
+    <td>${esc(h.name)}</td><td class="${h.status}">${h.status}</td><td>${mins(h.elapsed_secs)}</td><td>${h.finished}</td></tr>`).join('');
+}
+refresh();
+setInterval(refresh, 2000);
+</script></body></html>
+"##;
@@ -0,0 +1,79 @@
+//! `--device` playback targets and what each Apple TV generation can decode.
+
+use crate::h264::MAX_LEVEL;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// An Apple TV generation to tune output for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Device {
+    /// Apple TV (3rd generation): 1080p30, H.264 High@4.0
+    #[value(name = "appletv3")]
+    AppleTv3,
+    /// Apple TV HD: 1080p60, H.264 High@4.2 or HEVC
+    #[value(name = "appletvhd")]
+    AppleTvHd,
+    /// Apple TV 4K: 2160p60, H.264 High@5.1 or HEVC
+    #[value(name = "appletv4k")]
+    AppleTv4k,
+}
+
+/// Decoder limits that decide what is compatible and how to encode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceCaps {
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Highest frame rate played smoothly, if limited
+    pub max_frame_rate: Option<u32>,
+    /// Highest H.264 `level_idc` decoded (41 is level 4.1)
+    pub max_h264_level: u32,
+    /// Whether HEVC sources can be kept as they are
+    pub hevc: bool,
+    /// Audio codecs (ffprobe names) that can be copied instead of converted
+    pub audio_codecs: &'static [&'static str],
+}
+
+impl DeviceCaps {
+    /// Used without `--device`: H.264 with AAC, no size or level limit.
+    pub const GENERIC: DeviceCaps = DeviceCaps {
+        max_width: u32::MAX,
+        max_height: u32::MAX,
+        max_frame_rate: None,
+        max_h264_level: MAX_LEVEL,
+        hevc: false,
+        audio_codecs: &["aac"],
+    };
+}
+
+impl Device {
+    /// Decoder limits of this model.
+    pub fn caps(self) -> DeviceCaps {
+        match self {
+            Device::AppleTv3 => DeviceCaps {
+                max_width: 1920,
+                max_height: 1080,
+                max_frame_rate: Some(30),
+                max_h264_level: 40,
+                hevc: false,
+                audio_codecs: &["aac", "ac3"],
+            },
+            Device::AppleTvHd => DeviceCaps {
+                max_width: 1920,
+                max_height: 1080,
+                max_frame_rate: Some(60),
+                max_h264_level: 42,
+                hevc: true,
+                audio_codecs: &["aac", "ac3", "eac3"],
+            },
+            Device::AppleTv4k => DeviceCaps {
+                max_width: 3840,
+                max_height: 2160,
+                max_frame_rate: Some(60),
+                max_h264_level: 51,
+                hevc: true,
+                audio_codecs: &["aac", "ac3", "eac3"],
+            },
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Finding candidate video files on disk.
+
+use crate::ignore::{glob_match, IgnoreFile};
+use crate::plan::{is_output_dir, is_output_path};
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+/// File extensions (lowercase) treated as video inputs.
+pub const VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "mkv", "avi", "mov", "wmv", "flv", "webm",
+    "m4v", "mpg", "mpeg", "3gp", "ts", "m2ts",
+];
+
+/// Folders whose videos are bonus material, as Plex and scene releases
+/// name them (compared lowercase).
+const EXTRAS_DIRS: &[&str] = &[
+    "extras", "featurettes", "behind the scenes", "deleted scenes", "interviews", "trailers", "sample",
+    "samples", "bonus",
+];
+
+/// Name endings (lowercase, before the extension) that mark samples and
+/// Plex-style local extras, e.g. `movie-sample.mkv` or `movie-trailer.mp4`.
+const EXTRAS_SUFFIXES: &[&str] = &[
+    "-sample", ".sample", "_sample", " sample", "-trailer", "-featurette", "-behindthescenes", "-deleted",
+    "-interview",
+];
+
+/// `--include` and `--exclude` glob patterns, matched case-insensitively
+/// against file names on top of the extension list, whether samples and
+/// extras are left out and whether subfolders are searched.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    /// When not empty, a name must match one of these
+    pub include: Vec<String>,
+    /// A name matching any of these is left out
+    pub exclude: Vec<String>,
+    /// Leave out what [`is_extra`] recognizes
+    pub skip_extras: bool,
+    /// Descend into subfolders
+    pub recursive: bool,
+}
+
+impl FileFilter {
+    /// Whether to take `path`, judging extras by its name and by the folder
+    /// it sits in, as for a `--files-from` list.
+    pub fn accepts(&self, path: &Path) -> bool {
+        self.accepts_name(path) && !(self.skip_extras && path.parent().is_some_and(is_extras_dir))
+    }
+
+    /// Like [`accepts`](Self::accepts), leaving the folder to discovery,
+    /// which skips extras folders below the scanned one as a whole.
+    fn accepts_name(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+        let matches = |pattern: &String| glob_match(&pattern.to_lowercase(), &name);
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+            && !(self.skip_extras && has_extra_name(path))
+    }
+}
+
+/// Whether `path` looks like a sample, trailer, release-group promo or
+/// other bonus video rather than the feature: by its name, or by sitting in
+/// an `Extras`, `Featurettes`, `Sample`... folder.
+pub fn is_extra(path: &Path) -> bool {
+    has_extra_name(path) || path.parent().is_some_and(is_extras_dir)
+}
+
+fn has_extra_name(path: &Path) -> bool {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_lowercase();
+    stem == "sample"
+        || stem.starts_with("sample-")
+        || stem.starts_with("rarbg")
+        || EXTRAS_SUFFIXES.iter().any(|suffix| stem.ends_with(suffix))
+}
+
+/// Whether the folder `dir` is named like one holding bonus material.
+pub fn is_extras_dir(dir: &Path) -> bool {
+    dir.file_name().is_some_and(|name| EXTRAS_DIRS.contains(&name.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Lists the video files directly inside `dir`, leaving out tvcode's own
+/// outputs and those matched by its `.tvcodeignore`.
+pub fn find_video_files(dir: &Path) -> Vec<PathBuf> {
+    find_matching_video_files(dir, &FileFilter::default())
+}
+
+/// Like [`find_video_files`], keeping only names `filter` accepts and, with
+/// `filter.recursive`, searching subfolders too. Hidden folders and
+/// tvcode's output folders are never entered, and a folder a
+/// `.tvcodeignore` excludes is skipped with everything in it, as are extras
+/// folders with `filter.skip_extras`. The scanned folder's own name is not
+/// held against its files: pointing tvcode at an `Extras` folder takes
+/// what is in it.
+pub fn find_matching_video_files(dir: &Path, filter: &FileFilter) -> Vec<PathBuf> {
+    let mut video_files = Vec::new();
+    let mut ignores = vec![(dir.to_path_buf(), IgnoreFile::load(dir))];
+    collect_video_files(dir, &mut ignores, filter, &mut video_files);
+    video_files
+}
+
+/// Adds the videos in `dir` to `video_files`. `ignores` holds the
+/// `.tvcodeignore` of `dir` and of every folder above it up to the scanned
+/// one, outermost first.
+fn collect_video_files(
+    dir: &Path,
+    ignores: &mut Vec<(PathBuf, IgnoreFile)>,
+    filter: &FileFilter,
+    video_files: &mut Vec<PathBuf>,
+) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_dir = path.is_dir();
+        if is_ignored(ignores, &path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            let extras = filter.skip_extras && is_extras_dir(&path);
+            if filter.recursive && !hidden && !extras && !is_output_dir(&path) {
+                ignores.push((path.clone(), IgnoreFile::load(&path)));
+                collect_video_files(&path, ignores, filter, video_files);
+                ignores.pop();
+            }
+            continue;
+        }
+        let is_video = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| VIDEO_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+        if is_video && path.is_file() && !is_output_path(&path) && filter.accepts_name(&path) {
+            video_files.push(path);
+        }
+    }
+}
+
+/// Whether the `.tvcodeignore` files in `ignores` exclude `path`. Each is
+/// given the path relative to its own folder, and the deepest one with a
+/// matching pattern decides.
+fn is_ignored(ignores: &[(PathBuf, IgnoreFile)], path: &Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for (base, ignore) in ignores {
+        let Ok(relative) = path.strip_prefix(base) else {
+            continue;
+        };
+        let relative: Vec<String> = relative.iter().map(|part| part.to_string_lossy().into_owned()).collect();
+        if let Some(verdict) = ignore.verdict(&relative.join("/"), is_dir) {
+            ignored = verdict;
+        }
+    }
+    ignored
+}
+
+/// Reads a list of paths, one per line or (`null_separated`) separated by
+/// NUL bytes as from `find -print0`. Blank entries are dropped.
+pub fn read_file_list(mut reader: impl BufRead, null_separated: bool) -> io::Result<Vec<PathBuf>> {
+    let separator = if null_separated { b'\0' } else { b'\n' };
+    let mut files = Vec::new();
+    let mut entry = Vec::new();
+    while reader.read_until(separator, &mut entry)? > 0 {
+        if entry.last() == Some(&separator) {
+            entry.pop();
+        }
+        if !null_separated && entry.last() == Some(&b'\r') {
+            entry.pop();
+        }
+        if !entry.is_empty() {
+            files.push(path_from_bytes(std::mem::take(&mut entry)));
+        }
+        entry.clear();
+    }
+    Ok(files)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ignore::IGNORE_FILE;
+
+    fn names(files: Vec<PathBuf>, root: &Path) -> Vec<String> {
+        let mut names: Vec<String> =
+            files.iter().map(|file| file.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/")).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn recursion_honours_nested_ignore_files() {
+        let root = crate::scratch_dir("discover-recursive");
+        for dir in ["Season 1", "Season 2", "skip", ".hidden", "movie_appletv_hls"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "movie.mkv",
+            "movie_appletv.mp4",
+            "notes.txt",
+            "Season 1/e01.mkv",
+            "Season 1/e02.avi",
+            "Season 2/e01.mkv",
+            "skip/e01.mkv",
+            ".hidden/e01.mkv",
+            "movie_appletv_hls/e01.mkv",
+        ] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        fs::write(root.join(IGNORE_FILE), "skip/\n*.avi\n").unwrap();
+        fs::write(root.join("Season 2").join(IGNORE_FILE), "e01.mkv\n").unwrap();
+        fs::write(root.join("Season 1").join(IGNORE_FILE), "!e02.avi\n").unwrap();
+
+        assert_eq!(names(find_video_files(&root), &root), ["movie.mkv"]);
+        let filter = FileFilter { recursive: true, ..FileFilter::default() };
+        assert_eq!(
+            names(find_matching_video_files(&root, &filter), &root),
+            ["Season 1/e01.mkv", "Season 1/e02.avi", "movie.mkv"]
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn extras_are_known_by_name_or_folder() {
+        assert!(is_extra(Path::new("/media/Movie-trailer.mkv")));
+        assert!(is_extra(Path::new("/media/sample.mkv")));
+        assert!(is_extra(Path::new("/media/RARBG.COM.mp4")));
+        assert!(is_extra(Path::new("/media/Movie/Featurettes/Making Of.mkv")));
+        assert!(!is_extra(Path::new("/media/Movie/Movie.mkv")));
+        assert!(!is_extra(Path::new("/media/Samples and Stories.mkv")));
+    }
+
+    #[test]
+    fn extras_folders_are_skipped_below_the_scanned_one() {
+        let root = crate::scratch_dir("discover-extras");
+        fs::create_dir_all(root.join("Movie").join("Extras")).unwrap();
+        for file in ["Movie/Movie.mkv", "Movie/Movie-sample.mkv", "Movie/Extras/Making Of.mkv"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        let filter = FileFilter { recursive: true, skip_extras: true, ..FileFilter::default() };
+        assert_eq!(names(find_matching_video_files(&root, &filter), &root), ["Movie/Movie.mkv"]);
+        let extras = root.join("Movie").join("Extras");
+        assert_eq!(names(find_matching_video_files(&extras, &filter), &extras), ["Making Of.mkv"]);
+        let keep = FileFilter { skip_extras: false, ..filter };
+        assert_eq!(names(find_matching_video_files(&root, &keep), &root).len(), 3);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,407 @@
+//! Video filter graph construction with hardware frame placement.
+//!
+//! When a file is decoded on the GPU, frames stay in device memory and are
+//! resized with the backend's own scaler (`scale_cuda`, `scale_vaapi`, ...).
+//! CPU-only filters such as subtitle burning get `hwdownload` inserted before
+//! them, and encoders that only accept device frames get a final `hwupload`.
+//! Paths and text inside filter options go through [`escape_value`].
+
+use std::path::{Path, PathBuf};
+
+/// Hardware frame backends a filter graph can keep frames on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HwBackend {
+    Cuda,
+    Vaapi,
+    Qsv,
+    VideoToolbox,
+}
+
+/// Source codecs the GPU decoders handle reliably across vendors.
+const HW_DECODABLE: &[&str] = &["h264", "hevc", "mpeg2video", "vp9", "av1"];
+
+impl HwBackend {
+    /// The frame backend used by an encoder family from
+    /// [`crate::hwaccel::detect_hardware_acceleration`], if it has one.
+    pub fn for_encoder(hw: &str) -> Option<HwBackend> {
+        match hw {
+            "nvenc" => Some(HwBackend::Cuda),
+            "vaapi" => Some(HwBackend::Vaapi),
+            "qsv" => Some(HwBackend::Qsv),
+            "videotoolbox" => Some(HwBackend::VideoToolbox),
+            _ => None,
+        }
+    }
+
+    /// Whether the GPU decoder can handle `codec` (an ffprobe codec name).
+    pub fn can_decode(codec: &str) -> bool {
+        HW_DECODABLE.contains(&codec)
+    }
+
+    /// Input options that decode on the GPU (on `device`, if given) and keep
+    /// frames in device memory.
+    pub fn decode_args(self, device: Option<&str>) -> Vec<String> {
+        let (hwaccel, format) = match self {
+            HwBackend::Cuda => ("cuda", "cuda"),
+            HwBackend::Vaapi => ("vaapi", "vaapi"),
+            HwBackend::Qsv => ("qsv", "qsv"),
+            HwBackend::VideoToolbox => ("videotoolbox", "videotoolbox_vld"),
+        };
+        let mut args = vec![
+            "-hwaccel".to_string(),
+            hwaccel.to_string(),
+            "-hwaccel_output_format".to_string(),
+            format.to_string(),
+        ];
+        if let Some(device) = device {
+            args.extend(["-hwaccel_device".to_string(), device.to_string()]);
+        }
+        args
+    }
+
+    fn scale(self, width: u32, height: u32) -> String {
+        match self {
+            HwBackend::Cuda => format!("scale_cuda={}:{}", width, height),
+            HwBackend::Vaapi => format!("scale_vaapi=w={}:h={}", width, height),
+            HwBackend::Qsv => format!("scale_qsv=w={}:h={}", width, height),
+            HwBackend::VideoToolbox => format!("scale_vt=w={}:h={}", width, height),
+        }
+    }
+
+    fn upload(self) -> &'static str {
+        match self {
+            HwBackend::Cuda => "hwupload_cuda",
+            HwBackend::Vaapi => "format=nv12,hwupload",
+            HwBackend::Qsv => "format=nv12,hwupload=extra_hw_frames=64",
+            HwBackend::VideoToolbox => "hwupload",
+        }
+    }
+
+    fn download(self) -> &'static str {
+        "hwdownload,format=nv12|p010le"
+    }
+
+    /// VAAPI and QSV encoders only accept device frames; NVENC and
+    /// VideoToolbox take either.
+    fn encoder_needs_hw_frames(self) -> bool {
+        matches!(self, HwBackend::Vaapi | HwBackend::Qsv)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Filter(String),
+    /// Overlay bitmap subtitle stream `index` of input `input`, scaled to
+    /// `width`x`height`, moved down by `y` lines and shown `delay` seconds later
+    OverlaySubtitle {
+        input: usize,
+        index: usize,
+        width: u32,
+        height: u32,
+        y: i64,
+        delay: f64,
+    },
+    /// Overlay the image at `image`, scaled to `width` pixels wide with its
+    /// alpha multiplied by `opacity`, at `overlay` filter coordinates
+    Watermark {
+        image: PathBuf,
+        width: u32,
+        opacity: f64,
+        position: String,
+    },
+}
+
+/// Label of the video a `-filter_complex` graph puts out, to `-map` it by.
+pub const COMPLEX_OUTPUT: &str = "[vout]";
+
+/// The `-vf` or `-filter_complex` arguments a graph renders to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterArgs {
+    None,
+    /// A linear chain for `-vf`
+    Simple(String),
+    /// A graph with extra inputs for `-filter_complex`, its output labeled
+    /// [`COMPLEX_OUTPUT`]
+    Complex(String),
+}
+
+impl FilterArgs {
+    pub fn into_args(self) -> Vec<String> {
+        match self {
+            FilterArgs::None => Vec::new(),
+            FilterArgs::Simple(chain) => vec!["-vf".to_string(), chain],
+            FilterArgs::Complex(graph) => vec!["-filter_complex".to_string(), graph],
+        }
+    }
+}
+
+/// Builds a video filter chain, tracking whether frames are in device memory.
+#[derive(Debug, Clone)]
+pub struct FilterGraph {
+    backend: Option<HwBackend>,
+    on_gpu: bool,
+    downloaded: bool,
+    steps: Vec<Step>,
+}
+
+impl FilterGraph {
+    /// Starts a graph for an encoder using `backend`; `hw_decoded` means the
+    /// input frames arrive in device memory.
+    pub fn new(backend: Option<HwBackend>, hw_decoded: bool) -> FilterGraph {
+        FilterGraph {
+            backend,
+            on_gpu: backend.is_some() && hw_decoded,
+            downloaded: false,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Resizes to `width`x`height`, on the GPU when frames are already there.
+    pub fn scale(&mut self, width: u32, height: u32) {
+        match self.backend {
+            Some(backend) if self.on_gpu => self.push(backend.scale(width, height)),
+            _ => self.push(format!("scale={}:{}", width, height)),
+        }
+    }
+
+    /// Appends a filter that only runs on system-memory frames.
+    pub fn cpu(&mut self, filter: impl Into<String>) {
+        self.download_to_cpu();
+        self.push(filter.into());
+    }
+
+    /// Overlays bitmap subtitle stream `index` (among subtitle streams) of
+    /// ffmpeg input `input`, scaled to the current `width`x`height` of the
+    /// video, moved down by `y` lines (up when negative) and `delay` seconds
+    /// later (earlier).
+    pub fn overlay_subtitle(&mut self, input: usize, index: usize, width: u32, height: u32, y: i64, delay: f64) {
+        self.download_to_cpu();
+        self.steps.push(Step::OverlaySubtitle {
+            input,
+            index,
+            width,
+            height,
+            y,
+            delay,
+        });
+    }
+
+    /// Overlays the image at `image`, scaled to `width` pixels wide and made
+    /// `opacity` (0.0-1.0) opaque, at `position` (`overlay` coordinates such
+    /// as `x=W-w-20:y=H-h-20`). The image stays up for the whole video.
+    pub fn watermark(&mut self, image: &Path, width: u32, opacity: f64, position: String) {
+        self.download_to_cpu();
+        self.steps.push(Step::Watermark {
+            image: image.to_path_buf(),
+            width,
+            opacity,
+            position,
+        });
+    }
+
+    fn push(&mut self, filter: String) {
+        self.steps.push(Step::Filter(filter));
+    }
+
+    fn download_to_cpu(&mut self) {
+        if let (true, Some(backend)) = (self.on_gpu, self.backend) {
+            self.push(backend.download().to_string());
+            self.on_gpu = false;
+            self.downloaded = true;
+        }
+    }
+
+    /// Finishes the graph, uploading frames for encoders that need them.
+    pub fn render(mut self) -> FilterArgs {
+        if let Some(backend) = self.backend {
+            if backend.encoder_needs_hw_frames() && !self.on_gpu {
+                self.push(backend.upload().to_string());
+                self.on_gpu = true;
+            } else if self.downloaded && !self.on_gpu {
+                // 10-bit surfaces download as p010; H.264 encoders want 8-bit
+                self.push("format=nv12".to_string());
+            }
+        }
+
+        let overlays = self.steps.iter().any(|s| !matches!(s, Step::Filter(_)));
+        if !overlays {
+            if self.steps.is_empty() {
+                return FilterArgs::None;
+            }
+            let chain: Vec<&str> = self
+                .steps
+                .iter()
+                .filter_map(|s| match s {
+                    Step::Filter(f) => Some(f.as_str()),
+                    _ => None,
+                })
+                .collect();
+            return FilterArgs::Simple(chain.join(","));
+        }
+
+        // Each overlay ends the chain so far under a label and starts a new
+        // one from its output
+        let mut graph = String::new();
+        let mut current = "[0:v]".to_string();
+        let mut filtered = false;
+        for (i, step) in self.steps.iter().enumerate() {
+            if let Step::Filter(f) = step {
+                if filtered {
+                    current.push(',');
+                }
+                current.push_str(f);
+                filtered = true;
+                continue;
+            }
+            let base = if filtered {
+                graph.push_str(&format!("{}[base{}];", current, i));
+                format!("[base{}]", i)
+            } else {
+                current.clone()
+            };
+            match step {
+                Step::OverlaySubtitle {
+                    input,
+                    index,
+                    width,
+                    height,
+                    y,
+                    delay,
+                } => {
+                    let position = if *y == 0 { String::new() } else { format!("y={}:", y) };
+                    let shift = if *delay == 0.0 { String::new() } else { format!("{},", shift_pts(*delay)) };
+                    graph.push_str(&format!("[{}:s:{}]{}scale={}:{}[sub];", input, index, shift, width, height));
+                    current = format!("{}[sub]overlay={}eof_action=pass", base, position);
+                }
+                Step::Watermark {
+                    image,
+                    width,
+                    opacity,
+                    position,
+                } => {
+                    graph.push_str(&format!(
+                        "movie={},scale={}:-1,format=rgba,colorchannelmixer=aa={:.2}[logo];",
+                        escape_path(image),
+                        width,
+                        opacity
+                    ));
+                    current = format!("{}[logo]overlay={}", base, position);
+                }
+                Step::Filter(_) => unreachable!(),
+            }
+            filtered = true;
+        }
+        graph.push_str(&current);
+        graph.push_str(COMPLEX_OUTPUT);
+        FilterArgs::Complex(graph)
+    }
+}
+
+/// An `unsharp` filter sharpening luma by `amount` (up to 1.5) over a 5x5
+/// area. Chroma is left alone, where sharpening only brings out noise.
+pub fn unsharp(amount: f64) -> String {
+    format!("unsharp=5:5:{:.2}:5:5:0", amount)
+}
+
+/// A `setpts` filter moving frames `secs` later (earlier when negative).
+pub fn shift_pts(secs: f64) -> String {
+    if secs < 0.0 {
+        format!("setpts=PTS-{:.3}/TB", -secs)
+    } else {
+        format!("setpts=PTS+{:.3}/TB", secs)
+    }
+}
+
+/// Escapes `text` for use as a filter option value inside a graph. ffmpeg
+/// unescapes twice: the graph parser first (`\`, `'`, `[`, `]`, `,` and `;`
+/// are special), then the filter's option parser (`\`, `'` and `:`), so the
+/// option level is escaped first and the result again for the graph.
+pub fn escape_value(text: &str) -> String {
+    escape(&escape(text, &['\\', '\'', ':']), &['\\', '\'', '[', ']', ',', ';'])
+}
+
+fn escape(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// `path` as a filter option value, e.g. for the `subtitles` filter.
+pub fn escape_path(path: &Path) -> String {
+    escape_path_text(&path.to_string_lossy(), cfg!(windows))
+}
+
+/// On Windows, backslashes become forward slashes, which ffmpeg and the
+/// Windows APIs accept and which need no escaping; only `\\?\` paths must
+/// keep theirs. Elsewhere a backslash is part of a file name and is escaped.
+fn escape_path_text(path: &str, windows: bool) -> String {
+    if windows && !path.starts_with(r"\\?\") {
+        escape_value(&path.replace('\\', "/"))
+    } else {
+        escape_value(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_unix_path_is_unchanged() {
+        assert_eq!(escape_path_text("/media/Movies/Heat (1995).mkv", false), "/media/Movies/Heat (1995).mkv");
+    }
+
+    #[test]
+    fn spaces_need_no_escaping() {
+        assert_eq!(escape_path_text("/srv/My Movies/a b.mkv", false), "/srv/My Movies/a b.mkv");
+    }
+
+    #[test]
+    fn windows_drive_letter() {
+        assert_eq!(escape_path_text(r"C:\Users\Me\Videos\film.mkv", true), r"C\\:/Users/Me/Videos/film.mkv");
+    }
+
+    #[test]
+    fn windows_path_with_spaces_and_brackets() {
+        assert_eq!(
+            escape_path_text(r"D:\TV Shows\Show [2020]\S01E01.mkv", true),
+            r"D\\:/TV Shows/Show \[2020\]/S01E01.mkv"
+        );
+    }
+
+    #[test]
+    fn windows_unc_path() {
+        assert_eq!(escape_path_text(r"\\nas\media\film.mkv", true), "//nas/media/film.mkv");
+    }
+
+    #[test]
+    fn windows_verbatim_path_keeps_backslashes() {
+        assert_eq!(escape_path_text(r"\\?\C:\film.mkv", true), r"\\\\\\\\?\\\\C\\:\\\\film.mkv");
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(escape_path_text("/m/Bob's \"Cut\".mkv", false), r#"/m/Bob\\\'s "Cut".mkv"#);
+        assert_eq!(escape_path_text(r"C:\Bob's.mkv", true), r"C\\:/Bob\\\'s.mkv");
+    }
+
+    #[test]
+    fn graph_separators() {
+        assert_eq!(escape_path_text("/m/a,b;c[1].mkv", false), r"/m/a\,b\;c\[1\].mkv");
+    }
+
+    #[test]
+    fn backslash_in_unix_file_name() {
+        assert_eq!(escape_path_text(r"/m/a\b.mkv", false), r"/m/a\\\\b.mkv");
+    }
+
+    #[test]
+    fn colon_in_unix_path() {
+        assert_eq!(escape_path_text("/m/Alien: Covenant.mkv", false), r"/m/Alien\\: Covenant.mkv");
+    }
+}
@@ -0,0 +1,127 @@
+//! H.264 profile and level selection (ITU-T H.264 Annex A limits).
+
+/// H.264 profile written into the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum H264Profile {
+    Main,
+    High,
+}
+
+impl H264Profile {
+    /// Picks Main for SD, where older players are pickiest, and High for
+    /// 720p and up, where its 8x8 transforms pay off.
+    pub fn for_height(height: u32) -> H264Profile {
+        if height < 720 {
+            H264Profile::Main
+        } else {
+            H264Profile::High
+        }
+    }
+
+    /// ffmpeg `-profile:v` value.
+    pub fn name(self) -> &'static str {
+        match self {
+            H264Profile::Main => "main",
+            H264Profile::High => "high",
+        }
+    }
+
+    /// `profile_idc`, as used in RFC 6381 `avc1.PPCCLL` codec strings.
+    pub fn idc(self) -> u32 {
+        match self {
+            H264Profile::Main => 77,
+            H264Profile::High => 100,
+        }
+    }
+
+    /// Scale applied to the Main-profile MaxBR limits (High allows 25% more).
+    fn bitrate_factor(self) -> u32 {
+        match self {
+            H264Profile::Main => 4,
+            H264Profile::High => 5,
+        }
+    }
+}
+
+/// (`level_idc`, MaxMBPS macroblocks/s, MaxFS macroblocks, MaxBR kbit/s for Main)
+const LEVELS: [(u32, u64, u64, u32); 9] = [
+    (30, 40_500, 1_620, 10_000),
+    (31, 108_000, 3_600, 14_000),
+    (32, 216_000, 5_120, 20_000),
+    (40, 245_760, 8_192, 20_000),
+    (41, 245_760, 8_192, 50_000),
+    (42, 522_240, 8_704, 50_000),
+    (50, 589_824, 22_080, 135_000),
+    (51, 983_040, 36_864, 240_000),
+    (52, 2_073_600, 36_864, 240_000),
+];
+
+/// Highest level in the table; also the limit when no device caps it.
+pub const MAX_LEVEL: u32 = 52;
+
+/// The lowest `level_idc` whose frame size, macroblock rate and bitrate
+/// limits admit `width`x`height` at `frame_rate` fps peaking at
+/// `max_bitrate_kbps`. An unknown (zero) frame rate is treated as 30 fps.
+pub fn min_level(width: u32, height: u32, frame_rate: f64, max_bitrate_kbps: u32, profile: H264Profile) -> u32 {
+    let frame_rate = if frame_rate > 0.0 { frame_rate } else { 30.0 };
+    let frame_size = (width as u64).div_ceil(16) * (height as u64).div_ceil(16);
+    let mb_rate = (frame_size as f64 * frame_rate).ceil() as u64;
+    LEVELS
+        .iter()
+        .find(|&&(_, max_mbps, max_fs, max_br)| {
+            frame_size <= max_fs
+                && mb_rate <= max_mbps
+                && max_bitrate_kbps as u64 * 4 <= max_br as u64 * profile.bitrate_factor() as u64
+        })
+        .map_or(MAX_LEVEL, |&(level, ..)| level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_switches_to_high_at_720_lines() {
+        assert_eq!(H264Profile::for_height(576), H264Profile::Main);
+        assert_eq!(H264Profile::for_height(719), H264Profile::Main);
+        assert_eq!(H264Profile::for_height(720), H264Profile::High);
+        assert_eq!((H264Profile::Main.idc(), H264Profile::High.idc()), (77, 100));
+    }
+
+    #[test]
+    fn frame_size_limits() {
+        // 720x576 is exactly 1620 macroblocks, 1280x720 exactly 3600
+        assert_eq!(min_level(720, 576, 25.0, 1_000, H264Profile::Main), 30);
+        assert_eq!(min_level(720, 578, 25.0, 1_000, H264Profile::Main), 31);
+        assert_eq!(min_level(1280, 720, 25.0, 1_000, H264Profile::High), 31);
+        assert_eq!(min_level(1280, 736, 25.0, 1_000, H264Profile::High), 32);
+        // 1080 lines are coded as 1088, 8160 macroblocks
+        assert_eq!(min_level(1920, 1080, 24.0, 1_000, H264Profile::High), 40);
+        assert_eq!(min_level(2048, 1088, 24.0, 1_000, H264Profile::High), 42);
+        assert_eq!(min_level(3840, 2160, 24.0, 1_000, H264Profile::High), 51);
+    }
+
+    #[test]
+    fn frame_rate_limits() {
+        assert_eq!(min_level(720, 576, 30.0, 1_000, H264Profile::Main), 31);
+        assert_eq!(min_level(1280, 720, 30.0, 1_000, H264Profile::High), 31);
+        assert_eq!(min_level(1280, 720, 30.1, 1_000, H264Profile::High), 32);
+        assert_eq!(min_level(1280, 720, 60.0, 1_000, H264Profile::High), 32);
+        assert_eq!(min_level(1920, 1080, 30.0, 1_000, H264Profile::High), 40);
+        assert_eq!(min_level(1920, 1080, 60.0, 1_000, H264Profile::High), 42);
+        assert_eq!(min_level(3840, 2160, 60.0, 1_000, H264Profile::High), 52);
+        // Unknown rates count as 30 fps; rates past every level get the top one
+        assert_eq!(min_level(1280, 720, 0.0, 1_000, H264Profile::High), 31);
+        assert_eq!(min_level(3840, 2160, 120.0, 1_000, H264Profile::High), MAX_LEVEL);
+    }
+
+    #[test]
+    fn bitrate_limits_scale_with_the_profile() {
+        assert_eq!(min_level(1920, 1080, 24.0, 20_000, H264Profile::Main), 40);
+        assert_eq!(min_level(1920, 1080, 24.0, 20_001, H264Profile::Main), 41);
+        assert_eq!(min_level(1920, 1080, 24.0, 25_000, H264Profile::High), 40);
+        assert_eq!(min_level(1920, 1080, 24.0, 25_001, H264Profile::High), 41);
+        assert_eq!(min_level(720, 576, 25.0, 12_500, H264Profile::High), 30);
+        assert_eq!(min_level(720, 576, 25.0, 12_501, H264Profile::High), 31);
+    }
+}
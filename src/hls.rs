@@ -0,0 +1,72 @@
+//! `--hls` packaging: a ladder of H.264/AAC renditions plus a master playlist.
+//!
+//! Each rendition is a normal [`TranscodePlan`] encoded with the same encoder
+//! selection as a single-file transcode, written as fMP4 segments into its own
+//! subdirectory of the package. Keyframes are forced every
+//! [`SEGMENT_SECS`] so segment boundaries line up across renditions.
+
+use crate::options::EncodeOptions;
+use crate::plan::{fit_within, TranscodePlan, VideoSettings};
+use crate::probe::VideoInfo;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Rendition heights, largest first. Sources are never upscaled.
+pub const LADDER: [u32; 3] = [1080, 720, 480];
+
+/// Target segment length in seconds.
+pub const SEGMENT_SECS: u32 = 6;
+
+/// Master playlist file name inside the package directory.
+pub const MASTER_PLAYLIST: &str = "master.m3u8";
+
+
+/// One encoded variant of an HLS package.
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    /// Subdirectory name, e.g. `720p`
+    pub name: String,
+    pub plan: TranscodePlan,
+}
+
+/// Plans one rendition per ladder step at or below the package's output
+/// size. Each rendition writes `<package>/<name>/index.m3u8`.
+pub fn renditions(info: &VideoInfo, package: &TranscodePlan, options: &EncodeOptions) -> Vec<Rendition> {
+    let top = package.output_size.1.min(LADDER[0]);
+    let heights = std::iter::once(top).chain(LADDER.into_iter().filter(|&h| h < top));
+
+    heights
+        .map(|height| {
+            let (width, source_height) = info.display_size();
+            let size = fit_within(width, source_height, u32::MAX, height);
+            let name = format!("{}p", size.1);
+            let mut plan = package.clone();
+            plan.output = package.output.join(&name).join("index.m3u8");
+            plan.output_size = size;
+            plan.settings = VideoSettings {
+                two_pass: package.settings.two_pass,
+                ..VideoSettings::for_output(size.0, size.1, package.output_frame_rate(info), options)
+            };
+            Rendition { name, plan }
+        })
+        .collect()
+}
+
+/// Writes the master playlist listing `renditions` into the package directory.
+pub fn write_master_playlist(package_dir: &Path, renditions: &[Rendition]) -> io::Result<()> {
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+    for rendition in renditions {
+        let (width, height) = rendition.plan.output_size;
+        let audio_kbps = rendition.plan.audio_bitrate_kbps;
+        let bandwidth = (rendition.plan.settings.max_bitrate_kbps + audio_kbps) * 1000;
+        let average = (rendition.plan.settings.bitrate_kbps + audio_kbps) * 1000;
+        // avc1.PPCCLL is profile_idc, constraint flags, level_idc; mp4a.40.2 is AAC-LC
+        let settings = &rendition.plan.settings;
+        playlist.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},AVERAGE-BANDWIDTH={},RESOLUTION={}x{},CODECS=\"avc1.{:02x}00{:02x},mp4a.40.2\"\n{}/index.m3u8\n",
+            bandwidth, average, width, height, settings.profile.idc(), settings.level, rendition.name
+        ));
+    }
+    fs::write(package_dir.join(MASTER_PLAYLIST), playlist)
+}
@@ -0,0 +1,442 @@
+//! Hardware encoder detection and per-encoder H.264 arguments.
+
+use crate::plan::VideoSettings;
+use crate::preset::QualityPreset;
+use crate::runner::{FfmpegRunner, Tool};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A hardware encoder family plus the device it runs on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HwAccel {
+    /// Family name from [`detect_hardware_acceleration`], e.g. `nvenc`
+    pub family: String,
+    /// Device for the family, e.g. a VAAPI render node
+    pub device: Option<String>,
+}
+
+impl HwAccel {
+    pub fn new(family: &str) -> HwAccel {
+        HwAccel {
+            family: family.to_string(),
+            device: None,
+        }
+    }
+}
+
+/// Picks the fastest H.264 encoder family for this platform: `videotoolbox`,
+/// `nvenc`, `qsv`, `vaapi` or `v4l2m2m`. `None` means software (libx264).
+/// On Linux, QSV is tried after VAAPI and only if a test encode succeeds.
+pub fn detect_hardware_acceleration(runner: &dyn FfmpegRunner) -> Option<String> {
+    #[cfg(target_os = "macos")]
+    {
+        return Some("videotoolbox".to_string());
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        if check_encoder_available(runner, "h264_nvenc") {
+            return Some("nvenc".to_string());
+        } else if check_encoder_available(runner, "h264_qsv") {
+            return Some("qsv".to_string());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        if check_encoder_available(runner, "h264_nvenc") {
+            return Some("nvenc".to_string());
+        } else if check_encoder_available(runner, "h264_vaapi") {
+            return Some("vaapi".to_string());
+        } else if check_encoder_available(runner, "h264_qsv") && qsv_can_encode(runner, None) {
+            // Most ffmpeg builds list h264_qsv, so only use it if an iGPU answers
+            return Some("qsv".to_string());
+        }
+
+        // Raspberry Pi and other ARM boards expose their encoder through V4L2
+        #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+        if check_encoder_available(runner, "h264_v4l2m2m") {
+            return Some("v4l2m2m".to_string());
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    {
+        None
+    }
+}
+
+/// Whether `ffmpeg -encoders` lists `encoder`.
+pub fn check_encoder_available(runner: &dyn FfmpegRunner, encoder: &str) -> bool {
+    let output = runner.capture(
+        Tool::Ffmpeg,
+        &["-hide_banner".to_string(), "-encoders".to_string()],
+    );
+
+    if let Ok(output) = output {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        stdout.contains(encoder)
+    } else {
+        false
+    }
+}
+
+/// Render node used when discovery has not picked one.
+pub const DEFAULT_VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+/// DRM render nodes (`/dev/dri/renderD*`) in index order.
+pub fn list_vaapi_devices() -> Vec<PathBuf> {
+    let mut devices: Vec<PathBuf> = fs::read_dir("/dev/dri")
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| {
+                    p.file_name()
+                        .and_then(|n| n.to_str())
+                        .is_some_and(|n| n.starts_with("renderD"))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    devices.sort();
+    devices
+}
+
+/// Whether `device` can actually encode H.264 through VAAPI, checked with a
+/// one-frame test encode (a render node may belong to a GPU without an encoder).
+pub fn vaapi_device_can_encode(runner: &dyn FfmpegRunner, device: &Path) -> bool {
+    let setup = ["-vaapi_device".to_string(), device.to_string_lossy().into_owned()];
+    test_encode(runner, &setup, Some("format=nv12,hwupload"), "h264_vaapi")
+}
+
+/// Encodes one frame of a generated test pattern with `encoder`, after the
+/// global `setup` options and through `filter`. True if ffmpeg succeeds.
+fn test_encode(runner: &dyn FfmpegRunner, setup: &[String], filter: Option<&str>, encoder: &str) -> bool {
+    let mut args: Vec<String> = ["-hide_banner", "-loglevel", "error"]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+    args.extend(setup.iter().cloned());
+    args.extend(["-f", "lavfi", "-i", "color=black:s=256x256:d=0.1"].iter().map(|a| a.to_string()));
+    if let Some(filter) = filter {
+        args.extend(["-vf".to_string(), filter.to_string()]);
+    }
+    args.extend(
+        ["-c:v", encoder, "-frames:v", "1", "-f", "null", "-"]
+            .iter()
+            .map(|a| a.to_string()),
+    );
+    runner
+        .capture(Tool::Ffmpeg, &args)
+        .is_ok_and(|output| output.success())
+}
+
+/// Picks the VAAPI render node to encode on: `preferred` if it works,
+/// otherwise the first discovered node that passes a test encode.
+pub fn select_vaapi_device(runner: &dyn FfmpegRunner, preferred: Option<&Path>) -> Option<PathBuf> {
+    if let Some(device) = preferred {
+        if vaapi_device_can_encode(runner, device) {
+            return Some(device.to_path_buf());
+        }
+        eprintln!(
+            "   ⚠️  VAAPI device {} cannot encode H.264, searching for another",
+            device.display()
+        );
+    }
+    list_vaapi_devices()
+        .into_iter()
+        .filter(|d| Some(d.as_path()) != preferred)
+        .find(|d| vaapi_device_can_encode(runner, d))
+}
+
+/// Name of the QSV device created by [`qsv_init_args`].
+pub const QSV_DEVICE_NAME: &str = "qs";
+
+/// Global options creating the QSV device [`QSV_DEVICE_NAME`] (on top of the
+/// `device` render node or adapter, if given) and making it the filter device
+/// so `hwupload` can hand frames to h264_qsv. Must precede the input.
+pub fn qsv_init_args(device: Option<&str>) -> Vec<String> {
+    let mut spec = format!("qsv={}:hw_any", QSV_DEVICE_NAME);
+    if let Some(device) = device {
+        spec.push_str(&format!(",child_device={}", device));
+    }
+    vec![
+        "-init_hw_device".to_string(),
+        spec,
+        "-filter_hw_device".to_string(),
+        QSV_DEVICE_NAME.to_string(),
+    ]
+}
+
+/// Whether h264_qsv can encode on `device` (or the default adapter), checked
+/// with a one-frame test encode.
+pub fn qsv_can_encode(runner: &dyn FfmpegRunner, device: Option<&str>) -> bool {
+    test_encode(
+        runner,
+        &qsv_init_args(device),
+        Some("format=nv12,hwupload=extra_hw_frames=64"),
+        "h264_qsv",
+    )
+}
+
+/// Encoder families accepted by `--encoder` and the `encoders` config key.
+pub const ENCODER_FAMILIES: [&str; 7] = ["videotoolbox", "nvenc", "qsv", "vaapi", "amf", "v4l2m2m", "software"];
+
+/// Whether encoder `family` produces a successful test encode on its
+/// default device.
+pub fn encoder_works(runner: &dyn FfmpegRunner, family: &str) -> bool {
+    match family {
+        "software" => true,
+        "vaapi" => select_vaapi_device(runner, None).is_some(),
+        "qsv" => qsv_can_encode(runner, None),
+        "v4l2m2m" => test_encode(runner, &[], Some("format=yuv420p"), "h264_v4l2m2m"),
+        _ => test_encode(runner, &[], None, &format!("h264_{}", family)),
+    }
+}
+
+/// Walks `preferences` in order and returns the first hardware family that
+/// passes a test encode. `None` means software, either because `software`
+/// came first or because nothing earlier worked.
+pub fn select_encoder(runner: &dyn FfmpegRunner, preferences: &[String]) -> Option<String> {
+    for family in preferences {
+        if family == "software" {
+            return None;
+        }
+        if encoder_works(runner, family) {
+            return Some(family.clone());
+        }
+        eprintln!("   ⚠️  {} failed a test encode, trying the next encoder", family);
+    }
+    eprintln!("   ⚠️  None of the preferred encoders work, using software encoding");
+    None
+}
+
+/// The device string selecting GPU `index` for an encoder family: the CUDA
+/// device index for NVENC, the matching render node for VAAPI and (on Linux)
+/// QSV, or the adapter index for QSV elsewhere.
+pub fn device_for_gpu(family: &str, index: u32) -> Option<String> {
+    match family {
+        "nvenc" => Some(index.to_string()),
+        "vaapi" => list_vaapi_devices()
+            .get(index as usize)
+            .map(|d| d.to_string_lossy().into_owned()),
+        "qsv" if cfg!(target_os = "linux") => list_vaapi_devices()
+            .get(index as usize)
+            .map(|d| d.to_string_lossy().into_owned()),
+        "qsv" => Some(index.to_string()),
+        _ => None,
+    }
+}
+
+/// Video encoder arguments for a hardware encoder family returned by
+/// [`detect_hardware_acceleration`], running on `device` where relevant.
+pub fn get_hw_encoding_args(hw_type: &str, device: Option<&str>, settings: &VideoSettings) -> Vec<String> {
+    let preset = settings.preset;
+    let bitrate = format!("{}k", settings.bitrate_kbps);
+    match hw_type {
+        "videotoolbox" => vec![
+            "-c:v".to_string(),
+            "h264_videotoolbox".to_string(),
+            "-b:v".to_string(),
+            bitrate.clone(),
+            "-profile:v".to_string(),
+            settings.profile.name().to_string(),
+            "-level".to_string(),
+            settings.level_arg(),
+            "-allow_sw".to_string(),
+            "1".to_string(),
+        ],
+        "nvenc" => get_nvenc_args(device, settings),
+        // The device is set up before the input by qsv_init_args
+        "qsv" => vec![
+            "-c:v".to_string(),
+            "h264_qsv".to_string(),
+            "-preset".to_string(),
+            preset.qsv_preset().to_string(),
+            "-b:v".to_string(),
+            bitrate.clone(),
+            "-profile:v".to_string(),
+            settings.profile.name().to_string(),
+            "-level".to_string(),
+            settings.level_arg(),
+        ],
+        "amf" => vec![
+            "-c:v".to_string(),
+            "h264_amf".to_string(),
+            "-quality".to_string(),
+            preset.amf_quality().to_string(),
+            "-rc".to_string(),
+            "vbr_peak".to_string(),
+            "-b:v".to_string(),
+            bitrate.clone(),
+            "-maxrate".to_string(),
+            format!("{}k", settings.max_bitrate_kbps),
+            "-profile:v".to_string(),
+            settings.profile.name().to_string(),
+            "-level".to_string(),
+            settings.level_arg(),
+        ],
+        "vaapi" => vec![
+            "-vaapi_device".to_string(),
+            device.unwrap_or(DEFAULT_VAAPI_DEVICE).to_string(),
+            "-c:v".to_string(),
+            "h264_vaapi".to_string(),
+            "-b:v".to_string(),
+            bitrate.clone(),
+            "-profile:v".to_string(),
+            settings.profile.name().to_string(),
+        ],
+        "v4l2m2m" => get_v4l2m2m_args(settings),
+        _ => get_sw_encoding_args(settings),
+    }
+}
+
+/// h264_v4l2m2m arguments (Raspberry Pi / ARM SBC encoders). These only do
+/// bitrate rate control and top out at 1080p; the planner scales larger
+/// sources down and the bitrate is capped to what the encoder handles.
+fn get_v4l2m2m_args(settings: &VideoSettings) -> Vec<String> {
+    // The Pi encoder is unreliable much above 25 Mbit/s
+    let bitrate = settings.bitrate_kbps.min(25_000);
+    vec![
+        "-pix_fmt".to_string(),
+        "yuv420p".to_string(),
+        "-c:v".to_string(),
+        "h264_v4l2m2m".to_string(),
+        "-b:v".to_string(),
+        format!("{}k", bitrate),
+        "-profile:v".to_string(),
+        settings.profile.name().to_string(),
+        "-level".to_string(),
+        settings.level_arg(),
+        // The default buffer counts drop frames on high-bitrate sources
+        "-num_output_buffers".to_string(),
+        "32".to_string(),
+        "-num_capture_buffers".to_string(),
+        "16".to_string(),
+    ]
+}
+
+/// h264_nvenc arguments. By default this is constant-quality VBR (`-cq` with
+/// the bitrate table only as a ceiling), so simple content doesn't waste space
+/// and grainy content isn't starved. Two-pass and `--nvenc-bitrate` use a
+/// fixed average bitrate instead.
+fn get_nvenc_args(device: Option<&str>, settings: &VideoSettings) -> Vec<String> {
+    let mut args = vec![
+        "-c:v".to_string(),
+        "h264_nvenc".to_string(),
+        "-preset".to_string(),
+        settings.preset.nvenc_preset().to_string(),
+        "-rc".to_string(),
+        "vbr".to_string(),
+    ];
+    if let Some(gpu) = device {
+        args.extend(["-gpu".to_string(), gpu.to_string()]);
+    }
+    if settings.two_pass || settings.nvenc_fixed_bitrate {
+        args.extend([
+            "-b:v".to_string(),
+            format!("{}k", settings.bitrate_kbps),
+        ]);
+        // NVENC runs both passes internally in a single invocation
+        if settings.two_pass {
+            args.extend(["-multipass".to_string(), "fullres".to_string()]);
+        }
+    } else {
+        args.extend([
+            "-cq".to_string(),
+            settings.nvenc_cq.to_string(),
+            "-b:v".to_string(),
+            "0".to_string(),
+        ]);
+    }
+    args.extend([
+        "-maxrate".to_string(),
+        format!("{}k", settings.max_bitrate_kbps),
+        "-bufsize".to_string(),
+        format!("{}k", settings.max_bitrate_kbps * 2),
+        "-spatial-aq".to_string(),
+        "1".to_string(),
+        "-temporal-aq".to_string(),
+        "1".to_string(),
+        "-profile:v".to_string(),
+        settings.profile.name().to_string(),
+        "-level".to_string(),
+        settings.level_arg(),
+    ]);
+    args
+}
+
+/// libx264 arguments used for software encodes and filter-based subtitle burns.
+/// Two-pass encodes target the bitrate instead of a CRF; the caller adds the
+/// `-pass`/`-passlogfile` arguments.
+pub fn get_sw_encoding_args(settings: &VideoSettings) -> Vec<String> {
+    let mut args = vec![
+        "-c:v".to_string(),
+        "libx264".to_string(),
+        "-preset".to_string(),
+        settings.preset.x264_preset().to_string(),
+    ];
+    if let Some(tune) = settings.tune {
+        args.extend(["-tune".to_string(), tune.x264_tune().to_string()]);
+    }
+    if settings.two_pass {
+        args.extend([
+            "-b:v".to_string(),
+            format!("{}k", settings.bitrate_kbps),
+            "-maxrate".to_string(),
+            format!("{}k", settings.max_bitrate_kbps),
+            "-bufsize".to_string(),
+            format!("{}k", settings.max_bitrate_kbps * 2),
+        ]);
+    } else {
+        args.extend(["-crf".to_string(), settings.crf.to_string()]);
+    }
+    args.extend([
+        "-profile:v".to_string(),
+        settings.profile.name().to_string(),
+        "-level".to_string(),
+        settings.level_arg(),
+    ]);
+    args
+}
+
+/// Target video bitrate in kbit/s for the resolution tier, scaled by the preset.
+pub fn calculate_bitrate_kbps(width: u32, height: u32, preset: QualityPreset) -> u32 {
+    scale_kbps(base_bitrate_kbps(width, height), preset)
+}
+
+/// Peak video bitrate in kbit/s for the resolution tier, scaled by the preset.
+pub fn calculate_max_bitrate_kbps(width: u32, height: u32, preset: QualityPreset) -> u32 {
+    scale_kbps(base_max_bitrate_kbps(width, height), preset)
+}
+
+fn scale_kbps(kbps: u32, preset: QualityPreset) -> u32 {
+    (kbps as f64 * preset.bitrate_multiplier()).round() as u32
+}
+
+fn base_bitrate_kbps(width: u32, height: u32) -> u32 {
+    let pixels = width * height;
+    if pixels >= 3840 * 2160 {
+        20_000
+    } else if pixels >= 1920 * 1080 {
+        8_000
+    } else if pixels >= 1280 * 720 {
+        5_000
+    } else {
+        3_000
+    }
+}
+
+fn base_max_bitrate_kbps(width: u32, height: u32) -> u32 {
+    let pixels = width * height;
+    if pixels >= 3840 * 2160 {
+        30_000
+    } else if pixels >= 1920 * 1080 {
+        12_000
+    } else if pixels >= 1280 * 720 {
+        7_000
+    } else {
+        4_000
+    }
+}
@@ -0,0 +1,208 @@
+//! `.tvcodeignore`: gitignore-style patterns for files discovery leaves alone.
+//!
+//! Each line of the file is a pattern matched against paths relative to its
+//! directory. Blank lines and `#` comments are skipped, `!` re-includes what
+//! an earlier pattern excluded, a trailing `/` matches only directories and
+//! a `/` anywhere else anchors the pattern to the directory; patterns
+//! without one match a name at any depth. `*` and `?` stay within one path
+//! component, `**` spans any number of them and `[...]` matches a set.
+//!
+//! Paths below the directory only come up with `--recursive`, where each
+//! subfolder's own `.tvcodeignore` applies on top of those above it.
+
+use std::fs;
+use std::path::Path;
+
+/// Name of the ignore file read from each scanned directory.
+pub const IGNORE_FILE: &str = ".tvcodeignore";
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+/// The patterns of one `.tvcodeignore`.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreFile {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    /// Reads `dir/.tvcodeignore`; a missing or unreadable file ignores nothing.
+    pub fn load(dir: &Path) -> IgnoreFile {
+        fs::read_to_string(dir.join(IGNORE_FILE))
+            .map(|text| IgnoreFile::parse(&text))
+            .unwrap_or_default()
+    }
+
+    pub fn parse(text: &str) -> IgnoreFile {
+        let rules = text
+            .lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let (dir_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                Rule {
+                    pattern: line.trim_start_matches('/').to_string(),
+                    negated,
+                    dir_only,
+                    anchored,
+                }
+            })
+            .collect();
+        IgnoreFile { rules }
+    }
+
+    /// Whether `path` (relative to the ignore file's directory, `/`-separated)
+    /// is excluded. The last matching pattern decides.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.verdict(path, is_dir).unwrap_or(false)
+    }
+
+    /// Like [`is_ignored`](Self::is_ignored), but `None` when no pattern
+    /// matches, so the `.tvcodeignore` of a subfolder can override its
+    /// parent's only where it says something.
+    pub fn verdict(&self, path: &str, is_dir: bool) -> Option<bool> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let mut verdict = None;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let target = if rule.anchored { path } else { name };
+            if glob_match(&rule.pattern, target) {
+                verdict = Some(!rule.negated);
+            }
+        }
+        verdict
+    }
+}
+
+/// Whether `text` matches the glob `pattern`: `*` and `?` do not cross `/`,
+/// `**` does, and `[a-z]`/`[!abc]` match one character from a set.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_from(&pattern, &text)
+}
+
+fn matches_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            // `**/` also matches no directories at all
+            let rest = &pattern[2..];
+            let rest_after_slash = rest.strip_prefix(&['/']).unwrap_or(rest);
+            (0..=text.len()).any(|i| matches_from(rest, &text[i..]) || matches_from(rest_after_slash, &text[i..]))
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if matches_from(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => matches!(text.first(), Some(c) if *c != '/') && matches_from(&pattern[1..], &text[1..]),
+        Some('[') => match (text.first(), class_end(pattern)) {
+            (Some(&c), Some(end)) => {
+                c != '/' && class_contains(&pattern[1..end], c) && matches_from(&pattern[end + 1..], &text[1..])
+            }
+            (Some(&c), None) => c == '[' && matches_from(&pattern[1..], &text[1..]),
+            (None, _) => false,
+        },
+        Some('\\') if pattern.len() > 1 => text.first() == Some(&pattern[1]) && matches_from(&pattern[2..], &text[1..]),
+        Some(&p) => text.first() == Some(&p) && matches_from(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Index of the `]` closing the set that starts `pattern`.
+fn class_end(pattern: &[char]) -> Option<usize> {
+    let start = if matches!(pattern.get(1), Some('!' | '^')) { 2 } else { 1 };
+    // A `]` right after the opening is a member, not the end
+    pattern.iter().skip(start + 1).position(|&c| c == ']').map(|i| i + start + 1)
+}
+
+fn class_contains(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!' | '^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stars_stay_within_a_component() {
+        assert!(glob_match("*.mkv", "movie.mkv"));
+        assert!(!glob_match("*.mkv", "extras/movie.mkv"));
+        assert!(glob_match("movie.???", "movie.mkv"));
+        assert!(!glob_match("a?b", "a/b"));
+    }
+
+    #[test]
+    fn double_stars_span_folders() {
+        assert!(glob_match("**/*.mkv", "movie.mkv"));
+        assert!(glob_match("**/*.mkv", "a/b/movie.mkv"));
+        assert!(glob_match("extras/**", "extras/a/movie.mkv"));
+        assert!(glob_match("a/**/b", "a/b"));
+        assert!(glob_match("a/**/b", "a/x/y/b"));
+    }
+
+    #[test]
+    fn sets_and_escapes() {
+        assert!(glob_match("s0[1-3]e01.mkv", "s02e01.mkv"));
+        assert!(!glob_match("s0[1-3]e01.mkv", "s04e01.mkv"));
+        assert!(glob_match("[!a]*", "movie"));
+        assert!(!glob_match("[!m]*", "movie"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("\\*", "*"));
+        assert!(!glob_match("\\*", "movie"));
+        assert!(glob_match("[", "["));
+    }
+
+    #[test]
+    fn rules() {
+        let ignore = IgnoreFile::parse("# comment\n\n*.avi\n!keep.avi\nsamples/\n/top.mkv\ndeep/*.mkv\n");
+        assert!(ignore.is_ignored("old.avi", false));
+        assert!(ignore.is_ignored("season 1/old.avi", false));
+        assert!(!ignore.is_ignored("keep.avi", false));
+        assert!(ignore.is_ignored("samples", true));
+        assert!(!ignore.is_ignored("samples", false));
+        assert!(ignore.is_ignored("top.mkv", false));
+        assert!(!ignore.is_ignored("season 1/top.mkv", false));
+        assert!(ignore.is_ignored("deep/movie.mkv", false));
+        assert!(!ignore.is_ignored("other/deep/movie.mkv", false));
+        assert_eq!(ignore.verdict("keep.avi", false), Some(false));
+        assert_eq!(ignore.verdict("movie.mkv", false), None);
+    }
+}
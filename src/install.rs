@@ -0,0 +1,372 @@
+//! `setup-ffmpeg`: a tvcode-managed static ffmpeg build.
+//!
+//! The build is downloaded with `curl`, checked against a SHA-256 pinned
+//! here or given with `--sha256`, unpacked with `tar` (which also reads zip
+//! archives on macOS and Windows) and its `ffmpeg` and `ffprobe` are copied
+//! into `$XDG_DATA_HOME/tvcode/ffmpeg` (falling back to
+//! `~/.local/share/tvcode/ffmpeg`, `~/Library/Application Support/tvcode/ffmpeg`
+//! on macOS or `%LOCALAPPDATA%\tvcode\ffmpeg` on Windows). When both are
+//! there, [`use_managed_ffmpeg`] puts that directory first on `PATH`, so it
+//! wins over any system ffmpeg.
+
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A static build to download: the archives to fetch, in order.
+#[derive(Debug, Clone, Copy)]
+pub struct StaticBuild {
+    /// Who publishes it, for messages
+    pub source: &'static str,
+    pub archives: &'static [Archive],
+}
+
+/// One file of a [`StaticBuild`].
+#[derive(Debug, Clone, Copy)]
+pub struct Archive {
+    pub url: &'static str,
+    /// Lowercase hex SHA-256 the download must have. The URLs point at each
+    /// publisher's latest release, which changes under them, so none is
+    /// pinned until they point at fixed versions; until then the user
+    /// passes the published checksum with `--sha256`.
+    pub sha256: Option<&'static str>,
+}
+
+/// The known-good build for this platform, if there is one.
+pub fn static_build() -> Option<StaticBuild> {
+    if cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        Some(StaticBuild {
+            source: "johnvansickle.com",
+            archives: &[Archive {
+                url: "https://johnvansickle.com/ffmpeg/releases/ffmpeg-release-amd64-static.tar.xz",
+                sha256: None,
+            }],
+        })
+    } else if cfg!(all(target_os = "linux", target_arch = "aarch64")) {
+        Some(StaticBuild {
+            source: "johnvansickle.com",
+            archives: &[Archive {
+                url: "https://johnvansickle.com/ffmpeg/releases/ffmpeg-release-arm64-static.tar.xz",
+                sha256: None,
+            }],
+        })
+    } else if cfg!(target_os = "macos") {
+        // Intel builds; Apple silicon runs them through Rosetta
+        Some(StaticBuild {
+            source: "evermeet.cx",
+            archives: &[
+                Archive { url: "https://evermeet.cx/ffmpeg/getrelease/ffmpeg/zip", sha256: None },
+                Archive { url: "https://evermeet.cx/ffmpeg/getrelease/ffprobe/zip", sha256: None },
+            ],
+        })
+    } else if cfg!(all(windows, target_arch = "x86_64")) {
+        Some(StaticBuild {
+            source: "gyan.dev",
+            archives: &[Archive {
+                url: "https://www.gyan.dev/ffmpeg/builds/ffmpeg-release-essentials.zip",
+                sha256: None,
+            }],
+        })
+    } else {
+        None
+    }
+}
+
+/// Directory the managed build lives in, whether or not it is installed.
+pub fn managed_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if let Some(dir) = env::var_os("XDG_DATA_HOME") {
+        Some(PathBuf::from(dir))
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support"))
+    } else {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+    };
+    base.map(|dir| dir.join("tvcode").join("ffmpeg"))
+}
+
+/// `ffmpeg` or `ffprobe` with the platform's executable suffix.
+fn executable(name: &str) -> String {
+    format!("{}{}", name, env::consts::EXE_SUFFIX)
+}
+
+/// The managed directory, if both tools are installed in it.
+pub fn installed_dir() -> Option<PathBuf> {
+    managed_dir().filter(|dir| ["ffmpeg", "ffprobe"].iter().all(|tool| dir.join(executable(tool)).is_file()))
+}
+
+/// Puts the managed build first on `PATH` for this process and everything
+/// it runs. Call before any threads start. Returns the directory, if any.
+pub fn use_managed_ffmpeg() -> Option<PathBuf> {
+    let dir = installed_dir()?;
+    let mut paths = vec![dir.clone()];
+    paths.extend(env::var_os("PATH").map(|path| env::split_paths(&path).collect::<Vec<_>>()).unwrap_or_default());
+    env::set_var("PATH", env::join_paths(paths).ok()?);
+    Some(dir)
+}
+
+/// Downloads `build` and installs its ffmpeg and ffprobe into the managed
+/// directory, copying them over those of any earlier install. `sha256`
+/// holds the checksums given on the command line, one per archive in
+/// order, and takes precedence over the pinned ones; an archive with
+/// neither, or whose checksum differs, is not installed. Returns
+/// `ffmpeg -version`'s first line from the new build.
+pub fn install(build: &StaticBuild, sha256: &[String]) -> Result<String, String> {
+    if sha256.len() > build.archives.len() {
+        return Err(format!(
+            "{} --sha256 value(s) given, but the build from {} has {} archive(s)",
+            sha256.len(),
+            build.source,
+            build.archives.len()
+        ));
+    }
+    let dir = managed_dir().ok_or("cannot find a per-user data directory (is HOME set?)")?;
+    let work = dir.with_file_name("ffmpeg-download");
+    let _ = fs::remove_dir_all(&work);
+    fs::create_dir_all(&work).map_err(|e| format!("cannot create {}: {}", work.display(), e))?;
+    let result = download_and_unpack(build, sha256, &work).and_then(|unpacked| {
+        fs::create_dir_all(&dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+        for tool in ["ffmpeg", "ffprobe"] {
+            let name = executable(tool);
+            let found = find_file(&unpacked, &name).ok_or_else(|| format!("the download has no {}", name))?;
+            copy_executable(&found, &dir.join(&name))?;
+        }
+        version_line(&dir.join(executable("ffmpeg")))
+    });
+    let _ = fs::remove_dir_all(&work);
+    result
+}
+
+/// Deletes the managed build. Returns whether there was one.
+pub fn uninstall() -> Result<bool, String> {
+    match managed_dir() {
+        Some(dir) if dir.exists() => fs::remove_dir_all(&dir)
+            .map(|()| true)
+            .map_err(|e| format!("cannot remove {}: {}", dir.display(), e)),
+        _ => Ok(false),
+    }
+}
+
+/// Fetches every archive of `build` into `work`, checks it against its
+/// checksum and unpacks it into `work/unpacked`.
+fn download_and_unpack(build: &StaticBuild, sha256: &[String], work: &Path) -> Result<PathBuf, String> {
+    let unpacked = work.join("unpacked");
+    fs::create_dir_all(&unpacked).map_err(|e| e.to_string())?;
+    for (i, Archive { url, sha256: pinned }) in build.archives.iter().enumerate() {
+        let extension = if url.ends_with(".tar.xz") { "tar.xz" } else { "zip" };
+        let archive = work.join(format!("archive-{}.{}", i, extension));
+        let status = Command::new("curl")
+            .args(["-fL", "--progress-bar", "-o"])
+            .arg(&archive)
+            .arg(url)
+            .status()
+            .map_err(|e| format!("cannot run curl: {}", e))?;
+        if !status.success() {
+            return Err(format!("downloading {} failed", url));
+        }
+        let actual = sha256_file(&archive)?;
+        match sha256.get(i).map(String::as_str).or(*pinned) {
+            Some(expected) if expected.eq_ignore_ascii_case(&actual) => {}
+            Some(expected) => {
+                return Err(format!("{} has SHA-256 {}, not the expected {}; not installing it", url, actual, expected))
+            }
+            None => {
+                return Err(format!(
+                    "no SHA-256 is pinned for {}; if {} matches the checksum {} publishes, pass it with --sha256",
+                    url, actual, build.source
+                ))
+            }
+        }
+        let status = Command::new("tar")
+            .arg("-xf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&unpacked)
+            .status()
+            .map_err(|e| format!("cannot run tar: {}", e))?;
+        if !status.success() {
+            return Err(format!("unpacking {} failed", url));
+        }
+    }
+    Ok(unpacked)
+}
+
+/// The first file called `name` anywhere under `dir`.
+fn find_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir).ok()?.flatten().map(|entry| entry.path()).collect();
+    entries.sort();
+    for path in &entries {
+        if path.is_file() && path.file_name().is_some_and(|n| n == name) {
+            return Some(path.clone());
+        }
+    }
+    entries.iter().filter(|path| path.is_dir()).find_map(|path| find_file(path, name))
+}
+
+fn copy_executable(from: &Path, to: &Path) -> Result<(), String> {
+    fs::copy(from, to).map_err(|e| format!("cannot copy {} to {}: {}", from.display(), to.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(to, fs::Permissions::from_mode(0o755)).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// First line of `ffmpeg -version`, proving the binary runs here.
+fn version_line(ffmpeg: &Path) -> Result<String, String> {
+    let output = Command::new(ffmpeg)
+        .arg("-version")
+        .output()
+        .map_err(|e| format!("the downloaded ffmpeg does not run here: {}", e))?;
+    if !output.status.success() {
+        return Err("the downloaded ffmpeg does not run here".to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or_default().to_string())
+}
+
+/// Lowercase hex SHA-256 of the file at `path`.
+fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = fs::File::open(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+        }
+    }
+    Ok(hasher.finish().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// SHA-256 (FIPS 180-4), fed in pieces.
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    filled: usize,
+    length: u64,
+}
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98,
+    0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8,
+    0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819,
+    0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+    0xc67178f2,
+];
+
+impl Sha256 {
+    fn new() -> Sha256 {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+            ],
+            block: [0; 64],
+            filled: 0,
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let n = data.len().min(64 - self.filled);
+            self.block[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == 64 {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bits = self.length * 8;
+        self.update(&[0x80]);
+        while self.filled != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(ROUND_CONSTANTS[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(data: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.finish().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn sha256_test_vectors() {
+        assert_eq!(hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(hex(&[b'a'; 1_000_000]), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn sha256_of_a_file_read_in_pieces() {
+        let dir = crate::scratch_dir("install-sha256");
+        let path = dir.join("archive");
+        fs::write(&path, vec![b'a'; 200_000]).unwrap();
+        let mut hasher = Sha256::new();
+        for _ in 0..2_000 {
+            hasher.update(&[b'a'; 100]);
+        }
+        let expected: String = hasher.finish().iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(sha256_file(&path).unwrap(), expected);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,87 @@
+//! Checks on sources and outputs beyond the encode itself.
+//!
+//! `--verify-input` decodes a source end to end before encoding it, so a
+//! damaged download is reported in minutes instead of after a long encode
+//! that produces a broken output. After every encode the audio and video
+//! timing of the output is compared with the source's, to catch the
+//! desync variable frame rate sources are prone to.
+
+use crate::probe::{StreamTiming, VideoInfo};
+use crate::runner::{FfmpegRunner, Tool};
+use std::path::Path;
+
+/// Drift between audio and video, in seconds, past which an output is
+/// reported as out of sync. Lip sync errors start to show at about this.
+pub const MAX_SYNC_DRIFT_SECS: f64 = 0.1;
+
+/// Decodes every video and audio stream of `path` without writing anything
+/// and returns the errors ffmpeg reported, none for a clean file.
+pub fn decode_errors(runner: &dyn FfmpegRunner, path: &Path) -> Result<Vec<String>, String> {
+    let args: Vec<String> = [
+        "-hide_banner",
+        "-nostats",
+        "-v",
+        "error",
+        "-i",
+        path.to_str().unwrap(),
+        "-map",
+        "0:v?",
+        "-map",
+        "0:a?",
+        "-f",
+        "null",
+        "-",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    let output = runner
+        .capture(Tool::Ffmpeg, &args)
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+    let errors: Vec<String> = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if !output.success() && errors.is_empty() {
+        return Err("ffmpeg could not decode the file".to_string());
+    }
+    Ok(errors)
+}
+
+/// How far the audio of `output` has drifted from its video compared with
+/// `source`, described, when it is more than [`MAX_SYNC_DRIFT_SECS`] at the
+/// start or over the length. `audio_index` is the source audio stream that
+/// became the output's first. Streams whose timing is not reported are not
+/// compared.
+pub fn sync_drift(source: &VideoInfo, audio_index: usize, output: &VideoInfo) -> Option<String> {
+    let source_audio = source.audio_tracks.get(audio_index)?.timing;
+    let output_audio = output.audio_tracks.first()?.timing;
+    let offset = |video: StreamTiming, audio: StreamTiming| Some(audio.start_secs? - video.start_secs?);
+    let gap = |video: StreamTiming, audio: StreamTiming| Some(audio.duration_secs? - video.duration_secs?);
+
+    let mut drifts = Vec::new();
+    if let (Some(before), Some(after)) =
+        (offset(source.video_timing, source_audio), offset(output.video_timing, output_audio))
+    {
+        let drift = after - before;
+        if drift.abs() > MAX_SYNC_DRIFT_SECS {
+            let direction = if drift > 0.0 { "later" } else { "earlier" };
+            drifts.push(format!("the audio starts {:.0} ms {} than in the source", drift.abs() * 1000.0, direction));
+        }
+    }
+    if let (Some(before), Some(after)) = (gap(source.video_timing, source_audio), gap(output.video_timing, output_audio))
+    {
+        let drift = after - before;
+        if drift.abs() > MAX_SYNC_DRIFT_SECS {
+            let direction = if drift > 0.0 { "longer" } else { "shorter" };
+            drifts.push(format!(
+                "the audio runs {:.0} ms {} against the video than in the source",
+                drift.abs() * 1000.0,
+                direction
+            ));
+        }
+    }
+    (!drifts.is_empty()).then(|| drifts.join(", and "))
+}
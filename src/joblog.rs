@@ -0,0 +1,206 @@
+//! Structured JSON Lines log written by `--log-file`.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One line of the `--log-file` JSON Lines output.
+#[derive(Debug, Serialize)]
+struct LogRecord<'a> {
+    timestamp: String,
+    file: &'a str,
+    action: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ffmpeg_args: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// A record read back from a log file by [`read_history`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: String,
+    pub file: String,
+    pub action: String,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub duration_secs: Option<f64>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Reads every record from a log file written by [`JobLog`], oldest first.
+/// Lines that fail to parse (e.g. a torn final write) are skipped.
+pub fn read_history(path: &Path) -> Result<Vec<HistoryEntry>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read log file {}: {}", path.display(), e))?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Appends structured records to the log file, if one was requested.
+/// Logging failures are reported once and never abort a transcode. Safe to
+/// share between parallel jobs; each record is written as a single line.
+#[derive(Debug, Default)]
+pub struct JobLog {
+    file: Mutex<Option<File>>,
+}
+
+impl JobLog {
+    /// Opens `path` for appending, or returns a no-op log when `path` is `None`.
+    pub fn open(path: Option<&Path>) -> Result<JobLog, String> {
+        let file = match path {
+            Some(path) => Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("Failed to open log file {}: {}", path.display(), e))?,
+            ),
+            None => None,
+        };
+        Ok(JobLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write(&self, record: &LogRecord) {
+        let mut guard = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(file) = guard.as_mut() else {
+            return;
+        };
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if let Err(e) = writeln!(file, "{}", line) {
+            eprintln!("   ⚠️  Failed to write log file: {}", e);
+            *guard = None;
+        }
+    }
+
+    /// Records a non-ffmpeg action such as `skip`.
+    pub fn record(&self, file: &Path, action: &str) {
+        self.write(&LogRecord {
+            timestamp: rfc3339_now(),
+            file: &file.to_string_lossy(),
+            action,
+            ffmpeg_args: None,
+            exit_code: None,
+            duration_secs: None,
+            error: None,
+        });
+    }
+
+    pub fn record_error(&self, file: &Path, error: &str) {
+        self.write(&LogRecord {
+            timestamp: rfc3339_now(),
+            file: &file.to_string_lossy(),
+            action: "error",
+            ffmpeg_args: None,
+            exit_code: None,
+            duration_secs: None,
+            error: Some(error),
+        });
+    }
+
+    pub fn record_ffmpeg(
+        &self,
+        file: &Path,
+        args: &[String],
+        exit_code: Option<i32>,
+        duration_secs: f64,
+        error: Option<&str>,
+    ) {
+        self.write(&LogRecord {
+            timestamp: rfc3339_now(),
+            file: &file.to_string_lossy(),
+            action: "transcode",
+            ffmpeg_args: Some(args),
+            exit_code,
+            duration_secs: Some(duration_secs),
+            error,
+        });
+    }
+}
+
+/// Current UTC time as an RFC 3339 timestamp, e.g. `2024-05-01T21:14:03Z`.
+pub fn rfc3339_now() -> String {
+    let secs = unix_now();
+    let (year, month, day) = civil_date(secs / 86_400);
+    let rem = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        (rem % 3_600) / 60,
+        rem % 60
+    )
+}
+
+/// `secs` after the Unix epoch as an RFC 5322 `Date:` header value in UTC,
+/// e.g. `Wed, 01 May 2024 21:14:03 +0000`.
+pub fn rfc5322_date(secs: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let days = secs / 86_400;
+    let (year, month, day) = civil_date(days);
+    let rem = secs % 86_400;
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rem / 3_600,
+        (rem % 3_600) / 60,
+        rem % 60
+    )
+}
+
+/// Seconds since the Unix epoch, zero if the clock is before it.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Year, month and day of the `days`-th day after 1970-01-01.
+fn civil_date(days: u64) -> (i64, i64, i64) {
+    // Civil-from-days (Howard Hinnant)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mail_dates() {
+        assert_eq!(rfc5322_date(0), "Thu, 01 Jan 1970 00:00:00 +0000");
+        assert_eq!(rfc5322_date(1_714_598_043), "Wed, 01 May 2024 21:14:03 +0000");
+        assert_eq!(rfc5322_date(951_782_400), "Tue, 29 Feb 2000 00:00:00 +0000");
+    }
+}
@@ -0,0 +1,173 @@
+//! Guessing the language of untagged subtitle tracks from their text.
+//!
+//! Scripts of their own (Cyrillic, Greek, CJK...) give a language away by
+//! themselves; Latin-script languages are told apart by counting their most
+//! common short words. Languages are ISO 639-2 codes, as ffprobe reports
+//! them.
+//!
+//! Tags and `--keep-audio-langs` may name a language by its two-letter ISO
+//! 639-1 code or by either of the bibliographic (B) and terminology (T)
+//! ISO 639-2 codes some languages have, so codes are compared with
+//! [`same_language`].
+
+/// ISO 639-1, 639-2/B and 639-2/T codes of common languages; the 639-2
+/// codes differ only for a few.
+const CODES: &[(&str, &str, &str)] = &[
+    ("ar", "ara", "ara"), ("bo", "tib", "bod"), ("cs", "cze", "ces"), ("cy", "wel", "cym"),
+    ("da", "dan", "dan"), ("de", "ger", "deu"), ("el", "gre", "ell"), ("en", "eng", "eng"),
+    ("es", "spa", "spa"), ("eu", "baq", "eus"), ("fa", "per", "fas"), ("fi", "fin", "fin"),
+    ("fr", "fre", "fra"), ("he", "heb", "heb"), ("hi", "hin", "hin"), ("hu", "hun", "hun"),
+    ("hy", "arm", "hye"), ("is", "ice", "isl"), ("it", "ita", "ita"), ("ja", "jpn", "jpn"),
+    ("ka", "geo", "kat"), ("ko", "kor", "kor"), ("mi", "mao", "mri"), ("mk", "mac", "mkd"),
+    ("ms", "may", "msa"), ("my", "bur", "mya"), ("nl", "dut", "nld"), ("no", "nor", "nor"),
+    ("pl", "pol", "pol"), ("pt", "por", "por"), ("ro", "rum", "ron"), ("ru", "rus", "rus"),
+    ("sk", "slo", "slk"), ("sq", "alb", "sqi"), ("sv", "swe", "swe"), ("th", "tha", "tha"),
+    ("tr", "tur", "tur"), ("uk", "ukr", "ukr"), ("zh", "chi", "zho"),
+];
+
+/// `code` as a lowercase ISO 639-2/B code when it is a known 639-1 or
+/// 639-2 code, otherwise just lowercased.
+pub fn normalize_code(code: &str) -> String {
+    let code = code.trim().to_ascii_lowercase();
+    CODES
+        .iter()
+        .find(|(one, bibliographic, terminology)| [*one, *bibliographic, *terminology].contains(&code.as_str()))
+        .map_or(code, |(_, bibliographic, _)| bibliographic.to_string())
+}
+
+/// Whether the language codes `a` and `b` name the same language.
+pub fn same_language(a: &str, b: &str) -> bool {
+    normalize_code(a) == normalize_code(b)
+}
+
+/// Fewest common words a Latin-script guess needs.
+const MIN_HITS: usize = 12;
+
+/// The most frequent words of Latin-script languages, chosen to overlap as
+/// little as possible.
+const COMMON_WORDS: &[(&str, &[&str])] = &[
+    ("eng", &["the", "you", "and", "is", "it", "what", "that", "this", "are", "have", "was", "with", "don't", "i'm", "it's", "your", "know", "not", "be", "we"]),
+    ("fre", &["le", "les", "est", "vous", "je", "pas", "une", "et", "ce", "qui", "c'est", "tu", "dans", "pour", "mais", "avec", "nous", "il", "j'ai", "ça"]),
+    ("ger", &["ich", "nicht", "und", "sie", "das", "ist", "du", "der", "die", "es", "ein", "zu", "mit", "wir", "was", "auf", "den", "mir", "dich", "haben"]),
+    ("spa", &["el", "que", "los", "es", "no", "por", "una", "para", "qué", "está", "se", "con", "las", "pero", "muy", "yo", "del", "eso", "aquí", "sí"]),
+    ("ita", &["il", "che", "non", "di", "è", "un", "sono", "per", "della", "mi", "gli", "questo", "cosa", "hai", "ho", "ti", "sei", "anche", "perché", "bene"]),
+    ("por", &["vai", "que", "não", "é", "um", "uma", "você", "com", "para", "os", "eu", "isso", "está", "do", "da", "mas", "ele", "tem", "aqui", "então"]),
+    ("dut", &["het", "een", "niet", "ik", "je", "is", "dat", "de", "van", "wat", "en", "zijn", "maar", "hij", "we", "ben", "heb", "jij", "naar", "hier"]),
+    ("swe", &["och", "det", "är", "jag", "inte", "att", "en", "som", "på", "du", "med", "har", "vi", "för", "vad", "den", "han", "kan", "så", "här"]),
+    ("dan", &["og", "det", "er", "jeg", "ikke", "at", "en", "du", "har", "til", "på", "med", "hvad", "de", "vi", "kan", "mig", "så", "hvor", "nu"]),
+    ("nor", &["og", "det", "er", "jeg", "ikke", "at", "en", "du", "har", "til", "på", "med", "hva", "vi", "kan", "meg", "så", "hvor", "nå", "deg"]),
+    ("fin", &["on", "ja", "ei", "se", "että", "minä", "sinä", "mitä", "en", "hän", "me", "tämä", "olen", "mutta", "kun", "nyt", "vain", "niin", "oli", "mikä"]),
+    ("pol", &["nie", "się", "to", "jest", "że", "co", "na", "jak", "ale", "tak", "mnie", "czy", "już", "mi", "tylko", "jestem", "ty", "ten", "dla", "wiem"]),
+    ("cze", &["je", "to", "se", "na", "že", "ne", "jsem", "co", "jak", "tak", "ale", "mě", "by", "jsi", "už", "tady", "jsme", "když", "proč", "ano"]),
+    ("hun", &["hát", "az", "hogy", "nem", "és", "is", "egy", "ez", "van", "meg", "de", "csak", "már", "mi", "mit", "itt", "igen", "ezt", "nekem", "vagy"]),
+    ("tur", &["bir", "ve", "bu", "ne", "için", "ben", "sen", "mi", "çok", "var", "değil", "da", "de", "bunu", "ama", "neden", "evet", "şey", "beni", "seni"]),
+    ("rum", &["și", "nu", "să", "în", "este", "ce", "e", "la", "un", "pe", "că", "cu", "mai", "din", "sunt", "ai", "asta", "am", "dar", "ești"]),
+];
+
+/// The language `text` is most likely written in, or `None` when there is
+/// too little text or no clear winner.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    if let Some(language) = script_language(text) {
+        return Some(language);
+    }
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic() && c != '\'')
+        .map(|word| word.trim_matches('\'').to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let mut scores: Vec<(usize, &'static str)> = COMMON_WORDS
+        .iter()
+        .map(|(language, common)| (words.iter().filter(|word| common.contains(&word.as_str())).count(), *language))
+        .collect();
+    scores.sort_by_key(|&(hits, _)| std::cmp::Reverse(hits));
+    let (best, language) = scores[0];
+    // A clear lead over the runner-up, by a quarter
+    (best >= MIN_HITS && best * 4 > scores[1].0 * 5).then_some(language)
+}
+
+/// The language written in a script only it (of those recognized) uses,
+/// when at least a third of the letters of `text` are in one.
+fn script_language(text: &str) -> Option<&'static str> {
+    let mut letters = 0;
+    let mut counts = [0usize; 9];
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        letters += 1;
+        let script = match c as u32 {
+            0x0400..=0x04FF => 0,
+            0x0370..=0x03FF => 1,
+            0x0590..=0x05FF => 2,
+            0x0600..=0x06FF => 3,
+            0x0E00..=0x0E7F => 4,
+            0x3040..=0x30FF => 5,
+            0xAC00..=0xD7AF | 0x1100..=0x11FF => 6,
+            0x4E00..=0x9FFF => 7,
+            _ => 8,
+        };
+        counts[script] += 1;
+    }
+    if letters == 0 {
+        return None;
+    }
+    // Japanese mixes kana with Chinese characters, so any real share of kana decides it
+    if counts[5] * 10 >= letters {
+        return Some("jpn");
+    }
+    let languages = ["rus", "gre", "heb", "ara", "tha", "jpn", "kor", "chi"];
+    let (script, &count) = counts[..8].iter().enumerate().max_by_key(|(_, count)| **count)?;
+    (count * 3 >= letters).then_some(languages[script])
+}
+
+/// Whether `language` says nothing about the track: missing, or `und`.
+pub fn is_undetermined(language: Option<&str>) -> bool {
+    language.is_none_or(|language| language.is_empty() || language.eq_ignore_ascii_case("und"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_normalize_to_bibliographic() {
+        let cases = [
+            ("fr", "fre"),
+            ("fra", "fre"),
+            ("fre", "fre"),
+            ("DEU", "ger"),
+            ("de", "ger"),
+            ("nld", "dut"),
+            ("zho", "chi"),
+            ("ces", "cze"),
+            ("ron", "rum"),
+            ("en", "eng"),
+            ("eng", "eng"),
+            (" sv ", "swe"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(normalize_code(code), expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn unknown_codes_are_only_lowercased() {
+        for (code, expected) in [("und", "und"), ("XYZ", "xyz"), ("qaa", "qaa"), ("", "")] {
+            assert_eq!(normalize_code(code), expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn pairs_match_across_code_kinds() {
+        let cases = [
+            ("fr", "fra", true),
+            ("fre", "FRA", true),
+            ("de", "ger", true),
+            ("el", "gre", true),
+            ("eng", "en", true),
+            ("fra", "spa", false),
+            ("und", "eng", false),
+            ("xyz", "XYZ", true),
+        ];
+        for (a, b, same) in cases {
+            assert_eq!(same_language(a, b), same, "{} vs {}", a, b);
+        }
+    }
+}
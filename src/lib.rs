@@ -0,0 +1,92 @@
+//! tvcode converts videos to Apple TV-compatible H.264/AAC MP4 files.
+//!
+//! The pipeline is: [`discover`] candidate files, [`probe`] them with ffprobe,
+//! build a [`TranscodePlan`], then run it with a [`Transcoder`]. All external
+//! commands are executed through an [`FfmpegRunner`].
+//!
+//! ```no_run
+//! use tvcode::{JobLog, Transcoder};
+//!
+//! let transcoder = Transcoder::new(JobLog::default());
+//! let info = transcoder.probe("movie.mkv".as_ref()).unwrap();
+//! if tvcode::plan::needs_transcoding(&info, &transcoder.options().device_caps()) {
+//!     let plan = transcoder.plan(&info, None);
+//!     transcoder.transcode(&info, &plan).unwrap();
+//! }
+//! ```
+
+pub mod output;
+
+pub mod analyze;
+pub mod arr;
+pub mod cache;
+pub mod capabilities;
+pub mod config;
+pub mod dashboard;
+pub mod device;
+pub mod discover;
+pub mod filters;
+pub mod h264;
+pub mod hls;
+pub mod hwaccel;
+pub mod ignore;
+pub mod install;
+pub mod integrity;
+pub mod joblog;
+pub mod language;
+#[cfg(feature = "libav")]
+pub mod libav;
+pub mod library;
+pub mod multipart;
+pub mod notify;
+pub mod opensubtitles;
+pub mod options;
+pub mod pause;
+pub mod plan;
+pub mod preset;
+pub mod power;
+pub mod priority;
+pub mod probe;
+pub mod quality;
+pub mod queue;
+pub mod remote;
+pub mod remux;
+pub mod report;
+pub mod runner;
+pub mod runtime;
+pub mod schedule;
+pub mod sdh;
+pub mod segment;
+pub mod staging;
+pub mod subtitles;
+pub mod sweep;
+pub mod thumbnails;
+pub mod transcode;
+pub mod transfer;
+pub mod tui;
+
+pub use config::Config;
+pub use joblog::JobLog;
+pub use options::{AudioPolicy, Container, EncodeOptions, ExistingOutput, OutputLayout};
+pub use plan::TranscodePlan;
+pub use probe::{SubtitleTrack, VideoInfo};
+pub use runner::{FfmpegRunner, SystemRunner};
+pub use transcode::Transcoder;
+
+use runner::Tool;
+
+/// Whether both `ffmpeg` and `ffprobe` can be run by `runner`.
+pub fn check_ffmpeg_installed(runner: &dyn FfmpegRunner) -> bool {
+    let version = ["-version".to_string()];
+    runner.capture(Tool::Ffmpeg, &version).is_ok()
+        && runner.capture(Tool::Ffprobe, &version).is_ok()
+}
+
+/// An empty folder in the temp dir for the test `name`, created afresh.
+#[cfg(test)]
+pub(crate) fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("tvcode-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,237 @@
+//! [`LibavRunner`]: an in-process backend on the libav libraries, built with
+//! the `libav` cargo feature.
+//!
+//! Probing, which every file goes through (and `watch` and `scan` do for
+//! whole libraries), opens the input with libavformat instead of starting
+//! ffprobe, and failures carry libav's own error text. It answers the
+//! request [`run_ffprobe`] makes with the same JSON ffprobe would print, so
+//! [`parse_video_info`](crate::probe::parse_video_info) reads either. Every
+//! other job, encodes and remuxes included, still goes to [`SystemRunner`].
+//!
+//! Like ffprobe, it finds EIA-608/708 closed captions by decoding the start
+//! of each MPEG-2, H.264 or HEVC video stream, where broadcasts carry them.
+//!
+//! [`run_ffprobe`]: crate::probe::run_ffprobe
+
+use crate::probe::ffprobe_args;
+use crate::runner::{FfmpegRunner, RunOutput, SystemRunner, Tool};
+use ffmpeg_next as ffmpeg;
+use ffmpeg::codec::packet::side_data::Type as SideDataType;
+use ffmpeg::format::stream::Disposition;
+use ffmpeg::{codec, ffi, media, ChannelLayout, DictionaryRef, Rational};
+use serde_json::{json, Map, Value};
+use std::ffi::CStr;
+use std::io;
+use std::path::Path;
+
+/// Runs probes through libav and everything else through [`SystemRunner`].
+#[derive(Debug, Clone, Copy)]
+pub struct LibavRunner {
+    fallback: SystemRunner,
+}
+
+impl LibavRunner {
+    /// Initializes libav, which fails only when the linked libraries cannot
+    /// be set up.
+    pub fn new() -> Result<LibavRunner, String> {
+        ffmpeg::init().map_err(|e| format!("cannot initialize libav: {}", e))?;
+        Ok(LibavRunner { fallback: SystemRunner })
+    }
+}
+
+impl FfmpegRunner for LibavRunner {
+    fn run(&self, args: &[String], label: &str) -> io::Result<Option<i32>> {
+        self.fallback.run(args, label)
+    }
+
+    fn capture(&self, tool: Tool, args: &[String]) -> io::Result<RunOutput> {
+        let path = args.last().map(Path::new);
+        match path.filter(|path| tool == Tool::Ffprobe && args == ffprobe_args(path).as_slice()) {
+            Some(path) => Ok(match describe(path) {
+                Ok(description) => RunOutput {
+                    code: Some(0),
+                    stdout: description.to_string().into_bytes(),
+                    stderr: Vec::new(),
+                },
+                Err(e) => RunOutput {
+                    code: Some(1),
+                    stdout: Vec::new(),
+                    stderr: format!("{}: {}", path.display(), e).into_bytes(),
+                },
+            }),
+            None => self.fallback.capture(tool, args),
+        }
+    }
+}
+
+/// ffprobe's `-show_format -show_streams -show_chapters` JSON for `path`,
+/// limited to the fields tvcode reads.
+fn describe(path: &Path) -> Result<Value, ffmpeg::Error> {
+    let input = ffmpeg::format::input(&path)?;
+    let mut streams: Vec<Value> = input.streams().map(|stream| describe_stream(&stream)).collect();
+    for stream in input.streams() {
+        let broadcast = matches!(stream.parameters().id(), codec::Id::MPEG2VIDEO | codec::Id::H264 | codec::Id::HEVC);
+        if broadcast && has_closed_captions(path, stream.index()) {
+            streams[stream.index()]["closed_captions"] = 1.into();
+        }
+    }
+    let chapters: Vec<Value> = input
+        .chapters()
+        .map(|chapter| {
+            json!({
+                "start_time": seconds(chapter.start(), chapter.time_base()),
+                "end_time": seconds(chapter.end(), chapter.time_base()),
+                "tags": tags(&chapter.metadata()),
+            })
+        })
+        .collect();
+    let mut format = Map::new();
+    format.insert("format_name".into(), input.format().name().into());
+    if input.duration() != ffi::AV_NOPTS_VALUE {
+        format.insert("duration".into(), seconds(input.duration(), Rational::from(ffi::AV_TIME_BASE_Q)).into());
+    }
+    if input.bit_rate() > 0 {
+        format.insert("bit_rate".into(), input.bit_rate().to_string().into());
+    }
+    format.insert("tags".into(), tags(&input.metadata()));
+    Ok(json!({ "streams": streams, "format": format, "chapters": chapters }))
+}
+
+fn describe_stream(stream: &ffmpeg::Stream) -> Value {
+    let parameters = stream.parameters();
+    // Fields every supported libav version has in the same place
+    let (width, height, profile, bit_rate, sample_rate) = unsafe {
+        let raw = &*parameters.as_ptr();
+        (raw.width, raw.height, raw.profile, raw.bit_rate, raw.sample_rate)
+    };
+    let id = parameters.id();
+    let mut description = Map::new();
+    description.insert("index".into(), stream.index().into());
+    let codec_type = match parameters.medium() {
+        media::Type::Video => "video",
+        media::Type::Audio => "audio",
+        media::Type::Subtitle => "subtitle",
+        media::Type::Attachment => "attachment",
+        media::Type::Data => "data",
+        media::Type::Unknown => "unknown",
+    };
+    description.insert("codec_type".into(), codec_type.into());
+    if id != codec::Id::None {
+        description.insert("codec_name".into(), id.name().into());
+    }
+    let profile_name = unsafe { ffi::avcodec_profile_name(id.into(), profile) };
+    if !profile_name.is_null() {
+        let profile_name = unsafe { CStr::from_ptr(profile_name) };
+        description.insert("profile".into(), profile_name.to_string_lossy().into());
+    }
+    if bit_rate > 0 {
+        description.insert("bit_rate".into(), bit_rate.to_string().into());
+    }
+    match parameters.medium() {
+        media::Type::Video => {
+            description.insert("width".into(), width.into());
+            description.insert("height".into(), height.into());
+            let rate = stream.avg_frame_rate();
+            description.insert("avg_frame_rate".into(), format!("{}/{}", rate.numerator(), rate.denominator()).into());
+            let decoder = codec::Context::from_parameters(parameters).and_then(|context| context.decoder().video());
+            if let Some(pixel) = decoder.ok().and_then(|decoder| decoder.format().descriptor()) {
+                description.insert("pix_fmt".into(), pixel.name().into());
+            }
+            let rotations: Vec<Value> = stream
+                .side_data()
+                .filter(|side_data| side_data.kind() == SideDataType::DisplayMatrix && side_data.data().len() >= 36)
+                .map(|side_data| {
+                    let rotation = unsafe { ffi::av_display_rotation_get(side_data.data().as_ptr().cast()) };
+                    json!({ "rotation": rotation.round() })
+                })
+                .collect();
+            description.insert("side_data_list".into(), rotations.into());
+        }
+        media::Type::Audio => {
+            description.insert("sample_rate".into(), sample_rate.to_string().into());
+            let decoder = codec::Context::from_parameters(parameters).and_then(|context| context.decoder().audio());
+            if let Ok(decoder) = decoder {
+                description.insert("channels".into(), decoder.channels().into());
+                if let Some(layout) = layout_name(&decoder.channel_layout()) {
+                    description.insert("channel_layout".into(), layout.into());
+                }
+            }
+        }
+        _ => {}
+    }
+    let start = stream.start_time();
+    if start != ffi::AV_NOPTS_VALUE {
+        description.insert("start_time".into(), seconds(start, stream.time_base()).into());
+    }
+    let duration = stream.duration();
+    if duration != ffi::AV_NOPTS_VALUE {
+        description.insert("duration".into(), seconds(duration, stream.time_base()).into());
+    }
+    description.insert("tags".into(), tags(&stream.metadata()));
+    let forced = stream.disposition().contains(Disposition::FORCED);
+    description.insert("disposition".into(), json!({ "forced": u8::from(forced) }));
+    Value::Object(description)
+}
+
+/// Frames decoded from a video stream before deciding it has no captions
+const CAPTION_SEARCH_FRAMES: usize = 30;
+
+/// Whether decoding the start of video stream `index` of `path` turns up
+/// closed captions, which the decoder flags as it meets them.
+fn has_closed_captions(path: &Path, index: usize) -> bool {
+    let Ok(mut input) = ffmpeg::format::input(&path) else {
+        return false;
+    };
+    let Some(parameters) = input.stream(index).map(|stream| stream.parameters()) else {
+        return false;
+    };
+    let Ok(mut decoder) = codec::Context::from_parameters(parameters).and_then(|context| context.decoder().video())
+    else {
+        return false;
+    };
+    let mut frame = ffmpeg::frame::Video::empty();
+    let mut decoded = 0;
+    for (stream, packet) in input.packets() {
+        if stream.index() != index || decoder.send_packet(&packet).is_err() {
+            continue;
+        }
+        while decoder.receive_frame(&mut frame).is_ok() {
+            if unsafe { (*decoder.as_ptr()).properties } & ffi::FF_CODEC_PROPERTY_CLOSED_CAPTIONS != 0 {
+                return true;
+            }
+            decoded += 1;
+        }
+        if decoded >= CAPTION_SEARCH_FRAMES {
+            break;
+        }
+    }
+    false
+}
+
+/// `ticks` of `time_base` as ffprobe prints seconds, e.g. `"5.005000"`.
+fn seconds(ticks: i64, time_base: Rational) -> String {
+    format!("{:.6}", ticks as f64 * f64::from(time_base))
+}
+
+fn tags(metadata: &DictionaryRef) -> Value {
+    metadata.iter().map(|(key, value)| (key.to_string(), Value::from(value))).collect::<Map<_, _>>().into()
+}
+
+/// ffmpeg's name for the layouts tvcode tells apart.
+fn layout_name(layout: &ChannelLayout) -> Option<&'static str> {
+    [
+        (ChannelLayout::MONO, "mono"),
+        (ChannelLayout::STEREO, "stereo"),
+        (ChannelLayout::_2POINT1, "2.1"),
+        (ChannelLayout::QUAD, "quad"),
+        (ChannelLayout::_5POINT0, "5.0(side)"),
+        (ChannelLayout::_5POINT0_BACK, "5.0"),
+        (ChannelLayout::_5POINT1, "5.1(side)"),
+        (ChannelLayout::_5POINT1_BACK, "5.1"),
+        (ChannelLayout::_6POINT1, "6.1"),
+        (ChannelLayout::_7POINT1, "7.1"),
+        (ChannelLayout::_7POINT1_WIDE_BACK, "7.1(wide)"),
+    ]
+    .into_iter()
+    .find_map(|(known, name)| (*layout == known).then_some(name))
+}
@@ -0,0 +1,146 @@
+//! Asking Plex and Jellyfin to scan for new outputs.
+//!
+//! Configured with `[plex]` and `[jellyfin]` tables in the config file. Each
+//! directory that received an output is scanned on its own where the server
+//! sees the same paths; otherwise the server's libraries are rescanned.
+//! Requests go through `curl`, and failures are only reported.
+
+use crate::notify::{curl, quote};
+use crate::status;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+/// Seconds allowed for one request to a media server.
+const REQUEST_TIMEOUT_SECS: &str = "30";
+
+/// The `[plex]` table of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlexSettings {
+    /// e.g. `http://localhost:32400`
+    pub url: String,
+    /// An `X-Plex-Token`
+    pub token: String,
+    /// Library section id to rescan instead of matching by path
+    pub section: Option<String>,
+}
+
+/// The `[jellyfin]` table of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JellyfinSettings {
+    /// e.g. `http://localhost:8096`
+    pub url: String,
+    /// An API key from the dashboard
+    pub api_key: String,
+    /// Rescan every library instead of reporting the changed folders, for
+    /// servers that see the files under other paths
+    #[serde(default)]
+    pub full_scan: bool,
+}
+
+/// The media servers to refresh.
+#[derive(Debug, Clone, Default)]
+pub struct MediaServers {
+    pub plex: Option<PlexSettings>,
+    pub jellyfin: Option<JellyfinSettings>,
+}
+
+impl MediaServers {
+    pub fn is_empty(&self) -> bool {
+        self.plex.is_none() && self.jellyfin.is_none()
+    }
+
+    /// Has each server pick up new files in `dirs`.
+    pub fn refresh(&self, dirs: &[PathBuf]) {
+        if dirs.is_empty() {
+            return;
+        }
+        if let Some(plex) = &self.plex {
+            match refresh_plex(plex, dirs) {
+                Ok(()) => status!("   📚 Asked Plex to scan for the new files"),
+                Err(e) => eprintln!("   ⚠️  Plex refresh failed: {}", e),
+            }
+        }
+        if let Some(jellyfin) = &self.jellyfin {
+            match refresh_jellyfin(jellyfin, dirs) {
+                Ok(()) => status!("   📚 Asked Jellyfin to scan for the new files"),
+                Err(e) => eprintln!("   ⚠️  Jellyfin refresh failed: {}", e),
+            }
+        }
+    }
+}
+
+/// Sends a request with `header` (the credentials), which goes to curl on
+/// stdin rather than the command line. `query` is URL-encoded onto a GET;
+/// `json` makes it a POST with that body.
+fn request(url: &str, header: &str, query: Option<(&str, &str)>, json: Option<&str>) -> Result<String, String> {
+    let mut config = format!("url = {}\nheader = {}\n", quote(url), quote(header));
+    config.push_str("header = \"Accept: application/json\"\n");
+    if let Some((name, value)) = query {
+        config.push_str(&format!("get\ndata-urlencode = {}\n", quote(&format!("{}={}", name, value))));
+    }
+    if let Some(json) = json {
+        config.push_str("header = \"Content-Type: application/json\"\n");
+        config.push_str(&format!("data-binary = {}\n", quote(json)));
+    }
+    curl(&["-f", "-m", REQUEST_TIMEOUT_SECS, "-K", "-"], &config)
+}
+
+fn plex_get(plex: &PlexSettings, path: &str, query: Option<(&str, &str)>) -> Result<String, String> {
+    let url = format!("{}{}", plex.url.trim_end_matches('/'), path);
+    request(&url, &format!("X-Plex-Token: {}", plex.token), query, None)
+}
+
+/// Scans the configured section, or each section holding one of `dirs`
+/// limited to that directory, or every section when none matches.
+fn refresh_plex(plex: &PlexSettings, dirs: &[PathBuf]) -> Result<(), String> {
+    if let Some(section) = &plex.section {
+        return plex_get(plex, &format!("/library/sections/{}/refresh", section), None).map(drop);
+    }
+    let listing: Value = serde_json::from_str(&plex_get(plex, "/library/sections", None)?)
+        .map_err(|e| format!("unexpected section list: {}", e))?;
+    let sections: Vec<(String, Vec<PathBuf>)> = listing["MediaContainer"]["Directory"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|section| {
+            let key = section["key"].as_str()?.to_string();
+            let locations = section["Location"].as_array()?;
+            Some((key, locations.iter().filter_map(|l| l["path"].as_str()).map(PathBuf::from).collect()))
+        })
+        .collect();
+
+    let mut matched = false;
+    for dir in dirs {
+        for (key, locations) in &sections {
+            if locations.iter().any(|location| dir.starts_with(location)) {
+                matched = true;
+                let path = format!("/library/sections/{}/refresh", key);
+                plex_get(plex, &path, Some(("path", &dir.to_string_lossy())))?;
+            }
+        }
+    }
+    if !matched {
+        for (key, _) in &sections {
+            plex_get(plex, &format!("/library/sections/{}/refresh", key), None)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reports `dirs` as changed, or starts a full library scan.
+fn refresh_jellyfin(jellyfin: &JellyfinSettings, dirs: &[PathBuf]) -> Result<(), String> {
+    let base = jellyfin.url.trim_end_matches('/');
+    let key = format!("X-Emby-Token: {}", jellyfin.api_key);
+    if jellyfin.full_scan {
+        return request(&format!("{}/Library/Refresh", base), &key, None, Some("")).map(drop);
+    }
+    let updates: Vec<Value> = dirs
+        .iter()
+        .map(|dir| json!({ "Path": dir, "UpdateType": "Created" }))
+        .collect();
+    let body = json!({ "Updates": updates }).to_string();
+    request(&format!("{}/Library/Media/Updated", base), &key, None, Some(&body)).map(drop)
+}
@@ -1,586 +1,1800 @@
+mod cli;
+
 use clap::Parser;
-use serde::{Deserialize, Serialize};
-use std::env;
-use std::fs;
+use cli::{
+    CaptionsArgs, Cli, Commands, ConvertArgs, FileListArgs, GlobalArgs, HistoryArgs, ScanArgs, SetupFfmpegArgs,
+    SweepArgs, WatchArgs, WorkerArgs,
+};
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-
-#[derive(Parser, Debug)]
-#[command(name = "tvcode")]
-#[command(version)]
-#[command(about = "Convert videos to Apple TV-compatible H.264/AAC format with subtitle burning")]
-struct Args {
-    /// Enable subtitle burning mode (prompts for subtitle selection)
-    #[arg(short, long)]
-    subtitles: bool,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct FFProbeOutput {
-    streams: Vec<Stream>,
-    format: Format,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Stream {
-    index: usize,
-    codec_type: String,
-    codec_name: String,
-    #[serde(default)]
-    width: u32,
-    #[serde(default)]
-    height: u32,
-    #[serde(default)]
-    tags: StreamTags,
-}
-
-#[derive(Debug, Deserialize, Serialize, Default)]
-struct StreamTags {
-    #[serde(default)]
-    language: Option<String>,
-    #[serde(default)]
-    title: Option<String>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Format {
-    format_name: String,
-}
-
-#[derive(Debug)]
-struct VideoInfo {
-    path: PathBuf,
-    video_codec: String,
-    audio_codec: String,
-    container: String,
-    width: u32,
-    height: u32,
-    subtitles: Vec<SubtitleTrack>,
-}
-
-#[derive(Debug, Clone)]
-struct SubtitleTrack {
-    subtitle_index: usize,  // Index among subtitle streams only (0, 1, 2...)
-    codec: String,
-    language: Option<String>,
-    title: Option<String>,
-    is_bitmap: bool,        // PGS, DVB, DVD subtitles are bitmap-based
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tvcode::arr::ArrEvent;
+use tvcode::cache::ProbeCache;
+use tvcode::capabilities::FfmpegCapabilities;
+use tvcode::dashboard;
+use tvcode::discover::{find_matching_video_files, read_file_list, FileFilter};
+use tvcode::install;
+use tvcode::integrity::decode_errors;
+use tvcode::joblog::read_history;
+use tvcode::language::normalize_code;
+use tvcode::multipart;
+use tvcode::filters::unsharp;
+use tvcode::options::SampleStart;
+use tvcode::output::{format_duration, format_size, set_verbosity, Verbosity};
+use tvcode::notify::{BatchReport, FileReport, Notifier};
+use tvcode::pause;
+use tvcode::power::{self, PowerPolicy};
+use tvcode::priority;
+use tvcode::probe::{external_subtitle_files, get_video_info};
+use tvcode::queue::{FileStatus, RunQueue};
+use tvcode::remote::{self, RemoteJob};
+use tvcode::runtime;
+use tvcode::report::{print_report, write_report, ReportRow};
+use tvcode::tui;
+use tvcode::plan::{
+    is_tvcode_output, partial_output_path, required_conversion, unique_output_path, AudioAction, Conversion, VideoEncoder,
+};
+use tvcode::quality::{measure_quality, QualityMetric};
+use tvcode::schedule::Schedule;
+use tvcode::staging::staged_path;
+use tvcode::transcode::remove_output;
+use tvcode::subtitles::{closed_caption_track, extract_captions};
+use tvcode::sweep::{sweep_output_dir, sweep_points, DEFAULT_SWEEP_SECS};
+use tvcode::{
+    check_ffmpeg_installed, status, Config, EncodeOptions, ExistingOutput, JobLog, SubtitleTrack, SystemRunner,
+    TranscodePlan, Transcoder, VideoInfo,
+};
+
+/// Process exit codes, listed in the README for cron jobs and systemd units.
+mod exit_code {
+    /// Everything was converted, skipped or already compatible
+    pub const OK: i32 = 0;
+    /// At least one file failed to convert (or, for `scan`, to probe)
+    pub const FILES_FAILED: i32 = 1;
+    /// ffmpeg or ffprobe could not be run
+    pub const NO_FFMPEG: i32 = 2;
+    /// There were no video files to process
+    pub const NOTHING_FOUND: i32 = 3;
+    /// Invalid arguments, config file, file list or environment
+    pub const USAGE: i32 = 4;
+    /// Something else stopped the run, e.g. the log or dashboard could not be opened
+    pub const ERROR: i32 = 5;
+    /// Stopped with Ctrl-C; the next run resumes
+    pub const INTERRUPTED: i32 = 130;
 }
 
 fn main() {
-    let args = Args::parse();
-    
-    println!("📺 tvcode v{} - Apple TV Video Transcoder", env!("CARGO_PKG_VERSION"));
-    if args.subtitles {
-        println!("🔥 Subtitle burning mode enabled");
+    let cli = Cli::try_parse().unwrap_or_else(|e| {
+        let _ = e.print();
+        std::process::exit(if e.use_stderr() { exit_code::USAGE } else { exit_code::OK });
+    });
+    let (command, global) = cli.into_command();
+    install::use_managed_ffmpeg();
+    set_verbosity(match (global.quiet, global.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Verbose,
+        (false, _) => Verbosity::Debug,
+    });
+
+    if let Commands::History(args) = &command {
+        std::process::exit(run_history(args, &global));
+    }
+    if let Commands::Worker(args) = &command {
+        std::process::exit(run_worker(args, &global));
+    }
+    if let Commands::SetupFfmpeg(args) = &command {
+        std::process::exit(run_setup_ffmpeg(args));
+    }
+    if let Commands::Captions(args) = &command {
+        std::process::exit(run_captions(args));
     }
-    println!("======================================\n");
 
-    if !check_ffmpeg_installed() {
-        eprintln!("❌ Error: ffmpeg and ffprobe must be installed and in PATH");
-        eprintln!("   Install with: brew install ffmpeg (macOS)");
-        std::process::exit(1);
+    status!("📺 tvcode v{} - Apple TV Video Transcoder", env!("CARGO_PKG_VERSION"));
+    if let Commands::Convert(ConvertArgs { subtitles: true, .. }) = &command {
+        status!("🔥 Subtitle burning mode enabled");
     }
+    status!("======================================\n");
 
-    let current_dir = env::current_dir().expect("Failed to get current directory");
-    println!("📁 Scanning directory: {}\n", current_dir.display());
+    if !check_ffmpeg_installed(&SystemRunner) {
+        eprintln!("❌ Error: ffmpeg and ffprobe must be installed and in PATH");
+        eprintln!("   Install with: brew install ffmpeg (macOS), or run: tvcode setup-ffmpeg");
+        std::process::exit(exit_code::NO_FFMPEG);
+    }
 
-    let video_files = find_video_files(&current_dir);
-    
-    if video_files.is_empty() {
-        println!("No video files found in the current directory.");
-        return;
+    if let Some(nice) = global.nice {
+        if let Err(e) = priority::set_nice(nice) {
+            eprintln!("⚠️  {}", e);
+        }
+    }
+    if global.ionice {
+        if let Err(e) = priority::set_idle_io() {
+            eprintln!("⚠️  {}", e);
+        }
     }
 
-    println!("Found {} video file(s)\n", video_files.len());
+    let config = match Config::load(global.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            std::process::exit(exit_code::USAGE);
+        }
+    };
 
-    for video_path in video_files {
-        process_video(&video_path, args.subtitles);
-        println!();
+    let job_log = match JobLog::open(global.log_file.as_deref()) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            std::process::exit(exit_code::ERROR);
+        }
+    };
+
+    let options = match &command {
+        Commands::Scan(args) => args.encode.to_options(&config),
+        Commands::Convert(args) => args.encode.to_options(&config),
+        Commands::Watch(args) => args.encode.to_options(&config),
+        // Nobody is there to answer an overwrite prompt
+        Commands::Arr(args) => args.encode.to_options(&config).map(|mut options| {
+            if options.if_exists == ExistingOutput::Prompt {
+                options.if_exists = ExistingOutput::Skip;
+            }
+            options
+        }),
+        // Every point encodes the same excerpt, from the middle unless told
+        // otherwise, and is scored by run_sweep itself
+        Commands::Sweep(args) => args.encode.to_options(&config).map(|mut options| {
+            if options.sample_secs.is_none() {
+                options.sample_secs = Some(DEFAULT_SWEEP_SECS);
+                options.sample_from = SampleStart::Middle;
+            }
+            options.force = true;
+            options.quality_check = None;
+            options.thumbnails = None;
+            options.split = None;
+            options.split_chapters = false;
+            options.hls = false;
+            options
+        }),
+        _ => Ok(EncodeOptions::default()),
+    };
+    let options = match options {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            std::process::exit(exit_code::USAGE);
+        }
+    };
+    if options.download_subtitles.is_some() && config.opensubtitles.is_none() {
+        eprintln!("❌ Error: --download-subs needs an [opensubtitles] api_key in the config file");
+        std::process::exit(exit_code::USAGE);
     }
+    let notifier = match &command {
+        Commands::Convert(args) => args.notify.to_notifier(&config).with_report_file(args.report_file.clone()),
+        Commands::Watch(args) => args.notify.to_notifier(&config),
+        Commands::Arr(args) => args.notify.to_notifier(&config),
+        _ => Notifier::default(),
+    };
+    let probe_cache = if global.no_probe_cache {
+        ProbeCache::default()
+    } else {
+        ProbeCache::open_default()
+    };
+    let transcoder = Transcoder::new(job_log)
+        .with_options(options)
+        .with_notifier(notifier)
+        .with_probe_cache(probe_cache)
+        .with_opensubtitles(config.opensubtitles.clone())
+        .with_transfer(config.transfer.clone());
+    pause::install();
+    runtime::install();
 
-    println!("✅ All done!");
+    if let Commands::Convert(_) | Commands::Watch(_) | Commands::Arr(_) | Commands::Sweep(_) = &command {
+        let burn_subtitles = matches!(&command, Commands::Convert(args) if args.subtitles);
+        let missing = FfmpegCapabilities::probe(transcoder.runner()).missing_for(
+            transcoder.hw_accel(),
+            transcoder.options(),
+            burn_subtitles,
+        );
+        exit_if_missing(&missing);
+    }
+
+    let code = match command {
+        Commands::Scan(args) => run_scan(&args, &transcoder),
+        Commands::Convert(args) => run_convert(&args, &transcoder),
+        Commands::Watch(args) => run_watch(&args, &transcoder),
+        Commands::Arr(_) => run_arr(&transcoder),
+        Commands::Sweep(args) => run_sweep(&args, &transcoder),
+        Commands::History(_) | Commands::Worker(_) | Commands::SetupFfmpeg(_) | Commands::Captions(_) => {
+            unreachable!("handled above")
+        }
+    };
+    runtime::finish();
+    std::process::exit(code);
 }
 
-fn check_ffmpeg_installed() -> bool {
-    Command::new("ffmpeg")
-        .arg("-version")
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .is_ok()
-        && Command::new("ffprobe")
-            .arg("-version")
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()
-            .is_ok()
+/// Reports what ffmpeg lacks for this run and exits, if anything.
+fn exit_if_missing(missing: &[String]) {
+    if missing.is_empty() {
+        return;
+    }
+    for problem in missing {
+        eprintln!("❌ Error: {}", problem);
+    }
+    eprintln!("   Install a full build, e.g. brew install ffmpeg (macOS), or run: tvcode setup-ffmpeg");
+    std::process::exit(exit_code::NO_FFMPEG);
 }
 
-fn find_video_files(dir: &Path) -> Vec<PathBuf> {
-    let video_extensions = [
-        "mp4", "mkv", "avi", "mov", "wmv", "flv", "webm",
-        "m4v", "mpg", "mpeg", "3gp", "ts", "m2ts",
-    ];
-    let mut video_files = Vec::new();
+/// Lists the video files in `dir` (or the `--files-from` list) that
+/// `filter` accepts, or prints why there are none.
+fn discover(dir: &Path, file_list: &FileListArgs, filter: &FileFilter) -> Vec<PathBuf> {
+    if let Some(source) = file_list.source(dir) {
+        return read_listed_files(&source, file_list.null, filter);
+    }
+    status!("📁 Scanning directory: {}\n", dir.display());
 
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(extension) = path.extension() {
-                    if let Some(ext_str) = extension.to_str() {
-                        if video_extensions.contains(&ext_str.to_lowercase().as_str()) {
-                            video_files.push(path);
-                        }
-                    }
-                }
-            }
+    let video_files = find_matching_video_files(dir, filter);
+    if filter.skip_extras {
+        let with_extras = FileFilter {
+            skip_extras: false,
+            ..filter.clone()
+        };
+        let extras = find_matching_video_files(dir, &with_extras).len() - video_files.len();
+        if extras > 0 {
+            status!("⏭️  Leaving out {} sample/extras file(s) (--keep-extras takes them too)", extras);
         }
     }
+
+    if video_files.is_empty() {
+        status!("No video files found in {}.", dir.display());
+    } else {
+        status!("Found {} video file(s)\n", video_files.len());
+    }
     video_files
 }
 
-fn process_video(video_path: &Path, burn_subtitles: bool) {
-    println!(
-        "🎥 Processing: {}",
-        video_path.file_name().unwrap().to_string_lossy()
-    );
+/// Reads the `--files-from` list at `source` (`-` for stdin). Every listed
+/// file is taken, whatever its extension, unless `filter` rejects it;
+/// missing ones are reported and dropped. Exits if the list is unreadable.
+fn read_listed_files(source: &Path, null_separated: bool, filter: &FileFilter) -> Vec<PathBuf> {
+    let listed = if source == Path::new("-") {
+        status!("📄 Reading the file list from stdin\n");
+        read_file_list(io::stdin().lock(), null_separated)
+    } else {
+        status!("📄 Reading the file list from {}\n", source.display());
+        std::fs::File::open(source).and_then(|file| read_file_list(io::BufReader::new(file), null_separated))
+    };
+    let listed = listed.unwrap_or_else(|e| {
+        eprintln!("❌ Error: Failed to read the file list: {}", e);
+        std::process::exit(exit_code::USAGE);
+    });
+    let video_files: Vec<PathBuf> = listed
+        .into_iter()
+        .filter(|path| {
+            let exists = path.is_file();
+            if !exists {
+                eprintln!("   ⚠️  {} is not a file, leaving it out", path.display());
+            }
+            exists && filter.accepts(path)
+        })
+        .collect();
+    if video_files.is_empty() {
+        status!("No files to process in the list.");
+    } else {
+        status!("Found {} file(s) in the list\n", video_files.len());
+    }
+    video_files
+}
 
-    match get_video_info(video_path) {
-        Ok(info) => {
-            println!(
-                "   Video: {} ({}x{})",
-                info.video_codec, info.width, info.height
+fn run_convert(args: &ConvertArgs, transcoder: &Transcoder) -> i32 {
+    start_dashboard(args.dashboard.as_deref());
+    // Held to the end so the summary is among the lines replayed on exit
+    let _tui = args.tui.then(|| {
+        tui::start().unwrap_or_else(|e| {
+            eprintln!("❌ Error: {}", e);
+            std::process::exit(exit_code::ERROR);
+        })
+    });
+    // A listed batch has no directory to keep a queue in
+    let list_source = args.file_list.source(&args.dir);
+    let listed = list_source.is_some();
+    if (args.subtitles || args.interactive) && list_source.as_deref() == Some(Path::new("-")) {
+        eprintln!("❌ Error: --subtitles and --interactive prompt on stdin, so the file list cannot come from stdin too");
+        std::process::exit(exit_code::USAGE);
+    }
+    let resumed = if args.no_resume || listed {
+        None
+    } else {
+        RunQueue::resume(&args.dir).unwrap_or_else(|e| {
+            eprintln!("   ⚠️  {}, starting over", e);
+            None
+        })
+    };
+    let (mut video_files, queue, joined_files) = match resumed {
+        Some(queue) => {
+            let entries = queue.entries();
+            let video_files = queue.remaining();
+            status!(
+                "♻️  Resuming an interrupted run in {}: {} of {} file(s) left",
+                args.dir.display(),
+                video_files.len(),
+                entries.len()
             );
-            println!("   Audio: {}", info.audio_codec);
-            println!("   Container: {}", info.container);
-
-            if !info.subtitles.is_empty() {
-                println!("   Subtitles: {} track(s) found", info.subtitles.len());
+            remove_partial_outputs(&queue, transcoder.options().stage_dir.as_deref());
+            status!();
+            let joined_files = queue.joined();
+            (video_files, queue, joined_files)
+        }
+        None => {
+            let video_files = discover(&args.dir, &args.file_list, &args.discover.to_filter());
+            if video_files.is_empty() {
+                return exit_code::NOTHING_FOUND;
             }
-
-            let selected_subtitle = if burn_subtitles && !info.subtitles.is_empty() {
-                select_subtitle_track(&info.subtitles)
+            let (video_files, joined_files) = join_multi_part(video_files, args, transcoder);
+            let queue = if listed {
+                RunQueue::default()
             } else {
-                None
+                RunQueue::create(&args.dir, &video_files, &joined_files)
             };
+            (video_files, queue, joined_files)
+        }
+    };
 
-            let needs_transcode = needs_transcoding(&info) || selected_subtitle.is_some();
+    dashboard::files_queued(&video_files);
 
-            if needs_transcode {
-                if selected_subtitle.is_some() {
-                    println!("   ⚙️  Transcoding to H.264/AAC with burned subtitles...");
-                } else {
-                    println!("   ⚙️  Transcoding to H.264/AAC...");
-                }
-                transcode_video(&info, selected_subtitle);
-            } else {
-                println!("   ✅ Already H.264/AAC Apple TV compatible, skipping");
+    let started = Instant::now();
+    let plans = plan_batch(&video_files, transcoder);
+    for (_, plan) in &plans {
+        status!("{}", plan.line);
+    }
+    let encode_secs: f64 = plans.iter().filter_map(|(_, plan)| plan.encode_secs).sum();
+    let estimated_size: u64 = plans.iter().filter_map(|(_, plan)| plan.estimated_size).sum();
+    status!(
+        "\n📋 Plan: {} of {} file(s) to convert, {} of video, ~{} of output\n",
+        plans.iter().filter(|(_, plan)| plan.encode_secs.is_some()).count(),
+        plans.len(),
+        format_duration(encode_secs as u64),
+        format_size(estimated_size)
+    );
+    let eta = BatchEta::new(&plans);
+    let subtitles = args.subtitles.then(|| choose_subtitles(&plans, transcoder));
+
+    let summary = if args.jobs > 1 || !args.remotes.is_empty() {
+        run_parallel(video_files, args, subtitles.as_ref(), transcoder, &queue, &eta)
+    } else {
+        let mut summary = Summary::default();
+        while let Some(video_path) = next_file(&mut video_files, transcoder, &queue, &mut summary) {
+            let outcome = process_video(
+                &video_path,
+                subtitles.as_ref(),
+                args.interactive,
+                args.schedule.as_ref(),
+                transcoder,
+                &queue,
+            );
+            summary.add(outcome);
+            eta.finished(&video_path, outcome);
+            status!();
+        }
+        summary
+    };
+    if runtime::shutdown().is_cancelled() {
+        eprintln!("🛑 Stopped; run the same command again to resume");
+        return exit_code::INTERRUPTED;
+    }
+    queue.finish();
+    for joined in &joined_files {
+        let _ = std::fs::remove_file(joined);
+    }
+    transcoder.notifier().batch_finished(&BatchReport {
+        transcoded: summary.transcoded,
+        skipped: summary.skipped,
+        failed: summary.failed,
+        elapsed: started.elapsed(),
+    });
+
+    println!(
+        "✅ All done! {} transcoded, {} skipped, {} failed",
+        summary.transcoded, summary.skipped, summary.failed
+    );
+    if summary.failed > 0 {
+        exit_code::FILES_FAILED
+    } else {
+        exit_code::OK
+    }
+}
+
+/// Finds the parts of multi-part films among `video_files` and, with
+/// `--join-parts`, joins each set whose parts match into one file that
+/// takes their place (after asking, with `--interactive`). Without it, only
+/// points them out. Returns the files to convert and the joined files, which
+/// are removed when the run finishes.
+fn join_multi_part(
+    mut video_files: Vec<PathBuf>,
+    args: &ConvertArgs,
+    transcoder: &Transcoder,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut joined_files = Vec::new();
+    for parts in multipart::find_part_groups(&video_files) {
+        let names: Vec<String> =
+            parts.iter().map(|part| part.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        let names = names.join(", ");
+        if !args.join_parts {
+            status!("💡 {} look like parts of one film; --join-parts converts them as one file\n", names);
+            continue;
+        }
+        let joined = multipart::joined_path(&parts);
+        let joined_name = joined.file_name().unwrap().to_string_lossy().into_owned();
+        if joined.exists() {
+            eprintln!("   ⚠️  {} already exists, converting {} separately", joined_name, names);
+            continue;
+        }
+        let infos = match parts.iter().map(|part| transcoder.probe(part)).collect::<Result<Vec<_>, _>>() {
+            Ok(infos) => infos,
+            Err(e) => {
+                eprintln!("   ⚠️  Cannot join {}: {}", names, e);
+                continue;
             }
+        };
+        if let Some(reason) = multipart::parts_mismatch(&infos) {
+            eprintln!("   ⚠️  Not joining {}: {}", names, reason);
+            continue;
         }
-        Err(e) => {
-            eprintln!("   ❌ Error analyzing video: {}", e);
+        if args.interactive {
+            print!("❓ Join {} into {}? [Y/n]: ", names, joined_name);
+            io::stdout().flush().unwrap();
+            let mut input = String::new();
+            io::stdin().read_line(&mut input).unwrap();
+            if matches!(input.trim(), "n" | "N" | "no") {
+                continue;
+            }
+        }
+        status!("🧩 Joining {} into {}", names, joined_name);
+        if let Err(e) = transcoder.join_parts(&parts, &joined) {
+            eprintln!("   ⚠️  {}, converting the parts separately", e);
+            continue;
         }
+        let first = video_files.iter().position(|path| *path == parts[0]).unwrap();
+        video_files[first] = joined.clone();
+        video_files.retain(|path| !parts.contains(path));
+        joined_files.push(joined);
     }
+    (video_files, joined_files)
 }
 
-fn is_bitmap_subtitle(codec: &str) -> bool {
-    matches!(
-        codec,
-        "hdmv_pgs_subtitle" | "pgssub" | "dvd_subtitle" | "dvdsub" | "dvb_subtitle" | "dvbsub"
-    )
+/// Takes the next file to convert: the first one still pending in the
+/// dashboard's order (which `--tui` can change), otherwise the next in
+/// `files`. Files skipped from the TUI are recorded and dropped on the way.
+fn next_file(
+    files: &mut Vec<PathBuf>,
+    transcoder: &Transcoder,
+    queue: &RunQueue,
+    summary: &mut Summary,
+) -> Option<PathBuf> {
+    if runtime::shutdown().is_cancelled() {
+        return None;
+    }
+    files.retain(|path| {
+        if !dashboard::is_skipped(path) {
+            return true;
+        }
+        status!("⏭️  Skipped {}\n", path.display());
+        record_outcome(transcoder, queue, path, FileOutcome::Skipped, None);
+        summary.add(FileOutcome::Skipped);
+        false
+    });
+    let index = dashboard::pending_order()
+        .iter()
+        .find_map(|pending| files.iter().position(|path| path == pending))
+        .unwrap_or(0);
+    (!files.is_empty()).then(|| files.remove(index))
 }
 
-fn select_subtitle_track(subtitles: &[SubtitleTrack]) -> Option<SubtitleTrack> {
-    if subtitles.is_empty() {
-        return None;
+/// Serves the dashboard on `addr`, if given; exits if that is impossible.
+fn start_dashboard(addr: Option<&str>) {
+    let Some(addr) = addr else {
+        return;
+    };
+    if let Err(e) = dashboard::serve(addr) {
+        eprintln!("❌ Error: {}", e);
+        std::process::exit(exit_code::ERROR);
     }
+    status!("🌐 Dashboard at http://{}\n", addr);
+}
 
-    println!("\n   📝 Available subtitle tracks:");
-    for (idx, sub) in subtitles.iter().enumerate() {
-        let lang = sub.language.as_deref().unwrap_or("unknown");
-        let title = sub.title.as_deref().unwrap_or("");
-        let title_str = if !title.is_empty() {
-            format!(" - {}", title)
-        } else {
-            String::new()
+/// Deletes what an interrupted run was writing, so it is encoded afresh
+/// rather than treated as an existing output.
+fn remove_partial_outputs(queue: &RunQueue, stage_dir: Option<&Path>) {
+    for entry in queue.entries() {
+        let (FileStatus::Encoding, Some(output)) = (entry.status, &entry.output) else {
+            continue;
         };
-        let sub_type = if sub.is_bitmap { "bitmap" } else { "text" };
-        println!(
-            "      [{}] {} ({}, {}){}",
-            idx + 1,
-            lang,
-            sub.codec,
-            sub_type,
-            title_str
+        // Outputs are written under their partial name (or in the stage
+        // folder) until finished; the output itself is whole if it exists
+        let mut working = vec![partial_output_path(output)];
+        working.extend(stage_dir.map(|dir| staged_path(dir, output)));
+        let mut removed = false;
+        for path in &working {
+            removed |= remove_output(path);
+        }
+        if removed {
+            status!("   🗑️  Removed partial output {}", output.display());
+        }
+    }
+}
+
+/// Probes and plans files on this thread while `args.jobs` workers encode.
+/// Each worker keeps its own slot, so with several `--gpu`s every worker
+/// stays on one GPU.
+fn run_parallel(
+    mut video_files: Vec<PathBuf>,
+    args: &ConvertArgs,
+    subtitles: Option<&SubtitleChoices>,
+    transcoder: &Transcoder,
+    queue: &RunQueue,
+    eta: &BatchEta,
+) -> Summary {
+    if args.remotes.is_empty() {
+        status!("⚡ Encoding up to {} files in parallel\n", args.jobs);
+    } else {
+        status!(
+            "⚡ Encoding up to {} files in parallel ({} here, 1 each on {})\n",
+            args.jobs + args.remotes.len(),
+            args.jobs,
+            args.remotes.join(", ")
         );
     }
-    println!("      [0] Skip subtitle burning");
 
-    print!("\n   Select subtitle track [0-{}]: ", subtitles.len());
-    io::stdout().flush().unwrap();
+    // Rendezvous channel: planning runs at most one file ahead of the workers
+    let (sender, receiver) = mpsc::sync_channel::<(VideoInfo, TranscodePlan)>(0);
+    let receiver = Mutex::new(receiver);
+    let summary = Mutex::new(Summary::default());
+    // Workers wait to start one at a time so a schedule wait is announced once
+    let start_gate = Mutex::new(());
 
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).unwrap();
+    thread::scope(|scope| {
+        for slot in 0..args.jobs {
+            let (receiver, summary, start_gate) = (&receiver, &summary, &start_gate);
+            scope.spawn(move || loop {
+                let job = receiver.lock().unwrap().recv();
+                let Ok((info, mut plan)) = job else { break };
+                {
+                    let _gate = start_gate.lock().unwrap();
+                    wait_to_start(args.schedule.as_ref(), transcoder);
+                }
+                transcoder.assign_gpu(&mut plan, slot);
+                let outcome = encode_video(transcoder, &info, &plan, queue);
+                summary.lock().unwrap().add(outcome);
+                eta.finished(&info.path, outcome);
+            });
+        }
+        for host in &args.remotes {
+            let (receiver, summary, start_gate) = (&receiver, &summary, &start_gate);
+            scope.spawn(move || loop {
+                let job = receiver.lock().unwrap().recv();
+                let Ok((info, plan)) = job else { break };
+                {
+                    let _gate = start_gate.lock().unwrap();
+                    wait_to_start(args.schedule.as_ref(), transcoder);
+                }
+                let outcome = encode_remote_video(host, transcoder, &info, &plan, queue);
+                summary.lock().unwrap().add(outcome);
+                eta.finished(&info.path, outcome);
+            });
+        }
 
-    if let Ok(choice) = input.trim().parse::<usize>() {
-        if choice == 0 {
-            return None;
+        loop {
+            let video_path = {
+                let mut summary = summary.lock().unwrap();
+                let Some(video_path) = next_file(&mut video_files, transcoder, queue, &mut summary) else {
+                    break;
+                };
+                video_path
+            };
+            match prepare_video(&video_path, subtitles, args.interactive, transcoder) {
+                Prepared::Done(outcome) => {
+                    record_outcome(transcoder, queue, &video_path, outcome, None);
+                    summary.lock().unwrap().add(outcome);
+                    eta.finished(&video_path, outcome);
+                }
+                Prepared::Encode(info, plan) => {
+                    if sender.send((*info, *plan)).is_err() {
+                        break;
+                    }
+                }
+            }
+            status!();
         }
-        if choice > 0 && choice <= subtitles.len() {
-            return Some(subtitles[choice - 1].clone());
+        drop(sender);
+    });
+
+    summary.into_inner().unwrap()
+}
+
+/// Exits 1 when any file could not be probed.
+fn run_scan(args: &ScanArgs, transcoder: &Transcoder) -> i32 {
+    let video_files = discover(&args.dir, &args.file_list, &args.discover.to_filter());
+    if video_files.is_empty() {
+        return exit_code::NOTHING_FOUND;
+    }
+    if args.report || args.report_file.is_some() {
+        let rows = report_rows(&video_files, transcoder);
+        if args.report {
+            print_report(&rows);
+        }
+        if let Some(path) = &args.report_file {
+            if let Err(e) = write_report(path, &rows) {
+                eprintln!("❌ Error: {}", e);
+                return exit_code::ERROR;
+            }
+            status!("📝 Wrote a report of {} file(s) to {}", rows.len(), path.display());
         }
+        return if rows.iter().any(|row| row.error.is_some()) {
+            exit_code::FILES_FAILED
+        } else {
+            exit_code::OK
+        };
     }
 
-    println!("   ⚠️  Invalid selection, skipping subtitle burning");
-    None
+    let plans = plan_batch(&video_files, transcoder);
+    for (_, plan) in &plans {
+        if plan.failed {
+            eprintln!("{}", plan.line);
+        } else {
+            println!("{}", plan.line);
+        }
+    }
+    let to_convert = plans.iter().filter(|(_, plan)| plan.encode_secs.is_some()).count();
+    let estimated_total: u64 = plans.iter().filter_map(|(_, plan)| plan.estimated_size).sum();
+    println!(
+        "\n{} of {} file(s) would be converted, ~{} of output",
+        to_convert,
+        video_files.len(),
+        format_size(estimated_total)
+    );
+    if plans.iter().any(|(_, plan)| plan.failed) {
+        exit_code::FILES_FAILED
+    } else {
+        exit_code::OK
+    }
+}
+
+/// Probes `video_files` in parallel and judges each for a report.
+fn report_rows(video_files: &[PathBuf], transcoder: &Transcoder) -> Vec<ReportRow> {
+    status!("🔍 Probing {} file(s)...\n", video_files.len());
+    let probed = transcoder.probe_all(video_files);
+    video_files
+        .iter()
+        .zip(probed)
+        .map(|(path, result)| match result {
+            Ok(info) => ReportRow::new(&info, transcoder.hw_accel(), transcoder.options()),
+            Err(e) => ReportRow::unreadable(path, &e),
+        })
+        .collect()
+}
+
+/// What a run would do with one file.
+struct FilePlan {
+    /// e.g. `movie.mkv — hevc (1920x1080), dts, matroska: ⚙️  needs transcoding, ~2.10 GB`
+    line: String,
+    /// Seconds of output to write, if the file would be converted
+    encode_secs: Option<f64>,
+    estimated_size: Option<u64>,
+    /// The probe failed
+    failed: bool,
+    /// What the probe found, for files that were read
+    info: Option<VideoInfo>,
 }
 
-fn get_video_info(video_path: &Path) -> Result<VideoInfo, String> {
-    let output = Command::new("ffprobe")
-        .args([
-            "-v",
-            "quiet",
-            "-print_format",
-            "json",
-            "-show_format",
-            "-show_streams",
-            "-analyzeduration",
-            "100000000",  // 100 seconds - helps with PGS detection
-            "-probesize",
-            "100000000",  // 100 MB
-            video_path.to_str().unwrap(),
-        ])
-        .output()
-        .map_err(|e| format!("Failed to run ffprobe: {}", e))?;
-
-    if !output.status.success() {
-        return Err("ffprobe failed".to_string());
-    }
-
-    let probe_data: FFProbeOutput = serde_json::from_slice(&output.stdout)
-        .map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
-
-    let mut video_codec = String::from("unknown");
-    let mut audio_codec = String::from("unknown");
-    let mut width = 0;
-    let mut height = 0;
-    let mut subtitles = Vec::new();
-    let mut subtitle_stream_index = 0usize;
-
-    for stream in &probe_data.streams {
-        match stream.codec_type.as_str() {
-            "video" => {
-                video_codec = stream.codec_name.clone();
-                width = stream.width;
-                height = stream.height;
+impl FilePlan {
+    fn skipped(line: String) -> FilePlan {
+        FilePlan {
+            line,
+            encode_secs: None,
+            estimated_size: None,
+            failed: false,
+            info: None,
+        }
+    }
+}
+
+/// Probes `video_files` in parallel (except those below `--min-size`) and
+/// works out what would happen to each, in order.
+fn plan_batch(video_files: &[PathBuf], transcoder: &Transcoder) -> Vec<(PathBuf, FilePlan)> {
+    let options = transcoder.options();
+    let to_probe: Vec<PathBuf> = video_files
+        .iter()
+        .filter(|path| below_minimum(path, None, options).is_none())
+        .cloned()
+        .collect();
+    if to_probe.len() > 1 {
+        status!("🔍 Probing {} file(s)...\n", to_probe.len());
+    }
+    let mut probed: HashMap<PathBuf, Result<VideoInfo, String>> =
+        to_probe.iter().cloned().zip(transcoder.probe_all(&to_probe)).collect();
+
+    let plans = video_files.iter().map(|path| {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let plan = match probed.remove(path) {
+            None => {
+                let reason = below_minimum(path, None, options).unwrap_or_default();
+                FilePlan::skipped(format!("{} — ⏭️  {}, would be skipped", name, reason))
             }
-            "audio" => {
-                audio_codec = stream.codec_name.clone();
+            Some(Err(e)) => FilePlan {
+                failed: true,
+                ..FilePlan::skipped(format!("{} — ❌ Error analyzing video: {}", name, e))
+            },
+            Some(Ok(info)) => FilePlan {
+                info: Some(info.clone()),
+                ..plan_file(&name, &info, transcoder)
+            },
+        };
+        (path.clone(), plan)
+    });
+    plans.collect()
+}
+
+fn plan_file(name: &str, info: &VideoInfo, transcoder: &Transcoder) -> FilePlan {
+    let options = transcoder.options();
+    if let Some(reason) = below_minimum(&info.path, Some(info), options) {
+        return FilePlan::skipped(format!("{} — ⏭️  {}, would be skipped", name, reason));
+    }
+    if is_tvcode_output(info) {
+        return FilePlan::skipped(format!("{} — ⏭️  already converted by tvcode, would be skipped", name));
+    }
+    let transcode_plan = TranscodePlan::new(info, None, transcoder.hw_accel(), options);
+    let encoded = would_encode(info, &transcode_plan, options);
+    let verdict = match required_conversion(info, &options.device_caps()) {
+        Conversion::None if options.force => "✅ compatible, re-encoded for --force",
+        Conversion::None if encoded => "✅ compatible, rewritten for the output options",
+        Conversion::None => "✅ compatible",
+        Conversion::Remux => "📦 needs remux only",
+        Conversion::Audio => "🔊 needs audio conversion only",
+        Conversion::Transcode => "⚙️  needs transcoding",
+    };
+    let mut plan = FilePlan::skipped(String::new());
+    let mut estimate = String::new();
+    if encoded {
+        plan.encode_secs = Some(transcode_plan.output_duration(info));
+        plan.estimated_size = transcode_plan.estimated_size(info);
+        if let Some(size) = plan.estimated_size {
+            estimate = format!(", ~{}", format_size(size));
+        }
+    }
+    plan.line = format!(
+        "{} — {} ({}x{}), {}, {}: {}{}",
+        name, info.video_codec, info.width, info.height, info.audio_codec, info.container, verdict, estimate
+    );
+    plan
+}
+
+/// Whether `info` is written out under `plan` rather than skipped: skipped
+/// files are those the plan would only copy as they are, with no thumbnails
+/// to make either.
+fn would_encode(info: &VideoInfo, plan: &TranscodePlan, options: &EncodeOptions) -> bool {
+    options.thumbnails.is_some() || !plan.is_noop(info)
+}
+
+/// Time left in a convert run, from the probed lengths of the files still
+/// to encode and the throughput so far.
+struct BatchEta {
+    state: Mutex<EtaState>,
+}
+
+struct EtaState {
+    /// Output seconds of each file not yet finished
+    pending: HashMap<PathBuf, f64>,
+    encoded_secs: f64,
+    started: Instant,
+}
+
+impl BatchEta {
+    fn new(plans: &[(PathBuf, FilePlan)]) -> BatchEta {
+        let pending = plans
+            .iter()
+            .filter_map(|(path, plan)| Some((path.clone(), plan.encode_secs?)))
+            .collect();
+        BatchEta {
+            state: Mutex::new(EtaState {
+                pending,
+                encoded_secs: 0.0,
+                started: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `path` (and anything skipped from the TUI) off the remaining
+    /// work and announces the time left once something has been encoded.
+    fn finished(&self, path: &Path, outcome: FileOutcome) {
+        let mut state = self.state.lock().unwrap();
+        let Some(secs) = state.pending.remove(path) else {
+            return;
+        };
+        if outcome == FileOutcome::Transcoded {
+            state.encoded_secs += secs;
+        }
+        state.pending.retain(|path, _| !dashboard::is_skipped(path));
+        let remaining: f64 = state.pending.values().sum();
+        if state.encoded_secs > 0.0 && remaining > 0.0 {
+            let wall_per_media_sec = state.started.elapsed().as_secs_f64() / state.encoded_secs;
+            status!(
+                "   ⏱️  {} file(s) left, about {} to go",
+                state.pending.len(),
+                format_duration((remaining * wall_per_media_sec) as u64)
+            );
+        }
+    }
+}
+
+/// Polls `args.dir` and converts each new video once its size has stopped
+/// changing between two scans (i.e. the copy into the folder has finished).
+/// Runs until Ctrl-C, which also stops the file being converted.
+fn run_watch(args: &WatchArgs, transcoder: &Transcoder) -> i32 {
+    start_dashboard(args.dashboard.as_deref());
+    status!(
+        "👀 Watching {} every {}s (Ctrl-C to stop)\n",
+        args.dir.display(),
+        args.interval
+    );
+
+    let mut done: HashSet<PathBuf> = HashSet::new();
+    let mut pending: HashMap<PathBuf, u64> = HashMap::new();
+    let mut summary = Summary::default();
+    let filter = args.discover.to_filter();
+
+    while !runtime::shutdown().is_cancelled() {
+        // The files converted in one pass over the folder make up a batch
+        let mut batch = Summary::default();
+        let mut batch_started = None;
+        for video_path in find_matching_video_files(&args.dir, &filter) {
+            if done.contains(&video_path) {
+                continue;
+            }
+            if runtime::shutdown().is_cancelled() {
+                break;
             }
-            "subtitle" => {
-                let is_bitmap = is_bitmap_subtitle(&stream.codec_name);
-                subtitles.push(SubtitleTrack {
-                    subtitle_index: subtitle_stream_index,
-                    codec: stream.codec_name.clone(),
-                    language: stream.tags.language.clone(),
-                    title: stream.tags.title.clone(),
-                    is_bitmap,
-                });
-                subtitle_stream_index += 1;
+            let Ok(size) = std::fs::metadata(&video_path).map(|m| m.len()) else {
+                continue;
+            };
+            match pending.insert(video_path.clone(), size) {
+                Some(previous) if previous == size => {}
+                Some(_) => continue,
+                None => {
+                    dashboard::files_queued(std::slice::from_ref(&video_path));
+                    continue;
+                }
             }
-            _ => {}
+
+            pending.remove(&video_path);
+            done.insert(video_path.clone());
+            batch_started.get_or_insert_with(Instant::now);
+            let outcome = process_video(&video_path, None, false, args.schedule.as_ref(), transcoder, &RunQueue::default());
+            transcoder.notifier().refresh_libraries();
+            summary.add(outcome);
+            batch.add(outcome);
+            status!(
+                "   📊 So far: {} transcoded, {} skipped, {} failed\n",
+                summary.transcoded, summary.skipped, summary.failed
+            );
         }
+        if let Some(started) = batch_started {
+            transcoder.notifier().batch_finished(&BatchReport {
+                transcoded: batch.transcoded,
+                skipped: batch.skipped,
+                failed: batch.failed,
+                elapsed: started.elapsed(),
+            });
+        }
+        runtime::sleep(Duration::from_secs(args.interval));
     }
+    status!("🛑 Stopped watching");
+    exit_code::INTERRUPTED
+}
 
-    Ok(VideoInfo {
-        path: video_path.to_path_buf(),
-        video_codec,
-        audio_codec,
-        container: probe_data.format.format_name,
-        width,
-        height,
-        subtitles,
-    })
+/// Converts the file named by Sonarr or Radarr's environment. Exits 0 when
+/// it was converted or needed nothing (including events other than imports),
+/// 1 when the conversion failed, 3 when the file is missing and 4 when the
+/// environment is unusable.
+fn run_arr(transcoder: &Transcoder) -> i32 {
+    let event = match ArrEvent::from_env() {
+        Ok(event) => event,
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            return exit_code::USAGE;
+        }
+    };
+    let (app, path, title) = match event {
+        ArrEvent::Test(app) => {
+            println!("✅ {} test event received, ffmpeg and ffprobe found", app.name());
+            return exit_code::OK;
+        }
+        ArrEvent::Other(app, event) => {
+            status!("⏭️  Nothing to do for {} event {}", app.name(), event);
+            return exit_code::OK;
+        }
+        ArrEvent::Import { app, path, title } => (app, path, title),
+    };
+    if !path.is_file() {
+        eprintln!("❌ Error: {} imported {}, which does not exist here", app.name(), path.display());
+        return exit_code::NOTHING_FOUND;
+    }
+    if let Some(title) = title {
+        status!("📥 {} imported {}\n", app.name(), title);
+    }
+
+    let outcome = process_video(&path, None, false, None, transcoder, &RunQueue::default());
+    transcoder.notifier().refresh_libraries();
+    if outcome == FileOutcome::Failed {
+        exit_code::FILES_FAILED
+    } else {
+        exit_code::OK
+    }
 }
 
-fn needs_transcoding(info: &VideoInfo) -> bool {
-    let video_compatible = info.video_codec == "h264";
-    let audio_compatible = info.audio_codec == "aac";
-    let container_compatible =
-        info.container.contains("mp4") || info.container.contains("m4v");
-    !(video_compatible && audio_compatible && container_compatible)
+/// Encodes the same excerpt of `args.file` at every sweep point into the
+/// `_sweep` folder, scores each against the source and prints a table of
+/// sizes and scores.
+fn run_sweep(args: &SweepArgs, transcoder: &Transcoder) -> i32 {
+    let metric = args.metric;
+    if !FfmpegCapabilities::probe(transcoder.runner()).has_filter(metric.filter()) {
+        eprintln!(
+            "❌ Error: your ffmpeg lacks the {} filter, needed to score a sweep with {}",
+            metric.filter(),
+            metric.label()
+        );
+        return exit_code::NO_FFMPEG;
+    }
+    let name = args.file.file_name().unwrap_or_default().to_string_lossy();
+    let info = match transcoder.probe(&args.file) {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("❌ Error analyzing {}: {}", name, e);
+            return exit_code::ERROR;
+        }
+    };
+    let base = transcoder.plan(&info, None);
+    let Some(sample) = base.sample else {
+        eprintln!("❌ Error: cannot take an excerpt from {}", name);
+        return exit_code::ERROR;
+    };
+    let points = sweep_points(&args.crf, &args.bitrate, base.settings.crf);
+    let dir = sweep_output_dir(&info.path);
+    status!(
+        "🎚️  Encoding {}s of {} from {} at {} settings",
+        sample.duration_secs.round(),
+        name,
+        format_duration(sample.start_secs as u64),
+        points.len()
+    );
+
+    let max_level = transcoder.options().device_caps().max_h264_level;
+    let (width, height) = base.output_size;
+    let mut results = Vec::new();
+    for point in points {
+        status!("\n🎚️  {}", point.label());
+        let mut plan = base.clone();
+        point.apply(&mut plan.settings);
+        plan.settings.fit_level(width, height, plan.output_frame_rate(&info), max_level);
+        plan.output = dir.join(format!("{}.{}", point.file_stem(), plan.container.extension()));
+        if let Err(e) = transcoder.transcode(&info, &plan) {
+            eprintln!("   ❌ {}", e);
+            results.push((point, None, None));
+            continue;
+        }
+        let size = std::fs::metadata(&plan.output).map(|metadata| metadata.len()).ok();
+        status!("   📏 Comparing with the source ({})...", metric.label());
+        let score = match measure_quality(transcoder.runner(), &info, &plan, metric) {
+            Ok(score) => Some(score),
+            Err(e) => {
+                eprintln!("   ⚠️  {}", e);
+                None
+            }
+        };
+        results.push((point, size, score));
+    }
+
+    // Sizes scale to the whole file, so a season's total can be guessed
+    // from one episode
+    let scale = info.duration_secs / sample.duration_secs;
+    println!("\n📊 {} ({})", name, dir.display());
+    println!("   {:<10} {:>10} {:>12} {:>8}", "Setting", "Excerpt", "Whole file", metric.label());
+    for (point, size, score) in &results {
+        let excerpt = size.map_or("failed".to_string(), format_size);
+        let whole = size
+            .filter(|_| scale > 0.0)
+            .map_or("-".to_string(), |size| format!("~{}", format_size((size as f64 * scale) as u64)));
+        let score = match (score, metric) {
+            (Some(score), QualityMetric::Vmaf) => format!("{:.1}", score),
+            (Some(score), QualityMetric::Ssim) => format!("{:.4}", score),
+            (None, _) => "-".to_string(),
+        };
+        println!("   {:<10} {:>10} {:>12} {:>8}", point.label(), excerpt, whole, score);
+    }
+    if results.iter().any(|(_, size, _)| size.is_none()) {
+        exit_code::FILES_FAILED
+    } else {
+        exit_code::OK
+    }
 }
 
-fn transcode_video(info: &VideoInfo, subtitle_track: Option<SubtitleTrack>) {
-    let output_path = get_output_path(&info.path, subtitle_track.is_some());
-    println!(
-        "   📤 Output: {}",
-        output_path.file_name().unwrap().to_string_lossy()
+/// Runs one job sent by a `--remote` coordinator: the options and subtitle
+/// come as JSON on stdin, status goes to stdout, and the exit code reports
+/// the result.
+fn run_worker(args: &WorkerArgs, global: &GlobalArgs) -> i32 {
+    let mut line = String::new();
+    let job = io::stdin()
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())
+        .and_then(|_| serde_json::from_str::<RemoteJob>(&line).map_err(|e| format!("Invalid job: {}", e)));
+    let job = match job {
+        Ok(job) => job,
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            return exit_code::USAGE;
+        }
+    };
+    if !check_ffmpeg_installed(&SystemRunner) {
+        eprintln!("❌ Error: ffmpeg and ffprobe must be installed and in PATH on this worker");
+        return exit_code::NO_FFMPEG;
+    }
+    let job_log = JobLog::open(global.log_file.as_deref()).unwrap_or_default();
+    let transcoder = Transcoder::new(job_log).with_options(job.options);
+    let burn_subtitles = job.subtitle.as_ref().is_some_and(|track| !track.is_bitmap);
+    let missing = FfmpegCapabilities::probe(transcoder.runner()).missing_for(
+        transcoder.hw_accel(),
+        transcoder.options(),
+        burn_subtitles,
     );
+    exit_if_missing(&missing);
 
-    let hw_accel = detect_hardware_acceleration();
-
-    
-    let mut ffmpeg_args: Vec<String> = Vec::new();
-    
-    // Add analyzeduration and probesize for better stream detection
-    ffmpeg_args.extend([
-        "-analyzeduration".to_string(),
-        "100000000".to_string(),
-        "-probesize".to_string(),
-        "100000000".to_string(),
-    ]);
-    
-    // Input file
-    ffmpeg_args.extend(["-i".to_string(), info.path.to_str().unwrap().to_string()]);
-
-    // Handle subtitle burning based on type
-    if let Some(ref track) = subtitle_track {
-        if track.is_bitmap {
-            // Bitmap subtitles (PGS, DVD, DVB) - use filter_complex with overlay
-            // Scale subtitle to match video dimensions to avoid resolution mismatch
-            println!("   🔥 Burning bitmap subtitles (PGS/DVD) using overlay filter");
-            ffmpeg_args.extend([
-                "-filter_complex".to_string(),
-                format!(
-                    "[0:s:{}]scale={}:{}[sub];[0:v][sub]overlay=eof_action=pass",
-                    track.subtitle_index, info.width, info.height
-                ),
-            ]);
-            // Software encoding required for filter_complex
-            ffmpeg_args.extend(get_sw_encoding_args());
-        } else {
-            // Text subtitles (SRT, ASS, SSA, etc.) - use subtitles filter
-            println!("   🔥 Burning text subtitles using subtitles filter");
-            let input_file = info
-                .path
-                .to_str()
-                .unwrap()
-                .replace('\\', "\\\\")
-                .replace(':', "\\:")
-                .replace("'", "'\\''");
-            ffmpeg_args.extend([
-                "-vf".to_string(),
-                format!("subtitles='{}':si={}", input_file, track.subtitle_index),
-            ]);
-            // Software encoding required for vf filter
-            ffmpeg_args.extend(get_sw_encoding_args());
+    let result = transcoder.probe(&args.input).and_then(|info| {
+        let mut plan = transcoder.plan(&info, job.subtitle);
+        plan.output = job.output;
+        transcoder.transcode(&info, &plan)
+    });
+    match result {
+        Ok(()) => exit_code::OK,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            exit_code::FILES_FAILED
         }
+    }
+}
+
+/// Extracts the closed captions of each file to `<name>.cc.srt` beside it.
+fn run_captions(args: &CaptionsArgs) -> i32 {
+    if !check_ffmpeg_installed(&SystemRunner) {
+        eprintln!("❌ Error: ffmpeg and ffprobe must be installed and in PATH");
+        return exit_code::NO_FFMPEG;
+    }
+    let mut failed = 0;
+    for path in &args.files {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        match get_video_info(&SystemRunner, path) {
+            Ok(info) if info.closed_captions => {}
+            Ok(_) => {
+                eprintln!("   ⚠️  {} has no closed captions", name);
+                failed += 1;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("   ❌ Error analyzing {}: {}", name, e);
+                failed += 1;
+                continue;
+            }
+        }
+        let output = path.with_extension("cc.srt");
+        status!("📝 {} → {}", name, output.file_name().unwrap_or_default().to_string_lossy());
+        if let Err(e) = extract_captions(&SystemRunner, path, &output) {
+            eprintln!("   ❌ Error: {}", e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        exit_code::FILES_FAILED
     } else {
-        // No subtitles - can use hardware acceleration
-        match &hw_accel {
-            Some(hw) => {
-                println!("   🚀 Using hardware acceleration: {} (H.264)", hw);
-                ffmpeg_args.extend(get_hw_encoding_args(hw, info.width, info.height));
+        exit_code::OK
+    }
+}
+
+/// Installs or removes the managed ffmpeg build.
+fn run_setup_ffmpeg(args: &SetupFfmpegArgs) -> i32 {
+    if args.remove {
+        return match install::uninstall() {
+            Ok(true) => {
+                println!("🗑️  Removed the downloaded ffmpeg, using the one on PATH again");
+                exit_code::OK
             }
-            None => {
-                println!("   ⚠️  Using software encoding (H.264, slower)");
-                ffmpeg_args.extend(get_sw_encoding_args());
+            Ok(false) => {
+                println!("No downloaded ffmpeg to remove");
+                exit_code::OK
+            }
+            Err(e) => {
+                eprintln!("❌ Error: {}", e);
+                exit_code::ERROR
+            }
+        };
+    }
+    let Some(build) = install::static_build() else {
+        eprintln!("❌ Error: no static ffmpeg build is known for this platform; install ffmpeg yourself");
+        return exit_code::NO_FFMPEG;
+    };
+    println!("⬇️  Downloading ffmpeg from {}", build.source);
+    match install::install(&build, &args.sha256) {
+        Ok(version) => {
+            println!("✅ Installed {}", version);
+            if let Some(dir) = install::managed_dir() {
+                println!("   in {}; tvcode uses it from now on", dir.display());
             }
+            exit_code::OK
+        }
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            exit_code::NO_FFMPEG
         }
     }
+}
 
-    // Audio encoding
-    if info.audio_codec != "aac" {
-        println!("   🔊 Converting audio to AAC");
-        ffmpeg_args.extend([
-            "-c:a".to_string(),
-            "aac".to_string(),
-            "-b:a".to_string(),
-            "192k".to_string(),
-            "-ac".to_string(),
-            "2".to_string(),
-        ]);
-    } else {
-        println!("   🔊 Audio already AAC, copying");
-        ffmpeg_args.extend(["-c:a".to_string(), "copy".to_string()]);
+fn run_history(args: &HistoryArgs, global: &GlobalArgs) -> i32 {
+    let Some(path) = global.log_file.as_deref() else {
+        eprintln!("❌ Error: history needs --log-file to know which log to read");
+        return exit_code::USAGE;
+    };
+
+    let entries = match read_history(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            return exit_code::ERROR;
+        }
+    };
+
+    if entries.is_empty() {
+        println!("No history recorded in {}", path.display());
+        return exit_code::NOTHING_FOUND;
     }
 
-    // No subtitle streams in output (already burned into video)
-    ffmpeg_args.push("-sn".to_string());
+    let start = entries.len().saturating_sub(args.limit);
+    for entry in &entries[start..] {
+        let icon = match (entry.action.as_str(), entry.exit_code) {
+            ("skip", _) => "⏭️ ",
+            ("transcode", Some(0)) => "✅",
+            _ => "❌",
+        };
+        let duration = entry
+            .duration_secs
+            .map(|d| format!(" ({:.0}s)", d))
+            .unwrap_or_default();
+        let file = Path::new(&entry.file)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| entry.file.clone());
+        println!("{} {} {:<9} {}{}", entry.timestamp, icon, entry.action, file, duration);
+        if let Some(error) = &entry.error {
+            println!("      {}", error);
+        }
+    }
+    exit_code::OK
+}
+
+/// Result of processing a single input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileOutcome {
+    Transcoded,
+    Skipped,
+    Failed,
+}
+
+impl FileOutcome {
+    fn queue_status(self) -> FileStatus {
+        match self {
+            FileOutcome::Transcoded => FileStatus::Done,
+            FileOutcome::Skipped => FileStatus::Skipped,
+            FileOutcome::Failed => FileStatus::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    transcoded: usize,
+    skipped: usize,
+    failed: usize,
+}
 
-    // Output settings
-    ffmpeg_args.extend([
-        "-movflags".to_string(),
-        "+faststart".to_string(),
-        "-f".to_string(),
-        "mp4".to_string(),
-        "-y".to_string(),
-        output_path.to_str().unwrap().to_string(),
-    ]);
+impl Summary {
+    fn add(&mut self, outcome: FileOutcome) {
+        match outcome {
+            FileOutcome::Transcoded => self.transcoded += 1,
+            FileOutcome::Skipped => self.skipped += 1,
+            FileOutcome::Failed => self.failed += 1,
+        }
+    }
+}
 
-    println!("   🔄 Starting transcode...");
+/// What is left to do for a file once it has been probed and any
+/// interactive choices have been made.
+enum Prepared {
+    Done(FileOutcome),
+    Encode(Box<VideoInfo>, Box<TranscodePlan>),
+}
 
-    let status = Command::new("ffmpeg").args(&ffmpeg_args).status();
+/// Holds a new encode while the queue is paused, `schedule` is closed or
+/// `--on-battery pause` applies.
+fn wait_to_start(schedule: Option<&Schedule>, transcoder: &Transcoder) {
+    pause::wait_while_paused();
+    if transcoder.options().on_battery == Some(PowerPolicy::Pause) {
+        power::wait_for_power();
+    }
+    if let Some(schedule) = schedule {
+        schedule.wait_until_open();
+    }
+}
+
+/// Prepares and encodes one file, holding the encode until it may start and
+/// recording its progress in `queue`.
+fn process_video(
+    video_path: &Path,
+    subtitles: Option<&SubtitleChoices>,
+    interactive: bool,
+    schedule: Option<&Schedule>,
+    transcoder: &Transcoder,
+    queue: &RunQueue,
+) -> FileOutcome {
+    match prepare_video(video_path, subtitles, interactive, transcoder) {
+        Prepared::Done(outcome) => {
+            record_outcome(transcoder, queue, video_path, outcome, None);
+            outcome
+        }
+        Prepared::Encode(info, plan) => {
+            wait_to_start(schedule, transcoder);
+            encode_video(transcoder, &info, &plan, queue)
+        }
+    }
+}
 
-    match status {
-        Ok(status) if status.success() => {
-            if subtitle_track.is_some() {
-                println!("   ✅ Transcode completed: H.264/AAC/MP4 with burned subtitles");
+/// Why `path` is too small for `--min-size`, or once probed (`info`) too
+/// short for `--min-duration`. An unknown duration is never too short.
+fn below_minimum(path: &Path, info: Option<&VideoInfo>, options: &EncodeOptions) -> Option<String> {
+    if let Some(info) = info {
+        let min = options.min_duration_secs?;
+        return (info.duration_secs > 0.0 && info.duration_secs < min).then(|| {
+            format!(
+                "shorter than {} ({})",
+                format_duration(min as u64),
+                format_duration(info.duration_secs as u64)
+            )
+        });
+    }
+    let min = options.min_size?;
+    let size = std::fs::metadata(path).ok()?.len();
+    (size < min).then(|| format!("smaller than {} ({})", format_size(min), format_size(size)))
+}
+
+/// Subtitle tracks chosen for `--subtitles` before encoding starts, by
+/// video. Files without one get no subtitles.
+type SubtitleChoices = HashMap<PathBuf, SubtitleTrack>;
+
+/// Asks which subtitle track to burn for each file in `plans` that has
+/// any, before the first encode, so the run needs nobody once it starts.
+/// Works from the probes [`plan_batch`] made.
+fn choose_subtitles(plans: &[(PathBuf, FilePlan)], transcoder: &Transcoder) -> SubtitleChoices {
+    status!("📝 Choosing subtitles before encoding starts\n");
+    let options = transcoder.options();
+    let infos: Vec<&VideoInfo> = plans
+        .iter()
+        .filter_map(|(_, plan)| plan.info.as_ref())
+        .filter(|info| below_minimum(&info.path, Some(info), options).is_none() && !is_tvcode_output(info))
+        .collect();
+    // The subtitle files beside them are probed together too
+    let subtitle_files: Vec<PathBuf> = infos.iter().flat_map(|info| external_subtitle_files(&info.path)).collect();
+    transcoder.probe_all(&subtitle_files);
+
+    let mut choices = SubtitleChoices::new();
+    for info in infos {
+        let subtitles = subtitle_candidates(info, transcoder);
+        if subtitles.is_empty() {
+            continue;
+        }
+        status!("🎥 {}", info.path.file_name().unwrap().to_string_lossy());
+        if let Some(track) = select_subtitle_track(&subtitles) {
+            choices.insert(info.path.clone(), track);
+        }
+        status!();
+    }
+    status!("📝 Subtitles chosen; the rest of the run needs no answers\n");
+    choices
+}
+
+/// The tracks the subtitle menu offers for `info`: its own, those of the
+/// subtitle files beside it (downloaded first with `--download-subs`) and
+/// its closed captions.
+fn subtitle_candidates(info: &VideoInfo, transcoder: &Transcoder) -> Vec<SubtitleTrack> {
+    transcoder.download_subtitles(info);
+    let mut subtitles = info.subtitles.clone();
+    subtitles.extend(transcoder.external_subtitles(&info.path));
+    if info.closed_captions {
+        subtitles.push(closed_caption_track());
+    }
+    transcoder.detect_languages(&info.path, &mut subtitles);
+    subtitles
+}
+
+/// Probes `video_path` and plans the encode, burning the track chosen for
+/// it in `subtitles`. With `interactive`, the plan is shown and has to be
+/// confirmed.
+fn prepare_video(
+    video_path: &Path,
+    subtitles: Option<&SubtitleChoices>,
+    interactive: bool,
+    transcoder: &Transcoder,
+) -> Prepared {
+    status!(
+        "🎥 Processing: {}",
+        video_path.file_name().unwrap().to_string_lossy()
+    );
+    if let Some(reason) = below_minimum(video_path, None, transcoder.options()) {
+        status!("   ⏭️  File is {}, skipping", reason);
+        transcoder.job_log().record(video_path, "skip");
+        return Prepared::Done(FileOutcome::Skipped);
+    }
+
+    // A copy of the source, remuxed by --repair, to encode instead
+    let mut repaired = None;
+    let probed = match transcoder.probe(video_path) {
+        Err(e) if transcoder.options().repair => {
+            eprintln!("   ⚠️  Cannot read the source ({})", e);
+            probe_repaired(video_path, transcoder).map(|(info, copy)| {
+                repaired = Some(copy);
+                info
+            })
+        }
+        probed => probed,
+    };
+    match probed {
+        Ok(info) => {
+            status!(
+                "   Video: {} ({}x{})",
+                info.video_codec, info.width, info.height
+            );
+            if info.rotation != 0 {
+                status!("   Rotation: {}°", info.rotation);
+            }
+            status!("   Audio: {}", info.audio_codec);
+            status!("   Container: {}", info.container);
+            if let Some(reason) = below_minimum(video_path, Some(&info), transcoder.options()) {
+                status!("   ⏭️  Video is {}, skipping", reason);
+                transcoder.job_log().record(video_path, "skip");
+                return Prepared::Done(FileOutcome::Skipped);
+            }
+            if is_tvcode_output(&info) {
+                status!("   ⏭️  Already converted by tvcode, skipping");
+                transcoder.job_log().record(video_path, "skip");
+                return Prepared::Done(FileOutcome::Skipped);
+            }
+
+            transcoder.download_subtitles(&info);
+            if !info.subtitles.is_empty() {
+                status!("   Subtitles: {} track(s) found", info.subtitles.len());
+            }
+            let selected_subtitle = subtitles.and_then(|choices| choices.get(video_path)).cloned();
+            if let Some(track) = &selected_subtitle {
+                status!(
+                    "   📝 Subtitles: {} ({})",
+                    track.language.as_deref().unwrap_or("unknown"),
+                    track.codec
+                );
+            }
+
+            // Decided by what the encode would do, so output options such as
+            // --hls rewrite a file that already plays
+            let options = transcoder.options();
+            let needs_transcode = repaired.is_some()
+                || would_encode(&info, &TranscodePlan::new(&info, selected_subtitle.clone(), transcoder.hw_accel(), options), options);
+
+            if needs_transcode {
+                if transcoder.options().verify_input && repaired.is_none() {
+                    if let Err(e) = verify_source(&info, transcoder) {
+                        let copy = if transcoder.options().repair {
+                            transcoder.repair(video_path).inspect_err(|e| eprintln!("   ❌ {}", e))
+                        } else {
+                            Err(e)
+                        };
+                        match copy {
+                            Ok(copy) => repaired = Some(copy),
+                            Err(e) => {
+                                transcoder.job_log().record_error(video_path, &e);
+                                return Prepared::Done(FileOutcome::Failed);
+                            }
+                        }
+                    }
+                }
+                let mut plan = transcoder.plan(&info, selected_subtitle);
+                plan.input = repaired;
+                if interactive {
+                    match confirm_plan(&info, plan.clone(), transcoder) {
+                        Some(confirmed) => plan = confirmed,
+                        None => {
+                            remove_repaired(&plan);
+                            status!("   ⏭️  Skipped");
+                            transcoder.job_log().record(video_path, "skip");
+                            return Prepared::Done(FileOutcome::Skipped);
+                        }
+                    }
+                }
+                if let Some(size) = plan.estimated_size(&info) {
+                    status!("   📏 Estimated output: ~{}", format_size(size));
+                }
+                if !resolve_existing_output(&mut plan, transcoder.options().if_exists) {
+                    remove_repaired(&plan);
+                    status!("   ⏭️  Output already exists, skipping");
+                    transcoder.job_log().record(video_path, "skip");
+                    return Prepared::Done(FileOutcome::Skipped);
+                }
+                if plan.video == VideoEncoder::Copy && plan.audio == AudioAction::Copy {
+                    status!("   📦 Streams are already compatible, remuxing into MP4...");
+                } else if plan.video == VideoEncoder::Copy {
+                    let codec = if plan.audio == AudioAction::Eac3 { "E-AC3" } else { "AAC" };
+                    status!("   🔊 Video is already compatible, converting only the audio to {}...", codec);
+                } else if plan.subtitle.is_some() && !plan.soft_subtitle {
+                    status!("   ⚙️  Transcoding to H.264/AAC with burned subtitles...");
+                } else {
+                    status!("   ⚙️  Transcoding to H.264/AAC...");
+                }
+                Prepared::Encode(Box::new(info), Box::new(plan))
             } else {
-                println!("   ✅ Transcode completed: H.264/AAC/MP4");
+                status!("   ✅ Already H.264/AAC Apple TV compatible, skipping");
+                transcoder.job_log().record(video_path, "skip");
+                Prepared::Done(FileOutcome::Skipped)
             }
         }
-        Ok(status) => {
+        Err(e) => {
             eprintln!(
-                "   ❌ Transcode failed with exit code: {:?}",
-                status.code()
+                "   ❌ Error analyzing {}: {}",
+                video_path.file_name().unwrap().to_string_lossy(),
+                e
             );
+            transcoder.job_log().record_error(video_path, &e);
+            Prepared::Done(FileOutcome::Failed)
+        }
+    }
+}
+
+/// `--verify-input`: decodes the source in full, reporting the errors if it
+/// is damaged. The error is the message for the log.
+fn verify_source(info: &VideoInfo, transcoder: &Transcoder) -> Result<(), String> {
+    status!("   🔍 Checking that the source decodes cleanly...");
+    match decode_errors(transcoder.runner(), &info.path) {
+        Ok(errors) if errors.is_empty() => Ok(()),
+        Ok(errors) => {
+            eprintln!("   ❌ The source is damaged: {} decode error(s)", errors.len());
+            for line in errors.iter().take(3) {
+                eprintln!("      {}", line);
+            }
+            Err(format!("damaged source: {}", errors[0]))
         }
         Err(e) => {
-            eprintln!("   ❌ Failed to run ffmpeg: {}", e);
+            eprintln!("   ❌ Cannot check the source: {}", e);
+            Err(e)
         }
     }
 }
 
-fn get_output_path(input_path: &Path, has_subtitles: bool) -> PathBuf {
-    let stem = input_path.file_stem().unwrap().to_string_lossy();
-    let parent = input_path.parent().unwrap();
-    if has_subtitles {
-        parent.join(format!("{}_appletv_subs.mp4", stem))
-    } else {
-        parent.join(format!("{}_appletv.mp4", stem))
+/// `--repair` for a source ffprobe cannot read: remuxes it and probes the
+/// copy. The info keeps the source's path, so outputs are named after it.
+fn probe_repaired(video_path: &Path, transcoder: &Transcoder) -> Result<(VideoInfo, PathBuf), String> {
+    let copy = transcoder.repair(video_path)?;
+    match transcoder.probe(&copy) {
+        Ok(info) => Ok((VideoInfo { path: video_path.to_path_buf(), ..info }, copy)),
+        Err(e) => {
+            let _ = std::fs::remove_file(&copy);
+            Err(e)
+        }
     }
 }
 
-fn detect_hardware_acceleration() -> Option<String> {
-    #[cfg(target_os = "macos")]
-    {
-        return Some("videotoolbox".to_string());
+/// Deletes the `--repair` copy of a source that is not going to be encoded.
+fn remove_repaired(plan: &TranscodePlan) {
+    if let Some(copy) = &plan.input {
+        let _ = std::fs::remove_file(copy);
     }
+}
 
-    #[cfg(target_os = "windows")]
-    {
-        if check_encoder_available("h264_nvenc") {
-            return Some("nvenc".to_string());
-        } else if check_encoder_available("h264_qsv") {
-            return Some("qsv".to_string());
+/// `--interactive`: shows `plan` and asks whether to encode it as it is,
+/// with other subtitles (planning again) or not at all (`None`).
+fn confirm_plan(info: &VideoInfo, mut plan: TranscodePlan, transcoder: &Transcoder) -> Option<TranscodePlan> {
+    loop {
+        print_plan(info, &plan);
+        print!("   ❓ Encode it? [Y]es, [s]ubtitles, [n]o: ");
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+        match input.trim() {
+            "" | "y" | "Y" | "yes" => return Some(plan),
+            "n" | "N" | "no" => return None,
+            "s" | "S" => {
+                let subtitles = subtitle_candidates(info, transcoder);
+                if subtitles.is_empty() {
+                    println!("   ⚠️  {} has no subtitle tracks", info.path.file_name().unwrap().to_string_lossy());
+                    continue;
+                }
+                let input = plan.input.take();
+                plan = transcoder.plan(info, prompt_subtitle_track(&subtitles));
+                plan.input = input;
+            }
+            _ => println!("   ⚠️  Answer y, s or n"),
         }
     }
+}
 
-    #[cfg(target_os = "linux")]
-    {
-        if check_encoder_available("h264_nvenc") {
-            return Some("nvenc".to_string());
-        } else if check_encoder_available("h264_vaapi") {
-            return Some("vaapi".to_string());
+/// The choices in `plan` that `--interactive` asks about.
+fn print_plan(info: &VideoInfo, plan: &TranscodePlan) {
+    let (width, height) = plan.output_size;
+    let video = match &plan.video {
+        VideoEncoder::Copy => format!("copy {}", info.video_codec),
+        VideoEncoder::Software => format!("H.264 with libx264, {}x{}", width, height),
+        VideoEncoder::Hardware(family) => format!("H.264 with {}, {}x{}", family, width, height),
+    };
+    let audio = match plan.audio {
+        AudioAction::Copy => format!("copy {}", info.audio_codec),
+        AudioAction::Aac => {
+            let layout = if plan.audio_channels == 1 { "mono" } else { "stereo" };
+            format!("{} to {} kbps {} AAC", info.audio_codec, plan.audio_bitrate_kbps, layout)
         }
+        AudioAction::Eac3 => format!("{} to {} kbps 5.1 E-AC3", info.audio_codec, plan.audio_bitrate_kbps),
+        AudioAction::Drop => "none".to_string(),
+    };
+    let subtitles = match &plan.subtitle {
+        Some(track) => format!(
+            "{} ({}), {}",
+            track.language.as_deref().unwrap_or("unknown"),
+            track.codec,
+            if plan.soft_subtitle { "selectable track" } else { "burned" }
+        ),
+        None => "none".to_string(),
+    };
+    println!("\n   📋 Plan:");
+    println!("      Video:     {}", video);
+    let filters: Vec<String> = plan
+        .denoise
+        .map(|denoise| denoise.filter().to_string())
+        .into_iter()
+        .chain(plan.sharpen.map(unsharp))
+        .chain(plan.filters.iter().cloned())
+        .collect();
+    if !filters.is_empty() {
+        println!("      Filters:   {}", filters.join(", "));
+    }
+    println!("      Audio:     {}", audio);
+    println!("      Subtitles: {}", subtitles);
+    match plan.estimated_size(info) {
+        Some(size) => println!(
+            "      Output:    {} (~{})",
+            plan.output.file_name().unwrap_or_default().to_string_lossy(),
+            format_size(size)
+        ),
+        None => println!("      Output:    {}", plan.output.file_name().unwrap_or_default().to_string_lossy()),
     }
+}
 
-    #[cfg(not(target_os = "macos"))]
-    {
-        None
+/// Applies the `--if-exists` policy to `plan.output`. Returns false if the
+/// file should be skipped.
+fn resolve_existing_output(plan: &mut TranscodePlan, policy: ExistingOutput) -> bool {
+    let existing = plan.first_output();
+    if !existing.exists() {
+        return true;
+    }
+    match policy {
+        ExistingOutput::Overwrite => true,
+        ExistingOutput::Skip => false,
+        ExistingOutput::Prompt => {
+            print!(
+                "   ❓ {} already exists. Overwrite? [y/N]: ",
+                existing.file_name().unwrap().to_string_lossy()
+            );
+            io::stdout().flush().unwrap();
+            let mut input = String::new();
+            io::stdin().read_line(&mut input).unwrap();
+            matches!(input.trim(), "y" | "Y" | "yes")
+        }
+        ExistingOutput::Rename => {
+            plan.output = unique_output_path(&plan.output);
+            true
+        }
     }
 }
 
-#[cfg(any(target_os = "windows", target_os = "linux"))]
-fn check_encoder_available(encoder: &str) -> bool {
-    let output = Command::new("ffmpeg")
-        .args(["-hide_banner", "-encoders"])
-        .output();
+/// Marks `info` as being encoded in `queue` and on the dashboard.
+fn record_start(queue: &RunQueue, info: &VideoInfo, plan: &TranscodePlan) {
+    queue.set(&info.path, FileStatus::Encoding, Some(&plan.output));
+    dashboard::file_started(&info.path, &plan.first_output(), plan.output_duration(info));
+}
 
-    if let Ok(output) = output {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        stdout.contains(encoder)
-    } else {
-        false
+/// Records how `path` finished in `queue`, on the dashboard and with the
+/// notifier. `encode` is the output and start time if it was encoded.
+fn record_outcome(
+    transcoder: &Transcoder,
+    queue: &RunQueue,
+    path: &Path,
+    outcome: FileOutcome,
+    encode: Option<(&Path, Instant)>,
+) {
+    // A file that failed because the run was stopped is left unfinished
+    if matches!(outcome, FileOutcome::Failed) && runtime::shutdown().is_cancelled() {
+        return;
     }
+    queue.set(path, outcome.queue_status(), None);
+    dashboard::file_finished(path, outcome.queue_status());
+    transcoder.notifier().file_finished(&FileReport {
+        path: path.to_path_buf(),
+        status: outcome.queue_status(),
+        elapsed: encode.map(|(_, started)| started.elapsed()),
+        output: encode.map(|(output, _)| output.to_path_buf()),
+    });
 }
 
-fn get_hw_encoding_args(hw_type: &str, width: u32, height: u32) -> Vec<String> {
-    match hw_type {
-        "videotoolbox" => vec![
-            "-c:v".to_string(),
-            "h264_videotoolbox".to_string(),
-            "-b:v".to_string(),
-            calculate_bitrate(width, height),
-            "-profile:v".to_string(),
-            "high".to_string(),
-            "-level".to_string(),
-            "4.1".to_string(),
-            "-allow_sw".to_string(),
-            "1".to_string(),
-        ],
-        "nvenc" => vec![
-            "-c:v".to_string(),
-            "h264_nvenc".to_string(),
-            "-preset".to_string(),
-            "p7".to_string(),
-            "-b:v".to_string(),
-            calculate_bitrate(width, height),
-            "-maxrate".to_string(),
-            calculate_max_bitrate(width, height),
-            "-profile:v".to_string(),
-            "high".to_string(),
-            "-level".to_string(),
-            "4.1".to_string(),
-        ],
-        "qsv" => vec![
-            "-c:v".to_string(),
-            "h264_qsv".to_string(),
-            "-preset".to_string(),
-            "veryslow".to_string(),
-            "-b:v".to_string(),
-            calculate_bitrate(width, height),
-            "-profile:v".to_string(),
-            "high".to_string(),
-            "-level".to_string(),
-            "4.1".to_string(),
-        ],
-        "vaapi" => vec![
-            "-vaapi_device".to_string(),
-            "/dev/dri/renderD128".to_string(),
-            "-c:v".to_string(),
-            "h264_vaapi".to_string(),
-            "-b:v".to_string(),
-            calculate_bitrate(width, height),
-            "-profile:v".to_string(),
-            "high".to_string(),
-        ],
-        _ => get_sw_encoding_args(),
-    }
-}
-
-fn get_sw_encoding_args() -> Vec<String> {
-    vec![
-        "-c:v".to_string(),
-        "libx264".to_string(),
-        "-preset".to_string(),
-        "medium".to_string(),
-        "-crf".to_string(),
-        "20".to_string(),
-        "-profile:v".to_string(),
-        "high".to_string(),
-        "-level".to_string(),
-        "4.1".to_string(),
-    ]
-}
-
-fn calculate_bitrate(width: u32, height: u32) -> String {
-    let pixels = width * height;
-    if pixels >= 3840 * 2160 {
-        "20M".to_string()
-    } else if pixels >= 1920 * 1080 {
-        "8M".to_string()
-    } else if pixels >= 1280 * 720 {
-        "5M".to_string()
-    } else {
-        "3M".to_string()
+fn encode_video(transcoder: &Transcoder, info: &VideoInfo, plan: &TranscodePlan, queue: &RunQueue) -> FileOutcome {
+    record_start(queue, info, plan);
+    let started = Instant::now();
+    let outcome = match transcoder.transcode(info, plan) {
+        Ok(()) => FileOutcome::Transcoded,
+        Err(e) => {
+            eprintln!("   ❌ {}", e);
+            FileOutcome::Failed
+        }
+    };
+    record_outcome(transcoder, queue, &info.path, outcome, Some((&plan.first_output(), started)));
+    if matches!(outcome, FileOutcome::Transcoded) {
+        transcoder.push_outputs(info, plan);
     }
+    outcome
 }
 
-fn calculate_max_bitrate(width: u32, height: u32) -> String {
-    let pixels = width * height;
-    if pixels >= 3840 * 2160 {
-        "30M".to_string()
-    } else if pixels >= 1920 * 1080 {
-        "12M".to_string()
-    } else if pixels >= 1280 * 720 {
-        "7M".to_string()
-    } else {
-        "4M".to_string()
+/// Like [`encode_video`], but on `host` via `tvcode worker`.
+fn encode_remote_video(
+    host: &str,
+    transcoder: &Transcoder,
+    info: &VideoInfo,
+    plan: &TranscodePlan,
+    queue: &RunQueue,
+) -> FileOutcome {
+    record_start(queue, info, plan);
+    let started = Instant::now();
+    let outcome = match remote::encode_remote(host, info, plan, transcoder.options()) {
+        Ok(()) => {
+            transcoder.job_log().record(&info.path, &format!("remote:{}", host));
+            status!("   ✅ Encoded on {}: {}", host, plan.output.file_name().unwrap().to_string_lossy());
+            FileOutcome::Transcoded
+        }
+        Err(e) => {
+            transcoder.job_log().record_error(&info.path, &e);
+            eprintln!("   ❌ {}", e);
+            FileOutcome::Failed
+        }
+    };
+    remove_repaired(plan);
+    record_outcome(transcoder, queue, &info.path, outcome, Some((&plan.first_output(), started)));
+    if matches!(outcome, FileOutcome::Transcoded) {
+        transcoder.push_outputs(info, plan);
     }
+    outcome
+}
+
+/// A subtitle menu answer kept for the remaining files of the run.
+enum RememberedSubtitle {
+    Skip,
+    /// The first track with this language and codec, or failing that this
+    /// language
+    Like(SubtitleTrack),
+}
+
+/// Set by answering the subtitle menu with an `a` suffix.
+static REMEMBERED_SUBTITLE: Mutex<Option<RememberedSubtitle>> = Mutex::new(None);
+
+/// The track in `subtitles` matching a remembered choice of `track`.
+fn matching_track<'a>(subtitles: &'a [SubtitleTrack], track: &SubtitleTrack) -> Option<&'a SubtitleTrack> {
+    let language = track.language.as_deref().map(normalize_code);
+    let same_language =
+        |sub: &&SubtitleTrack| sub.language.as_deref().map(normalize_code) == language && sub.forced == track.forced;
+    subtitles
+        .iter()
+        .filter(same_language)
+        .find(|sub| sub.codec == track.codec)
+        .or_else(|| subtitles.iter().find(same_language))
+}
+
+fn select_subtitle_track(subtitles: &[SubtitleTrack]) -> Option<SubtitleTrack> {
+    if subtitles.is_empty() {
+        return None;
+    }
+
+    match &*REMEMBERED_SUBTITLE.lock().unwrap() {
+        Some(RememberedSubtitle::Skip) => return None,
+        Some(RememberedSubtitle::Like(track)) => match matching_track(subtitles, track) {
+            Some(sub) => {
+                status!(
+                    "   📝 Using the {} ({}) track, as for the previous files",
+                    sub.language.as_deref().unwrap_or("unknown"),
+                    sub.codec
+                );
+                return Some(sub.clone());
+            }
+            None => println!(
+                "   ⚠️  No {} track like the one chosen before",
+                track.language.as_deref().unwrap_or("unknown")
+            ),
+        },
+        None => {}
+    }
+    prompt_subtitle_track(subtitles)
+}
+
+/// Shows the subtitle menu for `subtitles` and reads the choice.
+fn prompt_subtitle_track(subtitles: &[SubtitleTrack]) -> Option<SubtitleTrack> {
+    println!("\n   📝 Available subtitle tracks:");
+    for (idx, sub) in subtitles.iter().enumerate() {
+        let lang = sub.language.as_deref().unwrap_or("unknown");
+        let title = sub.title.as_deref().unwrap_or("");
+        let title_str = if !title.is_empty() {
+            format!(" - {}", title)
+        } else {
+            String::new()
+        };
+        let sub_type = match (sub.is_bitmap, sub.forced) {
+            (true, false) => "bitmap",
+            (true, true) => "bitmap, forced",
+            (false, false) => "text",
+            (false, true) => "text, forced",
+        };
+        let detected = if sub.language_detected { ", language guessed" } else { "" };
+        let source = sub
+            .source
+            .as_ref()
+            .map(|path| format!(" [{}]", path.file_name().unwrap_or_default().to_string_lossy()))
+            .unwrap_or_default();
+        println!(
+            "      [{}] {} ({}, {}{}){}{}",
+            idx + 1,
+            lang,
+            sub.codec,
+            sub_type,
+            detected,
+            title_str,
+            source
+        );
+    }
+    println!("      [0] Skip subtitle burning");
+    println!("      Add \"a\" (e.g. 1a) to make the same choice for all remaining files");
+
+    print!("\n   Select subtitle track [0-{}]: ", subtitles.len());
+    io::stdout().flush().unwrap();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+
+    let input = input.trim();
+    let (input, for_all) = match input.strip_suffix(['a', 'A']) {
+        Some(choice) => (choice.trim(), true),
+        None => (input, false),
+    };
+    if let Ok(choice) = input.parse::<usize>() {
+        if choice == 0 {
+            if for_all {
+                *REMEMBERED_SUBTITLE.lock().unwrap() = Some(RememberedSubtitle::Skip);
+            }
+            return None;
+        }
+        if choice > 0 && choice <= subtitles.len() {
+            let track = subtitles[choice - 1].clone();
+            if for_all {
+                *REMEMBERED_SUBTITLE.lock().unwrap() = Some(RememberedSubtitle::Like(track.clone()));
+            }
+            return Some(track);
+        }
+    }
+
+    println!("   ⚠️  Invalid selection, skipping subtitle burning");
+    None
 }
@@ -0,0 +1,107 @@
+//! `--join-parts`: films split over several files (`Movie CD1.avi`,
+//! `Movie CD2.avi`, `Movie.part1.mkv`...) encoded as one output.
+//!
+//! The parts are joined without re-encoding through ffmpeg's concat demuxer
+//! into one file beside them, named without the part marker, which is then
+//! converted like any other file. That only works when every part was
+//! encoded the same way, so they are compared first.
+
+use crate::probe::VideoInfo;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Words (lowercase) that number the parts of a film.
+const PART_MARKERS: &[&str] = &["part", "disc", "disk", "cd", "pt"];
+
+/// Characters that separate the words of a release name.
+fn is_separator(c: u8) -> bool {
+    matches!(c, b' ' | b'.' | b'-' | b'_' | b'(' | b'[')
+}
+
+/// The part number in the name of `path` and the name without it, e.g.
+/// `(2, "Movie (1999)")` for `Movie (1999) CD2.avi`.
+pub fn part_number(path: &Path) -> Option<(u32, String)> {
+    let stem = path.file_stem()?.to_str()?;
+    let lower = stem.to_ascii_lowercase();
+    let bytes = lower.as_bytes();
+    let mut found = None;
+    for start in (0..bytes.len()).filter(|&i| i == 0 || is_separator(bytes[i - 1])) {
+        let Some(marker) = PART_MARKERS.iter().find(|marker| lower[start..].starts_with(*marker)) else {
+            continue;
+        };
+        let mut end = start + marker.len();
+        if end < bytes.len() && matches!(bytes[end], b' ' | b'.' | b'-' | b'_') {
+            end += 1;
+        }
+        let digits = bytes[end..].iter().take_while(|c| c.is_ascii_digit()).count();
+        // `cd1`, not `cd10000` or `partial`
+        if !(1..=2).contains(&digits) || bytes.get(end + digits).is_some_and(|c| c.is_ascii_alphanumeric()) {
+            continue;
+        }
+        let number = lower[end..end + digits].parse().ok()?;
+        // The separator before the marker goes with it
+        let cut = if start > 0 && bytes[start - 1] != b'(' && bytes[start - 1] != b'[' { start - 1 } else { start };
+        found = Some((number, cut, end + digits));
+    }
+    let (number, cut, end) = found?;
+    let mut name = format!("{}{}", &stem[..cut], &stem[end..]);
+    if stem[..cut].ends_with(['(', '[']) && name[cut..].starts_with([')', ']']) {
+        // `Movie (CD1)` leaves empty brackets behind
+        name.replace_range(cut - 1..cut + 1, "");
+    }
+    let name = name.trim_end_matches([' ', '.', '-', '_']).to_string();
+    (!name.is_empty()).then_some((number, name))
+}
+
+/// The parts of multi-part films among `files`, each group in part order:
+/// files in one folder with the same name and extension apart from a part
+/// marker, numbered 1, 2... without gaps.
+pub fn find_part_groups(files: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let mut groups: BTreeMap<(PathBuf, String), Vec<(u32, PathBuf)>> = BTreeMap::new();
+    for path in files {
+        let Some((number, name)) = part_number(path) else {
+            continue;
+        };
+        let extension = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        groups.entry((dir, format!("{}.{}", name, extension))).or_default().push((number, path.clone()));
+    }
+    groups
+        .into_values()
+        .filter_map(|mut parts| {
+            parts.sort();
+            let numbered = parts.iter().enumerate().all(|(i, (number, _))| *number == i as u32 + 1);
+            (parts.len() > 1 && numbered).then(|| parts.into_iter().map(|(_, path)| path).collect())
+        })
+        .collect()
+}
+
+/// Where the parts in `parts` are joined: beside them, named without the
+/// part marker, e.g. `Movie (1999).avi`.
+pub fn joined_path(parts: &[PathBuf]) -> PathBuf {
+    let first = &parts[0];
+    let (_, name) = part_number(first).expect("parts are named with a part number");
+    let extension = first.extension().unwrap_or_default().to_string_lossy();
+    first.with_file_name(format!("{}.{}", name, extension))
+}
+
+/// Why the parts probed as `infos` cannot be joined without re-encoding, if
+/// they differ in codecs, picture size or audio tracks.
+pub fn parts_mismatch(infos: &[VideoInfo]) -> Option<String> {
+    let first = infos.first()?;
+    infos.iter().skip(1).find_map(|info| {
+        let name = info.path.file_name().unwrap_or_default().to_string_lossy();
+        if info.video_codec != first.video_codec || info.audio_codec != first.audio_codec {
+            Some(format!(
+                "{} is {}/{}, not {}/{}",
+                name, info.video_codec, info.audio_codec, first.video_codec, first.audio_codec
+            ))
+        } else if (info.width, info.height) != (first.width, first.height) {
+            Some(format!("{} is {}x{}, not {}x{}", name, info.width, info.height, first.width, first.height))
+        } else if info.audio_tracks.len() != first.audio_tracks.len() {
+            Some(format!("{} has {} audio tracks, not {}", name, info.audio_tracks.len(), first.audio_tracks.len()))
+        } else {
+            None
+        }
+    })
+}
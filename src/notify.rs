@@ -0,0 +1,585 @@
+//! Notifications when a file finishes and when a batch completes.
+//!
+//! Webhooks are posted with `curl`: a generic JSON body by default, or the
+//! `{"content": ...}` / `{"text": ...}` messages Discord and Slack expect.
+//! They are delivered in order in the background on the [`crate::runtime`],
+//! so a slow endpoint never holds up the next file.
+//! The format is picked from the URL unless the config file names one.
+//! Desktop notifications use the platform's own tool: `osascript` on macOS,
+//! `notify-send` (libnotify) on Linux and a PowerShell toast on Windows.
+//! The `[email]` config table mails a summary of each `convert` run through
+//! an SMTP server, again with `curl`. Media servers are asked to scan the
+//! folders that received outputs (see [`crate::library`]), and
+//! `--report-file` gets every file's result (see [`crate::report`]).
+//! Delivery failures are only reported; they never affect a transcode.
+
+use crate::joblog::rfc5322_date;
+use crate::library::MediaServers;
+use crate::output::{format_duration, format_size};
+use crate::queue::FileStatus;
+use crate::report::{write_report, RunRow};
+use crate::runtime;
+use crate::status;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt as _;
+
+/// Seconds allowed for delivering one webhook.
+const WEBHOOK_TIMEOUT_SECS: u32 = 30;
+
+/// Seconds allowed for sending one email.
+const EMAIL_TIMEOUT_SECS: u32 = 60;
+
+/// Mails sent so far, to keep each message's temporary file apart.
+static MAILS_SENT: AtomicUsize = AtomicUsize::new(0);
+
+/// Body format of a webhook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The event as a JSON object
+    Json,
+    /// A Discord message
+    Discord,
+    /// A Slack message
+    Slack,
+}
+
+/// A `[[webhooks]]` entry of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    /// Detected from the URL when omitted
+    pub format: Option<WebhookFormat>,
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Webhook {
+        Webhook {
+            url: url.to_string(),
+            format: None,
+        }
+    }
+
+    fn format(&self) -> WebhookFormat {
+        self.format.unwrap_or(if self.url.contains("discord.com/api/webhooks") {
+            WebhookFormat::Discord
+        } else if self.url.contains("hooks.slack.com") {
+            WebhookFormat::Slack
+        } else {
+            WebhookFormat::Json
+        })
+    }
+}
+
+/// The `[email]` table of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailSettings {
+    /// `smtps://host:465`, or `smtp://host:587` (upgraded with STARTTLS)
+    pub smtp: String,
+    pub from: String,
+    pub to: Vec<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Require TLS (the default); `false` allows a plain local relay
+    pub tls: Option<bool>,
+}
+
+/// How one file ended.
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    /// Time spent encoding, if it got that far
+    pub elapsed: Option<Duration>,
+    /// Written output, for transcoded files
+    pub output: Option<PathBuf>,
+}
+
+/// Totals of a finished `convert` run, or of one pass of `watch`.
+#[derive(Debug, Clone)]
+pub struct BatchReport {
+    pub transcoded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub elapsed: Duration,
+}
+
+/// Sends notifications to every configured destination. Safe to share
+/// between parallel jobs.
+#[derive(Debug, Default)]
+pub struct Notifier {
+    webhooks: Vec<Webhook>,
+    desktop: bool,
+    email: Option<EmailSettings>,
+    library: MediaServers,
+    /// CSV or JSON file to write the per-file results to at the end of a batch
+    report_file: Option<PathBuf>,
+    /// Files reported since the last batch summary
+    finished: Mutex<Vec<FinishedFile>>,
+    /// Folders with outputs the media servers have not been told about yet
+    new_dirs: Mutex<Vec<PathBuf>>,
+}
+
+#[derive(Debug, Clone)]
+struct FinishedFile {
+    name: String,
+    path: PathBuf,
+    output: Option<PathBuf>,
+    status: FileStatus,
+    elapsed_secs: Option<u64>,
+    /// Source and output size of a transcoded file
+    sizes: Option<(u64, u64)>,
+}
+
+impl Notifier {
+    pub fn new(webhooks: Vec<Webhook>) -> Notifier {
+        Notifier {
+            webhooks,
+            desktop: false,
+            email: None,
+            library: MediaServers::default(),
+            report_file: None,
+            finished: Mutex::new(Vec::new()),
+            new_dirs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Also shows desktop notifications when `desktop` is set.
+    pub fn with_desktop(mut self, desktop: bool) -> Notifier {
+        self.desktop = desktop;
+        self
+    }
+
+    /// Also mails a summary of each batch.
+    pub fn with_email(mut self, email: Option<EmailSettings>) -> Notifier {
+        self.email = email;
+        self
+    }
+
+    /// Also has Plex and Jellyfin scan for new outputs.
+    pub fn with_library(mut self, library: MediaServers) -> Notifier {
+        self.library = library;
+        self
+    }
+
+    /// Also writes every file's result to `path` (CSV or JSON, by extension).
+    pub fn with_report_file(mut self, path: Option<PathBuf>) -> Notifier {
+        self.report_file = path;
+        self
+    }
+
+    fn is_empty(&self) -> bool {
+        self.webhooks.is_empty()
+            && !self.desktop
+            && self.email.is_none()
+            && self.library.is_empty()
+            && self.report_file.is_none()
+    }
+
+    /// Asks the media servers to scan the folders that received outputs
+    /// since the last call. Called at the end of a batch, and after each
+    /// file by `watch`.
+    pub fn refresh_libraries(&self) {
+        let dirs = std::mem::take(&mut *self.new_dirs.lock().unwrap_or_else(|e| e.into_inner()));
+        self.library.refresh(&dirs);
+    }
+
+    /// Reports a transcoded or failed file; skipped files are only counted.
+    pub fn file_finished(&self, report: &FileReport) {
+        if self.is_empty() {
+            return;
+        }
+        let name = file_name(&report.path);
+        let elapsed = report.elapsed.map(|elapsed| elapsed.as_secs());
+        let sizes = match (&report.output, report.status) {
+            (Some(output), FileStatus::Done) => Some((file_size(&report.path), file_size(output))),
+            _ => None,
+        };
+        self.finished.lock().unwrap_or_else(|e| e.into_inner()).push(FinishedFile {
+            name: name.clone(),
+            path: report.path.clone(),
+            output: report.output.clone().filter(|_| report.status == FileStatus::Done),
+            status: report.status,
+            elapsed_secs: elapsed,
+            sizes,
+        });
+        if let (Some(output), FileStatus::Done) = (&report.output, report.status) {
+            let dir = output.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+            let mut new_dirs = self.new_dirs.lock().unwrap_or_else(|e| e.into_inner());
+            if !new_dirs.contains(&dir) {
+                new_dirs.push(dir);
+            }
+        }
+        if report.status == FileStatus::Skipped {
+            return;
+        }
+
+        let message = match (report.status, sizes) {
+            (FileStatus::Done, Some((input, output))) => format!(
+                "✅ {} transcoded{}: {} → {} ({})",
+                name,
+                elapsed.map_or_else(String::new, |secs| format!(" in {}", format_duration(secs))),
+                format_size(input),
+                format_size(output),
+                describe_savings(input, output)
+            ),
+            (FileStatus::Done, None) => format!("✅ {} transcoded", name),
+            _ => format!(
+                "❌ {} failed{}",
+                name,
+                elapsed.map_or_else(String::new, |secs| format!(" after {}", format_duration(secs)))
+            ),
+        };
+        let event = json!({
+            "event": "file",
+            "file": name,
+            "path": report.path,
+            "result": report.status,
+            "duration_secs": elapsed,
+            "input_bytes": sizes.map(|(input, _)| input),
+            "output_bytes": sizes.map(|(_, output)| output),
+            "saved_bytes": sizes.map(|(input, output)| input as i64 - output as i64),
+        });
+        self.send(&event, &message);
+    }
+
+    /// Reports the end of a `convert` run, or of a `watch` pass that
+    /// converted something, with the savings of every file reported since
+    /// the last batch.
+    pub fn batch_finished(&self, report: &BatchReport) {
+        if self.is_empty() {
+            return;
+        }
+        self.refresh_libraries();
+        let finished = std::mem::take(&mut *self.finished.lock().unwrap_or_else(|e| e.into_inner()));
+        if let Some(path) = &self.report_file {
+            let rows: Vec<RunRow> = finished
+                .iter()
+                .map(|file| RunRow {
+                    file: file.path.clone(),
+                    result: file.status,
+                    output: file.output.clone(),
+                    input_size: file.sizes.map(|(input, _)| input),
+                    output_size: file.sizes.map(|(_, output)| output),
+                    elapsed_secs: file.elapsed_secs,
+                })
+                .collect();
+            match write_report(path, &rows) {
+                Ok(()) => status!("📝 Wrote the run report to {}", path.display()),
+                Err(e) => eprintln!("   ⚠️  {}", e),
+            }
+        }
+        let (input, output) = finished
+            .iter()
+            .filter_map(|file| file.sizes)
+            .fold((0, 0), |(input, output), sizes| (input + sizes.0, output + sizes.1));
+        let message = format!(
+            "{} Batch finished in {}: {} transcoded, {} skipped, {} failed{}",
+            if report.failed > 0 { "⚠️" } else { "📺" },
+            format_duration(report.elapsed.as_secs()),
+            report.transcoded,
+            report.skipped,
+            report.failed,
+            if report.transcoded > 0 { format!(" ({})", describe_savings(input, output)) } else { String::new() }
+        );
+        let event = json!({
+            "event": "batch",
+            "transcoded": report.transcoded,
+            "skipped": report.skipped,
+            "failed": report.failed,
+            "duration_secs": report.elapsed.as_secs(),
+            "input_bytes": input,
+            "output_bytes": output,
+            "saved_bytes": input as i64 - output as i64,
+        });
+        self.send(&event, &message);
+
+        if let Some(email) = &self.email {
+            let subject = format!(
+                "tvcode: {} transcoded, {} skipped, {} failed",
+                report.transcoded, report.skipped, report.failed
+            );
+            let mut body = format!("{}\r\n\r\n", message);
+            for file in &finished {
+                let mut line = format!("{:<8} {}", format!("{:?}", file.status), file.name);
+                if let Some((input, output)) = file.sizes {
+                    line.push_str(&format!("  {} -> {}", format_size(input), format_size(output)));
+                }
+                if let Some(secs) = file.elapsed_secs {
+                    line.push_str(&format!("  {}", format_duration(secs)));
+                }
+                body.push_str(&line);
+                body.push_str("\r\n");
+            }
+            if let Err(e) = send_email(email, &subject, &body) {
+                eprintln!("   ⚠️  Email to {} failed: {}", email.to.join(", "), e);
+            }
+        }
+    }
+
+    fn send(&self, event: &serde_json::Value, message: &str) {
+        for webhook in &self.webhooks {
+            let body = match webhook.format() {
+                WebhookFormat::Json => event.clone(),
+                WebhookFormat::Discord => json!({ "content": message }),
+                WebhookFormat::Slack => json!({ "text": message }),
+            };
+            let url = webhook.url.clone();
+            runtime::spawn_background(async move {
+                if let Err(e) = post_json(&url, &body.to_string()).await {
+                    eprintln!("   ⚠️  Webhook {} failed: {}", url, e);
+                }
+            });
+        }
+        if self.desktop {
+            if let Err(e) = show_desktop_notification(message) {
+                eprintln!("   ⚠️  Desktop notification failed: {}", e);
+            }
+        }
+    }
+}
+
+/// `saved 3.10 GB, 74%`, or `grew by ...` when the output is larger.
+fn describe_savings(input: u64, output: u64) -> String {
+    if output <= input {
+        let percent = ((input - output) * 100).checked_div(input).unwrap_or(0);
+        format!("saved {}, {}%", format_size(input - output), percent)
+    } else {
+        format!("grew by {}", format_size(output - input))
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned())
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map_or(0, |m| m.len())
+}
+
+async fn post_json(url: &str, body: &str) -> Result<(), String> {
+    let timeout = WEBHOOK_TIMEOUT_SECS.to_string();
+    let mut child = tokio::process::Command::new("curl")
+        .args(["-sS", "-f", "-m", &timeout, "-H", "Content-Type: application/json", "--data-binary", "@-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run curl: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(body.as_bytes()).await.map_err(|e| e.to_string())?;
+    }
+    let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// `text` as a double-quoted value for a curl `-K` config.
+pub(crate) fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Runs `curl -sS` with `args`, feeding it `input` on stdin, and returns
+/// what it printed.
+pub(crate) fn curl(args: &[&str], input: &str) -> Result<String, String> {
+    let mut child = Command::new("curl")
+        .arg("-sS")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run curl: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes()).map_err(|e| e.to_string())?;
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Shows `message` as a desktop notification titled "tvcode". The message is
+/// passed as an argument or environment variable, never spliced into a script.
+fn show_desktop_notification(message: &str) -> Result<(), String> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.args([
+            "-e",
+            "on run argv",
+            "-e",
+            "display notification (item 1 of argv) with title \"tvcode\"",
+            "-e",
+            "end run",
+            message,
+        ]);
+        command
+    } else if cfg!(windows) {
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-NonInteractive", "-Command", WINDOWS_TOAST]);
+        command.env("TVCODE_MESSAGE", message);
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args(["--app-name=tvcode", "tvcode", message]);
+        command
+    };
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("cannot run {}: {}", command.get_program().to_string_lossy(), e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Shows `$env:TVCODE_MESSAGE` as a toast, under PowerShell's own app id so
+/// no registration is needed.
+const WINDOWS_TOAST: &str = r#"
+[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] > $null
+$xml = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02)
+$text = $xml.GetElementsByTagName('text')
+$text[0].AppendChild($xml.CreateTextNode('tvcode')) > $null
+$text[1].AppendChild($xml.CreateTextNode($env:TVCODE_MESSAGE)) > $null
+$app = '{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\WindowsPowerShell\v1.0\powershell.exe'
+[Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier($app).Show([Windows.UI.Notifications.ToastNotification]::new($xml))
+"#;
+
+/// Mails `body` through `email.smtp` with curl. The message and the
+/// credentials go through a temporary file and stdin, not the command line.
+fn send_email(email: &EmailSettings, subject: &str, body: &str) -> Result<(), String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    // Unique across processes and across mails sent by this one
+    let id = format!(
+        "{}.{}.{}",
+        now.as_nanos(),
+        std::process::id(),
+        MAILS_SENT.fetch_add(1, Ordering::Relaxed)
+    );
+    let domain = email.from.rsplit_once('@').map_or("localhost", |(_, domain)| domain.trim_end_matches('>'));
+    let message = format!(
+        "From: {}\r\nTo: {}\r\nDate: {}\r\nMessage-ID: <tvcode.{}@{}>\r\nSubject: {}\r\n\
+         MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}",
+        email.from,
+        email.to.join(", "),
+        rfc5322_date(now.as_secs()),
+        id,
+        domain,
+        encode_header(subject),
+        body
+    );
+    let file = std::env::temp_dir().join(format!("tvcode-mail-{}.txt", id));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&file)
+        .and_then(|mut out| out.write_all(message.as_bytes()))
+        .map_err(|e| format!("cannot write {}: {}", file.display(), e))?;
+
+    // curl greets with the URL's path (or else the uploaded file's name)
+    let mut url = email.smtp.trim_end_matches('/').to_string();
+    if !url.split_once("://").map_or(&*url, |(_, rest)| rest).contains('/') {
+        url.push_str("/localhost");
+    }
+    let mut config = format!(
+        "url = {}\nmail-from = {}\nupload-file = {}\n",
+        quote(&url),
+        quote(&email.from),
+        quote(&file.to_string_lossy())
+    );
+    if email.tls.unwrap_or(true) {
+        config.push_str("ssl-reqd\n");
+    }
+    for to in &email.to {
+        config.push_str(&format!("mail-rcpt = {}\n", quote(to)));
+    }
+    if let Some(username) = &email.username {
+        let password = email.password.as_deref().unwrap_or("");
+        config.push_str(&format!("user = {}\n", quote(&format!("{}:{}", username, password))));
+    }
+
+    let result = curl(&["-m", &EMAIL_TIMEOUT_SECS.to_string(), "-K", "-"], &config).map(drop);
+    let _ = std::fs::remove_file(&file);
+    result
+}
+
+/// `text` as a header value: as-is when it is ASCII, otherwise as RFC 2047
+/// base64 encoded-words, folded so each stays within 75 characters.
+fn encode_header(text: &str) -> String {
+    if text.is_ascii() {
+        return text.to_string();
+    }
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in text.chars() {
+        // 45 bytes of text make 60 base64 characters, 72 with the wrapper
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(format!("=?UTF-8?B?{}?=", base64(chunk.as_bytes())));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    words.push(format!("=?UTF-8?B?{}?=", base64(chunk.as_bytes())));
+    words.join("\r\n ")
+}
+
+/// Standard base64 with padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let n = group.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_to_whole_groups() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn ascii_subjects_are_left_alone() {
+        assert_eq!(encode_header("tvcode: 2 transcoded"), "tvcode: 2 transcoded");
+    }
+
+    #[test]
+    fn other_subjects_become_encoded_words() {
+        assert_eq!(encode_header("Café"), "=?UTF-8?B?Q2Fmw6k=?=");
+        let long = encode_header(&"é".repeat(40));
+        let words: Vec<&str> = long.split("\r\n ").collect();
+        assert_eq!(words.len(), 2);
+        assert!(words.iter().all(|word| word.len() <= 75 && word.starts_with("=?UTF-8?B?") && word.ends_with("?=")));
+    }
+}
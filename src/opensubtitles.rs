@@ -0,0 +1,145 @@
+//! `--download-subs`: fetching subtitles for files that have none from
+//! OpenSubtitles.
+//!
+//! Configured with an `[opensubtitles]` table in the config file holding an
+//! API key (and, for more than a handful of downloads a day, an account).
+//! Files are matched by the OpenSubtitles hash of their contents, falling
+//! back to a search by file name. The subtitle is saved beside the video as
+//! `movie.<lang>.srt`, where the subtitle menu finds it. Requests go through
+//! `curl`.
+
+use crate::notify::{curl, quote};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const API_URL: &str = "https://api.opensubtitles.com/api/v1";
+
+/// Seconds allowed for one API request.
+const REQUEST_TIMEOUT_SECS: &str = "30";
+
+/// The `[opensubtitles]` table of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OpenSubtitlesSettings {
+    /// A consumer API key from opensubtitles.com
+    pub api_key: String,
+    /// Account to log in with; anonymous downloads are limited
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// The OpenSubtitles hash of `path`: its size plus the sums of the first and
+/// last 64 KiB as little-endian 64-bit words, wrapping.
+pub fn movie_hash(path: &Path) -> io::Result<u64> {
+    const CHUNK: u64 = 64 * 1024;
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut hash = size;
+    let mut sum_chunk = |file: &mut File, offset: u64| -> io::Result<()> {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buffer = Vec::with_capacity(CHUNK as usize);
+        file.take(CHUNK).read_to_end(&mut buffer)?;
+        for word in buffer.chunks(8) {
+            let mut bytes = [0u8; 8];
+            bytes[..word.len()].copy_from_slice(word);
+            hash = hash.wrapping_add(u64::from_le_bytes(bytes));
+        }
+        Ok(())
+    };
+    sum_chunk(&mut file, 0)?;
+    sum_chunk(&mut file, size.saturating_sub(CHUNK))?;
+    Ok(hash)
+}
+
+/// Where the subtitle for `video_path` in `language` is saved.
+pub fn sidecar_path(video_path: &Path, language: &str) -> PathBuf {
+    let stem = video_path.file_stem().unwrap_or_default().to_string_lossy();
+    video_path.with_file_name(format!("{}.{}.srt", stem, language))
+}
+
+/// Downloads the best-matching subtitle in `language` (e.g. `en`) for
+/// `video_path` to [`sidecar_path`]. Returns the saved file.
+pub fn download(settings: &OpenSubtitlesSettings, video_path: &Path, language: &str) -> Result<PathBuf, String> {
+    let hash = movie_hash(video_path).map_err(|e| format!("cannot read {}: {}", video_path.display(), e))?;
+    let stem = video_path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_id = match search(settings, &[("moviehash", &format!("{:016x}", hash)), ("languages", language)])? {
+        Some(id) => id,
+        None => search(settings, &[("query", &stem), ("languages", language)])?
+            .ok_or_else(|| format!("no {} subtitles found", language))?,
+    };
+
+    let token = match (&settings.username, &settings.password) {
+        (Some(username), Some(password)) => {
+            let body = json!({ "username": username, "password": password }).to_string();
+            let response = parse(&request(settings, "/login", &[], None, Some(&body))?)?;
+            Some(response["token"].as_str().ok_or("login failed")?.to_string())
+        }
+        _ => None,
+    };
+    let body = json!({ "file_id": file_id }).to_string();
+    let response = parse(&request(settings, "/download", &[], token.as_deref(), Some(&body))?)?;
+    let link = response["link"]
+        .as_str()
+        .ok_or_else(|| response["message"].as_str().unwrap_or("no download link").to_string())?;
+
+    let path = sidecar_path(video_path, language);
+    let config = format!("url = {}\noutput = {}\n", quote(link), quote(&path.to_string_lossy()));
+    curl(&["-f", "-L", "-m", REQUEST_TIMEOUT_SECS, "-K", "-"], &config)?;
+    Ok(path)
+}
+
+/// The file id of the best result for `query`: a hash match if there is
+/// one, then the most downloaded.
+fn search(settings: &OpenSubtitlesSettings, query: &[(&str, &str)]) -> Result<Option<u64>, String> {
+    let response = parse(&request(settings, "/subtitles", query, None, None)?)?;
+    let best = response["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|result| &result["attributes"])
+        .filter(|attributes| attributes["files"][0]["file_id"].is_u64())
+        .max_by_key(|attributes| {
+            (
+                attributes["moviehash_match"].as_bool().unwrap_or(false),
+                attributes["download_count"].as_u64().unwrap_or(0),
+            )
+        });
+    Ok(best.and_then(|attributes| attributes["files"][0]["file_id"].as_u64()))
+}
+
+/// Sends a request to the API, with the key (and `token`) going to curl on
+/// stdin rather than the command line. `query` is URL-encoded onto a GET;
+/// `json` makes it a POST with that body.
+fn request(
+    settings: &OpenSubtitlesSettings,
+    path: &str,
+    query: &[(&str, &str)],
+    token: Option<&str>,
+    json: Option<&str>,
+) -> Result<String, String> {
+    let mut config = format!("url = {}\n", quote(&format!("{}{}", API_URL, path)));
+    config.push_str(&format!("header = {}\n", quote(&format!("Api-Key: {}", settings.api_key))));
+    config.push_str(&format!("user-agent = {}\n", quote(&format!("tvcode v{}", env!("CARGO_PKG_VERSION")))));
+    config.push_str("header = \"Accept: application/json\"\n");
+    if let Some(token) = token {
+        config.push_str(&format!("header = {}\n", quote(&format!("Authorization: Bearer {}", token))));
+    }
+    if !query.is_empty() {
+        config.push_str("get\n");
+        for (name, value) in query {
+            config.push_str(&format!("data-urlencode = {}\n", quote(&format!("{}={}", name, value))));
+        }
+    }
+    if let Some(json) = json {
+        config.push_str("header = \"Content-Type: application/json\"\n");
+        config.push_str(&format!("data-binary = {}\n", quote(json)));
+    }
+    curl(&["-f", "-m", REQUEST_TIMEOUT_SECS, "-K", "-"], &config)
+}
+
+fn parse(response: &str) -> Result<Value, String> {
+    serde_json::from_str(response).map_err(|e| format!("unexpected response from OpenSubtitles: {}", e))
+}
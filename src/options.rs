@@ -0,0 +1,440 @@
+//! Encoding options chosen on the command line, shared by every file in a run.
+
+use crate::device::{Device, DeviceCaps};
+use crate::power::PowerPolicy;
+use crate::preset::{QualityPreset, Tune};
+use crate::quality::QualityMetric;
+use crate::subtitles::SubtitleStyle;
+use crate::thumbnails::Thumbnails;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// What to do when a file's output already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingOutput {
+    /// Replace it (ffmpeg `-y`)
+    #[default]
+    Overwrite,
+    /// Leave it alone and skip the file
+    Skip,
+    /// Ask on the terminal for each file
+    Prompt,
+    /// Write to a new, numbered name next to it
+    Rename,
+}
+
+/// How audio streams are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioPolicy {
+    /// Copy audio the device plays, convert everything else to AAC
+    #[default]
+    Auto,
+    /// Always re-encode to AAC
+    Aac,
+}
+
+/// Where a `--sample` window starts.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleStart {
+    #[default]
+    Beginning,
+    /// Centered on the middle of the file
+    Middle,
+    /// At this many seconds in
+    At(f64),
+}
+
+/// Parses `--sample-from`: `start`, `middle` or a number of seconds.
+pub fn parse_sample_start(text: &str) -> Result<SampleStart, String> {
+    match text {
+        "start" => Ok(SampleStart::Beginning),
+        "middle" => Ok(SampleStart::Middle),
+        secs => secs
+            .parse::<f64>()
+            .ok()
+            .filter(|s| *s >= 0.0)
+            .map(SampleStart::At)
+            .ok_or_else(|| format!("expected 'start', 'middle' or seconds, got '{}'", text)),
+    }
+}
+
+/// `--denoise` strength, for grainy camcorder tapes and VHS rips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Denoise {
+    /// Fine grain only; keeps detail (hqdn3d)
+    Light,
+    /// Tape noise and chroma crawl (stronger hqdn3d)
+    Medium,
+    /// Heavy noise, at a fraction of the encoding speed (nlmeans)
+    Heavy,
+}
+
+impl Denoise {
+    /// The ffmpeg filter, run at the source resolution.
+    pub fn filter(self) -> &'static str {
+        match self {
+            Denoise::Light => "hqdn3d=2:1.5:3:2.25",
+            Denoise::Medium => "hqdn3d=4:3:6:4.5",
+            Denoise::Heavy => "nlmeans=s=3:p=7:r=15",
+        }
+    }
+}
+
+/// Where a `--watermark` sits on the picture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+impl WatermarkPosition {
+    const NAMES: [(&'static str, WatermarkPosition); 5] = [
+        ("top-left", WatermarkPosition::TopLeft),
+        ("top-right", WatermarkPosition::TopRight),
+        ("bottom-left", WatermarkPosition::BottomLeft),
+        ("bottom-right", WatermarkPosition::BottomRight),
+        ("center", WatermarkPosition::Center),
+    ];
+
+    /// `overlay` filter coordinates for an image this far in from the edges.
+    pub fn overlay_position(self, margin: u32) -> String {
+        let x = match self {
+            WatermarkPosition::TopLeft | WatermarkPosition::BottomLeft => margin.to_string(),
+            WatermarkPosition::TopRight | WatermarkPosition::BottomRight => format!("W-w-{}", margin),
+            WatermarkPosition::Center => "(W-w)/2".to_string(),
+        };
+        let y = match self {
+            WatermarkPosition::TopLeft | WatermarkPosition::TopRight => margin.to_string(),
+            WatermarkPosition::BottomLeft | WatermarkPosition::BottomRight => format!("H-h-{}", margin),
+            WatermarkPosition::Center => "(H-h)/2".to_string(),
+        };
+        format!("x={}:y={}", x, y)
+    }
+}
+
+/// Opacity of a `--watermark` given without one.
+pub const DEFAULT_WATERMARK_OPACITY: f64 = 0.7;
+
+/// `--watermark`: an image laid over the picture of every output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Watermark {
+    pub image: PathBuf,
+    pub position: WatermarkPosition,
+    /// From 0.0 (invisible) to 1.0 (as the image is)
+    pub opacity: f64,
+}
+
+/// Parses `--watermark`: `image[:position][:opacity]`, e.g.
+/// `logo.png:top-left:0.5`. The image has to exist.
+pub fn parse_watermark(text: &str) -> Result<Watermark, String> {
+    let mut rest = text;
+    let mut opacity = DEFAULT_WATERMARK_OPACITY;
+    let mut position = WatermarkPosition::default();
+    // Taken from the end, so paths with colons (C:\logo.png) stay whole
+    if let Some((head, last)) = rest.rsplit_once(':') {
+        if let Ok(value) = last.parse::<f64>() {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("opacity must be between 0 and 1, got '{}'", last));
+            }
+            opacity = value;
+            rest = head;
+        }
+    }
+    if let Some((head, last)) = rest.rsplit_once(':') {
+        if let Some((_, named)) = WatermarkPosition::NAMES.iter().find(|(name, _)| *name == last) {
+            position = *named;
+            rest = head;
+        }
+    }
+    let image = PathBuf::from(rest);
+    if !image.is_file() {
+        return Err(format!("no image at '{}'", rest));
+    }
+    Ok(Watermark { image, position, opacity })
+}
+
+/// Output container flavor. Both are MP4 files; `.m4v` uses ffmpeg's `ipod`
+/// muxer so the `M4V` brand and iTunes-style atoms that the TV app import
+/// expects are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    #[default]
+    Mp4,
+    M4v,
+}
+
+impl Container {
+    /// Output file extension.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::M4v => "m4v",
+        }
+    }
+
+    /// ffmpeg `-f` muxer name.
+    pub fn muxer(self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::M4v => "ipod",
+        }
+    }
+}
+
+/// Where `--split-size` or `--split-every` cuts an output into parts.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OutputSplit {
+    /// Parts of at most this many bytes
+    Size(u64),
+    /// Parts of this many seconds
+    Duration(f64),
+}
+
+/// Where each output is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputLayout {
+    /// `movie_appletv.mp4` beside the source
+    #[default]
+    Beside,
+    /// `Plex Versions/Optimized for TV/movie.mp4` beside the source, which
+    /// Plex lists as another version of the source rather than a new item
+    PlexVersions,
+}
+
+/// User-selected encoding options applied when planning each file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncodeOptions {
+    pub preset: QualityPreset,
+    /// Two-pass average-bitrate encoding where the encoder supports it
+    pub two_pass: bool,
+    /// Pick CRF/bitrate per file from a content-complexity analysis
+    pub auto_quality: bool,
+    pub audio: AudioPolicy,
+    /// Extra ffmpeg video filters applied after scaling
+    pub filters: Vec<String>,
+    /// Apple TV generation to target; `None` is generic High@4.1 H.264/AAC
+    pub device: Option<Device>,
+    /// libx264 `-tune` for the content type
+    pub tune: Option<Tune>,
+    /// Override the preset's libx264 CRF
+    pub crf: Option<u32>,
+    /// Override the resolution-table target bitrate, in kbit/s
+    pub bitrate_kbps: Option<u32>,
+    /// Aim for this output file size in bytes (two-pass / fixed bitrate)
+    pub target_size: Option<u64>,
+    /// Override the preset's NVENC constant-quality level
+    pub nvenc_cq: Option<u32>,
+    /// Use fixed-bitrate NVENC encoding instead of constant quality
+    pub nvenc_bitrate: bool,
+    /// Output container flavor
+    pub container: Container,
+    /// Where outputs go relative to their sources
+    pub layout: OutputLayout,
+    /// Encode only this many seconds into a `_sample` file
+    pub sample_secs: Option<f64>,
+    /// Where the sample window starts
+    pub sample_from: SampleStart,
+    /// Compare each encode with its source using this metric
+    pub quality_check: Option<QualityMetric>,
+    /// Score below which an encode is flagged; `None` uses the metric's default
+    pub min_quality: Option<f64>,
+    /// Re-encode once at higher quality when the score is below the minimum
+    pub requality: bool,
+    /// Pause or speed up while on battery or thermally throttled (macOS)
+    pub on_battery: Option<PowerPolicy>,
+    /// Poster and/or preview sprite images to write beside each output
+    pub thumbnails: Option<Thumbnails>,
+    /// Package each file as a multi-rendition HLS stream instead of one MP4
+    pub hls: bool,
+    /// Write fragmented MP4 that stays playable when only partly copied
+    pub fragmented: bool,
+    /// Write the output as numbered parts
+    pub split: Option<OutputSplit>,
+    /// Write one output per chapter
+    pub split_chapters: bool,
+    /// Policy for outputs that already exist
+    pub if_exists: ExistingOutput,
+    /// Local folder to encode into before copying outputs to their folder
+    pub stage_dir: Option<PathBuf>,
+    /// Audio languages to keep, in output order; empty keeps ffmpeg's pick
+    pub keep_audio_languages: Vec<String>,
+    /// Compress the dynamic range of the audio (re-encoding it)
+    pub night_mode: bool,
+    /// Re-encode audio at other sample rates or in unusual channel layouts
+    pub resample_audio: bool,
+    /// Convert lossless surround tracks to 5.1 E-AC3 rather than stereo AAC
+    pub surround_eac3: bool,
+    /// Seconds to shift the audio by; negative plays it earlier
+    pub audio_delay_secs: f64,
+    /// Bitrate of converted audio in kbit/s instead of the default for its
+    /// codec and channel count
+    pub audio_bitrate_kbps: Option<u32>,
+    /// Noise filter run before scaling
+    pub denoise: Option<Denoise>,
+    /// `unsharp` luma amount, applied after scaling
+    pub sharpen: Option<f64>,
+    /// Image to lay over the picture
+    pub watermark: Option<Watermark>,
+    /// Fonts for burned text subtitles
+    pub subtitle_style: SubtitleStyle,
+    /// Add a chosen text subtitle track as a selectable track instead of
+    /// burning it
+    pub soft_subtitles: bool,
+    /// Remove sound descriptions and speaker labels from text subtitles
+    pub strip_sdh: bool,
+    /// Burn only the subtitles flagged as forced
+    pub forced_subtitles: bool,
+    /// Language to fetch subtitles in from OpenSubtitles for files without any
+    pub download_subtitles: Option<String>,
+    /// Re-encode even files that are already compatible, never stream-copying video
+    pub force: bool,
+    /// Skip inputs shorter than this many seconds (when the duration is known)
+    pub min_duration_secs: Option<f64>,
+    /// Skip inputs smaller than this many bytes
+    pub min_size: Option<u64>,
+    /// Decode each source in full before encoding it and fail damaged ones
+    pub verify_input: bool,
+    /// Remux sources that cannot be read or encoded into a clean copy and
+    /// encode that instead
+    pub repair: bool,
+    /// Downscale anything taller than this, keeping the aspect ratio
+    pub max_height: Option<u32>,
+    /// Decode on the GPU when the encoder is hardware and the codec allows it
+    pub hw_decode: bool,
+    /// VAAPI render node to use instead of the first one that works
+    pub vaapi_device: Option<PathBuf>,
+    /// Split each software encode into this many parts encoded in parallel
+    pub segments: Option<u32>,
+    /// Limit decoding, filtering and encoding to this many threads each
+    pub threads: Option<u32>,
+    /// Encoder families to test in order instead of per-OS detection
+    pub encoders: Vec<String>,
+    /// GPU indices to encode on; parallel jobs are spread across them
+    pub gpus: Vec<u32>,
+}
+
+impl EncodeOptions {
+    /// Decoder limits of the `--device` target.
+    pub fn device_caps(&self) -> DeviceCaps {
+        self.device.map_or(DeviceCaps::GENERIC, Device::caps)
+    }
+}
+
+/// Parses a size like `4GB`, `700M`, `1.5 GiB` or `123456` (bytes). SI units
+/// are powers of 1000 and `KiB`/`MiB`/`GiB`/`TiB` powers of 1024.
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", text))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        other => return Err(format!("unknown size unit '{}' in '{}'", other, text)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses `--sharpen`: the `unsharp` luma amount, above 0 and up to 1.5.
+pub fn parse_sharpen(text: &str) -> Result<f64, String> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|amount| *amount > 0.0 && *amount <= 1.5)
+        .ok_or_else(|| format!("expected a strength above 0 and up to 1.5, got '{}'", text))
+}
+
+/// Parses `--sample`: a length in seconds above 0.
+pub fn parse_sample_secs(text: &str) -> Result<f64, String> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs > 0.0)
+        .ok_or_else(|| format!("expected a number of seconds above 0, got '{}'", text))
+}
+
+/// Parses `--audio-delay`: milliseconds (`120`, `-80`), or a duration with
+/// a unit as for [`parse_duration`] (`1.5s`), optionally signed. In seconds.
+pub fn parse_audio_delay(text: &str) -> Result<f64, String> {
+    let text = text.trim();
+    let (sign, rest) = match text.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let secs = match rest.parse::<f64>() {
+        Ok(ms) => ms / 1000.0,
+        Err(_) => parse_duration(rest)?,
+    };
+    Ok(sign * secs)
+}
+
+/// Parses a duration like `10min`, `90s`, `1h30m`, `1.5h`, `250ms`, `45:00`,
+/// `1:30:00` or `600` (seconds) into seconds.
+pub fn parse_duration(text: &str) -> Result<f64, String> {
+    let text = text.trim();
+    let invalid = || format!("invalid duration '{}'", text);
+    if text.is_empty() {
+        return Err(invalid());
+    }
+    if text.contains(':') {
+        return text.split(':').try_fold(0.0, |total, part| {
+            part.parse::<f64>().map(|n| total * 60.0 + n).map_err(|_| invalid())
+        });
+    }
+    let mut total = 0.0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+        let number: f64 = rest[..split].parse().map_err(|_| invalid())?;
+        rest = &rest[split..];
+        let unit_len = rest.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(rest.len());
+        let seconds = match rest[..unit_len].trim().to_ascii_lowercase().as_str() {
+            "ms" => 0.001,
+            "" | "s" | "sec" | "secs" => 1.0,
+            "m" | "min" | "mins" => 60.0,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+            other => return Err(format!("unknown duration unit '{}' in '{}'", other, text)),
+        };
+        total += number * seconds;
+        rest = &rest[unit_len..];
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_length_must_be_positive() {
+        assert_eq!(parse_sample_secs("30"), Ok(30.0));
+        assert_eq!(parse_sample_secs(" 2.5 "), Ok(2.5));
+        assert!(parse_sample_secs("0").is_err());
+        assert!(parse_sample_secs("-10").is_err());
+        assert!(parse_sample_secs("inf").is_err());
+        assert!(parse_sample_secs("ten").is_err());
+    }
+}
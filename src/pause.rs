@@ -0,0 +1,170 @@
+//! Pausing with `SIGUSR1` and resuming with `SIGUSR2` (Unix only).
+//!
+//! The signal handler only flips a flag. A watcher task on the [`runtime`]
+//! notices the change and stops or continues every ffmpeg child the
+//! [`SystemRunner`] has registered, while the queue holds new encodes in
+//! [`wait_while_paused`]. Stopped children keep their progress, so nothing is
+//! lost.
+//!
+//! [`SystemRunner`]: crate::runner::SystemRunner
+//! [`runtime`]: crate::runtime
+
+use crate::runtime;
+use crate::status;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Process ids of the running ffmpeg/ffprobe children.
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// How often the watcher and waiting jobs look at the flag.
+const POLL: Duration = Duration::from_millis(250);
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+/// Pauses or resumes as the signals would, e.g. from a key press in `--tui`.
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::SeqCst);
+}
+
+/// Asks every running child to exit, for quitting mid-encode.
+pub fn terminate_children() {
+    for &pid in CHILDREN.lock().unwrap().iter() {
+        sys::resume(pid);
+        sys::terminate(pid);
+    }
+}
+
+/// A registered child process; unregisters it when dropped.
+pub struct TrackedChild(u32);
+
+/// Registers a freshly spawned child so pausing reaches it. A child started
+/// while paused is stopped straight away.
+pub fn track_child(pid: u32) -> TrackedChild {
+    let mut children = CHILDREN.lock().unwrap();
+    children.push(pid);
+    if is_paused() {
+        sys::stop(pid);
+    }
+    TrackedChild(pid)
+}
+
+impl Drop for TrackedChild {
+    fn drop(&mut self) {
+        CHILDREN.lock().unwrap().retain(|&pid| pid != self.0);
+    }
+}
+
+/// Installs the signal handlers and starts the watcher task. Does nothing
+/// on platforms without these signals.
+pub fn install() {
+    if !sys::install_handlers() {
+        return;
+    }
+    runtime::spawn(async {
+        let mut paused = false;
+        let mut poll = tokio::time::interval(POLL);
+        loop {
+            poll.tick().await;
+            if is_paused() == paused {
+                continue;
+            }
+            paused = !paused;
+            // Hold the lock so no child is registered half-way through
+            let children = CHILDREN.lock().unwrap();
+            for &pid in children.iter() {
+                if paused {
+                    sys::stop(pid)
+                } else {
+                    sys::resume(pid)
+                }
+            }
+            if paused {
+                status!("   ⏸️  Paused {} running job(s), send SIGUSR2 to resume", children.len());
+            } else {
+                status!("   ▶️  Resumed");
+            }
+        }
+    });
+}
+
+/// Blocks while paused, so no new encode starts, unless the run is stopped.
+pub fn wait_while_paused() {
+    while is_paused() && !runtime::shutdown().is_cancelled() {
+        thread::sleep(POLL);
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::PAUSED;
+    use libc::{c_int, SIGCONT, SIGSTOP, SIGTERM, SIGUSR1, SIGUSR2};
+    use std::sync::atomic::Ordering;
+
+    extern "C" fn on_signal(signum: c_int) {
+        PAUSED.store(signum == SIGUSR1, Ordering::SeqCst);
+    }
+
+    pub fn install_handlers() -> bool {
+        // SAFETY: `sigaction` is plain data, so all-zero is a valid starting
+        // point, and the handler only performs an atomic store. SA_RESTART
+        // keeps blocking reads elsewhere from failing with EINTR.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(SIGUSR1, &action, std::ptr::null_mut()) == 0
+                && libc::sigaction(SIGUSR2, &action, std::ptr::null_mut()) == 0
+        }
+    }
+
+    pub fn stop(pid: u32) {
+        // SAFETY: kill has no memory effects; a stale pid just fails
+        unsafe { libc::kill(pid as libc::pid_t, SIGSTOP) };
+    }
+
+    pub fn resume(pid: u32) {
+        // SAFETY: as above
+        unsafe { libc::kill(pid as libc::pid_t, SIGCONT) };
+    }
+
+    pub fn terminate(pid: u32) {
+        // SAFETY: as above
+        unsafe { libc::kill(pid as libc::pid_t, SIGTERM) };
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    pub fn install_handlers() -> bool {
+        false
+    }
+
+    pub fn stop(_pid: u32) {}
+
+    pub fn resume(_pid: u32) {}
+
+    pub fn terminate(_pid: u32) {}
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signals_pause_and_resume() {
+        assert!(sys::install_handlers());
+        // SAFETY: raise runs the handler on this thread before returning
+        unsafe { libc::raise(libc::SIGUSR1) };
+        assert!(is_paused());
+        unsafe { libc::raise(libc::SIGUSR2) };
+        assert!(!is_paused());
+    }
+}
@@ -0,0 +1,1054 @@
+//! Per-file decisions: whether to transcode, which encoder, what to do with audio.
+
+use crate::device::DeviceCaps;
+use crate::filters::HwBackend;
+use crate::h264::{self, H264Profile};
+use crate::hwaccel::{calculate_bitrate_kbps, calculate_max_bitrate_kbps, HwAccel};
+use crate::language::same_language;
+use crate::options::{AudioPolicy, Container, Denoise, EncodeOptions, OutputLayout, OutputSplit, SampleStart, Watermark};
+use crate::preset::{QualityPreset, Tune};
+use crate::probe::{AudioTrack, Chapter, SubtitleTrack, VideoInfo};
+use crate::subtitles::SubtitleStyle;
+use std::path::{Path, PathBuf};
+
+/// How the video stream is encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VideoEncoder {
+    /// A hardware encoder family from [`crate::hwaccel::detect_hardware_acceleration`]
+    Hardware(String),
+    /// libx264
+    Software,
+    /// Stream copy; the source is already H.264 and is only rewrapped
+    Copy,
+}
+
+/// AAC bitrate per output channel when audio is re-encoded, in kbit/s.
+pub const AAC_KBPS_PER_CHANNEL: u32 = 96;
+
+/// Cap on the AAC bitrate however many channels there are, in kbit/s.
+pub const AAC_MAX_KBPS: u32 = 384;
+
+/// E-AC3 bitrate for `--surround-eac3`, in kbit/s: what Blu-ray and
+/// streaming services use for 5.1.
+pub const EAC3_BITRATE_KBPS: u32 = 640;
+
+/// Bitrate assumed for source audio that reports none, in kbit/s.
+const UNKNOWN_AUDIO_KBPS: u32 = 192;
+
+/// The AAC bitrate for `channels` output channels, in kbit/s.
+pub fn aac_bitrate_kbps(channels: u32) -> u32 {
+    (AAC_KBPS_PER_CHANNEL * channels).min(AAC_MAX_KBPS)
+}
+
+/// `--night-mode` audio filters: a compressor that lifts quiet passages and
+/// tames loud ones, then a limiter that catches the peaks it lets through.
+pub const NIGHT_MODE_FILTER: &str =
+    "acompressor=threshold=-30dB:ratio=4:attack=10:release=250:makeup=8dB,alimiter=limit=-1dB";
+
+/// Sample rate `--resample-audio` converts to, in Hz.
+pub const AUDIO_SAMPLE_RATE: u32 = 48000;
+
+/// Folder, relative to a source, where `--layout plex-versions` writes.
+pub const PLEX_VERSIONS_DIR: &str = "Plex Versions/Optimized for TV";
+
+/// Start of the comment tag written into every MP4 output, so a renamed
+/// output is still recognized.
+pub const OUTPUT_COMMENT: &str = "Converted by tvcode";
+
+/// Share of a `--target-size` budget set aside for MP4 container overhead.
+const CONTAINER_OVERHEAD: f64 = 0.01;
+
+/// Share of a `--split-size` limit each part is planned to fill, leaving
+/// room for the bitrate varying and cuts waiting for the next keyframe.
+const SPLIT_SIZE_MARGIN: f64 = 0.9;
+
+/// How the audio stream is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioAction {
+    Copy,
+    /// Re-encode to mono or stereo AAC
+    Aac,
+    /// Re-encode to 5.1 E-AC3 (`--surround-eac3`)
+    Eac3,
+    /// Leave audio out (the video-only parts of a `--segments` encode)
+    Drop,
+}
+
+/// Rate control and tuning for the video encoder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoSettings {
+    pub preset: QualityPreset,
+    /// libx264 CRF for single-pass software encodes
+    pub crf: u32,
+    /// libx264 `-tune`
+    pub tune: Option<Tune>,
+    /// h264_nvenc `-cq` for constant-quality encodes
+    pub nvenc_cq: u32,
+    /// Use a fixed average bitrate for NVENC instead of constant quality
+    pub nvenc_fixed_bitrate: bool,
+    /// Target bitrate for hardware encoders and two-pass encodes
+    pub bitrate_kbps: u32,
+    pub max_bitrate_kbps: u32,
+    /// Two-pass average-bitrate encoding (libx264 pass files, NVENC multipass)
+    pub two_pass: bool,
+    /// H.264 profile, see [`H264Profile::for_height`]
+    pub profile: H264Profile,
+    /// H.264 `level_idc`, e.g. 41; the lowest that fits the output
+    pub level: u32,
+}
+
+impl VideoSettings {
+    /// Settings from the preset and the resolution-tier bitrate table, unless
+    /// the options override the CRF or bitrate, for a `width`x`height` output
+    /// at `frame_rate` fps.
+    pub fn for_output(width: u32, height: u32, frame_rate: f64, options: &EncodeOptions) -> VideoSettings {
+        let (bitrate_kbps, max_bitrate_kbps) = match options.bitrate_kbps {
+            Some(kbps) => (kbps, kbps * 3 / 2),
+            None => (
+                calculate_bitrate_kbps(width, height, options.preset),
+                calculate_max_bitrate_kbps(width, height, options.preset),
+            ),
+        };
+        let mut settings = VideoSettings {
+            preset: options.preset,
+            crf: options.crf.unwrap_or(options.preset.x264_crf()),
+            tune: options.tune,
+            nvenc_cq: options.nvenc_cq.unwrap_or(options.preset.nvenc_cq()),
+            nvenc_fixed_bitrate: options.nvenc_bitrate,
+            bitrate_kbps,
+            max_bitrate_kbps,
+            two_pass: options.two_pass,
+            profile: H264Profile::High,
+            level: h264::MAX_LEVEL,
+        };
+        settings.fit_level(width, height, frame_rate, options.device_caps().max_h264_level);
+        settings
+    }
+
+    /// Chooses the profile for the output height and the lowest level that
+    /// fits its size, frame rate and peak bitrate, capped at `max_level`.
+    /// Call again after changing the bitrates.
+    pub fn fit_level(&mut self, width: u32, height: u32, frame_rate: f64, max_level: u32) {
+        self.profile = H264Profile::for_height(height);
+        self.level = h264::min_level(width, height, frame_rate, self.max_bitrate_kbps, self.profile).min(max_level);
+    }
+
+    /// The level as ffmpeg's `-level` expects it, e.g. `4.1`.
+    pub fn level_arg(&self) -> String {
+        format!("{}.{}", self.level / 10, self.level % 10)
+    }
+}
+
+/// The part of the input a `--sample` encode (or a `--segments` part) covers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleWindow {
+    pub start_secs: f64,
+    pub duration_secs: f64,
+}
+
+impl SampleWindow {
+    /// Resolves `--sample`/`--sample-from` against a file of `duration_secs`
+    /// (0.0 if unknown), keeping the window inside the file where possible.
+    pub fn new(secs: f64, from: SampleStart, duration_secs: f64) -> SampleWindow {
+        let start_secs = match from {
+            SampleStart::Beginning => 0.0,
+            SampleStart::Middle => (duration_secs / 2.0 - secs / 2.0).max(0.0),
+            SampleStart::At(start) if duration_secs > 0.0 => start.min((duration_secs - secs).max(0.0)),
+            SampleStart::At(start) => start,
+        };
+        SampleWindow {
+            start_secs,
+            duration_secs: secs,
+        }
+    }
+}
+
+/// Everything needed to build the ffmpeg command for one input file.
+#[derive(Debug, Clone)]
+pub struct TranscodePlan {
+    pub output: PathBuf,
+    /// A `--repair` copy of the source to read instead of it
+    pub input: Option<PathBuf>,
+    /// Subtitle track to burn into the picture, if any
+    pub subtitle: Option<SubtitleTrack>,
+    /// Fonts for a burned text subtitle
+    pub subtitle_style: SubtitleStyle,
+    /// Mux `subtitle` as a selectable text track instead of burning it
+    pub soft_subtitle: bool,
+    /// Show only the cues of a bitmap `subtitle` flagged as forced
+    pub forced_only: bool,
+    pub video: VideoEncoder,
+    /// Device for the hardware encoder, e.g. a VAAPI render node
+    pub hw_device: Option<String>,
+    /// Decode on the GPU and keep frames in device memory through the filters
+    pub hw_decode: bool,
+    /// Output picture size; differs from the source when downscaling
+    pub output_size: (u32, u32),
+    /// Noise filter, run before scaling
+    pub denoise: Option<Denoise>,
+    /// `unsharp` luma amount, applied after scaling
+    pub sharpen: Option<f64>,
+    /// Extra CPU video filters from the profile, applied after scaling
+    pub filters: Vec<String>,
+    /// Image laid over the picture, last
+    pub watermark: Option<Watermark>,
+    /// Output frame rate cap (`-r`), when the source exceeds the device's
+    pub max_frame_rate: Option<u32>,
+    pub settings: VideoSettings,
+    pub audio: AudioAction,
+    /// Audio streams to keep, in output order, the first marked default;
+    /// empty leaves the choice of one to ffmpeg
+    pub audio_tracks: Vec<usize>,
+    /// Filters (`-af`) for converted audio
+    pub audio_filters: Vec<String>,
+    /// Seconds the audio is shifted by against the video
+    pub audio_delay_secs: f64,
+    /// Channels of converted audio: for AAC mono stays mono and anything
+    /// more is mixed down to stereo; E-AC3 is always 5.1
+    pub audio_channels: u32,
+    /// Bitrate of converted audio, in kbit/s
+    pub audio_bitrate_kbps: u32,
+    pub container: Container,
+    /// Fragmented MP4 (`frag_keyframe+empty_moov`) instead of faststart
+    pub fragmented: bool,
+    /// Encode only this window of the input
+    pub sample: Option<SampleWindow>,
+    /// Write the output as numbered parts of this many seconds (see
+    /// [`split_part_path`]) instead of one file
+    pub split_secs: Option<f64>,
+    /// One output per chapter (`--split-chapters`), each a window of the
+    /// input like a sample; `output` is the folder they go in
+    pub chapters: Vec<Chapter>,
+    /// HLS output: `output` is the package directory for the whole file and
+    /// the rendition playlist for each of [`crate::hls::renditions`]
+    pub hls: bool,
+    /// Cap on decoder, filter and encoder threads
+    pub threads: Option<u32>,
+}
+
+impl TranscodePlan {
+    /// Frame rate of the output: the source's, unless capped.
+    pub fn output_frame_rate(&self, info: &VideoInfo) -> f64 {
+        self.max_frame_rate.map_or(info.frame_rate, f64::from)
+    }
+
+    /// Plans a transcode of `info`, encoding with `hw_accel` when set. CPU-only
+    /// filters such as subtitle burning are bridged to hardware encoders by the
+    /// filter graph, see [`crate::filters`].
+    pub fn new(
+        info: &VideoInfo,
+        subtitle: Option<SubtitleTrack>,
+        hw_accel: Option<&HwAccel>,
+        options: &EncodeOptions,
+    ) -> TranscodePlan {
+        let hw_device = hw_accel.and_then(|hw| hw.device.clone());
+        let family = hw_accel.map(|hw| hw.family.as_str());
+        let caps = options.device_caps();
+
+        // The V4L2 M2M encoders top out at 1080p
+        let mut max_width = caps.max_width;
+        let mut max_height = caps.max_height.min(options.max_height.unwrap_or(u32::MAX));
+        if family == Some("v4l2m2m") {
+            max_width = max_width.min(1920);
+            max_height = max_height.min(1080);
+        }
+        // Limits apply to the coded picture; a portrait recording is then
+        // turned upright, so the output is the other way round
+        let (width, height) = fit_within(info.width, info.height, max_width, max_height);
+        let output_size = if info.display_size() == (info.width, info.height) {
+            (width, height)
+        } else {
+            (height, width)
+        };
+        let max_frame_rate = caps
+            .max_frame_rate
+            .filter(|&fps| info.frame_rate > fps as f64 + 0.01);
+
+        // Bitmap tracks cannot be stored in MP4, and HLS renditions carry
+        // no subtitles, so those are still burned
+        let soft_subtitle = options.soft_subtitles && !options.hls && subtitle.as_ref().is_some_and(|t| !t.is_bitmap);
+        let burned = subtitle.is_some() && !soft_subtitle;
+        let forced_only = options.forced_subtitles && subtitle.as_ref().is_some_and(|track| track.is_bitmap);
+
+        // Video and audio are decided separately: compatible video is copied
+        // whenever nothing has to touch the picture
+        let copy_video = !options.force
+            && !options.hls
+            && options.target_size.is_none()
+            && video_compatible(info, &caps)
+            && !burned
+            && options.filters.is_empty()
+            && options.denoise.is_none()
+            && options.sharpen.is_none()
+            && options.watermark.is_none()
+            && output_size == info.display_size()
+            && max_frame_rate.is_none();
+        let video = match family {
+            _ if copy_video => VideoEncoder::Copy,
+            Some(family) => VideoEncoder::Hardware(family.to_string()),
+            None => VideoEncoder::Software,
+        };
+        // Rotation is applied to decoded frames in system memory
+        let hw_decode = options.hw_decode
+            && !copy_video
+            && info.rotation == 0
+            && family.and_then(HwBackend::for_encoder).is_some()
+            && HwBackend::can_decode(&info.video_codec);
+
+        let mut audio_tracks = select_audio_tracks(info, &options.keep_audio_languages);
+        // An HLS rendition has room for one audio track
+        if options.hls {
+            audio_tracks.truncate(1);
+        }
+        let audio_compatible = if audio_tracks.is_empty() {
+            caps.audio_codecs.contains(&info.audio_codec.as_str())
+        } else {
+            audio_tracks
+                .iter()
+                .all(|&index| caps.audio_codecs.contains(&info.audio_tracks[index].codec.as_str()))
+        };
+        let mut audio_filters = Vec::new();
+        if options.resample_audio && needs_resampling(info, &audio_tracks) {
+            // The stereo downmix of every AAC conversion fixes the layout
+            audio_filters.push(format!("aresample={}", AUDIO_SAMPLE_RATE));
+        }
+        if options.night_mode {
+            audio_filters.push(NIGHT_MODE_FILTER.to_string());
+        }
+        // Filtered audio has to be re-encoded
+        let audio = if options.audio == AudioPolicy::Auto && audio_compatible && audio_filters.is_empty() {
+            AudioAction::Copy
+        } else if options.surround_eac3 && !options.hls && all_lossless_surround(info, &audio_tracks) {
+            AudioAction::Eac3
+        } else {
+            AudioAction::Aac
+        };
+        let (audio_channels, default_kbps) = match audio {
+            AudioAction::Eac3 => (6, EAC3_BITRATE_KBPS),
+            _ => {
+                let channels = aac_channels(info, &audio_tracks);
+                (channels, aac_bitrate_kbps(channels))
+            }
+        };
+        let audio_bitrate_kbps = options.audio_bitrate_kbps.unwrap_or(default_kbps);
+        let frame_rate = max_frame_rate.map_or(info.frame_rate, f64::from);
+        let mut settings = VideoSettings::for_output(output_size.0, output_size.1, frame_rate, options);
+        let audio_kbps = match audio {
+            AudioAction::Aac | AudioAction::Eac3 => audio_bitrate_kbps,
+            AudioAction::Copy => info.audio_bit_rate.map_or(UNKNOWN_AUDIO_KBPS, |b| (b / 1000) as u32),
+            AudioAction::Drop => 0,
+        };
+        if let Some(kbps) = options
+            .target_size
+            .and_then(|size| target_video_kbps(size, info.duration_secs, audio_kbps))
+        {
+            // Average-bitrate encoding is what makes the size predictable
+            settings.bitrate_kbps = kbps;
+            settings.max_bitrate_kbps = kbps * 3 / 2;
+            settings.two_pass = true;
+            settings.nvenc_fixed_bitrate = true;
+            settings.fit_level(output_size.0, output_size.1, frame_rate, caps.max_h264_level);
+        }
+
+        let sample = options
+            .sample_secs
+            .map(|secs| SampleWindow::new(secs, options.sample_from, info.duration_secs));
+        // A file without chapters to split is written whole
+        let chapters = if options.split_chapters && info.chapters.len() > 1 {
+            info.chapters.clone()
+        } else {
+            Vec::new()
+        };
+        let mut output = if options.hls {
+            get_hls_output_dir(&info.path, burned)
+        } else if !chapters.is_empty() {
+            get_chapters_output_dir(&info.path, burned)
+        } else if options.layout == OutputLayout::PlexVersions {
+            plex_version_path(&info.path, burned, options.container)
+        } else {
+            get_output_path(&info.path, burned, options.container)
+        };
+        if sample.is_some() {
+            output = sample_output_path(&output);
+        }
+
+        let mut plan = TranscodePlan {
+            output,
+            input: None,
+            subtitle,
+            subtitle_style: options.subtitle_style.clone(),
+            soft_subtitle,
+            forced_only,
+            video,
+            hw_device,
+            hw_decode,
+            output_size,
+            denoise: options.denoise,
+            sharpen: options.sharpen,
+            filters: options.filters.clone(),
+            watermark: options.watermark.clone(),
+            max_frame_rate,
+            settings,
+            audio,
+            audio_tracks,
+            audio_filters,
+            audio_delay_secs: options.audio_delay_secs,
+            audio_channels,
+            audio_bitrate_kbps,
+            container: options.container,
+            fragmented: options.fragmented,
+            sample,
+            split_secs: None,
+            chapters,
+            hls: options.hls,
+            threads: options.threads,
+        };
+        plan.split_secs = plan.split_length(info, options.split);
+        plan
+    }
+}
+
+impl TranscodePlan {
+    /// The first file written: `output`, or its first part when split.
+    pub fn first_output(&self) -> PathBuf {
+        match self.split_secs {
+            Some(_) => split_part_path(&self.output, 1),
+            None => self.output.clone(),
+        }
+    }
+
+    /// Whether this plan only copies streams into one faststart MP4: nothing
+    /// converted, added, cut, split or shifted.
+    pub fn is_plain_remux(&self) -> bool {
+        self.video == VideoEncoder::Copy
+            && self.audio == AudioAction::Copy
+            && self.subtitle.is_none()
+            && self.audio_delay_secs == 0.0
+            && !self.fragmented
+            && self.sample.is_none()
+            && self.split_secs.is_none()
+            && self.chapters.is_empty()
+            && !self.hls
+    }
+
+    /// Whether running this plan would only copy `info` into an equivalent
+    /// file: a plain remux keeping every audio track, of a source already in
+    /// the container asked for. Such files are skipped.
+    pub fn is_noop(&self, info: &VideoInfo) -> bool {
+        let all_audio = self.audio_tracks.is_empty() || self.audio_tracks.iter().copied().eq(0..info.audio_tracks.len());
+        let source_m4v = info.path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("m4v"));
+        self.is_plain_remux()
+            && all_audio
+            && (info.container.contains("mp4") || info.container.contains("m4v"))
+            && (self.container == Container::Mp4 || source_m4v)
+    }
+
+    /// Seconds per part for `split`, with a size turned into a length by the
+    /// estimated bitrate. `None` when the output fits in one part, or its
+    /// size cannot be estimated.
+    fn split_length(&self, info: &VideoInfo, split: Option<OutputSplit>) -> Option<f64> {
+        let duration_secs = self.output_duration(info);
+        let secs = match split? {
+            OutputSplit::Duration(secs) => secs,
+            OutputSplit::Size(bytes) => {
+                let estimated = self.estimated_size(info)?;
+                duration_secs * bytes as f64 * SPLIT_SIZE_MARGIN / estimated as f64
+            }
+        };
+        (secs > 0.0 && secs < duration_secs).then_some(secs)
+    }
+
+    /// Length of the output in seconds: the sample window, or the whole
+    /// source. Zero if the source duration is unknown.
+    pub fn output_duration(&self, info: &VideoInfo) -> f64 {
+        match self.sample {
+            Some(sample) => sample.duration_secs,
+            None => info.duration_secs,
+        }
+    }
+
+    /// Rough output size in bytes: planned (or, when copying, source) bitrate
+    /// times duration. `None` if the duration is unknown.
+    pub fn estimated_size(&self, info: &VideoInfo) -> Option<u64> {
+        let duration_secs = self.output_duration(info);
+        if duration_secs <= 0.0 {
+            return None;
+        }
+        let source_audio_kbps = info.audio_bit_rate.map_or(UNKNOWN_AUDIO_KBPS as u64, |b| b / 1000);
+        let video_kbps = match self.video {
+            VideoEncoder::Copy => (info.bit_rate? / 1000).saturating_sub(source_audio_kbps),
+            _ => self.settings.bitrate_kbps as u64,
+        };
+        let audio_kbps = match self.audio {
+            AudioAction::Aac | AudioAction::Eac3 => self.audio_bitrate_kbps as u64,
+            AudioAction::Copy => source_audio_kbps,
+            AudioAction::Drop => 0,
+        };
+        Some(((video_kbps + audio_kbps) as f64 * 1000.0 / 8.0 * duration_secs) as u64)
+    }
+}
+
+/// Video bitrate in kbit/s that makes a `duration_secs` file with
+/// `audio_kbps` of audio come out at `size_bytes`. `None` if the duration is
+/// unknown or the audio alone would not fit.
+pub fn target_video_kbps(size_bytes: u64, duration_secs: f64, audio_kbps: u32) -> Option<u32> {
+    if duration_secs <= 0.0 {
+        return None;
+    }
+    let total_kbps = size_bytes as f64 * 8.0 * (1.0 - CONTAINER_OVERHEAD) / duration_secs / 1000.0;
+    let video_kbps = total_kbps - audio_kbps as f64;
+    (video_kbps >= 1.0).then_some(video_kbps as u32)
+}
+
+/// Largest size no bigger than `max_width`x`max_height` with the aspect ratio
+/// of `width`x`height`, rounded to even dimensions. Never upscales.
+pub fn fit_within(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    if width == 0 || height == 0 || (width <= max_width && height <= max_height) {
+        return (width, height);
+    }
+    let scale = (max_width as f64 / width as f64).min(max_height as f64 / height as f64);
+    let even = |v: f64| ((v / 2.0).round() as u32 * 2).max(2);
+    (even(width as f64 * scale), even(height as f64 * scale))
+}
+
+/// What a file needs before it plays on an Apple TV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conversion {
+    /// Already H.264/AAC (or what the device plays) in an MP4 container
+    None,
+    /// Compatible streams in another container; rewrap with `-c copy`
+    Remux,
+    /// Compatible video is copied; only the audio is re-encoded
+    Audio,
+    /// The video has to be re-encoded
+    Transcode,
+}
+
+/// Whether the video stream plays on a device with `caps`: 8-bit 4:2:0 H.264
+/// in a Baseline, Main or High profile (or HEVC Main/Main 10 where the device
+/// decodes it) within the size and frame-rate limits. Unknown profile or
+/// pixel format counts as compatible.
+pub fn video_compatible(info: &VideoInfo, caps: &DeviceCaps) -> bool {
+    let profile = info.video_profile.as_deref();
+    let pix_fmt = info.pix_fmt.as_deref();
+    let codec_ok = match info.video_codec.as_str() {
+        "h264" => {
+            profile.is_none_or(|p| matches!(p, "Baseline" | "Constrained Baseline" | "Main" | "High"))
+                && pix_fmt.is_none_or(|f| matches!(f, "yuv420p" | "yuvj420p"))
+        }
+        "hevc" => {
+            caps.hevc
+                && profile.is_none_or(|p| matches!(p, "Main" | "Main 10"))
+                && pix_fmt.is_none_or(|f| matches!(f, "yuv420p" | "yuvj420p" | "yuv420p10le"))
+        }
+        _ => false,
+    };
+    let size_ok = info.width <= caps.max_width && info.height <= caps.max_height;
+    let fps_ok = caps
+        .max_frame_rate
+        .is_none_or(|fps| info.frame_rate <= fps as f64 + 0.01);
+    codec_ok && size_ok && fps_ok
+}
+
+/// The audio streams of `info` in `languages`, in that order of preference
+/// and stream order within a language. Empty when none match.
+pub fn select_audio_tracks(info: &VideoInfo, languages: &[String]) -> Vec<usize> {
+    let mut tracks = Vec::new();
+    for language in languages {
+        for track in &info.audio_tracks {
+            let matches = track.language.as_deref().is_some_and(|l| same_language(l, language));
+            if matches && !tracks.contains(&track.audio_index) {
+                tracks.push(track.audio_index);
+            }
+        }
+    }
+    tracks
+}
+
+/// The audio tracks of `info` that are output: those in `audio_tracks`, or
+/// ffmpeg's pick.
+fn output_audio_tracks<'a>(info: &'a VideoInfo, audio_tracks: &[usize]) -> Vec<&'a AudioTrack> {
+    let mut tracks: Vec<&AudioTrack> = audio_tracks.iter().map(|&index| &info.audio_tracks[index]).collect();
+    if tracks.is_empty() {
+        tracks.extend(info.default_audio_track());
+    }
+    tracks
+}
+
+/// How many channels AAC converted from the audio tracks in `audio_tracks`
+/// (or ffmpeg's pick) gets: 1 when they are all mono, otherwise 2.
+fn aac_channels(info: &VideoInfo, audio_tracks: &[usize]) -> u32 {
+    let tracks = output_audio_tracks(info, audio_tracks);
+    if !tracks.is_empty() && tracks.iter().all(|track| track.channels == 1) {
+        1
+    } else {
+        2
+    }
+}
+
+/// Whether any audio track `plan` would output (those in `audio_tracks`, or
+/// ffmpeg's pick) is not at [`AUDIO_SAMPLE_RATE`] or not in a common layout.
+fn needs_resampling(info: &VideoInfo, audio_tracks: &[usize]) -> bool {
+    output_audio_tracks(info, audio_tracks).iter().any(|track| {
+        track.sample_rate.is_some_and(|rate| rate != AUDIO_SAMPLE_RATE) || !track.has_common_layout()
+    })
+}
+
+/// Whether every audio track output (those in `audio_tracks`, or ffmpeg's
+/// pick) is lossless surround, which `--surround-eac3` keeps as 5.1. One
+/// stereo track among them means AAC for all.
+fn all_lossless_surround(info: &VideoInfo, audio_tracks: &[usize]) -> bool {
+    let tracks = output_audio_tracks(info, audio_tracks);
+    !tracks.is_empty() && tracks.iter().all(|track| track.is_lossless_surround())
+}
+
+/// Classifies `info` by the cheapest conversion that makes it play on a
+/// device with `caps`.
+pub fn required_conversion(info: &VideoInfo, caps: &DeviceCaps) -> Conversion {
+    let audio_compatible = caps.audio_codecs.contains(&info.audio_codec.as_str());
+    let container_compatible =
+        info.container.contains("mp4") || info.container.contains("m4v");
+    match (video_compatible(info, caps), audio_compatible, container_compatible) {
+        (true, true, true) => Conversion::None,
+        (true, true, false) => Conversion::Remux,
+        (true, false, _) => Conversion::Audio,
+        (false, _, _) => Conversion::Transcode,
+    }
+}
+
+/// Whether `info` is not already playable on a device with `caps` as an MP4.
+/// See [`required_conversion`] for whether a remux or audio conversion is enough.
+pub fn needs_transcoding(info: &VideoInfo, caps: &DeviceCaps) -> bool {
+    required_conversion(info, caps) != Conversion::None
+}
+
+/// Whether `info` carries the comment tag tvcode writes into its outputs.
+pub fn is_tvcode_output(info: &VideoInfo) -> bool {
+    info.comment.as_deref().is_some_and(|comment| comment.starts_with(OUTPUT_COMMENT))
+}
+
+/// Whether `path` is named like an output: `_appletv` (with any `_subs`,
+/// `_sample`, `-N` from `--if-exists rename` or `-partN` from a split),
+/// inside the `--layout plex-versions` folder, or a `--repair` copy.
+pub fn is_output_path(path: &Path) -> bool {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_lowercase();
+    if stem.ends_with(REPAIRED_SUFFIX) {
+        return true;
+    }
+    let stem = match stem.rsplit_once("-part") {
+        Some((base, n)) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => base.to_string(),
+        _ => stem,
+    };
+    let stem = match stem.rsplit_once('-') {
+        Some((base, n)) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => base,
+        _ => &stem,
+    };
+    let stem = stem.strip_suffix("_sample").unwrap_or(stem);
+    let stem = stem.strip_suffix("_subs").unwrap_or(stem);
+    stem.ends_with("_appletv") || path.parent().is_some_and(|dir| dir.ends_with(PLEX_VERSIONS_DIR))
+}
+
+/// Whether the folder `path` is one tvcode writes: an HLS package, a
+/// `--split-chapters` folder, a sweep or the Plex Versions folder.
+pub fn is_output_dir(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    let Some(stem) = ["_hls", "_chapters", "_sweep"].iter().find_map(|suffix| name.strip_suffix(suffix)) else {
+        return PLEX_VERSIONS_DIR.split('/').next().is_some_and(|plex| path.ends_with(plex));
+    };
+    stem.strip_suffix("_subs").unwrap_or(stem).ends_with("_appletv")
+}
+
+/// Part `number` (counting from 1) of the split output `output`, e.g.
+/// `movie_appletv-part2.mp4`. `%d` gives the pattern for ffmpeg.
+pub fn split_part_path(output: &Path, number: impl std::fmt::Display) -> PathBuf {
+    let stem = output.file_stem().unwrap().to_string_lossy();
+    let ext = output.extension().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{}-part{}.{}", stem, number, ext))
+}
+
+/// `path` if nothing exists there (whole or as a first part), otherwise the
+/// first free `<stem>-N.<ext>` beside it.
+pub fn unique_output_path(path: &Path) -> PathBuf {
+    let taken = |path: &Path| path.exists() || split_part_path(path, 1).exists();
+    if !taken(path) {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{}-{}{}", stem, n, ext)))
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+pub fn get_output_path(input_path: &Path, has_subtitles: bool, container: Container) -> PathBuf {
+    let stem = input_path.file_stem().unwrap().to_string_lossy();
+    let parent = input_path.parent().unwrap();
+    let ext = container.extension();
+    if has_subtitles {
+        parent.join(format!("{}_appletv_subs.{}", stem, ext))
+    } else {
+        parent.join(format!("{}_appletv.{}", stem, ext))
+    }
+}
+
+/// Output in Plex's optimized-versions folder beside the input, keeping the
+/// input's name so Plex matches it to the source.
+pub fn plex_version_path(input_path: &Path, has_subtitles: bool, container: Container) -> PathBuf {
+    let stem = input_path.file_stem().unwrap().to_string_lossy();
+    let dir = input_path.parent().unwrap().join(PLEX_VERSIONS_DIR);
+    if has_subtitles {
+        dir.join(format!("{} (subtitles).{}", stem, container.extension()))
+    } else {
+        dir.join(format!("{}.{}", stem, container.extension()))
+    }
+}
+
+/// `path` with `_sample` added before the extension, for `--sample` output.
+pub fn sample_output_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    match path.extension() {
+        Some(ext) => path.with_file_name(format!("{}_sample.{}", stem, ext.to_string_lossy())),
+        None => path.with_file_name(format!("{}_sample", stem)),
+    }
+}
+
+/// Where `output` is written until it is finished and checked: a hidden
+/// file beside it, e.g. `.movie_appletv.mp4.tvcode.partial`.
+pub fn partial_output_path(output: &Path) -> PathBuf {
+    output.with_file_name(format!(".{}.tvcode.partial", output.file_name().unwrap().to_string_lossy()))
+}
+
+/// Marks the stem of a `--repair` copy.
+const REPAIRED_SUFFIX: &str = ".tvcode-repair";
+
+/// Where `--repair` remuxes `input` to: a hidden file beside it in the same
+/// container, e.g. `.movie.tvcode-repair.avi`.
+pub fn repaired_path(input: &Path) -> PathBuf {
+    let stem = input.file_stem().unwrap().to_string_lossy();
+    match input.extension() {
+        Some(ext) => input.with_file_name(format!(".{}{}.{}", stem, REPAIRED_SUFFIX, ext.to_string_lossy())),
+        None => input.with_file_name(format!(".{}{}", stem, REPAIRED_SUFFIX)),
+    }
+}
+
+/// Folder for `--split-chapters` output, beside the input.
+pub fn get_chapters_output_dir(input_path: &Path, has_subtitles: bool) -> PathBuf {
+    let stem = input_path.file_stem().unwrap().to_string_lossy();
+    let parent = input_path.parent().unwrap();
+    if has_subtitles {
+        parent.join(format!("{}_appletv_subs_chapters", stem))
+    } else {
+        parent.join(format!("{}_appletv_chapters", stem))
+    }
+}
+
+/// File name of chapter `number` (counting from 1) in a `--split-chapters`
+/// folder: `03 - Title.mp4`, or `03.mp4` for an untitled chapter.
+pub fn chapter_file_name(number: usize, chapter: &Chapter, container: Container) -> String {
+    let title: String = chapter
+        .title
+        .as_deref()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if "/\\:*?\"<>|".contains(c) || c.is_control() { '-' } else { c })
+        .collect();
+    let title = title.trim().trim_start_matches('.');
+    if title.is_empty() {
+        format!("{:02}.{}", number, container.extension())
+    } else {
+        format!("{:02} - {}.{}", number, title, container.extension())
+    }
+}
+
+/// Package directory for `--hls` output, beside the input.
+pub fn get_hls_output_dir(input_path: &Path, has_subtitles: bool) -> PathBuf {
+    let stem = input_path.file_stem().unwrap().to_string_lossy();
+    let parent = input_path.parent().unwrap();
+    if has_subtitles {
+        parent.join(format!("{}_appletv_subs_hls", stem))
+    } else {
+        parent.join(format!("{}_appletv_hls", stem))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Device;
+    use crate::probe::AudioTrack;
+
+    fn mkv(path: &str) -> VideoInfo {
+        VideoInfo {
+            container: "matroska,webm".to_string(),
+            ..VideoInfo::example(path)
+        }
+    }
+
+    #[test]
+    fn compatible_mp4_needs_nothing() {
+        let info = VideoInfo::example("/media/movie.mp4");
+        assert_eq!(required_conversion(&info, &DeviceCaps::GENERIC), Conversion::None);
+        assert!(!needs_transcoding(&info, &DeviceCaps::GENERIC));
+    }
+
+    #[test]
+    fn compatible_streams_in_matroska_are_remuxed() {
+        assert_eq!(required_conversion(&mkv("/media/movie.mkv"), &DeviceCaps::GENERIC), Conversion::Remux);
+    }
+
+    #[test]
+    fn ac3_needs_audio_only_without_a_device_that_plays_it() {
+        let info = VideoInfo {
+            audio_codec: "ac3".to_string(),
+            ..VideoInfo::example("/media/movie.mp4")
+        };
+        assert_eq!(required_conversion(&info, &DeviceCaps::GENERIC), Conversion::Audio);
+        assert_eq!(required_conversion(&info, &Device::AppleTv3.caps()), Conversion::None);
+    }
+
+    #[test]
+    fn ten_bit_and_hevc_need_transcoding() {
+        let ten_bit = VideoInfo {
+            video_profile: Some("High 10".to_string()),
+            pix_fmt: Some("yuv420p10le".to_string()),
+            ..VideoInfo::example("/media/movie.mp4")
+        };
+        assert_eq!(required_conversion(&ten_bit, &DeviceCaps::GENERIC), Conversion::Transcode);
+        let hevc = VideoInfo {
+            video_codec: "hevc".to_string(),
+            video_profile: Some("Main".to_string()),
+            ..VideoInfo::example("/media/movie.mp4")
+        };
+        assert_eq!(required_conversion(&hevc, &DeviceCaps::GENERIC), Conversion::Transcode);
+        assert_eq!(required_conversion(&hevc, &Device::AppleTvHd.caps()), Conversion::None);
+    }
+
+    #[test]
+    fn too_large_for_the_device_needs_transcoding() {
+        let uhd = VideoInfo {
+            width: 3840,
+            height: 2160,
+            ..VideoInfo::example("/media/movie.mp4")
+        };
+        assert_eq!(required_conversion(&uhd, &Device::AppleTv3.caps()), Conversion::Transcode);
+    }
+
+    #[test]
+    fn compatible_streams_are_copied() {
+        let plan = TranscodePlan::new(&mkv("/media/movie.mkv"), None, None, &EncodeOptions::default());
+        assert_eq!(plan.video, VideoEncoder::Copy);
+        assert_eq!(plan.audio, AudioAction::Copy);
+        assert_eq!(plan.output, PathBuf::from("/media/movie_appletv.mp4"));
+    }
+
+    #[test]
+    fn force_encodes_with_the_hardware_family() {
+        let options = EncodeOptions { force: true, ..EncodeOptions::default() };
+        let hw = HwAccel { family: "nvenc".to_string(), device: None };
+        let plan = TranscodePlan::new(&VideoInfo::example("/media/movie.mp4"), None, Some(&hw), &options);
+        assert_eq!(plan.video, VideoEncoder::Hardware("nvenc".to_string()));
+        assert_eq!(plan.audio, AudioAction::Copy);
+    }
+
+    #[test]
+    fn burned_subtitles_encode_the_video() {
+        let subtitle = SubtitleTrack {
+            subtitle_index: 0,
+            codec: "hdmv_pgs_subtitle".to_string(),
+            language: Some("eng".to_string()),
+            title: None,
+            is_bitmap: true,
+            source: None,
+            language_detected: false,
+            forced: false,
+        };
+        let plan = TranscodePlan::new(&mkv("/media/movie.mkv"), Some(subtitle), None, &EncodeOptions::default());
+        assert_eq!(plan.video, VideoEncoder::Software);
+        assert!(!plan.soft_subtitle);
+        assert_eq!(plan.output, PathBuf::from("/media/movie_appletv_subs.mp4"));
+    }
+
+    #[test]
+    fn aac_policy_converts_compatible_audio() {
+        let options = EncodeOptions { audio: AudioPolicy::Aac, ..EncodeOptions::default() };
+        let plan = TranscodePlan::new(&VideoInfo::example("/media/movie.mp4"), None, None, &options);
+        assert_eq!(plan.video, VideoEncoder::Copy);
+        assert_eq!(plan.audio, AudioAction::Aac);
+        assert_eq!(plan.audio_channels, 2);
+        assert_eq!(plan.audio_bitrate_kbps, aac_bitrate_kbps(2));
+    }
+
+    #[test]
+    fn mono_sources_stay_mono() {
+        let info = VideoInfo {
+            audio_codec: "mp3".to_string(),
+            audio_tracks: vec![AudioTrack::example(0, "mp3", 1, None)],
+            ..VideoInfo::example("/media/movie.mp4")
+        };
+        let plan = TranscodePlan::new(&info, None, None, &EncodeOptions::default());
+        assert_eq!(plan.audio, AudioAction::Aac);
+        assert_eq!(plan.audio_channels, 1);
+    }
+
+    #[test]
+    fn kept_languages_are_in_the_order_asked() {
+        let info = VideoInfo {
+            audio_tracks: vec![
+                AudioTrack::example(0, "aac", 2, Some("eng")),
+                AudioTrack::example(1, "aac", 2, Some("fra")),
+                AudioTrack::example(2, "aac", 2, Some("deu")),
+                AudioTrack::example(3, "aac", 2, Some("FRA")),
+            ],
+            ..VideoInfo::example("/media/movie.mp4")
+        };
+        let languages = ["fra".to_string(), "eng".to_string(), "jpn".to_string()];
+        assert_eq!(select_audio_tracks(&info, &languages), vec![1, 3, 0]);
+        assert!(select_audio_tracks(&info, &["jpn".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn lossless_surround_becomes_eac3_when_asked() {
+        let info = VideoInfo {
+            audio_codec: "truehd".to_string(),
+            audio_tracks: vec![AudioTrack::example(0, "truehd", 8, Some("eng"))],
+            ..mkv("/media/movie.mkv")
+        };
+        let options = EncodeOptions { surround_eac3: true, ..EncodeOptions::default() };
+        let plan = TranscodePlan::new(&info, None, None, &options);
+        assert_eq!(plan.audio, AudioAction::Eac3);
+        assert_eq!((plan.audio_channels, plan.audio_bitrate_kbps), (6, EAC3_BITRATE_KBPS));
+        let plan = TranscodePlan::new(&info, None, None, &EncodeOptions::default());
+        assert_eq!(plan.audio, AudioAction::Aac);
+    }
+
+    #[test]
+    fn night_mode_filters_and_converts_the_audio() {
+        let options = EncodeOptions { night_mode: true, ..EncodeOptions::default() };
+        let plan = TranscodePlan::new(&VideoInfo::example("/media/movie.mp4"), None, None, &options);
+        assert_eq!(plan.audio, AudioAction::Aac);
+        assert_eq!(plan.audio_filters, vec![NIGHT_MODE_FILTER.to_string()]);
+    }
+
+    #[test]
+    fn hls_keeps_one_audio_track_and_encodes() {
+        let info = VideoInfo {
+            audio_tracks: vec![
+                AudioTrack::example(0, "aac", 2, Some("eng")),
+                AudioTrack::example(1, "aac", 2, Some("fra")),
+            ],
+            ..VideoInfo::example("/media/movie.mp4")
+        };
+        let options = EncodeOptions {
+            hls: true,
+            keep_audio_languages: vec!["fra".to_string(), "eng".to_string()],
+            ..EncodeOptions::default()
+        };
+        let plan = TranscodePlan::new(&info, None, None, &options);
+        assert_eq!(plan.audio_tracks, vec![1]);
+        assert_eq!(plan.video, VideoEncoder::Software);
+        assert_eq!(plan.output, PathBuf::from("/media/movie_appletv_hls"));
+    }
+
+    #[test]
+    fn target_size_encodes_in_two_passes() {
+        let options = EncodeOptions { target_size: Some(300_000_000), ..EncodeOptions::default() };
+        let plan = TranscodePlan::new(&VideoInfo::example("/media/movie.mp4"), None, None, &options);
+        assert_eq!(plan.video, VideoEncoder::Software);
+        assert!(plan.settings.two_pass);
+        assert_eq!(Some(plan.settings.bitrate_kbps), target_video_kbps(300_000_000, 600.0, 192));
+    }
+
+    #[test]
+    fn device_limits_scale_the_picture() {
+        let uhd = VideoInfo {
+            width: 3840,
+            height: 2160,
+            ..VideoInfo::example("/media/movie.mp4")
+        };
+        let options = EncodeOptions { device: Some(Device::AppleTv3), ..EncodeOptions::default() };
+        let plan = TranscodePlan::new(&uhd, None, None, &options);
+        assert_eq!(plan.output_size, (1920, 1080));
+        assert_eq!(plan.video, VideoEncoder::Software);
+    }
+
+    #[test]
+    fn compatible_mp4_is_a_noop() {
+        let info = VideoInfo::example("/media/movie.mp4");
+        assert!(TranscodePlan::new(&info, None, None, &EncodeOptions::default()).is_noop(&info));
+        let remux = mkv("/media/movie.mkv");
+        assert!(!TranscodePlan::new(&remux, None, None, &EncodeOptions::default()).is_noop(&remux));
+    }
+
+    #[test]
+    fn output_options_make_a_compatible_mp4_an_encode() {
+        let info = VideoInfo::example("/media/movie.mp4");
+        let rewritten = [
+            EncodeOptions { hls: true, ..EncodeOptions::default() },
+            EncodeOptions { fragmented: true, ..EncodeOptions::default() },
+            EncodeOptions { night_mode: true, ..EncodeOptions::default() },
+            EncodeOptions { audio_delay_secs: 0.1, ..EncodeOptions::default() },
+            EncodeOptions { split: Some(OutputSplit::Duration(60.0)), ..EncodeOptions::default() },
+            EncodeOptions { sharpen: Some(1.0), ..EncodeOptions::default() },
+            EncodeOptions { container: Container::M4v, ..EncodeOptions::default() },
+        ];
+        for options in rewritten {
+            assert!(!TranscodePlan::new(&info, None, None, &options).is_noop(&info), "{:?}", options);
+        }
+    }
+
+    #[test]
+    fn dropping_audio_tracks_is_not_a_noop() {
+        let info = VideoInfo {
+            audio_tracks: vec![
+                AudioTrack::example(0, "aac", 2, Some("eng")),
+                AudioTrack::example(1, "aac", 2, Some("fra")),
+            ],
+            ..VideoInfo::example("/media/movie.mp4")
+        };
+        let keep = |languages: &[&str]| EncodeOptions {
+            keep_audio_languages: languages.iter().map(|l| l.to_string()).collect(),
+            ..EncodeOptions::default()
+        };
+        assert!(!TranscodePlan::new(&info, None, None, &keep(&["fra"])).is_noop(&info));
+        assert!(TranscodePlan::new(&info, None, None, &keep(&["eng", "fra"])).is_noop(&info));
+    }
+
+    #[test]
+    fn fit_within_keeps_the_aspect_ratio() {
+        assert_eq!(fit_within(3840, 2160, 1920, 1080), (1920, 1080));
+        assert_eq!(fit_within(1440, 1080, 1920, 720), (960, 720));
+        assert_eq!(fit_within(1280, 720, 1920, 1080), (1280, 720));
+    }
+
+    #[test]
+    fn outputs_are_recognized_by_name() {
+        assert!(is_output_path(Path::new("/media/movie_appletv.mp4")));
+        assert!(is_output_path(Path::new("/media/movie_appletv_subs_sample-2.m4v")));
+        assert!(is_output_path(Path::new("/media/movie_appletv-part3.mp4")));
+        assert!(is_output_path(Path::new("/media/Plex Versions/Optimized for TV/movie.mp4")));
+        assert!(is_output_path(Path::new("/media/.movie.tvcode-repair.avi")));
+        assert!(!is_output_path(Path::new("/media/movie.mkv")));
+    }
+
+    #[test]
+    fn output_folders_are_recognized_by_name() {
+        assert!(is_output_dir(Path::new("/media/movie_appletv_hls")));
+        assert!(is_output_dir(Path::new("/media/movie_appletv_subs_chapters")));
+        assert!(is_output_dir(Path::new("/media/movie_appletv_sweep")));
+        assert!(is_output_dir(Path::new("/media/Plex Versions")));
+        assert!(!is_output_dir(Path::new("/media/Season 1")));
+        assert!(!is_output_dir(Path::new("/media/chapters")));
+    }
+
+    #[test]
+    fn working_file_names() {
+        let output = Path::new("/media/movie_appletv.mp4");
+        assert_eq!(split_part_path(output, 2), PathBuf::from("/media/movie_appletv-part2.mp4"));
+        assert_eq!(partial_output_path(output), PathBuf::from("/media/.movie_appletv.mp4.tvcode.partial"));
+        assert_eq!(sample_output_path(output), PathBuf::from("/media/movie_appletv_sample.mp4"));
+    }
+}
@@ -0,0 +1,103 @@
+//! `--on-battery`: easing off while a Mac laptop is unplugged or throttling.
+//!
+//! The power source and thermal state come from `pmset`. Elsewhere the state
+//! is unknown and treated as unconstrained.
+
+use crate::status;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+
+/// What to do between files while on battery or thermally throttled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerPolicy {
+    /// Hold the queue until back on AC power and cool
+    Pause,
+    /// Keep going with the fast encoder preset
+    Fast,
+}
+
+/// Power conditions that matter for long encodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PowerState {
+    pub on_battery: bool,
+    pub throttled: bool,
+}
+
+impl PowerState {
+    pub fn constrained(&self) -> bool {
+        self.on_battery || self.throttled
+    }
+
+    /// Why the state is constrained, for messages.
+    pub fn reason(&self) -> &'static str {
+        match (self.on_battery, self.throttled) {
+            (true, true) => "on battery and thermally throttled",
+            (true, false) => "on battery",
+            (false, true) => "thermally throttled",
+            (false, false) => "on AC power",
+        }
+    }
+}
+
+/// Whether `pmset -g batt` output says the battery is the power source.
+pub fn parse_battery(pmset_batt: &str) -> bool {
+    pmset_batt.contains("'Battery Power'")
+}
+
+/// Whether `pmset -g therm` output shows a recorded warning level or a CPU
+/// speed limit below 100%.
+pub fn parse_thermal(pmset_therm: &str) -> bool {
+    pmset_therm.lines().any(|line| {
+        let lower = line.to_ascii_lowercase();
+        if lower.contains("warning level set to") {
+            return true;
+        }
+        match line.split_once('=') {
+            Some((key, value)) if key.trim() == "CPU_Speed_Limit" => {
+                value.trim().parse::<u32>().is_ok_and(|limit| limit < 100)
+            }
+            _ => false,
+        }
+    })
+}
+
+/// The current power state, or the default (unconstrained) where it cannot
+/// be read.
+pub fn power_state() -> PowerState {
+    if !cfg!(target_os = "macos") {
+        return PowerState::default();
+    }
+    let pmset = |arg: &str| {
+        std::process::Command::new("pmset")
+            .args(["-g", arg])
+            .output()
+            .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+            .unwrap_or_default()
+    };
+    PowerState {
+        on_battery: parse_battery(&pmset("batt")),
+        throttled: parse_thermal(&pmset("therm")),
+    }
+}
+
+/// Blocks while on battery or throttled, re-checking every minute.
+pub fn wait_for_power() {
+    let mut announced = false;
+    loop {
+        let state = power_state();
+        if !state.constrained() {
+            if announced {
+                status!("   🔌 On AC power and cool again, continuing");
+            }
+            return;
+        }
+        if !announced {
+            status!("   🔋 Pausing while {}", state.reason());
+            announced = true;
+        }
+        thread::sleep(Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,113 @@
+//! `--preset` quality levels and the encoder settings each one implies.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Speed/quality trade-off applied consistently across every encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityPreset {
+    /// Quick encodes, noticeably larger or softer output
+    Fast,
+    /// The historical tvcode defaults
+    #[default]
+    Balanced,
+    /// Slower encodes with fewer visible artifacts
+    Quality,
+    /// Near-transparent quality for long-term storage
+    Archive,
+}
+
+impl QualityPreset {
+    /// libx264 `-preset`.
+    pub fn x264_preset(self) -> &'static str {
+        match self {
+            QualityPreset::Fast => "veryfast",
+            QualityPreset::Balanced => "medium",
+            QualityPreset::Quality => "slow",
+            QualityPreset::Archive => "veryslow",
+        }
+    }
+
+    /// libx264 `-crf`.
+    pub fn x264_crf(self) -> u32 {
+        match self {
+            QualityPreset::Fast => 23,
+            QualityPreset::Balanced => 20,
+            QualityPreset::Quality => 18,
+            QualityPreset::Archive => 16,
+        }
+    }
+
+    /// h264_nvenc `-preset` (p1 fastest .. p7 slowest).
+    pub fn nvenc_preset(self) -> &'static str {
+        match self {
+            QualityPreset::Fast => "p4",
+            QualityPreset::Balanced | QualityPreset::Quality | QualityPreset::Archive => "p7",
+        }
+    }
+
+    /// h264_nvenc `-cq` target quality.
+    pub fn nvenc_cq(self) -> u32 {
+        match self {
+            QualityPreset::Fast => 25,
+            QualityPreset::Balanced => 23,
+            QualityPreset::Quality => 20,
+            QualityPreset::Archive => 18,
+        }
+    }
+
+    /// h264_qsv `-preset`.
+    pub fn qsv_preset(self) -> &'static str {
+        match self {
+            QualityPreset::Fast => "faster",
+            QualityPreset::Balanced | QualityPreset::Quality | QualityPreset::Archive => "veryslow",
+        }
+    }
+
+    /// h264_amf `-quality`.
+    pub fn amf_quality(self) -> &'static str {
+        match self {
+            QualityPreset::Fast => "speed",
+            QualityPreset::Balanced => "balanced",
+            QualityPreset::Quality | QualityPreset::Archive => "quality",
+        }
+    }
+
+    /// Scale applied to the resolution-tier bitrates used by the
+    /// bitrate-driven hardware encoders (VideoToolbox, QSV, VAAPI, AMF, NVENC caps).
+    pub fn bitrate_multiplier(self) -> f64 {
+        match self {
+            QualityPreset::Fast => 0.75,
+            QualityPreset::Balanced => 1.0,
+            QualityPreset::Quality => 1.25,
+            QualityPreset::Archive => 1.5,
+        }
+    }
+}
+
+/// libx264 `-tune` content hint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tune {
+    /// Live-action film
+    Film,
+    /// Cartoons and anime: flat areas, hard edges
+    Animation,
+    /// Keep film grain instead of smoothing it away
+    Grain,
+    /// Cheaper decoding for weak players
+    Fastdecode,
+}
+
+impl Tune {
+    /// libx264 `-tune` value.
+    pub fn x264_tune(self) -> &'static str {
+        match self {
+            Tune::Film => "film",
+            Tune::Animation => "animation",
+            Tune::Grain => "grain",
+            Tune::Fastdecode => "fastdecode",
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! `--nice` and `--ionice`: lowering tvcode's CPU and disk priority.
+//!
+//! Both are set on the tvcode process itself before anything is spawned, and
+//! every ffmpeg child inherits them.
+
+/// Lowers the CPU priority to `nice` (0-19, higher is lower priority).
+#[cfg(unix)]
+pub fn set_nice(nice: i32) -> Result<(), String> {
+    use std::os::raw::{c_int, c_uint};
+    const PRIO_PROCESS: c_int = 0;
+    extern "C" {
+        fn setpriority(which: c_int, who: c_uint, prio: c_int) -> c_int;
+    }
+    // SAFETY: setpriority only changes scheduler state of this process
+    if unsafe { setpriority(PRIO_PROCESS, 0, nice) } == 0 {
+        Ok(())
+    } else {
+        Err(format!("could not set nice {}: {}", nice, std::io::Error::last_os_error()))
+    }
+}
+
+/// Maps `nice` onto a priority class: below normal, or idle from 15 up.
+/// Children inherit either class.
+#[cfg(windows)]
+pub fn set_nice(nice: i32) -> Result<(), String> {
+    use std::os::raw::c_void;
+    const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x4000;
+    const IDLE_PRIORITY_CLASS: u32 = 0x40;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+        fn SetPriorityClass(process: *mut c_void, class: u32) -> i32;
+    }
+    if nice <= 0 {
+        return Ok(());
+    }
+    let class = if nice >= 15 { IDLE_PRIORITY_CLASS } else { BELOW_NORMAL_PRIORITY_CLASS };
+    // SAFETY: the pseudo handle from GetCurrentProcess is always valid
+    if unsafe { SetPriorityClass(GetCurrentProcess(), class) } != 0 {
+        Ok(())
+    } else {
+        Err(format!("could not lower the priority class: {}", std::io::Error::last_os_error()))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn set_nice(_nice: i32) -> Result<(), String> {
+    Err("--nice is not supported on this platform".to_string())
+}
+
+/// Puts disk I/O in the idle class, so it only gets the disk when nothing
+/// else wants it. Uses util-linux `ionice`.
+#[cfg(target_os = "linux")]
+pub fn set_idle_io() -> Result<(), String> {
+    let status = std::process::Command::new("ionice")
+        .args(["-c", "3", "-p", &std::process::id().to_string()])
+        .status()
+        .map_err(|e| format!("could not run ionice: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err("ionice failed to set the idle I/O class".to_string())
+    }
+}
+
+/// Throttles disk I/O (`IOPOL_THROTTLE`) for this process and its children.
+#[cfg(target_os = "macos")]
+pub fn set_idle_io() -> Result<(), String> {
+    use std::os::raw::c_int;
+    const IOPOL_TYPE_DISK: c_int = 0;
+    const IOPOL_SCOPE_PROCESS: c_int = 0;
+    const IOPOL_THROTTLE: c_int = 3;
+    extern "C" {
+        fn setiopolicy_np(iotype: c_int, scope: c_int, policy: c_int) -> c_int;
+    }
+    // SAFETY: setiopolicy_np only changes I/O scheduling of this process
+    if unsafe { setiopolicy_np(IOPOL_TYPE_DISK, IOPOL_SCOPE_PROCESS, IOPOL_THROTTLE) } == 0 {
+        Ok(())
+    } else {
+        Err(format!("could not throttle I/O: {}", std::io::Error::last_os_error()))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn set_idle_io() -> Result<(), String> {
+    Err("--ionice is only supported on Linux and macOS".to_string())
+}
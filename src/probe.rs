@@ -0,0 +1,569 @@
+//! ffprobe invocation and the stream information tvcode cares about.
+
+use serde::{Deserialize, Serialize};
+use crate::language::is_undetermined;
+use crate::runner::{FfmpegRunner, Tool};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct FFProbeOutput {
+    streams: Vec<Stream>,
+    format: Format,
+    #[serde(default)]
+    chapters: Vec<ProbeChapter>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Stream {
+    index: usize,
+    codec_type: String,
+    // Missing for attachments ffmpeg has no codec for
+    #[serde(default)]
+    codec_name: String,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    #[serde(default)]
+    avg_frame_rate: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default)]
+    pix_fmt: Option<String>,
+    #[serde(default)]
+    bit_rate: Option<String>,
+    #[serde(default)]
+    sample_rate: Option<String>,
+    #[serde(default)]
+    channels: u32,
+    #[serde(default)]
+    channel_layout: Option<String>,
+    #[serde(default)]
+    start_time: Option<String>,
+    /// Missing from Matroska, which has a `DURATION` tag instead
+    #[serde(default)]
+    duration: Option<String>,
+    /// 1 when the video stream carries EIA-608/708 captions
+    #[serde(default)]
+    closed_captions: u8,
+    /// Where phone recordings keep their display matrix
+    #[serde(default)]
+    side_data_list: Vec<SideData>,
+    #[serde(default)]
+    tags: StreamTags,
+    #[serde(default)]
+    disposition: Disposition,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+struct Disposition {
+    #[serde(default)]
+    forced: u8,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct SideData {
+    #[serde(default)]
+    rotation: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+struct StreamTags {
+    #[serde(default)]
+    language: Option<String>,
+    /// Rotation as older ffmpeg versions report it
+    #[serde(default)]
+    rotate: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    /// Name of an attached file
+    #[serde(default)]
+    filename: Option<String>,
+    #[serde(default)]
+    mimetype: Option<String>,
+    /// Stream length in Matroska, e.g. `00:42:10.123000000`
+    #[serde(default, rename = "DURATION")]
+    duration: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Format {
+    format_name: String,
+    #[serde(default)]
+    duration: Option<String>,
+    #[serde(default)]
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: FormatTags,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ProbeChapter {
+    start_time: String,
+    end_time: String,
+    #[serde(default)]
+    tags: ChapterTags,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+struct ChapterTags {
+    #[serde(default)]
+    title: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+struct FormatTags {
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+/// Codec and stream summary of a probed input file.
+#[derive(Debug, Clone)]
+pub struct VideoInfo {
+    pub path: PathBuf,
+    pub video_codec: String,
+    pub audio_codec: String,
+    /// ffprobe `format_name`, e.g. `matroska,webm`
+    pub container: String,
+    /// Coded size of the picture, before any rotation
+    pub width: u32,
+    pub height: u32,
+    /// Degrees the picture is turned clockwise for display (0, 90, 180 or
+    /// 270), as phones record portrait video; ffmpeg applies it when decoding
+    pub rotation: u32,
+    /// Video codec profile as ffprobe names it, e.g. `High` or `High 10`
+    pub video_profile: Option<String>,
+    /// Video pixel format, e.g. `yuv420p`
+    pub pix_fmt: Option<String>,
+    /// Average video frame rate, 0.0 if unknown
+    pub frame_rate: f64,
+    /// Container duration in seconds, 0.0 if unknown
+    pub duration_secs: f64,
+    /// Overall container bitrate in bits/s, if reported
+    pub bit_rate: Option<u64>,
+    /// Audio stream bitrate in bits/s, if reported
+    pub audio_bit_rate: Option<u64>,
+    /// Container comment tag, where tvcode marks its own outputs
+    pub comment: Option<String>,
+    pub subtitles: Vec<SubtitleTrack>,
+    pub audio_tracks: Vec<AudioTrack>,
+    /// The video stream carries EIA-608/708 closed captions, as TV recordings do
+    pub closed_captions: bool,
+    /// Fonts attached to the container, as MKVs with ASS subtitles carry them
+    pub fonts: Vec<FontAttachment>,
+    pub chapters: Vec<Chapter>,
+    /// Start and length of the video stream
+    pub video_timing: StreamTiming,
+}
+
+impl VideoInfo {
+    /// Size of the picture as displayed, and as ffmpeg decodes it: the coded
+    /// size, turned on its side for a 90 or 270 degree rotation.
+    pub fn display_size(&self) -> (u32, u32) {
+        if self.rotation % 180 == 90 {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+
+    /// The audio track ffmpeg picks when none is mapped: the first with the
+    /// most channels.
+    pub fn default_audio_track(&self) -> Option<&AudioTrack> {
+        self.audio_tracks.iter().rev().max_by_key(|track| track.channels)
+    }
+}
+
+/// A chapter of the input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub start_secs: f64,
+    pub end_secs: f64,
+    pub title: Option<String>,
+}
+
+/// A font file attached to the container.
+#[derive(Debug, Clone)]
+pub struct FontAttachment {
+    /// Stream index among all streams
+    pub stream_index: usize,
+    pub filename: String,
+}
+
+/// Whether an attachment is a font, by MIME type or file extension.
+fn is_font(codec: &str, filename: &str, mimetype: Option<&str>) -> bool {
+    let extension = Path::new(filename)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    matches!(codec, "ttf" | "otf")
+        || mimetype.is_some_and(|mime| mime.contains("font") || mime.ends_with("truetype") || mime.ends_with("opentype"))
+        || matches!(extension.as_str(), "ttf" | "otf" | "ttc" | "otc")
+}
+
+#[cfg(test)]
+impl VideoInfo {
+    /// A 1080p H.264 High MP4 with one stereo AAC track, for tests.
+    pub(crate) fn example(path: &str) -> VideoInfo {
+        VideoInfo {
+            path: PathBuf::from(path),
+            video_codec: "h264".to_string(),
+            audio_codec: "aac".to_string(),
+            container: "mov,mp4,m4a,3gp,3g2,mj2".to_string(),
+            width: 1920,
+            height: 1080,
+            rotation: 0,
+            video_profile: Some("High".to_string()),
+            pix_fmt: Some("yuv420p".to_string()),
+            frame_rate: 24.0,
+            duration_secs: 600.0,
+            bit_rate: Some(8_000_000),
+            audio_bit_rate: Some(192_000),
+            comment: None,
+            subtitles: Vec::new(),
+            audio_tracks: vec![AudioTrack::example(0, "aac", 2, Some("eng"))],
+            closed_captions: false,
+            fonts: Vec::new(),
+            chapters: Vec::new(),
+            video_timing: StreamTiming::default(),
+        }
+    }
+}
+
+/// When a stream starts and how long it runs, where the container says.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StreamTiming {
+    pub start_secs: Option<f64>,
+    pub duration_secs: Option<f64>,
+}
+
+impl StreamTiming {
+    fn of(stream: &Stream) -> StreamTiming {
+        StreamTiming {
+            start_secs: stream.start_time.as_deref().and_then(|start| start.parse().ok()),
+            duration_secs: stream
+                .duration
+                .as_deref()
+                .and_then(|duration| duration.parse().ok())
+                .or_else(|| stream.tags.duration.as_deref().and_then(parse_timestamp)),
+        }
+    }
+}
+
+/// An audio stream of the input.
+#[derive(Debug, Clone)]
+pub struct AudioTrack {
+    /// Index among audio streams only (0, 1, 2...)
+    pub audio_index: usize,
+    pub codec: String,
+    /// Codec profile, e.g. `DTS-HD MA` for lossless DTS
+    pub profile: Option<String>,
+    pub language: Option<String>,
+    /// Samples per second, if reported
+    pub sample_rate: Option<u32>,
+    pub channels: u32,
+    /// ffmpeg's name for the layout, e.g. `5.1(side)`
+    pub channel_layout: Option<String>,
+    pub timing: StreamTiming,
+}
+
+impl AudioTrack {
+    /// Whether the channels are laid out as mono, stereo, 5.1 or 7.1, the
+    /// layouts receivers handle. An unnamed layout is judged by its count.
+    pub fn has_common_layout(&self) -> bool {
+        match self.channel_layout.as_deref() {
+            Some(layout) => matches!(layout, "mono" | "stereo" | "5.1" | "5.1(side)" | "7.1"),
+            None => matches!(self.channels, 1 | 2 | 6 | 8),
+        }
+    }
+
+    /// Whether this is lossless surround from a Blu-ray: TrueHD (Atmos
+    /// included) or DTS-HD Master Audio with more than two channels.
+    pub fn is_lossless_surround(&self) -> bool {
+        let lossless = self.codec == "truehd"
+            || (self.codec == "dts" && self.profile.as_deref().is_some_and(|profile| profile.starts_with("DTS-HD MA")));
+        lossless && self.channels > 2
+    }
+}
+
+#[cfg(test)]
+impl AudioTrack {
+    /// An audio track with a standard layout for its channel count, for tests.
+    pub(crate) fn example(audio_index: usize, codec: &str, channels: u32, language: Option<&str>) -> AudioTrack {
+        AudioTrack {
+            audio_index,
+            codec: codec.to_string(),
+            profile: None,
+            language: language.map(str::to_string),
+            sample_rate: Some(48_000),
+            channels,
+            channel_layout: None,
+            timing: StreamTiming::default(),
+        }
+    }
+}
+
+/// A subtitle stream that can be burned into the picture.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtitleTrack {
+    pub subtitle_index: usize,  // Index among subtitle streams only (0, 1, 2...)
+    pub codec: String,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub is_bitmap: bool,        // PGS, DVB, DVD subtitles are bitmap-based
+    /// Subtitle-only file the track is in, when it is not in the video itself
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+    /// Flagged as forced: only the lines meant to be shown to everyone,
+    /// such as translations of foreign dialogue
+    #[serde(default)]
+    pub forced: bool,
+    /// `language` was guessed from the text rather than tagged
+    #[serde(default)]
+    pub language_detected: bool,
+}
+
+impl SubtitleTrack {
+    /// Input the track is read from: its own file or the video.
+    pub fn input<'a>(&'a self, video_path: &'a Path) -> &'a Path {
+        self.source.as_deref().unwrap_or(video_path)
+    }
+}
+
+/// Extensions of the subtitle files found beside a video.
+const SUBTITLE_EXTENSIONS: &[&str] = &["mks", "srt", "ass", "ssa", "vtt"];
+
+/// Subtitle files that belong to `video_path`: subtitle-only Matroska and
+/// SRT, ASS and WebVTT files named `movie.<ext>` or `movie.<anything>.<ext>`
+/// (e.g. `movie.eng.mks`, `movie.en.srt`) in the same folder.
+pub fn external_subtitle_files(video_path: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(stem)) = (video_path.parent(), video_path.file_stem()) else {
+        return Vec::new();
+    };
+    let stem = stem.to_string_lossy();
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let is_subtitles = path.extension().is_some_and(|ext| {
+                SUBTITLE_EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known))
+            });
+            is_subtitles && (path.file_stem().is_some_and(|s| *s == *stem) || name.starts_with(&format!("{}.", stem)))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// The subtitle tracks of subtitle-only file `path` (probed as `info`),
+/// taking the language from a name like `movie.eng.mks` when untagged.
+pub fn external_tracks(path: &Path, video_path: &Path, info: VideoInfo) -> Vec<SubtitleTrack> {
+    let video_stem = video_path.file_stem().unwrap_or_default().to_string_lossy();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name_language = stem
+        .strip_prefix(video_stem.as_ref())
+        .and_then(|rest| rest.strip_prefix('.'))
+        .and_then(|rest| rest.split('.').next())
+        .filter(|language| !language.is_empty())
+        .map(str::to_string);
+    info.subtitles
+        .into_iter()
+        .map(|track| SubtitleTrack {
+            language: track.language.filter(|language| !is_undetermined(Some(language))).or_else(|| name_language.clone()),
+            source: Some(path.to_path_buf()),
+            ..track
+        })
+        .collect()
+}
+
+pub fn is_bitmap_subtitle(codec: &str) -> bool {
+    matches!(
+        codec,
+        "hdmv_pgs_subtitle" | "pgssub" | "dvd_subtitle" | "dvdsub" | "dvb_subtitle" | "dvbsub"
+    )
+}
+
+/// Runs ffprobe on `video_path` and summarizes its streams.
+pub fn get_video_info(runner: &dyn FfmpegRunner, video_path: &Path) -> Result<VideoInfo, String> {
+    parse_video_info(video_path, &run_ffprobe(runner, video_path)?)
+}
+
+/// ffprobe's JSON description of `video_path`'s format and streams.
+pub fn run_ffprobe(runner: &dyn FfmpegRunner, video_path: &Path) -> Result<Vec<u8>, String> {
+    let output = runner
+        .capture(Tool::Ffprobe, &ffprobe_args(video_path))
+        .map_err(|e| format!("Failed to run ffprobe: {}", e))?;
+
+    if !output.success() {
+        return Err("ffprobe failed".to_string());
+    }
+    Ok(output.stdout)
+}
+
+/// The ffprobe arguments [`run_ffprobe`] describes `video_path` with.
+pub fn ffprobe_args(video_path: &Path) -> Vec<String> {
+    [
+        "-v",
+        "quiet",
+        "-print_format",
+        "json",
+        "-show_format",
+        "-show_streams",
+        "-show_chapters",
+        "-analyzeduration",
+        "100000000",  // 100 seconds - helps with PGS detection
+        "-probesize",
+        "100000000",  // 100 MB
+        video_path.to_str().unwrap(),
+    ]
+    .iter()
+    .map(|a| a.to_string())
+    .collect()
+}
+
+/// Summarizes the [`run_ffprobe`] output `json` for `video_path`.
+pub fn parse_video_info(video_path: &Path, json: &[u8]) -> Result<VideoInfo, String> {
+    let probe_data: FFProbeOutput = serde_json::from_slice(json)
+        .map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
+
+    let mut video_codec = String::from("unknown");
+    let mut audio_codec = String::from("unknown");
+    let mut width = 0;
+    let mut height = 0;
+    let mut rotation = 0;
+    let mut frame_rate = 0.0;
+    let mut video_profile = None;
+    let mut pix_fmt = None;
+    let mut audio_bit_rate = None;
+    let mut subtitles = Vec::new();
+    let mut subtitle_stream_index = 0usize;
+    let mut audio_tracks: Vec<AudioTrack> = Vec::new();
+    let mut fonts = Vec::new();
+    let mut closed_captions = false;
+    let mut video_timing = StreamTiming::default();
+
+    for stream in &probe_data.streams {
+        match stream.codec_type.as_str() {
+            "video" => {
+                video_codec = stream.codec_name.clone();
+                width = stream.width;
+                height = stream.height;
+                // The display matrix turns counter-clockwise, the old tag clockwise
+                let degrees = stream
+                    .side_data_list
+                    .iter()
+                    .find_map(|side_data| side_data.rotation.map(|rotation| -rotation))
+                    .or_else(|| stream.tags.rotate.as_deref().and_then(|rotate| rotate.parse().ok()));
+                rotation = degrees.map_or(0, |degrees: f64| (degrees.round() as i64).rem_euclid(360) as u32);
+                frame_rate = stream
+                    .avg_frame_rate
+                    .as_deref()
+                    .map(parse_frame_rate)
+                    .unwrap_or(0.0);
+                video_profile = stream.profile.clone();
+                pix_fmt = stream.pix_fmt.clone();
+                closed_captions = stream.closed_captions != 0;
+                video_timing = StreamTiming::of(stream);
+            }
+            "audio" => {
+                audio_codec = stream.codec_name.clone();
+                audio_bit_rate = stream.bit_rate.as_deref().and_then(|b| b.parse().ok());
+                audio_tracks.push(AudioTrack {
+                    audio_index: audio_tracks.len(),
+                    codec: stream.codec_name.clone(),
+                    profile: stream.profile.clone(),
+                    language: stream.tags.language.clone(),
+                    sample_rate: stream.sample_rate.as_deref().and_then(|rate| rate.parse().ok()),
+                    channels: stream.channels,
+                    channel_layout: stream.channel_layout.clone(),
+                    timing: StreamTiming::of(stream),
+                });
+            }
+            "subtitle" => {
+                let is_bitmap = is_bitmap_subtitle(&stream.codec_name);
+                subtitles.push(SubtitleTrack {
+                    subtitle_index: subtitle_stream_index,
+                    codec: stream.codec_name.clone(),
+                    language: stream.tags.language.clone(),
+                    title: stream.tags.title.clone(),
+                    is_bitmap,
+                    source: None,
+                    forced: stream.disposition.forced != 0,
+                    language_detected: false,
+                });
+                subtitle_stream_index += 1;
+            }
+            "attachment" => {
+                if let Some(filename) = &stream.tags.filename {
+                    if is_font(&stream.codec_name, filename, stream.tags.mimetype.as_deref()) {
+                        fonts.push(FontAttachment {
+                            stream_index: stream.index,
+                            filename: filename.clone(),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(VideoInfo {
+        path: video_path.to_path_buf(),
+        video_codec,
+        audio_codec,
+        width,
+        height,
+        rotation,
+        video_profile,
+        pix_fmt,
+        frame_rate,
+        duration_secs: probe_data
+            .format
+            .duration
+            .as_deref()
+            .and_then(|d| d.parse().ok())
+            .unwrap_or(0.0),
+        bit_rate: probe_data.format.bit_rate.as_deref().and_then(|b| b.parse().ok()),
+        audio_bit_rate,
+        comment: probe_data.format.tags.comment,
+        container: probe_data.format.format_name,
+        subtitles,
+        audio_tracks,
+        closed_captions,
+        fonts,
+        chapters: probe_data
+            .chapters
+            .into_iter()
+            .filter_map(|chapter| {
+                Some(Chapter {
+                    start_secs: chapter.start_time.parse().ok()?,
+                    end_secs: chapter.end_time.parse().ok()?,
+                    title: chapter.tags.title.filter(|title| !title.trim().is_empty()),
+                })
+            })
+            .collect(),
+        video_timing,
+    })
+}
+
+/// Parses a Matroska `DURATION` tag, `HH:MM:SS.nnnnnnnnn`, into seconds.
+fn parse_timestamp(text: &str) -> Option<f64> {
+    text.split(':').try_fold(0.0, |secs, part| Some(secs * 60.0 + part.trim().parse::<f64>().ok()?))
+}
+
+/// Parses an ffprobe rational like `24000/1001` (or a plain number).
+fn parse_frame_rate(rate: &str) -> f64 {
+    match rate.split_once('/') {
+        Some((num, den)) => {
+            let num: f64 = num.parse().unwrap_or(0.0);
+            let den: f64 = den.parse().unwrap_or(0.0);
+            if den > 0.0 { num / den } else { 0.0 }
+        }
+        None => rate.parse().unwrap_or(0.0),
+    }
+}
@@ -0,0 +1,144 @@
+//! Post-encode quality gate for `--quality-check`.
+//!
+//! A few short segments of the output are compared against the same spans of
+//! the source with ffmpeg's `libvmaf` or `ssim` filter. The source is scaled
+//! (and frame-rate limited) to match the output first, so only encoder losses
+//! count. Burned-in subtitles lower the score slightly.
+
+use crate::plan::{TranscodePlan, VideoSettings};
+use crate::probe::VideoInfo;
+use crate::runner::{FfmpegRunner, Tool};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Seconds compared per segment.
+const SEGMENT_SECS: f64 = 10.0;
+
+/// Positions of the compared segments as fractions of the output duration.
+const SEGMENT_POSITIONS: &[f64] = &[0.2, 0.5, 0.8];
+
+/// Full-reference quality metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityMetric {
+    /// Netflix VMAF, 0-100 (needs an ffmpeg built with libvmaf)
+    Vmaf,
+    /// Structural similarity, 0-1
+    Ssim,
+}
+
+impl QualityMetric {
+    /// Score below which an encode is flagged when no threshold is given.
+    pub fn default_threshold(self) -> f64 {
+        match self {
+            QualityMetric::Vmaf => 90.0,
+            QualityMetric::Ssim => 0.95,
+        }
+    }
+
+    /// Display name, e.g. `VMAF`.
+    pub fn label(self) -> &'static str {
+        match self {
+            QualityMetric::Vmaf => "VMAF",
+            QualityMetric::Ssim => "SSIM",
+        }
+    }
+
+    /// ffmpeg filter that computes the metric.
+    pub fn filter(self) -> &'static str {
+        match self {
+            QualityMetric::Vmaf => "libvmaf",
+            QualityMetric::Ssim => "ssim",
+        }
+    }
+
+    /// Pulls the score out of the filter's summary line on stderr.
+    fn parse_score(self, stderr: &str) -> Option<f64> {
+        let (marker, line) = match self {
+            QualityMetric::Vmaf => ("VMAF score:", stderr.lines().rfind(|l| l.contains("VMAF score:"))?),
+            QualityMetric::Ssim => ("All:", stderr.lines().rfind(|l| l.contains("SSIM") && l.contains("All:"))?),
+        };
+        let rest = &line[line.find(marker)? + marker.len()..];
+        rest.split_whitespace().next()?.parse().ok()
+    }
+}
+
+/// Compares `plan.output` with its source and returns the mean score over
+/// the sampled segments.
+pub fn measure_quality(
+    runner: &dyn FfmpegRunner,
+    info: &VideoInfo,
+    plan: &TranscodePlan,
+    metric: QualityMetric,
+) -> Result<f64, String> {
+    let source_offset = plan.sample.map_or(0.0, |sample| sample.start_secs);
+    let duration = plan.output_duration(info);
+    let starts: Vec<f64> = if duration > SEGMENT_SECS * 4.0 {
+        SEGMENT_POSITIONS.iter().map(|p| duration * p).collect()
+    } else {
+        vec![0.0]
+    };
+
+    let (width, height) = plan.output_size;
+    let fps = plan
+        .max_frame_rate
+        .map(|fps| format!("fps={},", fps))
+        .unwrap_or_default();
+    let graph = format!(
+        "[0:v]format=yuv420p,setpts=PTS-STARTPTS[dist];\
+         [1:v]scale={}:{}:flags=bicubic,{}format=yuv420p,setpts=PTS-STARTPTS[ref];\
+         [dist][ref]{}",
+        width,
+        height,
+        fps,
+        metric.filter()
+    );
+
+    let mut scores = Vec::new();
+    for start in starts {
+        let args: Vec<String> = vec![
+            "-hide_banner".to_string(),
+            "-nostats".to_string(),
+            "-ss".to_string(),
+            format!("{:.3}", start),
+            "-t".to_string(),
+            SEGMENT_SECS.to_string(),
+            "-i".to_string(),
+            plan.output.to_str().unwrap().to_string(),
+            "-ss".to_string(),
+            format!("{:.3}", start + source_offset),
+            "-t".to_string(),
+            SEGMENT_SECS.to_string(),
+            "-i".to_string(),
+            info.path.to_str().unwrap().to_string(),
+            "-lavfi".to_string(),
+            graph.clone(),
+            "-f".to_string(),
+            "null".to_string(),
+            "-".to_string(),
+        ];
+        let output = runner
+            .capture(Tool::Ffmpeg, &args)
+            .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.success() {
+            let last = stderr.lines().last().unwrap_or("").trim();
+            return Err(format!("{} comparison failed: {}", metric.filter(), last));
+        }
+        let score = metric
+            .parse_score(&stderr)
+            .ok_or_else(|| format!("no {} score in ffmpeg output", metric.filter()))?;
+        scores.push(score);
+    }
+
+    Ok(scores.iter().sum::<f64>() / scores.len() as f64)
+}
+
+/// Raises `settings` one step for a re-encode after a failed check: CRF and
+/// NVENC CQ drop by 4 and the bitrates go up by half. Refit the level after.
+pub fn raise_quality(settings: &mut VideoSettings) {
+    settings.crf = settings.crf.saturating_sub(4);
+    settings.nvenc_cq = settings.nvenc_cq.saturating_sub(4);
+    settings.bitrate_kbps = settings.bitrate_kbps * 3 / 2;
+    settings.max_bitrate_kbps = settings.max_bitrate_kbps * 3 / 2;
+}
@@ -0,0 +1,180 @@
+//! Persistent work list for `convert`, so an interrupted batch resumes at the
+//! first unfinished file instead of starting over.
+//!
+//! The list lives in [`QUEUE_FILE`] inside the converted directory and is
+//! rewritten (via a temporary file and a rename) after every status change.
+//! It is deleted once every file has finished.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Queue file name inside the converted directory.
+pub const QUEUE_FILE: &str = ".tvcode-queue.json";
+
+/// Progress of one file in the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    Pending,
+    /// Started but not finished; `output` may be partial
+    Encoding,
+    Done,
+    Skipped,
+    Failed,
+}
+
+impl FileStatus {
+    fn unfinished(self) -> bool {
+        matches!(self, FileStatus::Pending | FileStatus::Encoding)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueEntry {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    /// Output being written while `Encoding`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// Made by joining the parts of a multi-part film; deleted when the run
+    /// finishes
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub joined: bool,
+}
+
+#[derive(Debug)]
+struct QueueState {
+    file: PathBuf,
+    entries: Vec<QueueEntry>,
+}
+
+impl QueueState {
+    fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(&self.entries).map_err(|e| e.to_string())?;
+        let temp = self.file.with_extension("json.tmp");
+        fs::write(&temp, json)
+            .and_then(|()| fs::rename(&temp, &self.file))
+            .map_err(|e| format!("Failed to write {}: {}", self.file.display(), e))
+    }
+}
+
+/// The persisted work list. Saving failures are reported once and turn the
+/// queue into a no-op; they never abort a transcode. Safe to share between
+/// parallel jobs.
+#[derive(Debug, Default)]
+pub struct RunQueue {
+    state: Mutex<Option<QueueState>>,
+}
+
+impl RunQueue {
+    /// Loads the queue left in `dir` by an interrupted run. `None` if there
+    /// is none or everything in it has finished.
+    pub fn resume(dir: &Path) -> Result<Option<RunQueue>, String> {
+        let file = dir.join(QUEUE_FILE);
+        let contents = match fs::read_to_string(&file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read {}: {}", file.display(), e)),
+        };
+        let entries: Vec<QueueEntry> =
+            serde_json::from_str(&contents).map_err(|e| format!("Invalid queue file {}: {}", file.display(), e))?;
+        if !entries.iter().any(|entry| entry.status.unfinished()) {
+            let _ = fs::remove_file(&file);
+            return Ok(None);
+        }
+        Ok(Some(RunQueue {
+            state: Mutex::new(Some(QueueState { file, entries })),
+        }))
+    }
+
+    /// Starts a queue of `files` in `dir`, all pending. `joined` are the
+    /// ones made by joining parts, so a resumed run still removes them.
+    pub fn create(dir: &Path, files: &[PathBuf], joined: &[PathBuf]) -> RunQueue {
+        let state = QueueState {
+            file: dir.join(QUEUE_FILE),
+            entries: files
+                .iter()
+                .map(|path| QueueEntry {
+                    path: path.clone(),
+                    status: FileStatus::Pending,
+                    output: None,
+                    joined: joined.contains(path),
+                })
+                .collect(),
+        };
+        let queue = RunQueue::default();
+        match state.save() {
+            Ok(()) => *queue.state.lock().unwrap() = Some(state),
+            Err(e) => eprintln!("   ⚠️  {}, an interrupted run will start over", e),
+        }
+        queue
+    }
+
+    /// Entries in queue order.
+    pub fn entries(&self) -> Vec<QueueEntry> {
+        let guard = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        guard.as_ref().map_or_else(Vec::new, |state| state.entries.clone())
+    }
+
+    /// Files still to do, in queue order.
+    pub fn remaining(&self) -> Vec<PathBuf> {
+        self.entries()
+            .into_iter()
+            .filter(|entry| entry.status.unfinished())
+            .map(|entry| entry.path)
+            .collect()
+    }
+
+    /// Files made by joining parts, to remove when the run finishes.
+    pub fn joined(&self) -> Vec<PathBuf> {
+        self.entries().into_iter().filter(|entry| entry.joined).map(|entry| entry.path).collect()
+    }
+
+    /// Records the status of `path`, with the output being written when
+    /// `Encoding`.
+    pub fn set(&self, path: &Path, status: FileStatus, output: Option<&Path>) {
+        let mut guard = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(state) = guard.as_mut() else {
+            return;
+        };
+        let Some(entry) = state.entries.iter_mut().find(|entry| entry.path == path) else {
+            return;
+        };
+        entry.status = status;
+        entry.output = output.map(Path::to_path_buf);
+        if let Err(e) = state.save() {
+            eprintln!("   ⚠️  {}", e);
+            *guard = None;
+        }
+    }
+
+    /// Deletes the queue file once nothing is left to do.
+    pub fn finish(&self) {
+        let guard = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(state) = guard.as_ref() {
+            if !state.entries.iter().any(|entry| entry.status.unfinished()) {
+                let _ = fs::remove_file(&state.file);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joined_files_survive_a_resume() {
+        let dir = crate::scratch_dir("queue-joined");
+        let (movie, joined) = (dir.join("movie.mkv"), dir.join("film.mkv"));
+        let queue = RunQueue::create(&dir, &[movie.clone(), joined.clone()], std::slice::from_ref(&joined));
+        queue.set(&movie, FileStatus::Done, None);
+
+        let resumed = RunQueue::resume(&dir).unwrap().unwrap();
+        assert_eq!(resumed.remaining(), std::slice::from_ref(&joined));
+        assert_eq!(resumed.joined(), [joined]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,185 @@
+//! `--remote`: handing files to other machines running tvcode, over SSH.
+//!
+//! The coordinator plans each file as usual, then a remote slot copies the
+//! source into a fresh temporary directory on the host with `scp`, runs
+//! `tvcode worker` there with the job (options and chosen subtitle) as JSON
+//! on stdin, and copies the results back beside the local output. The worker
+//! picks its own encoder, so each machine uses its best hardware. Its status
+//! lines are relayed prefixed with the host name.
+//!
+//! Hosts need passwordless SSH (keys or an agent) and tvcode and ffmpeg on
+//! their `PATH`.
+
+use crate::options::EncodeOptions;
+use crate::plan::TranscodePlan;
+use crate::probe::{SubtitleTrack, VideoInfo};
+use crate::status;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Everything a `tvcode worker` needs besides the input file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteJob {
+    pub options: EncodeOptions,
+    pub subtitle: Option<SubtitleTrack>,
+    /// Where the worker writes its output
+    pub output: PathBuf,
+}
+
+/// Name stem of the worker's output; renamed to the local stem on return.
+const OUTPUT_STEM: &str = "out";
+
+/// Quotes `text` for a POSIX shell.
+pub(crate) fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Runs `command` through the remote shell and returns its stdout.
+pub(crate) fn ssh(host: &str, command: &str) -> Result<String, String> {
+    let output = Command::new("ssh")
+        .args(["-o", "BatchMode=yes", host, command])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to run ssh: {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format!("{}: {}", host, stderr.lines().last().unwrap_or("ssh failed").trim()))
+    }
+}
+
+fn scp(from: &str, to: &str) -> Result<(), String> {
+    let status = Command::new("scp")
+        .args(["-q", "-r", "-B", from, to])
+        .status()
+        .map_err(|e| format!("Failed to run scp: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("Copying {} to {} failed", from, to))
+    }
+}
+
+/// Encodes `info` on `host` according to `plan`, leaving the results where
+/// a local encode would have. The remote temporary directory is always
+/// removed.
+pub fn encode_remote(
+    host: &str,
+    info: &VideoInfo,
+    plan: &TranscodePlan,
+    options: &EncodeOptions,
+) -> Result<(), String> {
+    let dir = ssh(host, "mktemp -d \"${TMPDIR:-/tmp}/tvcode.XXXXXX\"")?;
+    let result = run_job(host, &dir, info, plan, options);
+    if let Err(e) = ssh(host, &format!("rm -rf {}", shell_quote(&dir))) {
+        eprintln!("   ⚠️  Could not clean up {} on {}", dir, e);
+    }
+    result
+}
+
+fn run_job(
+    host: &str,
+    dir: &str,
+    info: &VideoInfo,
+    plan: &TranscodePlan,
+    options: &EncodeOptions,
+) -> Result<(), String> {
+    let source = plan.input.as_deref().unwrap_or(&info.path);
+    let input = match source.extension() {
+        Some(ext) => format!("{}/in.{}", dir, ext.to_string_lossy()),
+        None => format!("{}/in", dir),
+    };
+    let output = match plan.output.extension() {
+        Some(ext) => format!("{}/out/{}.{}", dir, OUTPUT_STEM, ext.to_string_lossy()),
+        None => format!("{}/out/{}", dir, OUTPUT_STEM),
+    };
+    ssh(host, &format!("mkdir {}", shell_quote(&format!("{}/out", dir))))?;
+
+    status!("   📡 Sending to {}", host);
+    scp(source.to_str().unwrap(), &format!("{}:{}", host, input))?;
+    let mut subtitle = plan.subtitle.clone();
+    if let Some(track) = subtitle.as_mut() {
+        if let Some(source) = &track.source {
+            let ext = source.extension().unwrap_or_default().to_string_lossy();
+            let remote = format!("{}/subtitles.{}", dir, ext);
+            scp(source.to_str().unwrap(), &format!("{}:{}", host, remote))?;
+            track.source = Some(PathBuf::from(remote));
+        }
+    }
+    let mut watermark = options.watermark.clone();
+    if let Some(watermark) = watermark.as_mut() {
+        let ext = watermark.image.extension().unwrap_or_default().to_string_lossy();
+        let remote = format!("{}/watermark.{}", dir, ext);
+        scp(watermark.image.to_str().unwrap(), &format!("{}:{}", host, remote))?;
+        watermark.image = PathBuf::from(remote);
+    }
+
+    // Device-specific choices belong to the worker's machine
+    let job = RemoteJob {
+        options: EncodeOptions {
+            vaapi_device: None,
+            encoders: Vec::new(),
+            gpus: Vec::new(),
+            watermark,
+            ..options.clone()
+        },
+        subtitle,
+        output: PathBuf::from(&output),
+    };
+    let job = serde_json::to_string(&job).map_err(|e| e.to_string())?;
+    let mut child = Command::new("ssh")
+        .args(["-o", "BatchMode=yes", host, &format!("tvcode worker {}", shell_quote(&input))])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run ssh: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "{}", job).map_err(|e| format!("Failed to send the job to {}: {}", host, e))?;
+    }
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            status!("   [{}] {}", host, line.trim_start());
+        }
+    }
+    let status = child.wait().map_err(|e| format!("Failed to run ssh: {}", e))?;
+    if !status.success() {
+        return Err(format!("Remote encode on {} failed", host));
+    }
+
+    status!("   📡 Fetching results from {}", host);
+    let local_dir = plan.output.with_file_name(format!(
+        ".{}.remote",
+        plan.output.file_name().unwrap().to_string_lossy()
+    ));
+    let _ = fs::remove_dir_all(&local_dir);
+    let parent = local_dir.parent().unwrap();
+    fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    let fetched = scp(&format!("{}:{}/out", host, dir), local_dir.to_str().unwrap())
+        .and_then(|()| move_results(&local_dir, &plan.output));
+    let _ = fs::remove_dir_all(&local_dir);
+    fetched
+}
+
+/// Moves every fetched file into place beside `output`, renaming the
+/// worker's `out` stem to the local one (`out.mp4`, `out-thumb.jpg`, ...).
+fn move_results(fetched: &Path, output: &Path) -> Result<(), String> {
+    let stem = output.file_stem().unwrap().to_string_lossy();
+    let entries = fs::read_dir(fetched).map_err(|e| format!("Failed to read {}: {}", fetched.display(), e))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let local_name = match name.strip_prefix(OUTPUT_STEM) {
+            Some(rest) => format!("{}{}", stem, rest),
+            None => name,
+        };
+        let target = output.with_file_name(local_name);
+        if target.is_dir() {
+            let _ = fs::remove_dir_all(&target);
+        }
+        fs::rename(entry.path(), &target).map_err(|e| format!("Failed to move {}: {}", target.display(), e))?;
+    }
+    Ok(())
+}
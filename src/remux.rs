@@ -0,0 +1,748 @@
+//! In-process MKV → MP4 repackaging for copy-only remuxes.
+//!
+//! Most files that only need a remux are Matroska with H.264 video and AAC
+//! audio. [`Remuxer`] moves their frames into an MP4 without ffmpeg: it reads
+//! them with `matroska-demuxer`, writes them with the `mp4` crate and then
+//! puts the index in front of the media data, as `-movflags +faststart` does.
+//! Progress is known frame by frame.
+//!
+//! [`Remuxer::open`] refuses anything else (other codecs, compressed or
+//! encrypted tracks, H.264 with several parameter sets, HE-AAC, chapters) so
+//! the caller can leave the file to ffmpeg.
+
+use crate::options::Container;
+use crate::plan::OUTPUT_COMMENT;
+use matroska_demuxer::{Frame, MatroskaFile, TrackEntry, TrackType};
+use mp4::{
+    AacConfig, AudioObjectType, AvcConfig, BoxHeader, Bytes, ChannelConfig, FourCC, MediaConfig, MoovBox, Mp4Config,
+    Mp4Sample, Mp4Writer, ReadBox, SampleFreqIndex, TrackConfig, WriteBox,
+};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Ticks per second of output video tracks
+const VIDEO_TIMESCALE: u32 = 90_000;
+/// Ticks per second of the movie header and edit lists
+const MOVIE_TIMESCALE: u32 = 1000;
+/// Video frames read ahead to work out decode times; B-frame reordering
+/// stays well within this
+const REORDER_FRAMES: usize = 32;
+/// Samples in each AAC frame
+const AAC_FRAME_SAMPLES: u32 = 1024;
+
+/// A Matroska file opened for remuxing, with the tracks that go into the
+/// output.
+pub struct Remuxer {
+    mkv: MatroskaFile<BufReader<File>>,
+    tracks: Vec<Track>,
+    /// Nanoseconds per Matroska timestamp tick
+    timestamp_scale: u64,
+    title: Option<String>,
+}
+
+/// An output track and what has been written to it.
+struct Track {
+    /// Matroska track number its frames carry
+    number: u64,
+    config: TrackConfig,
+    /// Earliest presentation time, in nanoseconds
+    start_ns: Option<u64>,
+    /// Decode times and durations of video frames; audio has fixed-length
+    /// frames
+    reorder: Option<Reorder>,
+}
+
+impl Remuxer {
+    /// Opens `path` for a remux keeping its first video track and the audio
+    /// tracks at `audio` (indices among audio tracks), in that order. The
+    /// error says why the file cannot be remuxed here.
+    pub fn open(path: &Path, audio: &[usize]) -> Result<Remuxer, String> {
+        let file = File::open(path).map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+        let mkv = MatroskaFile::open(BufReader::new(file)).map_err(|e| format!("not a readable Matroska file: {}", e))?;
+        if mkv.chapters().is_some_and(|editions| !editions.is_empty()) {
+            return Err("chapters would be lost".to_string());
+        }
+        let video = mkv.tracks().iter().find(|track| track.track_type() == TrackType::Video).ok_or("no video track")?;
+        let mut tracks = vec![video_track(video)?];
+        let audio_tracks: Vec<&TrackEntry> =
+            mkv.tracks().iter().filter(|track| track.track_type() == TrackType::Audio).collect();
+        for &index in audio {
+            let track = audio_tracks.get(index).ok_or_else(|| format!("no audio track {}", index))?;
+            tracks.push(audio_track(track)?);
+        }
+        let timestamp_scale = mkv.info().timestamp_scale().get();
+        let title = mkv.info().title().map(str::to_string);
+        Ok(Remuxer { mkv, tracks, timestamp_scale, title })
+    }
+
+    /// Writes the tracks to `output` as a faststart MP4 for `container`,
+    /// calling `progress` with the seconds of the source written so far.
+    pub fn remux(mut self, output: &Path, container: Container, mut progress: impl FnMut(f64)) -> Result<(), String> {
+        let muxed = muxed_path(output);
+        let result = self
+            .write_media(&muxed, container, &mut progress)
+            .and_then(|delays| faststart(&muxed, output, &delays, &user_data(self.title.as_deref())));
+        let _ = fs::remove_file(&muxed);
+        result
+    }
+
+    /// Writes every frame to `path` in an MP4 with the index at the end,
+    /// returning how long each track starts after the earliest one, in
+    /// movie ticks.
+    fn write_media(
+        &mut self,
+        path: &Path,
+        container: Container,
+        progress: &mut dyn FnMut(f64),
+    ) -> Result<Vec<u64>, String> {
+        let file = File::create(path).map_err(|e| format!("cannot create {}: {}", path.display(), e))?;
+        let mut writer = Mp4Writer::write_start(BufWriter::new(file), &mp4_config(container)).map_err(mux_error)?;
+        for track in &self.tracks {
+            writer.add_track(&track.config).map_err(mux_error)?;
+        }
+
+        let mut frame = Frame::default();
+        while self.mkv.next_frame(&mut frame).map_err(|e| format!("cannot read a frame: {}", e))? {
+            let Some(index) = self.tracks.iter().position(|track| track.number == frame.track) else {
+                continue;
+            };
+            let track = &mut self.tracks[index];
+            let ns = frame.timestamp.saturating_mul(self.timestamp_scale);
+            track.start_ns = Some(track.start_ns.map_or(ns, |start| start.min(ns)));
+            let bytes = Bytes::from(std::mem::take(&mut frame.data));
+            let sample = match &mut track.reorder {
+                Some(reorder) => {
+                    let is_sync = frame.is_keyframe.unwrap_or_else(|| has_idr(&bytes));
+                    reorder.push(ticks(ns, VIDEO_TIMESCALE), is_sync, bytes)
+                }
+                None => Some(Mp4Sample {
+                    start_time: 0,
+                    duration: AAC_FRAME_SAMPLES,
+                    rendering_offset: 0,
+                    is_sync: true,
+                    bytes,
+                }),
+            };
+            if let Some(sample) = sample {
+                writer.write_sample(index as u32 + 1, &sample).map_err(mux_error)?;
+            }
+            progress(ns as f64 / 1e9);
+        }
+
+        for (index, track) in self.tracks.iter_mut().enumerate() {
+            for sample in track.reorder.as_mut().map(Reorder::finish).unwrap_or_default() {
+                writer.write_sample(index as u32 + 1, &sample).map_err(mux_error)?;
+            }
+        }
+        writer.write_end().map_err(mux_error)?;
+        writer.into_writer().flush().map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+
+        if self.tracks[0].start_ns.is_none() {
+            return Err("no video frames".to_string());
+        }
+        let first = self.tracks.iter().filter_map(|track| track.start_ns).min().unwrap_or(0);
+        Ok(self
+            .tracks
+            .iter()
+            .map(|track| ticks(track.start_ns.map_or(0, |start| start - first), MOVIE_TIMESCALE))
+            .collect())
+    }
+}
+
+/// The output track for the H.264 track `entry`, when its frames use 4-byte
+/// NAL lengths and it has one SPS and PPS, which is all the `mp4` crate
+/// writes.
+fn video_track(entry: &TrackEntry) -> Result<Track, String> {
+    if entry.codec_id() != "V_MPEG4/ISO/AVC" {
+        return Err(format!("video codec {} is not H.264", entry.codec_id()));
+    }
+    check_unencoded(entry)?;
+    let (sps, pps) = entry.codec_private().and_then(parse_avcc).ok_or("unsupported H.264 configuration")?;
+    let video = entry.video().ok_or("video track without a picture size")?;
+    let width = u16::try_from(video.pixel_width().get()).map_err(|_| "picture too wide")?;
+    let height = u16::try_from(video.pixel_height().get()).map_err(|_| "picture too tall")?;
+    let default_duration = entry.default_duration().map_or(0, |ns| ticks(ns.get(), VIDEO_TIMESCALE));
+    Ok(Track {
+        number: entry.track_number().get(),
+        config: TrackConfig {
+            track_type: mp4::TrackType::Video,
+            timescale: VIDEO_TIMESCALE,
+            language: language(entry),
+            media_conf: MediaConfig::AvcConfig(AvcConfig { width, height, seq_param_set: sps, pic_param_set: pps }),
+        },
+        start_ns: None,
+        reorder: Some(Reorder::new(u32::try_from(default_duration).unwrap_or(0))),
+    })
+}
+
+/// The output track for the AAC-LC track `entry`, whose `esds` the `mp4`
+/// crate builds from the sample rate and channel layout. Rates above 48 kHz
+/// and 7.1 do not fit the fields it writes them to.
+fn audio_track(entry: &TrackEntry) -> Result<Track, String> {
+    if entry.codec_id() != "A_AAC" {
+        return Err(format!("audio codec {} is not AAC", entry.codec_id()));
+    }
+    check_unencoded(entry)?;
+    // The two-byte AudioSpecificConfig: object type, rate index, channel
+    // layout and the 960-sample frame flag
+    let Some(&[first, second]) = entry.codec_private() else {
+        return Err("unsupported AAC configuration".to_string());
+    };
+    let config = (first >> 3, ((first & 0x07) << 1) | (second >> 7), (second >> 3) & 0x0f, second & 0x04);
+    let (freq_index, chan_conf) = match config {
+        (2, freq @ 3..=12, channels @ 1..=6, 0) => (
+            SampleFreqIndex::try_from(freq).map_err(mux_error)?,
+            ChannelConfig::try_from(channels).map_err(mux_error)?,
+        ),
+        _ => return Err("AAC profile, rate or channel layout it cannot carry over".to_string()),
+    };
+    Ok(Track {
+        number: entry.track_number().get(),
+        config: TrackConfig {
+            track_type: mp4::TrackType::Audio,
+            timescale: freq_index.freq(),
+            language: language(entry),
+            media_conf: MediaConfig::AacConfig(AacConfig {
+                bitrate: 0,
+                profile: AudioObjectType::AacLowComplexity,
+                freq_index,
+                chan_conf,
+            }),
+        },
+        start_ns: None,
+        reorder: None,
+    })
+}
+
+/// Fails tracks whose frames are stored compressed or encrypted.
+fn check_unencoded(entry: &TrackEntry) -> Result<(), String> {
+    match entry.content_encodings() {
+        Some(encodings) if !encodings.is_empty() => {
+            Err(format!("track {} is compressed or encrypted", entry.track_number()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The SPS and PPS of an `avcC` record with 4-byte NAL lengths and exactly
+/// one of each.
+fn parse_avcc(avcc: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let length = |at: usize| Some(usize::from(u16::from_be_bytes([*avcc.get(at)?, *avcc.get(at + 1)?])));
+    if avcc.first() != Some(&1) || avcc.get(4)? & 0x03 != 3 || avcc.get(5)? & 0x1f != 1 {
+        return None;
+    }
+    let sps = avcc.get(8..8 + length(6)?)?;
+    let pps_count = 8 + sps.len();
+    if *avcc.get(pps_count)? != 1 || sps.len() < 4 {
+        return None;
+    }
+    let pps = avcc.get(pps_count + 3..pps_count + 3 + length(pps_count + 1)?)?;
+    Some((sps.to_vec(), pps.to_vec()))
+}
+
+/// The ISO 639-2 language of `entry` as MP4 stores it. Matroska tracks
+/// without one are English.
+fn language(entry: &TrackEntry) -> String {
+    let code = entry.language().unwrap_or("eng");
+    match code.get(..3) {
+        Some(code) if code.bytes().all(|byte| byte.is_ascii_lowercase()) => code.to_string(),
+        _ => "und".to_string(),
+    }
+}
+
+/// Whether an H.264 frame of 4-byte-length NAL units holds an IDR slice, for
+/// blocks that carry no keyframe flag.
+fn has_idr(mut data: &[u8]) -> bool {
+    while data.len() > 4 {
+        if data[4] & 0x1f == 5 {
+            return true;
+        }
+        let length = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        data = data.get(4 + length..).unwrap_or_default();
+    }
+    false
+}
+
+/// `ns` nanoseconds in ticks of `timescale`.
+fn ticks(ns: u64, timescale: u32) -> u64 {
+    (u128::from(ns) * u128::from(timescale) / 1_000_000_000) as u64
+}
+
+fn mux_error(e: mp4::Error) -> String {
+    format!("cannot write the MP4: {}", e)
+}
+
+/// `ftyp` brands as ffmpeg's `mp4` and `ipod` muxers write them.
+fn mp4_config(container: Container) -> Mp4Config {
+    let (major, compatible) = match container {
+        Container::Mp4 => (*b"isom", [*b"isom", *b"iso2", *b"avc1", *b"mp41"]),
+        Container::M4v => (*b"M4V ", [*b"M4V ", *b"M4A ", *b"mp42", *b"isom"]),
+    };
+    Mp4Config {
+        major_brand: FourCC::from(major),
+        minor_version: 512,
+        compatible_brands: compatible.into_iter().map(FourCC::from).collect(),
+        timescale: MOVIE_TIMESCALE,
+    }
+}
+
+/// Turns video frames, read in decode order with their presentation times,
+/// into samples with durations and composition offsets. A frame's decode
+/// time is the earliest presentation time still waiting for a frame.
+struct Reorder {
+    /// Presentation time, keyframe flag and data of frames read ahead
+    frames: VecDeque<(u64, bool, Bytes)>,
+    times: BinaryHeap<Reverse<u64>>,
+    /// The last frame given a decode time, which the next one ends
+    held: Option<Held>,
+    /// Length of the last sample written, for the final one
+    duration: u32,
+}
+
+struct Held {
+    dts: u64,
+    pts: u64,
+    is_sync: bool,
+    bytes: Bytes,
+}
+
+impl Held {
+    fn sample(self, duration: u32) -> Mp4Sample {
+        let offset = self.pts as i64 - self.dts as i64;
+        Mp4Sample {
+            start_time: self.dts,
+            duration,
+            rendering_offset: offset.clamp(i32::MIN.into(), i32::MAX.into()) as i32,
+            is_sync: self.is_sync,
+            bytes: self.bytes,
+        }
+    }
+}
+
+impl Reorder {
+    /// Starts a track whose frames last `default_duration` ticks, if known.
+    fn new(default_duration: u32) -> Reorder {
+        Reorder {
+            frames: VecDeque::new(),
+            times: BinaryHeap::new(),
+            held: None,
+            duration: default_duration,
+        }
+    }
+
+    /// Takes the next frame, returning the sample it completes, if any.
+    fn push(&mut self, pts: u64, is_sync: bool, bytes: Bytes) -> Option<Mp4Sample> {
+        self.frames.push_back((pts, is_sync, bytes));
+        self.times.push(Reverse(pts));
+        if self.frames.len() > REORDER_FRAMES {
+            self.advance()
+        } else {
+            None
+        }
+    }
+
+    /// The samples still to write once every frame is in.
+    fn finish(&mut self) -> Vec<Mp4Sample> {
+        let mut samples = Vec::new();
+        while !self.frames.is_empty() {
+            samples.extend(self.advance());
+        }
+        if let Some(last) = self.held.take() {
+            samples.push(last.sample(self.duration));
+        }
+        samples
+    }
+
+    /// Gives the oldest frame its decode time, completing the one before.
+    fn advance(&mut self) -> Option<Mp4Sample> {
+        let (pts, is_sync, bytes) = self.frames.pop_front()?;
+        let Reverse(earliest) = self.times.pop()?;
+        // Frames reordered further than the window still must not go back
+        let dts = self.held.as_ref().map_or(earliest, |held| earliest.max(held.dts));
+        let held = self.held.replace(Held { dts, pts, is_sync, bytes })?;
+        let duration = u32::try_from(dts - held.dts).unwrap_or(u32::MAX);
+        if duration > 0 {
+            self.duration = duration;
+        }
+        Some(held.sample(duration))
+    }
+}
+
+/// Where the media data is written before the index is moved in front of it.
+fn muxed_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".mdat");
+    output.with_file_name(name)
+}
+
+/// Copies the MP4 at `muxed` to `output` with its `moov` ahead of its
+/// `mdat`, delaying each track by `delays` (movie ticks) with an edit list
+/// and adding `udta`.
+fn faststart(muxed: &Path, output: &Path, delays: &[u64], udta: &[u8]) -> Result<(), String> {
+    let read_error = |e: io::Error| format!("cannot read {}: {}", muxed.display(), e);
+    let mut input = BufReader::new(File::open(muxed).map_err(read_error)?);
+    let (mdat, moov) = find_boxes(&mut input).map_err(read_error)?;
+    input.seek(SeekFrom::Start(moov.start)).map_err(read_error)?;
+    let header = BoxHeader::read(&mut input).map_err(mux_error)?;
+    let mut moov = MoovBox::read_box(&mut input, header.size).map_err(mux_error)?;
+    let edits = prepare_tracks(&mut moov, delays);
+
+    // Chunk offsets move on by the size of the index put before them, which
+    // itself grows when they no longer fit in 32 bits
+    let mut index = Vec::new();
+    loop {
+        let mut shifted = moov.clone();
+        shift_chunks(&mut shifted, index.len() as u64);
+        let next = moov_bytes(&shifted, &edits, udta).map_err(mux_error)?;
+        let settled = next.len() == index.len();
+        index = next;
+        if settled {
+            break;
+        }
+    }
+
+    let write_error = |e: io::Error| format!("cannot write {}: {}", output.display(), e);
+    let mut out = BufWriter::new(File::create(output).map_err(write_error)?);
+    input.seek(SeekFrom::Start(0)).map_err(read_error)?;
+    io::copy(&mut (&mut input).take(mdat.start), &mut out).map_err(write_error)?;
+    out.write_all(&index).map_err(write_error)?;
+    input.seek(SeekFrom::Start(mdat.start)).map_err(read_error)?;
+    io::copy(&mut (&mut input).take(mdat.end - mdat.start), &mut out).map_err(write_error)?;
+    out.flush().map_err(write_error)
+}
+
+/// Where the `mdat` and `moov` boxes of the MP4 `input` lie.
+fn find_boxes(input: &mut (impl Read + Seek)) -> io::Result<(Range<u64>, Range<u64>)> {
+    let end = input.seek(SeekFrom::End(0))?;
+    let (mut mdat, mut moov) = (None, None);
+    let mut position = 0;
+    while position + 8 <= end {
+        input.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 16];
+        input.read_exact(&mut header[..8])?;
+        let mut length = u64::from(u32::from_be_bytes([header[0], header[1], header[2], header[3]]));
+        if length == 1 {
+            input.read_exact(&mut header[8..])?;
+            length = u64::from_be_bytes(header[8..].try_into().unwrap());
+        }
+        if length < 8 {
+            break;
+        }
+        match &header[4..8] {
+            b"mdat" => mdat = Some(position..position + length),
+            b"moov" => moov = Some(position..position + length),
+            _ => {}
+        }
+        position += length;
+    }
+    match (mdat, moov) {
+        (Some(mdat), Some(moov)) => Ok((mdat, moov)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "no mdat or moov box")),
+    }
+}
+
+/// Fixes up the track headers of `moov` and returns the `edts` box of each.
+/// Offsets are shifted so none is negative, with the edit list skipping the
+/// lead this gives; tracks starting late begin with an empty edit; the
+/// first audio track is the enabled one of their group, as ffmpeg marks
+/// them.
+fn prepare_tracks(moov: &mut MoovBox, delays: &[u64]) -> Vec<Vec<u8>> {
+    let mut edits = Vec::new();
+    let mut audio_seen = false;
+    for (trak, &delay) in moov.traks.iter_mut().zip(delays) {
+        let lead = match &mut trak.mdia.minf.stbl.ctts {
+            Some(ctts) => {
+                let lowest = ctts.entries.iter().map(|entry| entry.sample_offset).min().unwrap_or(0).min(0);
+                ctts.entries.iter_mut().for_each(|entry| entry.sample_offset -= lowest);
+                u64::from(lowest.unsigned_abs())
+            }
+            None => 0,
+        };
+        let timescale = u64::from(trak.mdia.mdhd.timescale.max(1));
+        let media = trak.mdia.mdhd.duration * u64::from(MOVIE_TIMESCALE) / timescale;
+        trak.tkhd.duration = delay + media;
+        trak.tkhd.version = u8::from(trak.tkhd.duration > u64::from(u32::MAX));
+        if trak.mdia.hdlr.handler_type == FourCC::from(*b"soun") {
+            trak.tkhd.alternate_group = 1;
+            trak.tkhd.flags = if audio_seen { 0x2 } else { 0x3 };
+            audio_seen = true;
+        } else {
+            trak.tkhd.flags = 0x3;
+        }
+        edits.push(edit_list(delay, media, lead));
+    }
+    moov.mvhd.duration = moov.traks.iter().map(|trak| trak.tkhd.duration).max().unwrap_or(0);
+    moov.mvhd.version = u8::from(moov.mvhd.duration > u64::from(u32::MAX));
+    moov.mvhd.next_track_id = moov.traks.len() as u32 + 1;
+    edits
+}
+
+/// An `edts` box playing `duration` movie ticks of media from `media_time`
+/// after `delay` ticks of nothing, or nothing when neither is needed.
+fn edit_list(delay: u64, duration: u64, media_time: u64) -> Vec<u8> {
+    if delay == 0 && media_time == 0 {
+        return Vec::new();
+    }
+    let mut entries = Vec::new();
+    if delay > 0 {
+        entries.push((delay, -1));
+    }
+    entries.push((duration, media_time as i64));
+    let wide = entries.iter().any(|&(length, time)| length > u64::from(u32::MAX) || time > i64::from(i32::MAX));
+    let mut elst = vec![u8::from(wide), 0, 0, 0];
+    elst.extend((entries.len() as u32).to_be_bytes());
+    for (length, time) in entries {
+        if wide {
+            elst.extend(length.to_be_bytes());
+            elst.extend(time.to_be_bytes());
+        } else {
+            elst.extend((length as u32).to_be_bytes());
+            elst.extend((time as i32).to_be_bytes());
+        }
+        // Normal playback rate, 1.0
+        elst.extend([0, 1, 0, 0]);
+    }
+    mp4_box(b"edts", &mp4_box(b"elst", &elst))
+}
+
+/// Moves every chunk offset of `moov` `shift` bytes on, switching a track to
+/// 64-bit offsets when they no longer fit in 32.
+fn shift_chunks(moov: &mut MoovBox, shift: u64) {
+    for trak in &mut moov.traks {
+        let stbl = &mut trak.mdia.minf.stbl;
+        if let Some(stco) = &mut stbl.stco {
+            if stco.entries.iter().all(|&offset| u64::from(offset) + shift <= u64::from(u32::MAX)) {
+                stco.entries.iter_mut().for_each(|offset| *offset += shift as u32);
+                continue;
+            }
+            let entries = stco.entries.iter().map(|&offset| u64::from(offset)).collect();
+            stbl.stco = None;
+            stbl.co64 = Some(Default::default());
+            if let Some(co64) = &mut stbl.co64 {
+                co64.entries = entries;
+            }
+        }
+        if let Some(co64) = &mut stbl.co64 {
+            co64.entries.iter_mut().for_each(|offset| *offset += shift);
+        }
+    }
+}
+
+/// `moov` serialized with an edit list in each track and `udta` at the end,
+/// neither of which the `mp4` crate writes.
+fn moov_bytes(moov: &MoovBox, edits: &[Vec<u8>], udta: &[u8]) -> mp4::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    moov.mvhd.write_box(&mut body)?;
+    for (trak, edts) in moov.traks.iter().zip(edits) {
+        let mut trak_body = Vec::new();
+        trak.tkhd.write_box(&mut trak_body)?;
+        trak_body.extend(edts);
+        trak.mdia.write_box(&mut trak_body)?;
+        body.extend(mp4_box(b"trak", &trak_body));
+    }
+    body.extend(udta);
+    Ok(mp4_box(b"moov", &body))
+}
+
+/// A `udta` box of iTunes-style tags: the comment that marks tvcode's
+/// outputs, and the title if there is one.
+fn user_data(title: Option<&str>) -> Vec<u8> {
+    let item = |name: &[u8; 4], text: &str| {
+        // Type 1 is UTF-8 text, in the default locale
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+        data.extend(text.as_bytes());
+        mp4_box(name, &mp4_box(b"data", &data))
+    };
+    let mut items = item(b"\xa9cmt", &format!("{} {}", OUTPUT_COMMENT, env!("CARGO_PKG_VERSION")));
+    if let Some(title) = title {
+        items.extend(item(b"\xa9nam", title));
+    }
+    let mut handler = vec![0; 8];
+    handler.extend(b"mdirappl");
+    handler.extend([0; 9]);
+    let mut meta = vec![0; 4];
+    meta.extend(mp4_box(b"hdlr", &handler));
+    meta.extend(mp4_box(b"ilst", &items));
+    mp4_box(b"udta", &mp4_box(b"meta", &meta))
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(8 + body.len());
+    bytes.extend((8 + body.len() as u32).to_be_bytes());
+    bytes.extend(kind);
+    bytes.extend(body);
+    bytes
+}
+
+/// Matroska files built by hand, for tests here and of the transcoder.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    /// An EBML element: the ID as written, then the size in 8 bytes.
+    fn element(id: u32, body: &[u8]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = id.to_be_bytes().into_iter().skip_while(|&byte| byte == 0).collect();
+        bytes.push(0x01);
+        bytes.extend(&(body.len() as u64).to_be_bytes()[1..]);
+        bytes.extend(body);
+        bytes
+    }
+
+    fn uint(id: u32, value: u64) -> Vec<u8> {
+        element(id, &value.to_be_bytes())
+    }
+
+    fn simple_block(track: u8, timestamp: i16, keyframe: bool, data: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x80 | track];
+        body.extend(timestamp.to_be_bytes());
+        body.push(if keyframe { 0x80 } else { 0 });
+        body.extend(data);
+        element(0xA3, &body)
+    }
+
+    pub fn avcc() -> Vec<u8> {
+        let sps = [0x67, 0x64, 0x00, 0x28, 0xAC];
+        let pps = [0x68, 0xEE, 0x3C, 0x80];
+        let mut avcc = vec![1, 0x64, 0x00, 0x28, 0xFF, 0xE1, 0, sps.len() as u8];
+        avcc.extend(sps);
+        avcc.extend([1, 0, pps.len() as u8]);
+        avcc.extend(pps);
+        avcc
+    }
+
+    /// A Matroska file with an H.264 track of 7 frames in I P B B P B B
+    /// order at 25 fps, and a Swedish AAC track of 10 frames starting 20ms
+    /// later.
+    pub fn sample_mkv(video_codec: &str) -> Vec<u8> {
+        let header = [
+            uint(0x4286, 1),
+            uint(0x42F7, 1),
+            uint(0x42F2, 4),
+            uint(0x42F3, 8),
+            element(0x4282, b"matroska"),
+            uint(0x4287, 4),
+            uint(0x4285, 2),
+        ]
+        .concat();
+        let info = [
+            uint(0x2AD7B1, 1_000_000),
+            element(0x4D80, b"test"),
+            element(0x5741, b"test"),
+            element(0x7BA9, b"Home movie"),
+        ]
+        .concat();
+        let video = [
+            uint(0xD7, 1),
+            uint(0x73C5, 1),
+            uint(0x83, 1),
+            element(0x86, video_codec.as_bytes()),
+            element(0x63A2, &avcc()),
+            uint(0x23E383, 40_000_000),
+            element(0xE0, &[uint(0xB0, 1920), uint(0xBA, 1080)].concat()),
+        ]
+        .concat();
+        let audio = [
+            uint(0xD7, 2),
+            uint(0x73C5, 2),
+            uint(0x83, 2),
+            element(0x86, b"A_AAC"),
+            element(0x63A2, &[0x11, 0x90]),
+            element(0x22B59C, b"swe"),
+            element(0xE1, &[element(0xB5, &48_000f64.to_be_bytes()), uint(0x9F, 2)].concat()),
+        ]
+        .concat();
+        let tracks = [element(0xAE, &video), element(0xAE, &audio)].concat();
+        let mut cluster = uint(0xE7, 0);
+        for (number, pts) in [0, 120, 40, 80, 240, 160, 200].into_iter().enumerate() {
+            let nal = if number == 0 { 0x65 } else { 0x41 };
+            cluster.extend(simple_block(1, pts, number == 0, &[0, 0, 0, 2, nal, number as u8]));
+        }
+        for number in 0..10 {
+            cluster.extend(simple_block(2, 20 + number * 64 / 3, true, &[0x21, number as u8]));
+        }
+        let segment =
+            [element(0x1549A966, &info), element(0x1654AE6B, &tracks), element(0x1F43B675, &cluster)].concat();
+        [element(0x1A45DFA3, &header), element(0x18538067, &segment)].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::{avcc, sample_mkv};
+    use super::*;
+
+    #[test]
+    fn remuxes_h264_and_aac_into_a_faststart_mp4() {
+        let dir = crate::scratch_dir("remux");
+        let source = dir.join("movie.mkv");
+        let output = dir.join("movie.mp4");
+        fs::write(&source, sample_mkv("V_MPEG4/ISO/AVC")).unwrap();
+        let mut reached = 0.0;
+        Remuxer::open(&source, &[0]).unwrap().remux(&output, Container::Mp4, |secs| reached = secs).unwrap();
+        assert_eq!(reached, 0.212);
+        assert!(!muxed_path(&output).exists());
+
+        let mut file = BufReader::new(File::open(&output).unwrap());
+        let (mdat, moov) = find_boxes(&mut file).unwrap();
+        assert!(moov.end <= mdat.start);
+        let size = file.seek(SeekFrom::End(0)).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut mp4 = mp4::Mp4Reader::read_header(file, size).unwrap();
+        assert_eq!(mp4.tracks().len(), 2);
+        assert_eq!(mp4.tracks()[&2].language(), "swe");
+        assert_eq!(mp4.sample_count(2).unwrap(), 10);
+
+        // Decode times are the presentation times in order; offsets are
+        // shifted to be positive, and the edit list skips the shift
+        let mut video = Vec::new();
+        for id in 1..=7 {
+            let sample = mp4.read_sample(1, id).unwrap().unwrap();
+            assert_eq!(sample.bytes.last(), Some(&(id as u8 - 1)));
+            video.push((sample.start_time, sample.rendering_offset, sample.is_sync));
+        }
+        let offsets = [3600, 10800, 0, 0, 10800, 0, 0];
+        let expected: Vec<_> = (0..7).map(|n| (n as u64 * 3600, offsets[n], n == 0)).collect();
+        assert_eq!(video, expected);
+        let audio = mp4.read_sample(2, 10).unwrap().unwrap();
+        assert_eq!((audio.start_time, audio.bytes.as_ref()), (9 * 1024, &[0x21, 9][..]));
+
+        let edit = |track: usize| {
+            let elst = mp4.moov.traks[track].edts.as_ref().and_then(|edts| edts.elst.as_ref()).unwrap();
+            elst.entries.iter().map(|entry| (entry.segment_duration, entry.media_time)).collect::<Vec<_>>()
+        };
+        assert_eq!(edit(0), [(280, 3600)]);
+        assert_eq!(edit(1), [(20, u64::from(u32::MAX)), (213, 0)]);
+
+        let bytes = fs::read(&output).unwrap();
+        let comment = format!("{} {}", OUTPUT_COMMENT, env!("CARGO_PKG_VERSION"));
+        assert!(bytes.windows(comment.len()).any(|window| window == comment.as_bytes()));
+        assert!(bytes.windows(10).any(|window| window == b"Home movie"));
+    }
+
+    #[test]
+    fn other_codecs_are_left_to_ffmpeg() {
+        let dir = crate::scratch_dir("remux-hevc");
+        let source = dir.join("movie.mkv");
+        fs::write(&source, sample_mkv("V_MPEGH/ISO/HEVC")).unwrap();
+        let error = Remuxer::open(&source, &[0]).err().unwrap();
+        assert_eq!(error, "video codec V_MPEGH/ISO/HEVC is not H.264");
+        fs::write(&source, b"not a video").unwrap();
+        assert!(Remuxer::open(&source, &[]).is_err());
+        let missing = Remuxer::open(&dir.join("missing.mkv"), &[]).err().unwrap();
+        assert!(missing.starts_with("cannot open"));
+    }
+
+    #[test]
+    fn several_parameter_sets_are_refused() {
+        assert!(parse_avcc(&avcc()).is_some());
+        let mut two_sps = avcc();
+        two_sps[5] = 0xE2;
+        assert_eq!(parse_avcc(&two_sps), None);
+        let mut short_lengths = avcc();
+        short_lengths[4] = 0xFD;
+        assert_eq!(parse_avcc(&short_lengths), None);
+        assert_eq!(parse_avcc(&[1, 0x64]), None);
+    }
+}
@@ -0,0 +1,321 @@
+//! `scan --report`: per-file compatibility verdicts and library statistics,
+//! and the CSV and JSON files `--report-file` writes for scans and runs.
+
+use crate::hwaccel::HwAccel;
+use crate::options::EncodeOptions;
+use crate::output::{format_duration, format_size};
+use crate::plan::{required_conversion, Conversion, TranscodePlan};
+use crate::probe::VideoInfo;
+use crate::queue::FileStatus;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What a file needs to play on the target device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verdict {
+    Compatible,
+    /// Compatible streams in another container
+    NeedsRemux,
+    /// Compatible video, incompatible audio
+    NeedsAudio,
+    /// The video has to be re-encoded
+    NeedsVideo,
+    /// ffprobe could not read it
+    Unreadable,
+}
+
+impl Verdict {
+    pub fn label(self) -> &'static str {
+        match self {
+            Verdict::Compatible => "✅ compatible",
+            Verdict::NeedsRemux => "📦 needs remux",
+            Verdict::NeedsAudio => "🔊 needs audio",
+            Verdict::NeedsVideo => "⚙️  needs video",
+            Verdict::Unreadable => "❌ unreadable",
+        }
+    }
+}
+
+impl From<Conversion> for Verdict {
+    fn from(conversion: Conversion) -> Verdict {
+        match conversion {
+            Conversion::None => Verdict::Compatible,
+            Conversion::Remux => Verdict::NeedsRemux,
+            Conversion::Audio => Verdict::NeedsAudio,
+            Conversion::Transcode => Verdict::NeedsVideo,
+        }
+    }
+}
+
+/// One file of a report.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportRow {
+    pub file: PathBuf,
+    /// Size on disk in bytes
+    pub size: u64,
+    pub verdict: Verdict,
+    /// Has PGS/DVD/DVB subtitles, which can only be burned in
+    pub bitmap_subtitles: bool,
+    pub container: String,
+    pub video_codec: String,
+    pub width: u32,
+    pub height: u32,
+    pub audio_codec: String,
+    pub duration_secs: f64,
+    /// Planned output size for files that need converting
+    pub estimated_size: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl ReportRow {
+    /// Judges `info` against the target in `options`.
+    pub fn new(info: &VideoInfo, hw_accel: Option<&HwAccel>, options: &EncodeOptions) -> ReportRow {
+        let verdict = Verdict::from(required_conversion(info, &options.device_caps()));
+        let estimated_size = match verdict {
+            Verdict::Compatible => None,
+            _ => TranscodePlan::new(info, None, hw_accel, options).estimated_size(info),
+        };
+        ReportRow {
+            file: info.path.clone(),
+            size: file_size(&info.path),
+            verdict,
+            bitmap_subtitles: info.subtitles.iter().any(|sub| sub.is_bitmap),
+            container: info.container.clone(),
+            video_codec: info.video_codec.clone(),
+            width: info.width,
+            height: info.height,
+            audio_codec: info.audio_codec.clone(),
+            duration_secs: info.duration_secs,
+            estimated_size,
+            error: None,
+        }
+    }
+
+    /// A file that could not be probed.
+    pub fn unreadable(path: &Path, error: &str) -> ReportRow {
+        ReportRow {
+            file: path.to_path_buf(),
+            size: file_size(path),
+            verdict: Verdict::Unreadable,
+            bitmap_subtitles: false,
+            container: String::new(),
+            video_codec: String::new(),
+            width: 0,
+            height: 0,
+            audio_codec: String::new(),
+            duration_secs: 0.0,
+            estimated_size: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+impl CsvRecord for ReportRow {
+    const HEADER: &'static [&'static str] = &[
+        "file",
+        "size",
+        "verdict",
+        "bitmap_subtitles",
+        "container",
+        "video_codec",
+        "width",
+        "height",
+        "audio_codec",
+        "duration_secs",
+        "estimated_size",
+        "error",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.file.display().to_string(),
+            self.size.to_string(),
+            json_name(&self.verdict),
+            self.bitmap_subtitles.to_string(),
+            self.container.clone(),
+            self.video_codec.clone(),
+            self.width.to_string(),
+            self.height.to_string(),
+            self.audio_codec.clone(),
+            format!("{:.3}", self.duration_secs),
+            optional(self.estimated_size),
+            self.error.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+/// How one file of a `convert` run ended.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunRow {
+    pub file: PathBuf,
+    pub result: FileStatus,
+    /// Written output, for transcoded files
+    pub output: Option<PathBuf>,
+    pub input_size: Option<u64>,
+    pub output_size: Option<u64>,
+    /// Time spent encoding, if it got that far
+    pub elapsed_secs: Option<u64>,
+}
+
+impl CsvRecord for RunRow {
+    const HEADER: &'static [&'static str] =
+        &["file", "result", "output", "input_size", "output_size", "elapsed_secs"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.file.display().to_string(),
+            json_name(&self.result),
+            self.output.as_ref().map_or_else(String::new, |output| output.display().to_string()),
+            optional(self.input_size),
+            optional(self.output_size),
+            optional(self.elapsed_secs),
+        ]
+    }
+}
+
+/// A row type that can be written as a CSV line.
+pub trait CsvRecord {
+    /// Column names, in the order of [`CsvRecord::fields`]
+    const HEADER: &'static [&'static str];
+    fn fields(&self) -> Vec<String>;
+}
+
+/// Writes `rows` to `path`: CSV with a header line if it ends in `.csv`,
+/// otherwise a JSON array.
+pub fn write_report<T: CsvRecord + Serialize>(path: &Path, rows: &[T]) -> Result<(), String> {
+    let is_csv = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    let text = if is_csv {
+        let mut text = csv_line(T::HEADER.iter().map(|name| name.to_string()));
+        for row in rows {
+            text.push_str(&csv_line(row.fields().into_iter()));
+        }
+        text
+    } else {
+        let mut text = serde_json::to_string_pretty(rows).map_err(|e| e.to_string())?;
+        text.push('\n');
+        text
+    };
+    fs::write(path, text).map_err(|e| format!("Cannot write report {}: {}", path.display(), e))
+}
+
+/// One CSV line, quoting fields that contain commas, quotes or line breaks.
+fn csv_line(fields: impl Iterator<Item = String>) -> String {
+    let fields: Vec<String> = fields
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+/// The serialized name of a unit enum variant, e.g. `needs-remux`.
+fn json_name(value: &impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+fn optional(value: Option<u64>) -> String {
+    value.map_or_else(String::new, |value| value.to_string())
+}
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map_or(0, |m| m.len())
+}
+
+/// Prints one line per row, then counts by verdict, codec, container and
+/// resolution.
+pub fn print_report(rows: &[ReportRow]) {
+    for row in rows {
+        let name = row.file.file_name().unwrap_or_default().to_string_lossy();
+        match &row.error {
+            Some(error) => println!("{:<16} {} ({})", row.verdict.label(), name, error),
+            None => println!(
+                "{:<16} {}  [{} {}x{}, {}, {}, {}{}]",
+                row.verdict.label(),
+                name,
+                row.video_codec,
+                row.width,
+                row.height,
+                row.audio_codec,
+                row.container,
+                format_size(row.size),
+                if row.bitmap_subtitles { ", 🖼️  bitmap subs" } else { "" }
+            ),
+        }
+    }
+
+    let total_size: u64 = rows.iter().map(|row| row.size).sum();
+    let total_secs: f64 = rows.iter().map(|row| row.duration_secs).sum();
+    println!(
+        "\n📊 {} file(s), {}, {} of video",
+        rows.len(),
+        format_size(total_size),
+        format_duration(total_secs as u64)
+    );
+
+    let mut by_verdict: BTreeMap<Verdict, (usize, u64)> = BTreeMap::new();
+    for row in rows {
+        let entry = by_verdict.entry(row.verdict).or_default();
+        entry.0 += 1;
+        entry.1 += row.size;
+    }
+    for (verdict, (count, size)) in &by_verdict {
+        println!("   {:<16} {:>6}  {}", verdict.label(), count, format_size(*size));
+    }
+    let bitmap = rows.iter().filter(|row| row.bitmap_subtitles).count();
+    println!("   {:<16} {:>6}", "🖼️  bitmap subs", bitmap);
+
+    println!();
+    let readable: Vec<&ReportRow> = rows.iter().filter(|row| row.error.is_none()).collect();
+    print_counts("Video codecs", readable.iter().map(|row| row.video_codec.clone()));
+    print_counts("Audio codecs", readable.iter().map(|row| row.audio_codec.clone()));
+    print_counts("Containers", readable.iter().map(|row| row.container.clone()));
+    print_counts("Resolutions", readable.iter().map(|row| resolution_class(row.height).to_string()));
+
+    let estimated: u64 = rows.iter().filter_map(|row| row.estimated_size).sum();
+    let to_convert: u64 = rows
+        .iter()
+        .filter(|row| row.estimated_size.is_some())
+        .map(|row| row.size)
+        .sum();
+    if estimated > 0 {
+        println!(
+            "\nConverting would turn {} of sources into ~{} of output",
+            format_size(to_convert),
+            format_size(estimated)
+        );
+    }
+}
+
+/// Prints `label: a 12, b 3` with the most common values first.
+fn print_counts(label: &str, values: impl Iterator<Item = String>) {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let list: Vec<String> = counts.iter().map(|(value, count)| format!("{} {}", value, count)).collect();
+    println!("{}: {}", label, list.join(", "));
+}
+
+/// `2160p`, `1080p`, `720p` or `SD` by frame height.
+fn resolution_class(height: u32) -> &'static str {
+    match height {
+        h if h > 1080 => "2160p",
+        h if h > 720 => "1080p",
+        h if h > 576 => "720p",
+        _ => "SD",
+    }
+}
@@ -0,0 +1,184 @@
+//! The [`FfmpegRunner`] abstraction over spawning ffmpeg and ffprobe.
+//!
+//! Everything in the library that executes an external tool goes through a
+//! runner, so argument construction and planning can be exercised against a
+//! mock and alternate backends can be slotted in.
+
+use crate::dashboard;
+use crate::output::{verbosity, Verbosity};
+use crate::pause::track_child;
+use crate::runtime;
+use std::io::{self, Write};
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use tokio::process::{ChildStderr, Command};
+
+/// The external tools tvcode drives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Ffmpeg,
+    Ffprobe,
+}
+
+impl Tool {
+    pub fn program(self) -> &'static str {
+        match self {
+            Tool::Ffmpeg => "ffmpeg",
+            Tool::Ffprobe => "ffprobe",
+        }
+    }
+}
+
+/// Captured result of a finished tool invocation.
+#[derive(Debug, Clone, Default)]
+pub struct RunOutput {
+    /// Exit code, or `None` if the process was killed by a signal
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl RunOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Executes ffmpeg and ffprobe on behalf of the library.
+pub trait FfmpegRunner: Send + Sync {
+    /// Runs an ffmpeg job whose console output follows the current verbosity,
+    /// returning its exit code. `label` identifies the job in streamed output.
+    fn run(&self, args: &[String], label: &str) -> io::Result<Option<i32>>;
+
+    /// Runs `tool` to completion with captured stdout and stderr.
+    fn capture(&self, tool: Tool, args: &[String]) -> io::Result<RunOutput>;
+}
+
+/// Runs the `ffmpeg` and `ffprobe` binaries found on `PATH`, awaiting them
+/// on the [`runtime`] so stopping the run kills them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl FfmpegRunner for SystemRunner {
+    /// At `-v` and above, ffmpeg's stderr is streamed live with each line
+    /// prefixed by `label`; otherwise it is passed through as-is. While the
+    /// dashboard runs, every line is also handed to it.
+    fn run(&self, args: &[String], label: &str) -> io::Result<Option<i32>> {
+        if verbosity() >= Verbosity::Debug {
+            println!("   $ ffmpeg {}", args.join(" "));
+        }
+
+        let verbose = verbosity() >= Verbosity::Verbose;
+        let mut command = Command::new("ffmpeg");
+        command.args(args).kill_on_drop(true);
+        if verbose || dashboard::enabled() {
+            command.stdin(Stdio::null()).stderr(Stdio::piped());
+        }
+        runtime::block_on(runtime::until_cancelled(runtime::shutdown(), async {
+            let mut child = command.spawn()?;
+            let _tracked = child.id().map(track_child);
+            if let Some(stderr) = child.stderr.take() {
+                relay_stderr(stderr, label, verbose).await;
+            }
+            child.wait().await.map(|s| s.code())
+        }))?
+    }
+
+    fn capture(&self, tool: Tool, args: &[String]) -> io::Result<RunOutput> {
+        let mut command = Command::new(tool.program());
+        command
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let output = runtime::block_on(runtime::until_cancelled(runtime::shutdown(), async {
+            let child = command.spawn()?;
+            let _tracked = child.id().map(track_child);
+            child.wait_with_output().await
+        }))??;
+        Ok(RunOutput {
+            code: output.status.code(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+}
+
+/// Reads ffmpeg's stderr as it arrives and shows it line by line.
+async fn relay_stderr(mut stderr: ChildStderr, label: &str, verbose: bool) {
+    // ffmpeg redraws its progress line with '\r', so split on both
+    let mut line = Vec::new();
+    let mut console = io::stderr();
+    let mut buffer = [0; 4096];
+    while let Ok(read @ 1..) = stderr.read(&mut buffer).await {
+        for &byte in &buffer[..read] {
+            if byte == b'\n' || byte == b'\r' {
+                if !line.is_empty() {
+                    emit_line(&line, byte, label, verbose, &mut console);
+                    line.clear();
+                } else if !verbose {
+                    let _ = console.write_all(&[byte]);
+                }
+            } else {
+                line.push(byte);
+            }
+        }
+    }
+    if !line.is_empty() {
+        emit_line(&line, b'\n', label, verbose, &mut console);
+    }
+}
+
+/// Shows one ffmpeg stderr line (prefixed when `verbose`, otherwise exactly
+/// as ffmpeg wrote it, `end` included) and passes it to the dashboard.
+fn emit_line(line: &[u8], end: u8, label: &str, verbose: bool, console: &mut io::Stderr) {
+    let text = String::from_utf8_lossy(line);
+    if verbose {
+        println!("      [{}] {}", label, text);
+    } else {
+        let _ = console.write_all(line).and_then(|()| console.write_all(&[end]));
+    }
+    if dashboard::enabled() {
+        dashboard::ffmpeg_line(label, &text);
+    }
+}
+
+/// Records the jobs it is given instead of running them, for tests. Every
+/// ffmpeg job succeeds and leaves an empty file at its output; every capture
+/// fails, as if the tool were not installed.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct RecordingRunner {
+    runs: std::sync::Mutex<Vec<Vec<String>>>,
+    captures: std::sync::Mutex<Vec<(Tool, Vec<String>)>>,
+}
+
+#[cfg(test)]
+impl RecordingRunner {
+    /// Arguments of every ffmpeg job run so far, in order.
+    pub(crate) fn runs(&self) -> Vec<Vec<String>> {
+        self.runs.lock().unwrap().clone()
+    }
+
+    /// Tool and arguments of every capture so far, in order.
+    pub(crate) fn captures(&self) -> Vec<(Tool, Vec<String>)> {
+        self.captures.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl FfmpegRunner for RecordingRunner {
+    fn run(&self, args: &[String], _label: &str) -> io::Result<Option<i32>> {
+        self.runs.lock().unwrap().push(args.to_vec());
+        if let Some(output) = args.last().filter(|arg| arg.as_str() != "-") {
+            std::fs::write(output, b"")?;
+        }
+        Ok(Some(0))
+    }
+
+    fn capture(&self, tool: Tool, args: &[String]) -> io::Result<RunOutput> {
+        self.captures.lock().unwrap().push((tool, args.to_vec()));
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not run in tests", tool.program())))
+    }
+}
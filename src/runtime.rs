@@ -0,0 +1,196 @@
+//! The tokio runtime that tvcode's concurrent work shares.
+//!
+//! ffmpeg and ffprobe children are awaited here, with their progress read
+//! as it arrives. The dashboard and the pause watcher also run here as
+//! tasks, and webhooks are delivered in the background. Nobody needs a
+//! thread of their own. The processing loop stays synchronous and waits
+//! on the runtime with [`block_on`].
+//!
+//! Ctrl-C (once [`install`]ed) cancels the [`shutdown`] token rather than
+//! killing the process. Every child being awaited through [`until_cancelled`]
+//! is then killed. No new file is started, and the run exits so the next one
+//! resumes. A second Ctrl-C quits at once.
+
+use crate::status;
+use std::future::Future;
+use std::io;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Exit code after a second Ctrl-C, as a shell reports death by SIGINT
+const INTERRUPTED: i32 = 130;
+
+/// How long [`finish`] waits for background deliveries before exiting
+const BACKGROUND_GRACE: Duration = Duration::from_secs(30);
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+static SHUTDOWN: OnceLock<Cancellation> = OnceLock::new();
+
+/// The last background task; each new one waits for it, so deliveries keep
+/// their order.
+static BACKGROUND: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        // The work is waiting on children, sockets and timers, not computing
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("tvcode-runtime")
+            .enable_all()
+            .build()
+            .expect("cannot start the async runtime")
+    })
+}
+
+/// Runs `future` to completion on the runtime, blocking this thread. Must
+/// not be called from a runtime task.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+/// Starts `future` as a runtime task.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    runtime().spawn(future)
+}
+
+/// Starts `future` once the background work started before it is done, and
+/// lets [`finish`] wait for it.
+pub fn spawn_background<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let mut last = BACKGROUND.lock().unwrap_or_else(|e| e.into_inner());
+    let previous = last.take();
+    *last = Some(spawn(async move {
+        if let Some(previous) = previous {
+            let _ = previous.await;
+        }
+        future.await;
+    }));
+}
+
+/// Waits (for a while) for the background work to be done, before exiting.
+pub fn finish() {
+    let last = BACKGROUND.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(last) = last {
+        let _ = block_on(async { tokio::time::timeout(BACKGROUND_GRACE, last).await });
+    }
+}
+
+/// A flag that, once set, wakes everyone waiting on it.
+#[derive(Debug)]
+pub struct Cancellation {
+    sender: watch::Sender<bool>,
+}
+
+impl Default for Cancellation {
+    fn default() -> Cancellation {
+        Cancellation {
+            sender: watch::Sender::new(false),
+        }
+    }
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Completes once cancelled, straight away if it already is.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.sender.subscribe();
+        let _ = receiver.wait_for(|&cancelled| cancelled).await;
+    }
+}
+
+/// The token Ctrl-C cancels.
+pub fn shutdown() -> &'static Cancellation {
+    SHUTDOWN.get_or_init(Cancellation::default)
+}
+
+/// Runs `future` unless `cancellation` comes first, in which case `future`
+/// is dropped unfinished; a child it spawned with `kill_on_drop` is killed.
+pub async fn until_cancelled<F: Future>(cancellation: &Cancellation, future: F) -> io::Result<F::Output> {
+    tokio::select! {
+        biased;
+        () = cancellation.cancelled() => Err(io::Error::new(io::ErrorKind::Interrupted, "stopped")),
+        output = future => Ok(output),
+    }
+}
+
+/// Sleeps for `duration`; false if the run was stopped first.
+pub fn sleep(duration: Duration) -> bool {
+    block_on(async { until_cancelled(shutdown(), tokio::time::sleep(duration)).await }).is_ok()
+}
+
+/// Makes Ctrl-C cancel [`shutdown`] instead of killing the process outright.
+pub fn install() {
+    spawn(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        status!("\n🛑 Stopping, press Ctrl-C again to quit at once");
+        shutdown().cancel();
+        let _ = tokio::signal::ctrl_c().await;
+        std::process::exit(INTERRUPTED);
+    });
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn cancelling_kills_the_awaited_child() {
+        let cancellation = std::sync::Arc::new(Cancellation::default());
+        let canceller = cancellation.clone();
+        let started = Instant::now();
+        let result = block_on(async move {
+            spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                canceller.cancel();
+            });
+            let mut child = tokio::process::Command::new("sleep").arg("30").kill_on_drop(true).spawn().unwrap();
+            until_cancelled(&cancellation, child.wait()).await
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn nothing_starts_once_cancelled() {
+        let cancellation = Cancellation::default();
+        cancellation.cancel();
+        let mut started = false;
+        let result = block_on(until_cancelled(&cancellation, async { started = true }));
+        assert!(result.is_err());
+        assert!(!started);
+    }
+
+    #[test]
+    fn background_work_runs_in_order() {
+        let order = std::sync::Arc::new(Mutex::new(Vec::new()));
+        for (i, delay) in [(1, 40), (2, 0)] {
+            let order = order.clone();
+            spawn_background(async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                order.lock().unwrap().push(i);
+            });
+        }
+        finish();
+        assert_eq!(*order.lock().unwrap(), [1, 2]);
+    }
+}
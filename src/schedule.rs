@@ -0,0 +1,202 @@
+//! `--schedule`: only start encodes inside a daily local-time window.
+
+use crate::status;
+use std::thread;
+use std::time::Duration;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// A daily window such as `23:00-07:00`, which may wrap past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    /// Opening time in minutes after midnight
+    pub start: u32,
+    /// Closing time in minutes after midnight; equal to `start` means all day
+    pub end: u32,
+}
+
+/// Parses `HH:MM-HH:MM` (24-hour local time) for `--schedule`.
+pub fn parse_schedule(text: &str) -> Result<Schedule, String> {
+    let (start, end) = text
+        .split_once('-')
+        .ok_or_else(|| format!("'{}' is not a window like 23:00-07:00", text))?;
+    Ok(Schedule {
+        start: parse_time(start.trim())?,
+        end: parse_time(end.trim())?,
+    })
+}
+
+fn parse_time(text: &str) -> Result<u32, String> {
+    let (hours, minutes) = text.split_once(':').unwrap_or((text, "0"));
+    match (hours.parse::<u32>(), minutes.parse::<u32>()) {
+        (Ok(h), Ok(m)) if h < 24 && m < 60 => Ok(h * 60 + m),
+        _ => Err(format!("'{}' is not a time like 07:30", text)),
+    }
+}
+
+impl Schedule {
+    /// Whether `minute` (after midnight) is inside the window.
+    pub fn contains(&self, minute: u32) -> bool {
+        match self.start.cmp(&self.end) {
+            std::cmp::Ordering::Equal => true,
+            std::cmp::Ordering::Less => minute >= self.start && minute < self.end,
+            std::cmp::Ordering::Greater => minute >= self.start || minute < self.end,
+        }
+    }
+
+    /// Minutes from `minute` until the window next opens; zero when open.
+    pub fn minutes_until_open(&self, minute: u32) -> u32 {
+        if self.contains(minute) {
+            0
+        } else {
+            (self.start + MINUTES_PER_DAY - minute) % MINUTES_PER_DAY
+        }
+    }
+
+    /// Blocks until the window is open, announcing the wait once. The clock
+    /// is re-read every minute so clock and DST changes are followed.
+    pub fn wait_until_open(&self) {
+        let mut announced = false;
+        loop {
+            let minute = local_minute_of_day();
+            if self.contains(minute) {
+                return;
+            }
+            if !announced {
+                status!(
+                    "   ⏸️  Outside the {} schedule, waiting until {} ({}h{:02}m)",
+                    self,
+                    format_minute(self.start),
+                    self.minutes_until_open(minute) / 60,
+                    self.minutes_until_open(minute) % 60
+                );
+                announced = true;
+            }
+            thread::sleep(Duration::from_secs(60));
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", format_minute(self.start), format_minute(self.end))
+    }
+}
+
+fn format_minute(minute: u32) -> String {
+    format!("{:02}:{:02}", minute / 60, minute % 60)
+}
+
+/// Minutes since local midnight, from the C library's time zone handling.
+#[cfg(unix)]
+pub fn local_minute_of_day() -> u32 {
+    // SAFETY: `tm` is plain integers and a nullable pointer, so all-zero is
+    // valid, and both calls only write through the valid pointers passed
+    let (ok, tm) = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        let now = libc::time(std::ptr::null_mut());
+        (!libc::localtime_r(&now, &mut tm).is_null(), tm)
+    };
+    if ok {
+        (tm.tm_hour * 60 + tm.tm_min) as u32
+    } else {
+        utc_minute_of_day()
+    }
+}
+
+/// Minutes since local midnight, from `GetLocalTime`.
+#[cfg(windows)]
+pub fn local_minute_of_day() -> u32 {
+    #[repr(C)]
+    struct SystemTime {
+        year: u16,
+        month: u16,
+        day_of_week: u16,
+        day: u16,
+        hour: u16,
+        minute: u16,
+        second: u16,
+        milliseconds: u16,
+    }
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetLocalTime(time: *mut SystemTime);
+    }
+
+    let mut st = SystemTime {
+        year: 0,
+        month: 0,
+        day_of_week: 0,
+        day: 0,
+        hour: 0,
+        minute: 0,
+        second: 0,
+        milliseconds: 0,
+    };
+    // SAFETY: GetLocalTime fills the struct it is given and cannot fail
+    unsafe { GetLocalTime(&mut st) };
+    st.hour as u32 * 60 + st.minute as u32
+}
+
+/// Fallback for platforms without a local-time source.
+#[cfg(not(any(unix, windows)))]
+pub fn local_minute_of_day() -> u32 {
+    utc_minute_of_day()
+}
+
+#[cfg_attr(windows, allow(dead_code))]
+fn utc_minute_of_day() -> u32 {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    ((secs / 60) % MINUTES_PER_DAY as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_windows() {
+        assert_eq!(parse_schedule("23:00-07:00"), Ok(Schedule { start: 23 * 60, end: 7 * 60 }));
+        assert_eq!(parse_schedule("1:30 - 6"), Ok(Schedule { start: 90, end: 360 }));
+        assert_eq!(parse_schedule("00:00-00:00").unwrap().to_string(), "00:00-00:00");
+        for bad in ["23:00", "24:00-07:00", "23:60-07:00", "23:00-7pm", "-"] {
+            assert!(parse_schedule(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn daytime_window() {
+        let window = parse_schedule("09:00-17:30").unwrap();
+        assert!(!window.contains(9 * 60 - 1));
+        assert!(window.contains(9 * 60));
+        assert!(window.contains(17 * 60 + 29));
+        assert!(!window.contains(17 * 60 + 30));
+        assert_eq!(window.minutes_until_open(8 * 60), 60);
+        assert_eq!(window.minutes_until_open(18 * 60), 15 * 60);
+    }
+
+    #[test]
+    fn window_wrapping_past_midnight() {
+        let window = parse_schedule("23:00-07:00").unwrap();
+        assert!(window.contains(23 * 60));
+        assert!(window.contains(0));
+        assert!(window.contains(7 * 60 - 1));
+        assert!(!window.contains(7 * 60));
+        assert!(!window.contains(23 * 60 - 1));
+        assert_eq!(window.minutes_until_open(12 * 60), 11 * 60);
+        assert_eq!(window.minutes_until_open(2 * 60), 0);
+    }
+
+    #[test]
+    fn equal_ends_are_open_all_day() {
+        let window = parse_schedule("06:00-06:00").unwrap();
+        assert!((0..MINUTES_PER_DAY).all(|minute| window.contains(minute)));
+    }
+
+    #[test]
+    fn local_time_is_within_a_day() {
+        assert!(local_minute_of_day() < MINUTES_PER_DAY);
+    }
+}
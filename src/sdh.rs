@@ -0,0 +1,194 @@
+//! `--strip-sdh`: removing what subtitles for the deaf and hard of hearing
+//! add to the dialogue, namely sound descriptions like `[door creaks]` or
+//! `(SIGHS)` and speaker labels like `JOHN:`.
+
+use std::fs;
+use std::path::Path;
+
+/// Strips the SRT or ASS (by extension) subtitles in `path` in place.
+/// Returns how many subtitles changed.
+pub fn strip_sdh_file(path: &Path) -> Result<usize, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let (stripped, changed) = if path.extension().is_some_and(|ext| ext == "ass") {
+        strip_ass(&text)
+    } else {
+        strip_srt(&text)
+    };
+    fs::write(path, stripped).map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+    Ok(changed)
+}
+
+/// Strips every cue of an SRT file, dropping cues left empty and numbering
+/// the rest again.
+pub fn strip_srt(text: &str) -> (String, usize) {
+    let text = text.replace("\r\n", "\n");
+    let mut output = String::new();
+    let mut changed = 0;
+    let mut number = 0;
+    for block in text.split("\n\n").filter(|block| !block.trim().is_empty()) {
+        let lines: Vec<&str> = block.trim_matches('\n').lines().collect();
+        let Some(timing) = lines.iter().position(|line| line.contains("-->")) else {
+            continue;
+        };
+        let cue = &lines[timing + 1..];
+        let kept = strip_cue(cue);
+        if kept.len() != cue.len() || kept.iter().zip(cue).any(|(kept, line)| kept != line) {
+            changed += 1;
+        }
+        if kept.is_empty() {
+            continue;
+        }
+        number += 1;
+        output.push_str(&format!("{}\n{}\n{}\n\n", number, lines[timing], kept.join("\n")));
+    }
+    (output, changed)
+}
+
+/// Strips the text of every `Dialogue:` line of an ASS file, dropping lines
+/// left empty. Everything else is kept as it is.
+pub fn strip_ass(text: &str) -> (String, usize) {
+    let mut output = String::new();
+    let mut changed = 0;
+    for line in text.lines() {
+        let Some(rest) = line.strip_prefix("Dialogue:") else {
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        };
+        // Nine fields come before the text, which may itself contain commas
+        let fields: Vec<&str> = rest.splitn(10, ',').collect();
+        let Some((&dialogue, header)) = fields.split_last().filter(|_| fields.len() == 10) else {
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        };
+        let cue: Vec<&str> = dialogue.split("\\N").collect();
+        let kept = strip_cue(&cue);
+        if kept.len() != cue.len() || kept.iter().zip(&cue).any(|(kept, line)| kept != line) {
+            changed += 1;
+        }
+        if !kept.is_empty() {
+            output.push_str(&format!("Dialogue:{},{}\n", header.join(","), kept.join("\\N")));
+        }
+    }
+    (output, changed)
+}
+
+/// The lines of one cue without sound descriptions and speaker labels,
+/// leaving out lines with nothing else.
+fn strip_cue(lines: &[&str]) -> Vec<String> {
+    // Descriptions can wrap onto the next line, so remove them from the whole cue
+    let text = remove_descriptions(&lines.join("\n"));
+    text.lines()
+        .map(remove_speaker_label)
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| has_visible_text(line))
+        .collect()
+}
+
+/// `text` without anything in square brackets or parentheses.
+fn remove_descriptions(text: &str) -> String {
+    let mut output = String::new();
+    let mut closing = None;
+    for c in text.chars() {
+        match (closing, c) {
+            (None, '[') => closing = Some(']'),
+            (None, '(') => closing = Some(')'),
+            (None, c) => output.push(c),
+            (Some(end), c) if c == end => closing = None,
+            // Keep line breaks so the lines around a description stay apart
+            (Some(_), '\n') => output.push('\n'),
+            (Some(_), _) => {}
+        }
+    }
+    output
+}
+
+/// `line` without a leading `NAME:` or `- NAME:`, where the name is written
+/// in capitals.
+fn remove_speaker_label(line: &str) -> String {
+    let trimmed = line.trim_start();
+    let (dash, rest) = match trimmed.strip_prefix('-') {
+        Some(rest) => ("- ", rest.trim_start()),
+        None => ("", trimmed),
+    };
+    let Some((name, text)) = rest.split_once(':') else {
+        return line.to_string();
+    };
+    let is_label = name.chars().filter(|c| c.is_alphabetic()).count() >= 2
+        && name.chars().all(|c| c.is_uppercase() || c.is_ascii_digit() || " .'-#".contains(c))
+        // Not a time of day like 10:30
+        && !text.starts_with(|c: char| c.is_ascii_digit());
+    if is_label {
+        format!("{}{}", dash, text.trim_start())
+    } else {
+        line.to_string()
+    }
+}
+
+/// Whether `line` shows anything besides dashes, HTML tags like `<i>` and
+/// ASS override blocks like `{\i1}`.
+fn has_visible_text(line: &str) -> bool {
+    let mut inside = None;
+    for c in line.chars() {
+        match (inside, c) {
+            (None, '<') => inside = Some('>'),
+            (None, '{') => inside = Some('}'),
+            (None, c) if !c.is_whitespace() && c != '-' => return true,
+            (Some(end), c) if c == end => inside = None,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bracketed_descriptions_are_removed() {
+        assert_eq!(strip_cue(&["[door creaks]", "Who's there?"]), ["Who's there?"]);
+        assert_eq!(strip_cue(&["(SIGHS HEAVILY", "AND LEAVES) Fine."]), ["Fine."]);
+        assert_eq!(strip_cue(&["I said [quietly] no."]), ["I said no."]);
+    }
+
+    #[test]
+    fn speaker_labels_are_removed() {
+        assert_eq!(remove_speaker_label("JOHN: Get down!"), "Get down!");
+        assert_eq!(remove_speaker_label("- MARY: Run."), "- Run.");
+        assert_eq!(remove_speaker_label("DR. SMITH: Hello."), "Hello.");
+        assert_eq!(remove_speaker_label("Meet me at 10:30."), "Meet me at 10:30.");
+        assert_eq!(remove_speaker_label("Note: it's late."), "Note: it's late.");
+    }
+
+    #[test]
+    fn cues_left_empty_are_dropped_and_the_rest_renumbered() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:02,000\r\n[door creaks]\r\n\r\n\
+                   2\r\n00:00:03,000 --> 00:00:04,000\r\nJOHN: Who's there?\r\n\r\n\
+                   3\r\n00:00:05,000 --> 00:00:06,000\r\n<i>[thunder]</i>\r\n- [gasps]\r\n\r\n\
+                   4\r\n00:00:07,000 --> 00:00:08,000\r\nNobody.\r\n";
+        let (stripped, changed) = strip_srt(srt);
+        assert_eq!(
+            stripped,
+            "1\n00:00:03,000 --> 00:00:04,000\nWho's there?\n\n2\n00:00:07,000 --> 00:00:08,000\nNobody.\n\n"
+        );
+        assert_eq!(changed, 3);
+    }
+
+    #[test]
+    fn ass_dialogue_is_stripped_and_empty_lines_dropped() {
+        let ass = "[Events]\n\
+                   Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+                   Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,[SIGHS]\\NJOHN: Fine, then.\n\
+                   Dialogue: 0,0:00:03.00,0:00:04.00,Default,,0,0,0,,(laughs)\n";
+        let (stripped, changed) = strip_ass(ass);
+        assert_eq!(
+            stripped,
+            "[Events]\n\
+             Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+             Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,Fine, then.\n"
+        );
+        assert_eq!(changed, 2);
+    }
+}
@@ -0,0 +1,103 @@
+//! `--segments`: encoding one long file as several parts in parallel.
+//!
+//! The source is cut at keyframes near even intervals. Each part's video is
+//! encoded as its own job (an input-seeked window, like `--sample`) while the
+//! audio is converted once over the whole file, so there are no gaps or
+//! priming glitches at the joins. The parts are then joined with ffmpeg's
+//! concat demuxer and muxed with the audio without re-encoding.
+
+use crate::plan::SampleWindow;
+use crate::probe::VideoInfo;
+use crate::runner::{FfmpegRunner, Tool};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Parts shorter than this are not worth a separate encoder start-up.
+pub const MIN_SEGMENT_SECS: f64 = 120.0;
+
+/// Presentation times of the video keyframes of `path`, in seconds. Reads
+/// packet flags only, so no frames are decoded.
+pub fn keyframe_times(runner: &dyn FfmpegRunner, path: &Path) -> Result<Vec<f64>, String> {
+    let args: Vec<String> = [
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-show_entries",
+        "packet=pts_time,flags",
+        "-of",
+        "csv=p=0",
+    ]
+    .iter()
+    .map(|a| a.to_string())
+    .chain([path.to_str().unwrap().to_string()])
+    .collect();
+    let output = runner
+        .capture(Tool::Ffprobe, &args)
+        .map_err(|e| format!("Failed to run ffprobe: {}", e))?;
+    if !output.success() {
+        return Err("ffprobe could not list keyframes".to_string());
+    }
+    let mut times: Vec<f64> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (pts, flags) = line.split_once(',')?;
+            if flags.starts_with('K') {
+                pts.parse().ok()
+            } else {
+                None
+            }
+        })
+        .collect();
+    times.sort_by(f64::total_cmp);
+    Ok(times)
+}
+
+/// Splits `duration_secs` into up to `count` windows whose boundaries are
+/// the keyframes nearest to even intervals. Fewer windows come back when
+/// parts would be shorter than [`MIN_SEGMENT_SECS`] or keyframes are sparse.
+pub fn split_windows(keyframes: &[f64], duration_secs: f64, count: u32) -> Vec<SampleWindow> {
+    let count = count.min((duration_secs / MIN_SEGMENT_SECS) as u32).max(1);
+    let mut cuts = vec![0.0];
+    for i in 1..count {
+        let target = duration_secs * i as f64 / count as f64;
+        let nearest = keyframes
+            .iter()
+            .copied()
+            .min_by(|a, b| (a - target).abs().total_cmp(&(b - target).abs()));
+        if let Some(cut) = nearest {
+            if cut > cuts[cuts.len() - 1] + MIN_SEGMENT_SECS / 2.0 && cut < duration_secs - MIN_SEGMENT_SECS / 2.0 {
+                cuts.push(cut);
+            }
+        }
+    }
+    cuts.push(duration_secs);
+    cuts.windows(2)
+        .map(|pair| SampleWindow {
+            start_secs: pair[0],
+            duration_secs: pair[1] - pair[0],
+        })
+        .collect()
+}
+
+/// Hidden scratch directory for the parts of `output`, beside it so the
+/// final mux stays on one filesystem.
+pub fn work_dir(output: &Path) -> PathBuf {
+    let name = output.file_name().unwrap().to_string_lossy();
+    output.with_file_name(format!(".{}.parts", name))
+}
+
+/// Writes a concat demuxer list of `parts` to `list`.
+pub fn write_concat_list(list: &Path, parts: &[PathBuf]) -> io::Result<()> {
+    let lines: String = parts
+        .iter()
+        .map(|part| format!("file '{}'\n", part.to_string_lossy().replace('\'', "'\\''")))
+        .collect();
+    fs::write(list, lines)
+}
+
+/// Whether `info` is long enough to be split into at least two parts.
+pub fn worth_splitting(info: &VideoInfo, count: u32) -> bool {
+    count > 1 && info.duration_secs >= MIN_SEGMENT_SECS * 2.0
+}
@@ -0,0 +1,321 @@
+//! `--stage-dir`: encoding on a local disk for outputs that go to a network
+//! share.
+//!
+//! ffmpeg writing an MP4 over SMB or NFS is slow (the faststart pass reads
+//! the whole file back) and a dropped connection ruins the encode. Staged
+//! outputs (files, split parts, HLS and chapter folders) are written into the
+//! local folder instead and copied to their destination once finished, under
+//! hidden partial names, then read back and compared with what was written
+//! before they are renamed into place.
+//!
+//! The read back is a sanity check on the copy, not proof that it is safely
+//! stored: each copy is synced first, but the read may still be answered
+//! from the page cache (or a network client's cache). It catches data damaged
+//! on its way through the copy, not a disk or server that loses it later.
+
+use crate::output::{format_size, verbosity, Verbosity};
+use crate::plan::{partial_output_path, split_part_path};
+use crate::status;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Bytes read and written at a time while copying.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// CRC-32 (IEEE) lookup table.
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Continues the CRC-32 `crc` (0 to start) over `bytes`.
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |crc, &byte| (crc >> 8) ^ CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize])
+}
+
+/// Where the output `output` is encoded in `stage_dir`, under the same
+/// hidden name it would have beside its destination.
+pub fn staged_path(stage_dir: &Path, output: &Path) -> PathBuf {
+    stage_dir.join(partial_output_path(output).file_name().unwrap())
+}
+
+/// CRC-32 of the file at `path`.
+fn checksum(path: &Path) -> io::Result<u32> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut crc = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(crc);
+        }
+        crc = crc32_update(crc, &buffer[..read]);
+    }
+}
+
+/// How far the copies of one move have got, for the progress line.
+struct Progress {
+    copied: u64,
+    total: u64,
+    shown: Option<u64>,
+    show: bool,
+}
+
+impl Progress {
+    fn new(total: u64) -> Progress {
+        Progress {
+            copied: 0,
+            total,
+            shown: None,
+            show: verbosity() >= Verbosity::Normal,
+        }
+    }
+
+    fn add(&mut self, bytes: usize) {
+        self.copied += bytes as u64;
+        let percent = self.copied * 100 / self.total.max(1);
+        if self.show && self.shown != Some(percent) {
+            print!("\r   📦 Copying: {:>3}% ({} of {})", percent, format_size(self.copied), format_size(self.total));
+            let _ = io::stdout().flush();
+            self.shown = Some(percent);
+        }
+    }
+
+    /// Ends the progress line, if one was shown.
+    fn finish(&self) {
+        if self.show && self.shown.is_some() {
+            println!();
+        }
+    }
+}
+
+/// Bytes in the file at `path`, or in every file inside it.
+fn total_size(path: &Path) -> u64 {
+    match fs::read_dir(path) {
+        Ok(entries) => entries.flatten().map(|entry| total_size(&entry.path())).sum(),
+        Err(_) => fs::metadata(path).map_or(0, |metadata| metadata.len()),
+    }
+}
+
+/// Copies the file `from` to `to`, adding to `progress`, and returns the
+/// CRC-32 of what was copied. The copy is synced before returning.
+fn copy_with_progress(from: &Path, to: &Path, progress: &mut Progress) -> io::Result<u32> {
+    let mut reader = File::open(from)?;
+    let mut writer = File::create(to)?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut crc = 0;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read])?;
+        crc = crc32_update(crc, &buffer[..read]);
+        progress.add(read);
+    }
+    writer.sync_all()?;
+    Ok(crc)
+}
+
+/// Copies `from`, a file or a folder, to `to`, reading each copied file
+/// back and comparing its CRC-32 with what was written. Returns how many
+/// files were copied.
+fn copy_checked(from: &Path, to: &Path, progress: &mut Progress) -> Result<usize, String> {
+    let failed = |e: io::Error| format!("Failed to copy {} to {}: {}", from.display(), to.display(), e);
+    if from.is_dir() {
+        fs::create_dir_all(to).map_err(failed)?;
+        let mut count = 0;
+        for entry in fs::read_dir(from).map_err(failed)? {
+            let entry = entry.map_err(failed)?;
+            count += copy_checked(&entry.path(), &to.join(entry.file_name()), progress)?;
+        }
+        return Ok(count);
+    }
+    let copied = copy_with_progress(from, to, progress).map_err(failed)?;
+    let written = checksum(to).map_err(failed)?;
+    if written != copied {
+        return Err(format!(
+            "The copy of {} at {} is damaged (CRC-32 {:08x}, expected {:08x})",
+            from.display(),
+            to.display(),
+            written,
+            copied
+        ));
+    }
+    Ok(1)
+}
+
+/// Deletes the file or folder at `path`.
+fn remove(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Moves the finished `staged` output (a file or folder, or the parts of a
+/// split output when `split`) to `output`. Each piece goes to a hidden name
+/// beside its destination first, by a rename when both are on one
+/// filesystem, otherwise by a copy that is read back and compared. Only once
+/// every piece is there are they renamed into place, a folder replacing an
+/// older one. The staged output is kept when anything fails.
+pub fn move_to_destination(staged: &Path, output: &Path, split: bool) -> Result<(), String> {
+    let pieces: Vec<(PathBuf, PathBuf)> = if split {
+        (1..)
+            .map(|number| (split_part_path(staged, number), split_part_path(output, number)))
+            .take_while(|(from, _)| from.exists())
+            .collect()
+    } else {
+        vec![(staged.to_path_buf(), output.to_path_buf())]
+    };
+    let mut progress = Progress::new(pieces.iter().map(|(from, _)| total_size(from)).sum());
+    // Pieces already beside their destination, and whether they were renamed there
+    let mut placed: Vec<(PathBuf, bool)> = Vec::new();
+    let mut copied = 0;
+    let mut result = Ok(());
+    for (from, to) in &pieces {
+        let partial = partial_output_path(to);
+        let _ = remove(&partial);
+        if fs::rename(from, &partial).is_ok() {
+            placed.push((partial, true));
+            continue;
+        }
+        placed.push((partial.clone(), false));
+        match copy_checked(from, &partial, &mut progress) {
+            Ok(count) => copied += count,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    progress.finish();
+    let result = result.and_then(|()| {
+        for ((_, to), (partial, _)) in pieces.iter().zip(&placed) {
+            if to.is_dir() {
+                fs::remove_dir_all(to).map_err(|e| format!("Failed to replace {}: {}", to.display(), e))?;
+            }
+            fs::rename(partial, to).map_err(|e| format!("Failed to move the output to {}: {}", to.display(), e))?;
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        // Renamed pieces go back to the stage folder, copies are dropped
+        for ((from, _), (partial, renamed)) in pieces.iter().zip(&placed) {
+            if *renamed {
+                let _ = fs::rename(partial, from);
+            } else {
+                let _ = remove(partial);
+            }
+        }
+        return Err(format!("{} (the encode is kept in {})", e, staged.parent().unwrap().display()));
+    }
+    if copied > 0 {
+        status!(
+            "   📦 Copied {} file(s) to {} and read each back with a matching CRC-32",
+            copied,
+            output.parent().unwrap().display()
+        );
+    }
+    for ((from, _), (_, renamed)) in pieces.iter().zip(&placed) {
+        if !renamed {
+            let _ = remove(from);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32_update(crc32_update(0, b"1234"), b"56789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn staged_under_the_partial_name() {
+        let staged = staged_path(Path::new("/scratch"), Path::new("/mnt/nas/movie_appletv.mp4"));
+        assert_eq!(staged, PathBuf::from("/scratch/.movie_appletv.mp4.tvcode.partial"));
+    }
+
+    #[test]
+    fn copy_returns_the_checksum_of_the_copy() {
+        let dir = crate::scratch_dir("staging-copy");
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::write(&from, b"123456789").unwrap();
+        assert_eq!(copy_with_progress(&from, &to, &mut Progress::new(9)).unwrap(), 0xCBF4_3926);
+        assert_eq!(checksum(&to).unwrap(), 0xCBF4_3926);
+        assert_eq!(fs::read(&to).unwrap(), b"123456789");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn move_on_one_filesystem_renames() {
+        let dir = crate::scratch_dir("staging-move");
+        let output = dir.join("movie_appletv.mp4");
+        let staged = staged_path(&dir.join("stage"), &output);
+        fs::create_dir_all(staged.parent().unwrap()).unwrap();
+        fs::write(&staged, b"encoded").unwrap();
+        move_to_destination(&staged, &output, false).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"encoded");
+        assert!(!staged.exists());
+        assert!(!partial_output_path(&output).exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn folders_are_copied_and_checked_file_by_file() {
+        let dir = crate::scratch_dir("staging-folder");
+        let (from, to) = (dir.join("package"), dir.join("copy"));
+        fs::create_dir_all(from.join("720p")).unwrap();
+        fs::write(from.join("master.m3u8"), b"#EXTM3U").unwrap();
+        fs::write(from.join("720p/index.m3u8"), b"123456789").unwrap();
+        assert_eq!(total_size(&from), 16);
+        assert_eq!(copy_checked(&from, &to, &mut Progress::new(16)).unwrap(), 2);
+        assert_eq!(fs::read(to.join("720p/index.m3u8")).unwrap(), b"123456789");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn staged_parts_and_folders_are_moved_whole() {
+        let dir = crate::scratch_dir("staging-pieces");
+        let stage = dir.join("stage");
+        fs::create_dir_all(&stage).unwrap();
+        let output = dir.join("movie_appletv.mp4");
+        let staged = staged_path(&stage, &output);
+        fs::write(split_part_path(&staged, 1), b"1").unwrap();
+        fs::write(split_part_path(&staged, 2), b"2").unwrap();
+        move_to_destination(&staged, &output, true).unwrap();
+        assert_eq!(fs::read(split_part_path(&output, 2)).unwrap(), b"2");
+        assert!(!split_part_path(&staged, 1).exists());
+
+        let package = dir.join("movie_appletv_hls");
+        fs::create_dir_all(&package).unwrap();
+        fs::write(package.join("stale.m4s"), b"").unwrap();
+        let staged = staged_path(&stage, &package);
+        fs::create_dir_all(&staged).unwrap();
+        fs::write(staged.join("master.m3u8"), b"").unwrap();
+        move_to_destination(&staged, &package, false).unwrap();
+        assert!(package.join("master.m3u8").exists() && !package.join("stale.m4s").exists());
+        assert!(!staged.exists() && !partial_output_path(&package).exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,532 @@
+//! How burned subtitles are rendered: the `subtitles` filter (libass) with
+//! the font and style settings passed to it for text tracks, the fonts
+//! attached to the file, the character set their text is read in, and where
+//! bitmap tracks are overlaid.
+
+use crate::filters::{escape_path, escape_value};
+use crate::language::detect_language;
+use crate::options::parse_duration;
+use crate::probe::{SubtitleTrack, VideoInfo};
+use crate::runner::{FfmpegRunner, Tool};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// An RGB color with opacity, as given to `--sub-color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    /// 255 is opaque
+    pub opacity: u8,
+}
+
+impl Color {
+    /// ASS `&HAABBGGRR`, where `AA` is transparency rather than opacity.
+    fn ass(self) -> String {
+        format!("&H{:02X}{:02X}{:02X}{:02X}", 255 - self.opacity, self.blue, self.green, self.red)
+    }
+}
+
+/// Parses a color name (`white`, `yellow`, ...), `#RRGGBB` or `#RRGGBBAA`
+/// with `AA` the opacity.
+pub fn parse_color(text: &str) -> Result<Color, String> {
+    let named = match text.to_ascii_lowercase().as_str() {
+        "white" => Some((255, 255, 255)),
+        "black" => Some((0, 0, 0)),
+        "yellow" => Some((255, 255, 0)),
+        "red" => Some((255, 0, 0)),
+        "green" => Some((0, 255, 0)),
+        "blue" => Some((0, 0, 255)),
+        "cyan" => Some((0, 255, 255)),
+        "magenta" => Some((255, 0, 255)),
+        "gray" | "grey" => Some((128, 128, 128)),
+        _ => None,
+    };
+    if let Some((red, green, blue)) = named {
+        return Ok(Color { red, green, blue, opacity: 255 });
+    }
+    let invalid = || format!("expected a color name, #RRGGBB or #RRGGBBAA, got '{}'", text);
+    let hex = text.strip_prefix('#').ok_or_else(invalid)?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+    Ok(Color {
+        red: byte(0)?,
+        green: byte(2)?,
+        blue: byte(4)?,
+        opacity: if hex.len() == 8 { byte(6)? } else { 255 },
+    })
+}
+
+/// Parses `--sub-delay`: a duration as for [`parse_duration`], optionally
+/// signed, e.g. `1.5s`, `-0.4`, `-250ms`.
+pub fn parse_delay(text: &str) -> Result<f64, String> {
+    let text = text.trim();
+    match text.strip_prefix('-') {
+        Some(rest) => parse_duration(rest).map(|secs| -secs),
+        None => parse_duration(text.strip_prefix('+').unwrap_or(text)),
+    }
+}
+
+/// Which edge of the picture burned subtitles sit at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitlePosition {
+    #[default]
+    Bottom,
+    /// Out of the way of subtitles already burned into the source
+    Top,
+}
+
+/// Font, style and placement settings for burned subtitles. The text
+/// overrides apply to subtitles without styles of their own (SRT, WebVTT,
+/// mov_text); ASS/SSA tracks keep theirs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubtitleStyle {
+    /// Extra directory of fonts for libass, e.g. CJK fonts the system lacks
+    pub fonts_dir: Option<PathBuf>,
+    /// Font family instead of libass's default
+    pub font: Option<String>,
+    /// Font size, in libass units (the default is 16 on a 288-line canvas)
+    pub size: Option<u32>,
+    pub color: Option<Color>,
+    /// Outline width; 0 turns it off
+    pub outline: Option<f64>,
+    /// Draw an opaque box of this color behind the text instead of a shadow
+    pub background: Option<Color>,
+    pub position: SubtitlePosition,
+    /// Distance from the edge, in percent of the picture height
+    pub margin: Option<f64>,
+    /// Show subtitles this many seconds later (earlier when negative)
+    pub delay_secs: f64,
+    /// Character set of text subtitles, e.g. `CP1250`; detected per track
+    /// when unset
+    pub charenc: Option<String>,
+}
+
+impl SubtitleStyle {
+    /// The ASS style fields for `force_style`, e.g. `FontName=Arial,Fontsize=24`.
+    fn overrides(&self) -> Vec<String> {
+        let mut fields = Vec::new();
+        if let Some(font) = &self.font {
+            fields.push(format!("FontName={}", font));
+        }
+        if let Some(size) = self.size {
+            fields.push(format!("Fontsize={}", size));
+        }
+        if let Some(color) = self.color {
+            fields.push(format!("PrimaryColour={}", color.ass()));
+        }
+        if let Some(outline) = self.outline {
+            fields.push(format!("Outline={}", outline));
+        }
+        if let Some(background) = self.background {
+            // libass fills the box with the outline color and shades it with the back color
+            fields.push("BorderStyle=3".to_string());
+            fields.push(format!("OutlineColour={}", background.ass()));
+            fields.push(format!("BackColour={}", background.ass()));
+            fields.push("Shadow=0".to_string());
+        }
+        if self.position == SubtitlePosition::Top {
+            // Numpad layout: 8 is top center
+            fields.push("Alignment=8".to_string());
+        }
+        if let Some(margin) = self.margin {
+            // Unstyled subtitles are laid out on a canvas 288 lines tall
+            fields.push(format!("MarginV={}", (margin * 2.88).round() as i64));
+        }
+        fields
+    }
+
+    /// How far to move a bitmap subtitle canvas of `height` lines down (or,
+    /// when negative, up) from where the track places it. Bitmap subtitles
+    /// sit in the bottom third of their canvas, so `Top` moves that third to
+    /// the top of the picture; the margin then moves them away from the edge.
+    pub fn bitmap_offset(&self, height: u32) -> i64 {
+        let height = height as f64;
+        let margin = self.margin.unwrap_or(0.0) * height / 100.0;
+        let offset = match self.position {
+            SubtitlePosition::Bottom => -margin,
+            SubtitlePosition::Top => margin - height * 2.0 / 3.0,
+        };
+        offset.round() as i64
+    }
+}
+
+/// Whether `codec` carries its own styles, fonts included.
+pub fn is_styled(codec: &str) -> bool {
+    matches!(codec, "ass" | "ssa")
+}
+
+/// The `subtitles` filter burning `track` of `input` with `style`.
+pub fn subtitles_filter(input: &Path, track: &SubtitleTrack, style: &SubtitleStyle) -> String {
+    let mut filter = format!("subtitles={}:si={}", escape_path(input), track.subtitle_index);
+    if let Some(dir) = &style.fonts_dir {
+        filter.push_str(&format!(":fontsdir={}", escape_path(dir)));
+    }
+    if let Some(charenc) = &style.charenc {
+        filter.push_str(&format!(":charenc={}", escape_value(charenc)));
+    }
+    let overrides = if is_styled(&track.codec) { Vec::new() } else { style.overrides() };
+    if !overrides.is_empty() {
+        filter.push_str(&format!(":force_style={}", escape_value(&overrides.join(","))));
+    }
+    filter
+}
+
+/// Fonts extracted from a file's attachments into a temporary directory,
+/// which is deleted on drop.
+#[derive(Debug)]
+pub struct AttachedFonts {
+    pub dir: PathBuf,
+    pub count: usize,
+}
+
+impl Drop for AttachedFonts {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Extracts the fonts attached to `info` for burning an ASS track with
+/// them. `None` when it has none or none could be written.
+pub fn extract_fonts(runner: &dyn FfmpegRunner, info: &VideoInfo) -> Option<AttachedFonts> {
+    if info.fonts.is_empty() {
+        return None;
+    }
+    let dir = env::temp_dir().join(format!(
+        "tvcode-fonts-{}-{}",
+        std::process::id(),
+        info.path.file_stem().unwrap_or_default().to_string_lossy()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).ok()?;
+
+    let mut args = vec!["-v".to_string(), "error".to_string()];
+    for (i, font) in info.fonts.iter().enumerate() {
+        // Attachment names come from the file, so keep only the last component
+        let name = Path::new(&font.filename)
+            .file_name()
+            .map_or_else(|| format!("font-{}", i), |name| name.to_string_lossy().into_owned());
+        args.push(format!("-dump_attachment:{}", font.stream_index));
+        args.push(dir.join(name).display().to_string());
+    }
+    args.push("-i".to_string());
+    args.push(info.path.display().to_string());
+    // ffmpeg fails for want of an output after dumping, so count what it wrote
+    let _ = runner.capture(Tool::Ffmpeg, &args);
+    let fonts = AttachedFonts {
+        count: fs::read_dir(&dir).map_or(0, |entries| entries.flatten().count()),
+        dir,
+    };
+    (fonts.count > 0).then_some(fonts)
+}
+
+/// Codec name of the menu entry for closed captions carried in the video
+/// stream, which have no subtitle stream of their own.
+pub const CLOSED_CAPTIONS: &str = "eia_608";
+
+/// The menu entry for a video's EIA-608/708 closed captions.
+pub fn closed_caption_track() -> SubtitleTrack {
+    SubtitleTrack {
+        subtitle_index: 0,
+        codec: CLOSED_CAPTIONS.to_string(),
+        language: None,
+        title: Some("Closed captions".to_string()),
+        is_bitmap: false,
+        source: None,
+        forced: false,
+        language_detected: false,
+    }
+}
+
+/// Subtitles written to a temporary file, deleted on drop.
+#[derive(Debug)]
+pub struct TempSubtitles {
+    pub path: PathBuf,
+}
+
+impl TempSubtitles {
+    /// A file in the temp dir for `what` (e.g. `captions`) of `input`, with
+    /// `extension` `srt` or `ass`.
+    pub fn new(what: &str, input: &Path, extension: &str) -> TempSubtitles {
+        TempSubtitles {
+            path: env::temp_dir().join(format!(
+                "tvcode-{}-{}-{}.{}",
+                what,
+                std::process::id(),
+                input.file_stem().unwrap_or_default().to_string_lossy(),
+                extension
+            )),
+        }
+    }
+
+    /// `track`, read from this file instead.
+    pub fn track(&self, track: &SubtitleTrack) -> SubtitleTrack {
+        let is_ass = self.path.extension().is_some_and(|ext| ext == "ass");
+        SubtitleTrack {
+            subtitle_index: 0,
+            codec: if is_ass { "ass" } else { "subrip" }.to_string(),
+            source: Some(self.path.clone()),
+            ..track.clone()
+        }
+    }
+}
+
+impl Drop for TempSubtitles {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Runs ffmpeg with `args` writing `output`, failing when it fails or
+/// writes nothing.
+fn write_subtitles(runner: &dyn FfmpegRunner, args: &[String], output: &Path, what: &str) -> Result<(), String> {
+    let result = runner
+        .capture(Tool::Ffmpeg, args)
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+    if !result.success() {
+        let message = String::from_utf8_lossy(&result.stderr);
+        return Err(format!("extracting {} failed: {}", what, message.lines().last().unwrap_or("")));
+    }
+    if fs::metadata(output).map_or(0, |m| m.len()) == 0 {
+        let _ = fs::remove_file(output);
+        return Err(format!("the {} are empty", what));
+    }
+    Ok(())
+}
+
+/// Writes the closed captions in `input`'s video stream to `output` as SRT.
+/// This decodes the whole video, so it takes a while.
+pub fn extract_captions(runner: &dyn FfmpegRunner, input: &Path, output: &Path) -> Result<(), String> {
+    let args: Vec<String> = vec![
+        "-v".into(),
+        "error".into(),
+        "-f".into(),
+        "lavfi".into(),
+        "-i".into(),
+        format!("movie={}[out0+subcc]", escape_path(input)),
+        "-map".into(),
+        "0:s:0".into(),
+        "-c:s".into(),
+        "srt".into(),
+        "-y".into(),
+        output.display().to_string(),
+    ];
+    write_subtitles(runner, &args, output, "closed captions")
+}
+
+/// Writes text `track` of `video_path` to `output` as UTF-8 SRT, or ASS when
+/// `output` ends in `.ass`, reading it in `charenc` when given.
+pub fn extract_track(
+    runner: &dyn FfmpegRunner,
+    video_path: &Path,
+    track: &SubtitleTrack,
+    charenc: Option<&str>,
+    output: &Path,
+) -> Result<(), String> {
+    let mut args = vec!["-v".to_string(), "error".to_string()];
+    if let Some(charenc) = charenc {
+        args.extend(["-sub_charenc".to_string(), charenc.to_string()]);
+    }
+    let codec = if output.extension().is_some_and(|ext| ext == "ass") { "ass" } else { "srt" };
+    args.extend([
+        "-i".to_string(),
+        track.input(video_path).display().to_string(),
+        "-map".to_string(),
+        format!("0:s:{}", track.subtitle_index),
+        "-c:s".to_string(),
+        codec.to_string(),
+        "-y".to_string(),
+        output.display().to_string(),
+    ]);
+    write_subtitles(runner, &args, output, "subtitles")
+}
+
+/// A single-byte character set text subtitles are commonly found in.
+struct Charset {
+    /// Name for ffmpeg's `charenc` (iconv)
+    name: &'static str,
+    /// Bytes 0x80 to 0x9F, with U+FFFD for the undefined ones
+    c1: &'static str,
+    /// Bytes 0xA0 to 0xFF, when they differ from Latin-1
+    high: Option<&'static str>,
+    /// Non-ASCII letters common in the languages written in it
+    letters: &'static str,
+}
+
+impl Charset {
+    fn decode(&self, byte: u8) -> char {
+        match byte {
+            0x80..=0x9F => self.c1.chars().nth(byte as usize - 0x80).unwrap_or('\u{FFFD}'),
+            0xA0.. => match self.high {
+                Some(high) => high.chars().nth(byte as usize - 0xA0).unwrap_or('\u{FFFD}'),
+                None => char::from(byte),
+            },
+            _ => char::from(byte),
+        }
+    }
+
+    /// How plausible `text` is in this character set: common letters count
+    /// for it, stray symbols and undefined bytes against it.
+    fn score(&self, text: &[u8]) -> i64 {
+        text.iter()
+            .filter(|byte| !byte.is_ascii())
+            .map(|&byte| match self.decode(byte) {
+                '\u{FFFD}' => -5,
+                c if self.letters.contains(c) => 2,
+                c if c.is_alphabetic() || "‚„…†‡‰‹›‘’“”•–—™«»°\u{A0}\u{AD}".contains(c) => 0,
+                _ => -2,
+            })
+            .sum()
+    }
+}
+
+/// Candidates for text that is not UTF-8, Western European first so it wins
+/// ties.
+const CHARSETS: &[Charset] = &[
+    Charset {
+        name: "CP1252",
+        c1: "€\u{FFFD}‚ƒ„…†‡ˆ‰Š‹Œ\u{FFFD}Ž\u{FFFD}\u{FFFD}‘’“”•–—˜™š›œ\u{FFFD}žŸ",
+        high: None,
+        letters: "¿¡àâäçèéêëîïôöùûüÿñáíóúãõœæøåßìòÀÂÄÇÈÉÊËÎÏÔÖÙÛÜÑÁÍÓÚÃÕŒÆØÅÌÒ",
+    },
+    Charset {
+        name: "CP1250",
+        c1: "€\u{FFFD}‚\u{FFFD}„…†‡\u{FFFD}‰Š‹ŚŤŽŹ\u{FFFD}‘’“”•–—\u{FFFD}™š›śťžź",
+        high: Some(concat!(
+            "\u{A0}ˇ˘Ł¤Ą¦§¨©Ş«¬\u{AD}®Ż°±˛ł´µ¶·¸ąş»Ľ˝ľż",
+            "ŔÁÂĂÄĹĆÇČÉĘËĚÍÎĎĐŃŇÓÔŐÖ×ŘŮÚŰÜÝŢß",
+            "ŕáâăäĺćçčéęëěíîďđńňóôőö÷řůúűüýţ˙",
+        )),
+        letters: "ąćęłńóśźżčďěňřšťůžáéíúýäöüőűôĺľŕĄĆĘŁŃÓŚŹŻČĎĚŇŘŠŤŮŽÁÉÍÚÝÄÖÜŐŰÔĹĽŔ",
+    },
+];
+
+/// The character set `text` is in, or `None` for UTF-8 (and plain ASCII).
+pub fn detect_charset(text: &[u8]) -> Option<&'static str> {
+    if text.starts_with(&[0xFF, 0xFE]) || text.starts_with(&[0xFE, 0xFF]) {
+        return Some("UTF-16");
+    }
+    if std::str::from_utf8(text).is_ok() {
+        return None;
+    }
+    let mut best = &CHARSETS[0];
+    for charset in &CHARSETS[1..] {
+        if charset.score(text) > best.score(text) {
+            best = charset;
+        }
+    }
+    Some(best.name)
+}
+
+/// Whether `codec` is plain text ffmpeg can hand over as it is stored.
+fn is_plain_text(codec: &str) -> bool {
+    matches!(codec, "subrip" | "srt" | "ass" | "ssa" | "webvtt" | "text" | "microdvd" | "subviewer")
+}
+
+/// `text` decoded from the character set [`detect_charset`] finds for it.
+fn decode_text(text: &[u8]) -> String {
+    let charset = detect_charset(text).and_then(|name| CHARSETS.iter().find(|charset| charset.name == name));
+    match charset {
+        Some(charset) => text.iter().map(|&byte| charset.decode(byte)).collect(),
+        None => String::from_utf8_lossy(text).into_owned(),
+    }
+}
+
+/// The stored text of `track` in `input`, or of its first `seconds` only.
+/// `None` when it is not plain text or cannot be read.
+fn track_text(runner: &dyn FfmpegRunner, input: &Path, track: &SubtitleTrack, seconds: Option<u32>) -> Option<Vec<u8>> {
+    if track.is_bitmap || !is_plain_text(&track.codec) {
+        return None;
+    }
+    let mut args: Vec<String> = vec!["-v".into(), "error".into()];
+    if let Some(seconds) = seconds {
+        args.extend(["-t".into(), seconds.to_string()]);
+    }
+    args.extend([
+        "-i".into(),
+        input.display().to_string(),
+        "-map".into(),
+        format!("0:s:{}", track.subtitle_index),
+        "-c:s".into(),
+        "copy".into(),
+        "-f".into(),
+        "data".into(),
+        "-".into(),
+    ]);
+    let output = runner.capture(Tool::Ffmpeg, &args).ok()?;
+    Some(output.stdout)
+}
+
+/// Extracts text `track` of `input` and detects its character set. `None`
+/// when it is UTF-8, not plain text or cannot be read.
+pub fn track_charset(runner: &dyn FfmpegRunner, input: &Path, track: &SubtitleTrack) -> Option<&'static str> {
+    detect_charset(&track_text(runner, input, track, None)?)
+}
+
+/// Seconds from the start of a track read to guess its language.
+const LANGUAGE_SAMPLE_SECS: u32 = 1200;
+
+/// Guesses the language of text `track` of `input` from the cues of its
+/// first [`LANGUAGE_SAMPLE_SECS`]. `None` when it is not plain text, cannot
+/// be read or is not clearly one language.
+pub fn track_language(runner: &dyn FfmpegRunner, input: &Path, track: &SubtitleTrack) -> Option<&'static str> {
+    let text = decode_text(&track_text(runner, input, track, Some(LANGUAGE_SAMPLE_SECS))?);
+    detect_language(&strip_markup(&text))
+}
+
+/// `text` without HTML tags like `<i>` and ASS override blocks like `{\i1}`,
+/// and with ASS line breaks as spaces.
+fn strip_markup(text: &str) -> String {
+    let mut output = String::new();
+    let mut inside = None;
+    for c in text.replace("\\N", " ").replace("\\n", " ").chars() {
+        match (inside, c) {
+            (None, '<') => inside = Some('>'),
+            (None, '{') => inside = Some('}'),
+            (None, c) => output.push(c),
+            (Some(end), c) if c == end => inside = None,
+            (Some(_), _) => {}
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_is_left_alone_with_or_without_a_bom() {
+        assert_eq!(detect_charset("Déjà vu, señor".as_bytes()), None);
+        assert_eq!(detect_charset(b"\xEF\xBB\xBFD\xC3\xA9j\xC3\xA0 vu"), None);
+        assert_eq!(detect_charset(b"plain ASCII"), None);
+    }
+
+    #[test]
+    fn utf16_is_recognized_by_its_bom() {
+        assert_eq!(detect_charset(b"\xFF\xFEH\x00i\x00"), Some("UTF-16"));
+        assert_eq!(detect_charset(b"\xFE\xFF\x00H\x00i"), Some("UTF-16"));
+    }
+
+    #[test]
+    fn latin1_is_read_as_western_european() {
+        let text = b"Voil\xE0, \xE7a marche d\xE9j\xE0. \xBFQu\xE9 pas\xF3?";
+        assert_eq!(detect_charset(text), Some("CP1252"));
+        assert_eq!(decode_text(text), "Voilà, ça marche déjà. ¿Qué pasó?");
+    }
+
+    #[test]
+    fn central_european_text_is_read_as_cp1250() {
+        // "Zażółć gęślą jaźń" in Windows-1250
+        let text = b"Za\xBF\xF3\xB3\xE6 g\xEA\x9Cl\xB9 ja\x9F\xF1";
+        assert_eq!(detect_charset(text), Some("CP1250"));
+        assert_eq!(decode_text(text), "Zażółć gęślą jaźń");
+    }
+}
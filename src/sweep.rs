@@ -0,0 +1,75 @@
+//! `tvcode sweep`: the same excerpt encoded at several CRF or bitrate
+//! points, each scored against the source, to pick settings for a whole
+//! season from one episode.
+//!
+//! The excerpts go into a `_sweep` folder beside the input, one file per
+//! point, so they can be watched as well as compared by numbers.
+
+use crate::plan::VideoSettings;
+use std::path::{Path, PathBuf};
+
+/// Seconds encoded per point when no `--sample` length is given.
+pub const DEFAULT_SWEEP_SECS: f64 = 60.0;
+
+/// One setting tried by a sweep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepPoint {
+    /// libx264 CRF, also used as the NVENC CQ
+    Crf(u32),
+    /// Average video bitrate in kbit/s, encoded in two passes
+    Bitrate(u32),
+}
+
+impl SweepPoint {
+    /// Display name, e.g. `CRF 20` or `4000k`.
+    pub fn label(self) -> String {
+        match self {
+            SweepPoint::Crf(crf) => format!("CRF {}", crf),
+            SweepPoint::Bitrate(kbps) => format!("{}k", kbps),
+        }
+    }
+
+    /// Name of this point's excerpt in the sweep folder, without extension.
+    pub fn file_stem(self) -> String {
+        match self {
+            SweepPoint::Crf(crf) => format!("crf{}", crf),
+            SweepPoint::Bitrate(kbps) => format!("{}k", kbps),
+        }
+    }
+
+    /// Switches `settings` to this point. Refit the level after.
+    pub fn apply(self, settings: &mut VideoSettings) {
+        match self {
+            SweepPoint::Crf(crf) => {
+                settings.crf = crf;
+                settings.nvenc_cq = crf;
+                settings.two_pass = false;
+                settings.nvenc_fixed_bitrate = false;
+            }
+            SweepPoint::Bitrate(kbps) => {
+                settings.bitrate_kbps = kbps;
+                settings.max_bitrate_kbps = kbps * 3 / 2;
+                settings.two_pass = true;
+                settings.nvenc_fixed_bitrate = true;
+            }
+        }
+    }
+}
+
+/// The points to try: the given CRFs and bitrates, or when there are none,
+/// five CRFs two apart around `crf` (the preset's).
+pub fn sweep_points(crfs: &[u32], bitrates: &[u32], crf: u32) -> Vec<SweepPoint> {
+    if crfs.is_empty() && bitrates.is_empty() {
+        return (0..5).map(|step| SweepPoint::Crf((crf + step * 2).saturating_sub(4))).collect();
+    }
+    crfs.iter()
+        .map(|&crf| SweepPoint::Crf(crf))
+        .chain(bitrates.iter().map(|&kbps| SweepPoint::Bitrate(kbps)))
+        .collect()
+}
+
+/// Folder for the excerpts of a sweep of `input_path`, beside it.
+pub fn sweep_output_dir(input_path: &Path) -> PathBuf {
+    let stem = input_path.file_stem().unwrap().to_string_lossy();
+    input_path.parent().unwrap().join(format!("{}_appletv_sweep", stem))
+}
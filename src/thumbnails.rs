@@ -0,0 +1,131 @@
+//! `--thumbnails`: a poster frame and a tiled preview sprite beside the output.
+//!
+//! Both are grabbed from the finished output, so they show exactly what was
+//! encoded (burned-in subtitles included). The poster is named
+//! `<output>-thumb.jpg`, which Jellyfin, Plex and Kodi pick up as the item
+//! thumbnail. The sprite is a single [`SPRITE_COLUMNS`]x[`SPRITE_ROWS`] sheet
+//! of evenly spaced frames for scrubbing previews in web UIs.
+
+use crate::plan::TranscodePlan;
+use crate::probe::VideoInfo;
+use crate::runner::{FfmpegRunner, Tool};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Tiles per sprite row.
+pub const SPRITE_COLUMNS: u32 = 10;
+
+/// Sprite rows.
+pub const SPRITE_ROWS: u32 = 10;
+
+/// Width of each sprite tile in pixels.
+const TILE_WIDTH: u32 = 320;
+
+/// Poster frame position as a fraction of the duration, past most intros.
+const POSTER_POSITION: f64 = 0.1;
+
+/// Which images `--thumbnails` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Thumbnails {
+    /// A single poster frame
+    Poster,
+    /// A tiled preview sprite
+    Sprite,
+    /// Both
+    All,
+}
+
+impl Thumbnails {
+    fn poster(self) -> bool {
+        matches!(self, Thumbnails::Poster | Thumbnails::All)
+    }
+
+    fn sprite(self) -> bool {
+        matches!(self, Thumbnails::Sprite | Thumbnails::All)
+    }
+}
+
+/// `<output stem>-<suffix>.jpg` next to `output`.
+fn image_path(output: &Path, suffix: &str) -> PathBuf {
+    let stem = output.file_stem().unwrap().to_string_lossy();
+    output.with_file_name(format!("{}-{}.jpg", stem, suffix))
+}
+
+/// Where the poster and sprite of `output` go, whether or not they exist.
+pub fn thumbnail_paths(output: &Path) -> [PathBuf; 2] {
+    [image_path(output, "thumb"), image_path(output, "sprite")]
+}
+
+/// Writes the images selected by `kind` for the finished `plan` and returns
+/// their paths.
+pub fn generate_thumbnails(
+    runner: &dyn FfmpegRunner,
+    info: &VideoInfo,
+    plan: &TranscodePlan,
+    kind: Thumbnails,
+) -> Result<Vec<PathBuf>, String> {
+    let duration = plan.output_duration(info);
+    let output = plan.output.to_str().unwrap().to_string();
+    let mut written = Vec::new();
+
+    if kind.poster() {
+        let path = image_path(&plan.output, "thumb");
+        let args = vec![
+            "-hide_banner".to_string(),
+            "-ss".to_string(),
+            format!("{:.3}", duration * POSTER_POSITION),
+            "-i".to_string(),
+            output.clone(),
+            "-frames:v".to_string(),
+            "1".to_string(),
+            "-q:v".to_string(),
+            "2".to_string(),
+            "-y".to_string(),
+            path.to_str().unwrap().to_string(),
+        ];
+        run(runner, &args, "poster")?;
+        written.push(path);
+    }
+
+    if kind.sprite() {
+        if duration <= 0.0 {
+            return Err("cannot space sprite frames without a known duration".to_string());
+        }
+        let path = image_path(&plan.output, "sprite");
+        let interval = duration / (SPRITE_COLUMNS * SPRITE_ROWS) as f64;
+        let args = vec![
+            "-hide_banner".to_string(),
+            "-i".to_string(),
+            output,
+            "-vf".to_string(),
+            format!(
+                "fps=1/{:.3},scale={}:-2,tile={}x{}",
+                interval, TILE_WIDTH, SPRITE_COLUMNS, SPRITE_ROWS
+            ),
+            "-frames:v".to_string(),
+            "1".to_string(),
+            "-q:v".to_string(),
+            "3".to_string(),
+            "-y".to_string(),
+            path.to_str().unwrap().to_string(),
+        ];
+        run(runner, &args, "sprite")?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+fn run(runner: &dyn FfmpegRunner, args: &[String], what: &str) -> Result<(), String> {
+    let output = runner
+        .capture(Tool::Ffmpeg, args)
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+    if output.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format!("{} extraction failed: {}", what, stderr.lines().last().unwrap_or("").trim()))
+    }
+}
@@ -0,0 +1,1648 @@
+//! Building and running the ffmpeg command for a [`TranscodePlan`].
+
+use crate::analyze::analyze_complexity;
+use crate::cache::ProbeCache;
+use crate::dashboard;
+use crate::filters::{shift_pts, unsharp, FilterArgs, FilterGraph, HwBackend, COMPLEX_OUTPUT};
+use crate::hwaccel::{
+    detect_hardware_acceleration, device_for_gpu, get_hw_encoding_args, get_sw_encoding_args, qsv_init_args,
+    select_encoder, select_vaapi_device, HwAccel, QSV_DEVICE_NAME,
+};
+use crate::hls::{self, SEGMENT_SECS};
+use crate::integrity::sync_drift;
+use crate::joblog::JobLog;
+use crate::language::is_undetermined;
+use crate::notify::Notifier;
+use crate::opensubtitles::{self, OpenSubtitlesSettings};
+use crate::options::EncodeOptions;
+use crate::output::{verbosity, Verbosity};
+use crate::power::{power_state, PowerPolicy};
+use crate::preset::QualityPreset;
+use crate::plan::{
+    chapter_file_name, partial_output_path, repaired_path, split_part_path, AudioAction, SampleWindow, TranscodePlan, VideoEncoder, AUDIO_SAMPLE_RATE, NIGHT_MODE_FILTER, OUTPUT_COMMENT,
+};
+use crate::probe::{self, SubtitleTrack, VideoInfo};
+use crate::quality::{measure_quality, raise_quality, QualityMetric};
+use crate::remux::Remuxer;
+use crate::runner::{FfmpegRunner, SystemRunner};
+use crate::sdh::strip_sdh_file;
+use crate::segment;
+use crate::staging::{move_to_destination, staged_path};
+use crate::subtitles::{
+    extract_captions, extract_fonts, extract_track, is_styled, subtitles_filter, track_charset, track_language,
+    TempSubtitles, CLOSED_CAPTIONS,
+};
+use crate::thumbnails::generate_thumbnails;
+use crate::transfer::{finished_files, TransferSettings};
+use crate::status;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// Most ffprobe processes [`Transcoder::probe_all`] runs at once; probing is
+/// mostly waiting on disk or network reads.
+pub const PROBE_JOBS: usize = 8;
+
+/// Runs transcodes with the encoder detected once at construction, recording
+/// each ffmpeg invocation in the job log.
+pub struct Transcoder {
+    runner: Arc<dyn FfmpegRunner>,
+    hw_family: Option<String>,
+    hw_accel: Option<HwAccel>,
+    options: EncodeOptions,
+    job_log: JobLog,
+    notifier: Notifier,
+    probe_cache: ProbeCache,
+    opensubtitles: Option<OpenSubtitlesSettings>,
+    transfer: Option<TransferSettings>,
+    /// Results of [`Transcoder::probe_all`] not yet taken by `probe`
+    probed: Mutex<HashMap<PathBuf, VideoInfo>>,
+}
+
+impl Transcoder {
+    /// Creates a transcoder that runs the system ffmpeg, probing it for a
+    /// usable hardware encoder. Built with the `libav` feature, it probes
+    /// through [`LibavRunner`](crate::libav::LibavRunner) instead.
+    pub fn new(job_log: JobLog) -> Transcoder {
+        Transcoder::with_runner(default_runner(), job_log)
+    }
+
+    /// Creates a transcoder that executes everything through `runner`.
+    pub fn with_runner(runner: Arc<dyn FfmpegRunner>, job_log: JobLog) -> Transcoder {
+        let hw_family = detect_hardware_acceleration(runner.as_ref());
+        let mut transcoder = Transcoder {
+            runner,
+            hw_family,
+            hw_accel: None,
+            options: EncodeOptions::default(),
+            job_log,
+            notifier: Notifier::default(),
+            probe_cache: ProbeCache::default(),
+            opensubtitles: None,
+            transfer: None,
+            probed: Mutex::new(HashMap::new()),
+        };
+        transcoder.resolve_hw_accel();
+        transcoder
+    }
+
+    /// Applies `options` to every subsequently planned file. An encoder
+    /// preference list replaces the detected encoder.
+    pub fn with_options(mut self, options: EncodeOptions) -> Transcoder {
+        let reselect_device = options.vaapi_device.is_some() || !options.gpus.is_empty();
+        let reselect_encoder = !options.encoders.is_empty();
+        self.options = options;
+        if reselect_encoder {
+            self.hw_family = select_encoder(self.runner(), &self.options.encoders);
+        }
+        if reselect_device || reselect_encoder {
+            self.resolve_hw_accel();
+        }
+        self
+    }
+
+    /// Binds the detected encoder family to a device, dropping to software
+    /// when the family needs a device and none works.
+    fn resolve_hw_accel(&mut self) {
+        self.hw_accel = match self.hw_family.as_deref() {
+            Some("vaapi") => {
+                match select_vaapi_device(self.runner(), self.options.vaapi_device.as_deref()) {
+                    Some(device) => Some(HwAccel {
+                        family: "vaapi".to_string(),
+                        device: Some(device.to_string_lossy().into_owned()),
+                    }),
+                    None => {
+                        eprintln!("   ⚠️  No VAAPI device can encode H.264, using software encoding");
+                        None
+                    }
+                }
+            }
+            Some(family) => Some(HwAccel::new(family)),
+            None => None,
+        };
+
+        // An explicit --gpu pins the first job's device
+        if let (Some(hw), Some(&gpu)) = (self.hw_accel.as_mut(), self.options.gpus.first()) {
+            match device_for_gpu(&hw.family, gpu) {
+                Some(device) => hw.device = Some(device),
+                None => eprintln!("   ⚠️  GPU {} is not available for {}", gpu, hw.family),
+            }
+        }
+    }
+
+    /// Moves `plan` onto the GPU for parallel job `slot`, round-robin over the
+    /// `--gpu` list. Does nothing unless several GPUs were given.
+    pub fn assign_gpu(&self, plan: &mut TranscodePlan, slot: usize) {
+        let gpus = &self.options.gpus;
+        if gpus.len() < 2 {
+            return;
+        }
+        if let VideoEncoder::Hardware(family) = &plan.video {
+            if let Some(device) = device_for_gpu(family, gpus[slot % gpus.len()]) {
+                plan.hw_device = Some(device);
+            }
+        }
+    }
+
+    pub fn options(&self) -> &EncodeOptions {
+        &self.options
+    }
+
+    pub fn runner(&self) -> &dyn FfmpegRunner {
+        self.runner.as_ref()
+    }
+
+    /// Probes `path` through this transcoder's runner, unless
+    /// [`probe_all`](Self::probe_all) or the probe cache already has it.
+    pub fn probe(&self, path: &Path) -> Result<VideoInfo, String> {
+        if let Some(info) = self.probed.lock().unwrap().remove(path) {
+            return Ok(info);
+        }
+        let cached = self.probe_cache.get(path);
+        if let Some(info) = cached.and_then(|json| probe::parse_video_info(path, &json).ok()) {
+            return Ok(info);
+        }
+        let json = probe::run_ffprobe(self.runner(), path)?;
+        let info = probe::parse_video_info(path, &json)?;
+        self.probe_cache.put(path, &json);
+        Ok(info)
+    }
+
+    /// Tracks of the subtitle files beside `video_path`. Files
+    /// that cannot be probed are skipped with a warning.
+    pub fn external_subtitles(&self, video_path: &Path) -> Vec<SubtitleTrack> {
+        let mut tracks = Vec::new();
+        for path in probe::external_subtitle_files(video_path) {
+            match self.probe(&path) {
+                Ok(info) => tracks.extend(probe::external_tracks(&path, video_path, info)),
+                Err(e) => eprintln!(
+                    "   ⚠️  Cannot read {} ({})",
+                    path.file_name().unwrap_or_default().to_string_lossy(),
+                    e
+                ),
+            }
+        }
+        tracks
+    }
+
+    /// Guesses the language of each text track in `tracks` that is not
+    /// tagged with one, from a sample of its cues.
+    pub fn detect_languages(&self, video_path: &Path, tracks: &mut [SubtitleTrack]) {
+        for track in tracks.iter_mut().filter(|track| is_undetermined(track.language.as_deref())) {
+            if let Some(language) = track_language(self.runner(), track.input(video_path), track) {
+                track.language = Some(language.to_string());
+                track.language_detected = true;
+            }
+        }
+    }
+
+    /// Joins the files in `parts` into `joined` with the concat demuxer,
+    /// copying every stream.
+    pub fn join_parts(&self, parts: &[PathBuf], joined: &Path) -> Result<(), String> {
+        let name = joined.file_name().unwrap().to_string_lossy();
+        let list = joined.with_file_name(format!(".{}.parts.txt", name));
+        segment::write_concat_list(&list, parts).map_err(|e| format!("Failed to write {}: {}", list.display(), e))?;
+        let mut ffmpeg_args = get_loglevel_args();
+        ffmpeg_args.extend(
+            ["-f", "concat", "-safe", "0", "-i", list.to_str().unwrap(), "-map", "0", "-c", "copy", "-y"]
+                .iter()
+                .map(|a| a.to_string()),
+        );
+        ffmpeg_args.push(joined.to_str().unwrap().to_string());
+        let status = self.runner.run(&ffmpeg_args, &name);
+        let _ = fs::remove_file(&list);
+        match status {
+            Ok(Some(0)) => Ok(()),
+            Ok(code) => {
+                let _ = fs::remove_file(joined);
+                Err(format!("Joining the parts of {} failed with exit code: {:?}", name, code))
+            }
+            Err(e) => Err(format!("Failed to run ffmpeg for {}: {}", name, e)),
+        }
+    }
+
+    /// `--repair`: remuxes `path` into [`repaired_path`], ignoring decode
+    /// errors, dropping corrupt packets and generating missing timestamps.
+    /// Writing the container again rebuilds its index. Returns the copy.
+    pub fn repair(&self, path: &Path) -> Result<PathBuf, String> {
+        status!("   🩹 Remuxing the source into a clean copy...");
+        let name = path.file_name().unwrap().to_string_lossy();
+        let repaired = repaired_path(path);
+        let mut ffmpeg_args = get_loglevel_args();
+        ffmpeg_args.extend(
+            [
+                "-err_detect",
+                "ignore_err",
+                "-fflags",
+                "+genpts+discardcorrupt",
+                "-i",
+                path.to_str().unwrap(),
+                "-map",
+                "0",
+                "-c",
+                "copy",
+                "-avoid_negative_ts",
+                "make_zero",
+                "-y",
+            ]
+            .iter()
+            .map(|a| a.to_string()),
+        );
+        ffmpeg_args.push(repaired.to_str().unwrap().to_string());
+        match self.runner.run(&ffmpeg_args, &name) {
+            Ok(Some(0)) => Ok(repaired),
+            Ok(code) => {
+                let _ = fs::remove_file(&repaired);
+                Err(format!("Repairing {} failed with exit code: {:?}", name, code))
+            }
+            Err(e) => Err(format!("Failed to run ffmpeg for {}: {}", name, e)),
+        }
+    }
+
+    /// Probes every file in `paths` on up to [`PROBE_JOBS`] threads, in order.
+    /// Each success is kept for the next `probe` of that file, so planning
+    /// does not run ffprobe again.
+    pub fn probe_all(&self, paths: &[PathBuf]) -> Vec<Result<VideoInfo, String>> {
+        let jobs = thread::available_parallelism().map_or(1, |n| n.get()).clamp(1, PROBE_JOBS);
+        let next = AtomicUsize::new(0);
+        let results: Vec<Mutex<Option<Result<VideoInfo, String>>>> = paths.iter().map(|_| Mutex::new(None)).collect();
+        thread::scope(|scope| {
+            for _ in 0..jobs.min(paths.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(i) else { break };
+                    *results[i].lock().unwrap() = Some(self.probe(path));
+                });
+            }
+        });
+        let results: Vec<Result<VideoInfo, String>> =
+            results.into_iter().map(|result| result.into_inner().unwrap().unwrap()).collect();
+        let mut probed = self.probed.lock().unwrap();
+        for info in results.iter().flatten() {
+            probed.insert(info.path.clone(), info.clone());
+        }
+        results
+    }
+
+    /// The detected hardware encoder and its device, if any.
+    pub fn hw_accel(&self) -> Option<&HwAccel> {
+        self.hw_accel.as_ref()
+    }
+
+    pub fn job_log(&self) -> &JobLog {
+        &self.job_log
+    }
+
+    /// Sends file and batch notifications through `notifier`.
+    pub fn with_notifier(mut self, notifier: Notifier) -> Transcoder {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Reuses ffprobe results from `cache` for unchanged files.
+    pub fn with_probe_cache(mut self, cache: ProbeCache) -> Transcoder {
+        self.probe_cache = cache;
+        self
+    }
+
+    pub fn notifier(&self) -> &Notifier {
+        &self.notifier
+    }
+
+    /// Fetches subtitles for `--download-subs` with this account.
+    pub fn with_opensubtitles(mut self, settings: Option<OpenSubtitlesSettings>) -> Transcoder {
+        self.opensubtitles = settings;
+        self
+    }
+
+    /// Pushes finished outputs to this host.
+    pub fn with_transfer(mut self, settings: Option<TransferSettings>) -> Transcoder {
+        self.transfer = settings;
+        self
+    }
+
+    /// Copies the outputs of the finished `plan` for `info` to the
+    /// `[transfer]` host, deleting them here once the copies are verified.
+    /// Samples stay local. Failures are reported and keep the files.
+    pub fn push_outputs(&self, info: &VideoInfo, plan: &TranscodePlan) {
+        let Some(transfer) = self.transfer.as_ref().filter(|_| plan.sample.is_none()) else {
+            return;
+        };
+        let files = finished_files(plan);
+        match transfer.push(&files) {
+            Ok(()) => {
+                self.job_log.record(&info.path, &format!("transfer:{}", transfer.destination));
+                status!("   🚚 Moved {} file(s) to {}", files.len(), transfer.destination);
+            }
+            Err(e) => {
+                self.job_log.record_error(&info.path, &e);
+                eprintln!("   ⚠️  Transfer failed, keeping the output here ({})", e);
+            }
+        }
+    }
+
+    /// `--download-subs`: saves subtitles from OpenSubtitles beside `info`
+    /// when it has no subtitles of its own, inside or beside it. Failures
+    /// are warnings; the file is converted without them.
+    pub fn download_subtitles(&self, info: &VideoInfo) {
+        let (Some(language), Some(settings)) = (&self.options.download_subtitles, &self.opensubtitles) else {
+            return;
+        };
+        if !info.subtitles.is_empty() || info.closed_captions || !probe::external_subtitle_files(&info.path).is_empty() {
+            return;
+        }
+        status!("   🌐 Searching OpenSubtitles for {} subtitles...", language);
+        match opensubtitles::download(settings, &info.path, language) {
+            Ok(path) => status!(
+                "   💬 Saved {}",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ),
+            Err(e) => eprintln!("   ⚠️  Cannot download subtitles: {}", e),
+        }
+    }
+
+    /// Plans a transcode of `info` using this transcoder's encoder, running
+    /// the complexity analysis first when `--auto-quality` is enabled.
+    pub fn plan(&self, info: &VideoInfo, subtitle: Option<SubtitleTrack>) -> TranscodePlan {
+        let subtitle = match subtitle {
+            Some(track) if self.options.forced_subtitles => Some(forced_track(info, track)),
+            subtitle => subtitle,
+        };
+        let mut plan = TranscodePlan::new(info, subtitle, self.hw_accel(), &self.options);
+        if let Some(track) = &plan.subtitle {
+            if plan.subtitle_style.charenc.is_none() {
+                if let Some(charset) = track_charset(self.runner(), track.input(&info.path), track) {
+                    status!("   🔤 Subtitle text is not UTF-8, reading it as {}", charset);
+                    plan.subtitle_style.charenc = Some(charset.to_string());
+                }
+            }
+        }
+        let languages = &self.options.keep_audio_languages;
+        if !languages.is_empty() && plan.audio_tracks.is_empty() && !info.audio_tracks.is_empty() {
+            eprintln!("   ⚠️  No {} audio track, keeping the default one", languages.join("/"));
+        }
+        if let Some(size) = self.options.target_size {
+            if plan.settings.two_pass && info.duration_secs > 0.0 {
+                status!(
+                    "   🎯 Targeting {:.2} GB: {}k video",
+                    size as f64 / 1e9,
+                    plan.settings.bitrate_kbps
+                );
+                if plan.settings.bitrate_kbps < 500 {
+                    status!("   ⚠️  That is a very low bitrate, expect visible artifacts");
+                }
+            } else {
+                status!("   ⚠️  Cannot hit a target size without a known duration, using normal settings");
+            }
+        }
+        if self.options.on_battery == Some(PowerPolicy::Fast) && plan.video != VideoEncoder::Copy {
+            let power = power_state();
+            if power.constrained() {
+                status!("   🔋 Using the fast preset while {}", power.reason());
+                plan.settings.preset = QualityPreset::Fast;
+            }
+        }
+        if self.options.auto_quality && plan.video != VideoEncoder::Copy {
+            status!("   🔬 Analyzing content complexity...");
+            match analyze_complexity(self.runner(), info, plan.settings.crf) {
+                Ok(complexity) => {
+                    complexity.apply(&mut plan.settings);
+                    let (width, height) = plan.output_size;
+                    let max_level = self.options.device_caps().max_h264_level;
+                    plan.settings.fit_level(width, height, plan.output_frame_rate(info), max_level);
+                    status!(
+                        "   🔬 Content is {} ({:.3} bits/pixel): CRF {}, {}k target",
+                        complexity.label(),
+                        complexity.bits_per_pixel,
+                        plan.settings.crf,
+                        plan.settings.bitrate_kbps
+                    );
+                }
+                Err(e) => status!("   ⚠️  Complexity analysis failed ({}), using defaults", e),
+            }
+        }
+        plan
+    }
+
+    /// Runs ffmpeg for `plan`. The error is a human-readable failure message.
+    /// With `--repair`, a failed encode is tried once more from a repaired
+    /// copy of the source. A repaired copy is removed afterwards.
+    pub fn transcode(&self, info: &VideoInfo, plan: &TranscodePlan) -> Result<(), String> {
+        let result = match &plan.input {
+            Some(input) => self.transcode_from(&VideoInfo { path: input.clone(), ..info.clone() }, plan),
+            None => match self.transcode_from(info, plan) {
+                Err(e) if self.options.repair => {
+                    eprintln!("   ❌ {}", e);
+                    let input = self.repair(&info.path)?;
+                    let result = self.transcode_from(&VideoInfo { path: input.clone(), ..info.clone() }, plan);
+                    let _ = fs::remove_file(&input);
+                    result
+                }
+                result => result,
+            },
+        };
+        if let Some(input) = &plan.input {
+            let _ = fs::remove_file(input);
+        }
+        result
+    }
+
+    /// [`Transcoder::transcode`] reading `info.path`.
+    fn transcode_from(&self, info: &VideoInfo, plan: &TranscodePlan) -> Result<(), String> {
+        status!(
+            "   📤 Output: {}",
+            plan.output.file_name().unwrap().to_string_lossy()
+        );
+
+        let label = info.path.file_name().unwrap().to_string_lossy().into_owned();
+        let dir = plan.output.parent().unwrap();
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+        // Closed captions and --strip-sdh work on a copy of the text in a
+        // temporary file; like the fonts below, it lives until we return
+        let mut text: Option<(SubtitleTrack, TempSubtitles)> = None;
+        if let Some(track) = plan.subtitle.as_ref().filter(|track| !track.is_bitmap) {
+            if track.codec == CLOSED_CAPTIONS {
+                status!("   📝 Extracting closed captions...");
+                let captions = TempSubtitles::new("captions", &info.path, "srt");
+                extract_captions(self.runner(), &info.path, &captions.path)?;
+                text = Some((captions.track(track), captions));
+            }
+            if self.options.strip_sdh {
+                let (track, charenc) = match &text {
+                    Some((track, _)) => (track, None),
+                    None => (track, plan.subtitle_style.charenc.as_deref()),
+                };
+                let extension = if is_styled(&track.codec) { "ass" } else { "srt" };
+                let stripped = TempSubtitles::new("sdh", &info.path, extension);
+                extract_track(self.runner(), &info.path, track, charenc, &stripped.path)?;
+                let removed = strip_sdh_file(&stripped.path)?;
+                status!("   🔇 Stripped sound descriptions and speaker labels from {} subtitle(s)", removed);
+                text = Some((stripped.track(track), stripped));
+            }
+        }
+
+        // ASS tracks are styled for the fonts shipped with them, so hand those
+        // to libass instead of --fonts-dir
+        let fonts = match &plan.subtitle {
+            Some(track) if is_styled(&track.codec) && !plan.soft_subtitle => match &track.source {
+                Some(source) => self.probe(source).ok().and_then(|source| extract_fonts(self.runner(), &source)),
+                None => extract_fonts(self.runner(), info),
+            },
+            _ => None,
+        };
+        let mut prepared: Option<TranscodePlan> = None;
+        if let Some((track, _)) = &text {
+            let copy = prepared.get_or_insert_with(|| plan.clone());
+            copy.subtitle = Some(track.clone());
+            // The temporary file is UTF-8
+            copy.subtitle_style.charenc = None;
+        }
+        if let Some(fonts) = &fonts {
+            status!("   🔤 Using {} font(s) attached to the file", fonts.count);
+            prepared.get_or_insert_with(|| plan.clone()).subtitle_style.fonts_dir = Some(fonts.dir.clone());
+        }
+        let plan = prepared.as_ref().unwrap_or(plan);
+
+        // Written under a hidden name (a file, the parts of a split or an HLS
+        // or chapters folder) until it is finished and checked, so an
+        // interrupted run never leaves something that looks like an output
+        let stage_dir = self.options.stage_dir.as_ref();
+        let working = TranscodePlan {
+            output: match stage_dir {
+                Some(dir) => staged_path(dir, &plan.output),
+                None => partial_output_path(&plan.output),
+            },
+            ..plan.clone()
+        };
+        if let Some(dir) = stage_dir {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        // Anything left there by an interrupted run would be mixed in
+        remove_output(&working.output);
+        let encoded = if plan.hls {
+            self.transcode_hls(info, &working, &label)
+                .inspect(|()| status!("   ✅ HLS package completed: H.264/AAC"))
+        } else if !plan.chapters.is_empty() {
+            self.transcode_chapters(info, &working, &label)
+                .inspect(|()| status!("   ✅ {} chapters completed: H.264/AAC/MP4", plan.chapters.len()))
+        } else {
+            self.encode_checked(info, &working, &label)
+        };
+        if let Err(e) = encoded {
+            remove_output(&working.output);
+            return Err(e);
+        }
+        if stage_dir.is_some() {
+            move_to_destination(&working.output, &plan.output, plan.split_secs.is_some())?;
+        } else {
+            rename_output(&working.output, &plan.output, plan.split_secs.is_some())
+                .map_err(|e| format!("Failed to move the output to {}: {}", plan.output.display(), e))?;
+        }
+        if plan.hls || !plan.chapters.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(kind) = self.options.thumbnails {
+            match generate_thumbnails(self.runner(), info, plan, kind) {
+                Ok(images) => {
+                    for image in images {
+                        status!("   🖼️  Wrote {}", image.file_name().unwrap().to_string_lossy());
+                    }
+                }
+                Err(e) => eprintln!("   ⚠️  Thumbnail generation failed ({})", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes `plan`, then runs `--quality-check` and the A/V sync check
+    /// on the result.
+    fn encode_checked(&self, info: &VideoInfo, plan: &TranscodePlan, label: &str) -> Result<(), String> {
+        match self.segment_count(info, plan) {
+            Some(count) => self.transcode_segmented(info, plan, label, count)?,
+            None => self.encode(info, plan, label)?,
+        }
+
+        if plan.video == VideoEncoder::Copy && plan.audio == AudioAction::Copy {
+            status!("   ✅ Remux completed: {}/{}/MP4", info.video_codec, info.audio_codec);
+        } else if plan.video == VideoEncoder::Copy {
+            status!("   ✅ Audio converted, video copied: {}/AAC/MP4", info.video_codec);
+        } else if plan.subtitle.is_some() && !plan.soft_subtitle {
+            status!("   ✅ Transcode completed: H.264/AAC/MP4 with burned subtitles");
+        } else {
+            status!("   ✅ Transcode completed: H.264/AAC/MP4");
+        }
+        if plan.split_secs.is_some() {
+            let parts = (1..).take_while(|&n| split_part_path(&plan.output, n).exists()).count();
+            status!("   ✂️  Split into {} parts", parts);
+        }
+
+        if let Some(metric) = self.options.quality_check {
+            if plan.video != VideoEncoder::Copy {
+                self.check_quality(info, plan, metric, label)?;
+            }
+        }
+        // A sample starts mid-file and --audio-delay moves the audio on purpose
+        let whole = plan.sample.is_none() && plan.split_secs.is_none();
+        if whole && plan.audio_delay_secs == 0.0 && plan.audio != AudioAction::Drop {
+            self.check_sync(info, plan);
+        }
+        Ok(())
+    }
+
+    /// Warns when the audio of a finished encode is out of step with its
+    /// video in a way the source's was not.
+    fn check_sync(&self, info: &VideoInfo, plan: &TranscodePlan) {
+        let audio_index = plan
+            .audio_tracks
+            .first()
+            .copied()
+            .or_else(|| info.default_audio_track().map(|track| track.audio_index))
+            .unwrap_or(0);
+        match probe::get_video_info(self.runner(), &plan.output) {
+            Ok(output) => {
+                if let Some(drift) = sync_drift(info, audio_index, &output) {
+                    eprintln!("   ⚠️  Audio may be out of sync: {}", drift);
+                    self.job_log.record(&info.path, &format!("sync-drift: {}", drift));
+                }
+            }
+            Err(e) => eprintln!("   ⚠️  Cannot check A/V sync of the output ({})", e),
+        }
+    }
+
+    /// Scores a finished encode against its source, warning when it falls
+    /// below the minimum and, with `--requality`, re-encoding once at higher
+    /// quality. A comparison that cannot run only warns.
+    fn check_quality(
+        &self,
+        info: &VideoInfo,
+        plan: &TranscodePlan,
+        metric: QualityMetric,
+        label: &str,
+    ) -> Result<(), String> {
+        let threshold = self.options.min_quality.unwrap_or(metric.default_threshold());
+        status!("   🔬 Measuring {}...", metric.label());
+        let score = match measure_quality(self.runner(), info, plan, metric) {
+            Ok(score) => score,
+            Err(e) => {
+                eprintln!("   ⚠️  Quality check failed ({}), keeping the encode", e);
+                return Ok(());
+            }
+        };
+        if score >= threshold {
+            status!("   ✅ {} {:.3} (minimum {})", metric.label(), score, threshold);
+            return Ok(());
+        }
+        eprintln!("   ⚠️  {} {:.3} is below the minimum {} for {}", metric.label(), score, threshold, label);
+        if !self.options.requality {
+            return Ok(());
+        }
+
+        let mut retry = plan.clone();
+        raise_quality(&mut retry.settings);
+        let (width, height) = retry.output_size;
+        let max_level = self.options.device_caps().max_h264_level;
+        retry.settings.fit_level(width, height, retry.output_frame_rate(info), max_level);
+        status!(
+            "   🔁 Re-encoding at higher quality (CRF {}, {}k target)...",
+            retry.settings.crf,
+            retry.settings.bitrate_kbps
+        );
+        self.encode(info, &retry, label)?;
+        match measure_quality(self.runner(), info, &retry, metric) {
+            Ok(score) if score >= threshold => status!("   ✅ {} {:.3} after re-encode", metric.label(), score),
+            Ok(score) => eprintln!("   ⚠️  {} is still {:.3} after re-encode, keeping it", metric.label(), score),
+            Err(e) => eprintln!("   ⚠️  Quality check failed ({}), keeping the re-encode", e),
+        }
+        Ok(())
+    }
+
+    /// Encodes each rendition of an `--hls` package into its own directory,
+    /// then writes the master playlist.
+    fn transcode_hls(&self, info: &VideoInfo, plan: &TranscodePlan, label: &str) -> Result<(), String> {
+        let renditions = hls::renditions(info, plan, &self.options);
+        for (i, rendition) in renditions.iter().enumerate() {
+            status!("   📺 Rendition {} of {}: {}", i + 1, renditions.len(), rendition.name);
+            let dir = rendition.plan.output.parent().unwrap();
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+            self.encode(info, &rendition.plan, &format!("{} {}", label, rendition.name))?;
+        }
+        hls::write_master_playlist(&plan.output, &renditions)
+            .map_err(|e| format!("Failed to write master playlist for {}: {}", label, e))
+    }
+
+    /// How many parts `--segments` splits this encode into, or `None` for a
+    /// single job. Only software encodes of whole, long enough files split.
+    fn segment_count(&self, info: &VideoInfo, plan: &TranscodePlan) -> Option<u32> {
+        let count = self.options.segments.filter(|&count| count > 1)?;
+        if plan.video != VideoEncoder::Software {
+            if plan.video != VideoEncoder::Copy {
+                status!("   ⚠️  --segments only applies to software encodes, encoding in one job");
+            }
+            return None;
+        }
+        // Parts carry no subtitle track to join
+        if plan.sample.is_some() || plan.soft_subtitle || !segment::worth_splitting(info, count) {
+            return None;
+        }
+        Some(count)
+    }
+
+    /// Encodes each of `plan.chapters` into its own file in the
+    /// `plan.output` folder, one after another.
+    fn transcode_chapters(&self, info: &VideoInfo, plan: &TranscodePlan, label: &str) -> Result<(), String> {
+        fs::create_dir_all(&plan.output).map_err(|e| format!("Failed to create {}: {}", plan.output.display(), e))?;
+        for (i, chapter) in plan.chapters.iter().enumerate() {
+            let name = chapter_file_name(i + 1, chapter, plan.container);
+            status!("   📖 Chapter {} of {}: {}", i + 1, plan.chapters.len(), name);
+            let mut part = plan.clone();
+            part.chapters = Vec::new();
+            part.sample = Some(SampleWindow {
+                start_secs: chapter.start_secs,
+                duration_secs: chapter.end_secs - chapter.start_secs,
+            });
+            part.output = plan.output.join(name);
+            self.encode(info, &part, &format!("{} chapter {}", label, i + 1))?;
+        }
+        Ok(())
+    }
+
+    /// Encodes the video as up to `count` keyframe-aligned parts in parallel
+    /// alongside one audio job, then joins them into `plan.output`.
+    fn transcode_segmented(
+        &self,
+        info: &VideoInfo,
+        plan: &TranscodePlan,
+        label: &str,
+        count: u32,
+    ) -> Result<(), String> {
+        let keyframes = segment::keyframe_times(self.runner(), &info.path)?;
+        let windows = segment::split_windows(&keyframes, info.duration_secs, count);
+        if windows.len() < 2 {
+            status!("   ⚠️  Too few keyframes to split, encoding in one job");
+            return self.encode(info, plan, label);
+        }
+
+        let dir = segment::work_dir(&plan.output);
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        // Share the cores between the parts unless --threads already limits them
+        let cores = thread::available_parallelism().map_or(1, |n| n.get() as u32);
+        let threads = plan.threads.or(Some((cores / windows.len() as u32).max(1)));
+        let parts: Vec<TranscodePlan> = windows
+            .iter()
+            .enumerate()
+            .map(|(i, window)| TranscodePlan {
+                output: dir.join(format!("part_{:03}.mp4", i)),
+                audio: AudioAction::Drop,
+                sample: Some(*window),
+                fragmented: false,
+                threads,
+                ..plan.clone()
+            })
+            .collect();
+        // The probe reports "unknown" when there is no audio stream
+        let audio = (info.audio_codec != "unknown").then(|| dir.join("audio.mka"));
+
+        status!("   🧩 Encoding {} parts in parallel", parts.len());
+        let result = thread::scope(|scope| {
+            let audio_job = scope.spawn(|| match &audio {
+                Some(audio) => {
+                    let args = build_segment_audio_args(info, plan, audio);
+                    self.run_logged(info, &args, &format!("{} audio", label))
+                }
+                None => Ok(()),
+            });
+            let part_jobs: Vec<_> = parts
+                .iter()
+                .enumerate()
+                .map(|(i, part)| scope.spawn(move || self.encode(info, part, &format!("{} part {}", label, i + 1))))
+                .collect();
+            let mut result = audio_job.join().unwrap();
+            for job in part_jobs {
+                result = result.and(job.join().unwrap());
+            }
+            result
+        })
+        .and_then(|()| {
+            status!("   🧩 Joining {} parts", parts.len());
+            let list = dir.join("parts.txt");
+            let paths: Vec<PathBuf> = parts.iter().map(|part| part.output.clone()).collect();
+            segment::write_concat_list(&list, &paths)
+                .map_err(|e| format!("Failed to write {}: {}", list.display(), e))?;
+            self.run_logged(info, &build_concat_args(plan, &list, audio.as_deref()), label)
+        });
+        let _ = fs::remove_dir_all(&dir);
+        result
+    }
+
+    /// Runs the ffmpeg invocation(s) for one output, two passes for software
+    /// two-pass encodes.
+    fn encode(&self, info: &VideoInfo, plan: &TranscodePlan, label: &str) -> Result<(), String> {
+        if plan.is_plain_remux() && info.container.contains("matroska") && self.remux_in_process(info, plan) {
+            return Ok(());
+        }
+        if plan.settings.two_pass && plan.video == VideoEncoder::Software {
+            let passlog = passlog_prefix(&plan.output);
+            status!("   🔄 Starting transcode (pass 1 of 2)...");
+            let first = build_ffmpeg_args(info, plan, Pass::First(&passlog));
+            let result = self.run_logged(info, &first, &format!("{} pass 1", label)).and_then(|()| {
+                status!("   🔄 Starting transcode (pass 2 of 2)...");
+                let second = build_ffmpeg_args(info, plan, Pass::Second(&passlog));
+                self.run_logged(info, &second, &format!("{} pass 2", label))
+            });
+            remove_passlog_files(&passlog);
+            result?;
+        } else {
+            if plan.settings.two_pass && matches!(&plan.video, VideoEncoder::Hardware(hw) if hw != "nvenc") {
+                status!("   ⚠️  Two-pass is only supported by libx264 and NVENC, encoding in one pass");
+            }
+            let ffmpeg_args = build_ffmpeg_args(info, plan, Pass::Single);
+            status!("   🔄 Starting transcode...");
+            self.run_logged(info, &ffmpeg_args, label)?;
+        }
+        Ok(())
+    }
+
+    /// Repackages the Matroska source of a plain remux without ffmpeg (see
+    /// [`crate::remux`]). Whether it did; files it cannot handle or fails on
+    /// are left to ffmpeg.
+    fn remux_in_process(&self, info: &VideoInfo, plan: &TranscodePlan) -> bool {
+        let source = plan.input.as_deref().unwrap_or(&info.path);
+        let audio: Vec<usize> = if plan.audio_tracks.is_empty() {
+            info.default_audio_track().map(|track| track.audio_index).into_iter().collect()
+        } else {
+            plan.audio_tracks.clone()
+        };
+        let remuxer = match Remuxer::open(source, &audio) {
+            Ok(remuxer) => remuxer,
+            Err(e) => {
+                if verbosity() >= Verbosity::Verbose {
+                    println!("   Remuxing with ffmpeg: {}", e);
+                }
+                return false;
+            }
+        };
+
+        status!("   🔄 Remuxing in-process...");
+        let show = verbosity() >= Verbosity::Normal && info.duration_secs > 0.0;
+        let started = Instant::now();
+        let mut shown = None;
+        let result = remuxer.remux(&plan.output, plan.container, |secs| {
+            dashboard::file_progress(&info.path, secs, secs / started.elapsed().as_secs_f64().max(0.001));
+            let percent = (secs / info.duration_secs * 100.0).min(100.0) as u32;
+            if show && shown != Some(percent) {
+                print!("\r   ⏩ Remuxing: {:>3}%", percent);
+                let _ = io::stdout().flush();
+                shown = Some(percent);
+            }
+        });
+        if shown.is_some() {
+            println!();
+        }
+        match result {
+            Ok(()) => {
+                self.job_log.record(&info.path, "remux:in-process");
+                true
+            }
+            Err(e) => {
+                eprintln!("   ⚠️  In-process remux failed ({}), remuxing with ffmpeg", e);
+                remove_output(&plan.output);
+                false
+            }
+        }
+    }
+
+    /// Runs one ffmpeg invocation and records it in the job log.
+    fn run_logged(&self, info: &VideoInfo, ffmpeg_args: &[String], label: &str) -> Result<(), String> {
+        let started = Instant::now();
+        let status = self.runner.run(ffmpeg_args, label);
+        let elapsed = started.elapsed().as_secs_f64();
+
+        match &status {
+            Ok(code) => self.job_log.record_ffmpeg(&info.path, ffmpeg_args, *code, elapsed, None),
+            Err(e) => self.job_log.record_ffmpeg(&info.path, ffmpeg_args, None, elapsed, Some(&e.to_string())),
+        }
+
+        match status {
+            Ok(Some(0)) => Ok(()),
+            Ok(code) => Err(format!(
+                "Transcode of {} failed with exit code: {:?}",
+                label, code
+            )),
+            Err(e) => Err(format!("Failed to run ffmpeg for {}: {}", label, e)),
+        }
+    }
+}
+
+/// The track to burn for `--forced-only` when `track` was chosen. Bitmap
+/// tracks flag each forced cue, so the decoder can skip the rest; text
+/// tracks cannot, so a track in the same language flagged forced as a whole
+/// is used instead when there is one.
+fn forced_track(info: &VideoInfo, track: SubtitleTrack) -> SubtitleTrack {
+    if track.is_bitmap || track.forced {
+        return track;
+    }
+    let forced = info
+        .subtitles
+        .iter()
+        .find(|other| other.forced && !other.is_bitmap && other.language == track.language);
+    match forced {
+        Some(forced) => {
+            status!("   🎯 Using the forced {} track", forced.language.as_deref().unwrap_or("subtitle"));
+            forced.clone()
+        }
+        None => {
+            eprintln!("   ⚠️  Text subtitles have no forced flags and there is no forced track, burning all of them");
+            track
+        }
+    }
+}
+
+/// Deletes the output written at `path`: a file, a folder, or the parts of
+/// a split output named after it. Whether there was anything to delete.
+pub fn remove_output(path: &Path) -> bool {
+    let parts: Vec<PathBuf> = (1..).map(|number| split_part_path(path, number)).take_while(|part| part.exists()).collect();
+    let mut removed = false;
+    for part in parts {
+        removed |= fs::remove_file(part).is_ok();
+    }
+    if path.is_dir() {
+        removed |= fs::remove_dir_all(path).is_ok();
+    } else {
+        removed |= fs::remove_file(path).is_ok();
+    }
+    removed
+}
+
+/// Renames the finished output at `working` to `output`: every part of it
+/// when `split`, and a folder in place of an older one.
+fn rename_output(working: &Path, output: &Path, split: bool) -> std::io::Result<()> {
+    if split {
+        let mut number = 1;
+        while split_part_path(working, number).exists() {
+            fs::rename(split_part_path(working, number), split_part_path(output, number))?;
+            number += 1;
+        }
+        return Ok(());
+    }
+    if working.is_dir() && output.is_dir() {
+        fs::remove_dir_all(output)?;
+    }
+    fs::rename(working, output)
+}
+
+/// Which invocation of a (possibly two-pass) encode the arguments are for.
+#[derive(Debug, Clone, Copy)]
+pub enum Pass<'a> {
+    Single,
+    /// Analysis pass writing x264 stats to the given `-passlogfile` prefix
+    First(&'a Path),
+    /// Final pass reading the stats written by [`Pass::First`]
+    Second(&'a Path),
+}
+
+/// `-passlogfile` prefix for a two-pass encode of `output`, in the temp dir so
+/// stat files never land next to the media. The path hash keeps parallel
+/// jobs with the same file name (e.g. HLS `index` playlists) apart.
+fn passlog_prefix(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap().to_string_lossy();
+    let mut hasher = DefaultHasher::new();
+    output.hash(&mut hasher);
+    env::temp_dir().join(format!("tvcode-{}-{}-{:x}", std::process::id(), stem, hasher.finish()))
+}
+
+/// Deletes the `<prefix>-0.log` / `.mbtree` files x264 leaves behind.
+fn remove_passlog_files(prefix: &Path) {
+    let (Some(dir), Some(name)) = (prefix.parent(), prefix.file_name()) else {
+        return;
+    };
+    let name = name.to_string_lossy();
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(name.as_ref()) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/// Builds the full ffmpeg argument list for `plan`.
+pub fn build_ffmpeg_args(info: &VideoInfo, plan: &TranscodePlan, pass: Pass) -> Vec<String> {
+    let mut ffmpeg_args: Vec<String> = get_loglevel_args();
+
+    // Add analyzeduration and probesize for better stream detection
+    ffmpeg_args.extend([
+        "-analyzeduration".to_string(),
+        "100000000".to_string(),
+        "-probesize".to_string(),
+        "100000000".to_string(),
+    ]);
+
+    if let Some(threads) = plan.threads {
+        ffmpeg_args.extend([
+            "-filter_threads".to_string(),
+            threads.to_string(),
+            "-filter_complex_threads".to_string(),
+            threads.to_string(),
+        ]);
+    }
+
+    let backend = match &plan.video {
+        VideoEncoder::Hardware(hw) => HwBackend::for_encoder(hw),
+        VideoEncoder::Software | VideoEncoder::Copy => None,
+    };
+    let mut decode_device = plan.hw_device.as_deref();
+    if backend == Some(HwBackend::Qsv) {
+        ffmpeg_args.extend(qsv_init_args(plan.hw_device.as_deref()));
+        decode_device = Some(QSV_DEVICE_NAME);
+    }
+    if let (true, Some(backend)) = (plan.hw_decode, backend) {
+        status!("   🎞️  Decoding on the GPU, frames stay on-device");
+        ffmpeg_args.extend(backend.decode_args(decode_device));
+    }
+
+    // Input seeking is fast and keeps every input stream (including bitmap
+    // subtitles) in step
+    if let Some(sample) = plan.sample {
+        status!("   ✂️  Encoding {:.0}s starting at {:.0}s", sample.duration_secs, sample.start_secs);
+        ffmpeg_args.extend(["-ss".to_string(), format!("{:.3}", sample.start_secs)]);
+    }
+
+    // Input file, with the decoder's threads limited too
+    if let Some(threads) = plan.threads {
+        ffmpeg_args.extend(["-threads".to_string(), threads.to_string()]);
+    }
+    // Decoder options for the input holding the subtitle track: text read
+    // into a selectable track is converted by the decoder, and bitmap
+    // decoders can drop cues not flagged as forced
+    let soft = plan.subtitle.as_ref().filter(|_| plan.soft_subtitle);
+    let mut subtitle_options = Vec::new();
+    if let (Some(charenc), Some(_)) = (&plan.subtitle_style.charenc, soft) {
+        subtitle_options.extend(["-sub_charenc".to_string(), charenc.clone()]);
+    }
+    if plan.forced_only {
+        subtitle_options.extend(["-forced_subs_only".to_string(), "1".to_string()]);
+    }
+    let external = plan.subtitle.as_ref().is_some_and(|track| track.source.is_some());
+    if !external {
+        ffmpeg_args.extend(subtitle_options.iter().cloned());
+    }
+    ffmpeg_args.extend(["-i".to_string(), info.path.to_str().unwrap().to_string()]);
+
+    // A subtitle-only file is a second input for the overlay or the mux;
+    // the subtitles filter opens it by itself
+    let mut subtitle_input = 0;
+    let second_input = plan.subtitle.as_ref().filter(|track| track.is_bitmap || plan.soft_subtitle);
+    if let Some(source) = second_input.and_then(|track| track.source.as_ref()) {
+        if let Some(sample) = plan.sample {
+            ffmpeg_args.extend(["-ss".to_string(), format!("{:.3}", sample.start_secs)]);
+        }
+        ffmpeg_args.extend(subtitle_options);
+        ffmpeg_args.extend(["-i".to_string(), source.to_str().unwrap().to_string()]);
+        subtitle_input = 1;
+    }
+
+    // --audio-delay reads the audio from the input again, offset
+    let mut audio_input = 0;
+    if plan.audio_delay_secs != 0.0 {
+        status!("   ⏱️  Shifting the audio by {:+.0} ms", plan.audio_delay_secs * 1000.0);
+        if let Some(sample) = plan.sample {
+            ffmpeg_args.extend(["-ss".to_string(), format!("{:.3}", sample.start_secs)]);
+        }
+        ffmpeg_args.extend(["-itsoffset".to_string(), format!("{:.3}", plan.audio_delay_secs)]);
+        ffmpeg_args.extend(["-i".to_string(), info.path.to_str().unwrap().to_string()]);
+        audio_input = subtitle_input + 1;
+    }
+
+    let mut graph = FilterGraph::new(backend, plan.hw_decode);
+    if info.rotation != 0 && plan.video != VideoEncoder::Copy {
+        status!("   🔄 Turning the picture {}° as the recording asks", info.rotation);
+    }
+    // Noise is easier to tell from detail before scaling blends the two
+    if let Some(denoise) = plan.denoise {
+        status!("   🧹 Denoising with {}", denoise.filter());
+        graph.cpu(denoise.filter());
+    }
+    let (width, height) = plan.output_size;
+    let (source_width, source_height) = info.display_size();
+    if (width, height) != (source_width, source_height) {
+        status!("   📐 Scaling {}x{} → {}x{}", source_width, source_height, width, height);
+        graph.scale(width, height);
+    }
+    // After scaling, so it works on the pixels that are encoded
+    if let Some(amount) = plan.sharpen {
+        status!("   🔍 Sharpening (strength {})", amount);
+        graph.cpu(unsharp(amount));
+    }
+    for filter in &plan.filters {
+        graph.cpu(filter.clone());
+    }
+
+    // Handle subtitle burning based on type
+    if let Some(track) = plan.subtitle.as_ref().filter(|_| !plan.soft_subtitle) {
+        if track.is_bitmap {
+            // Bitmap subtitles (PGS, DVD, DVB) - overlay from a filter_complex
+            // Scale subtitle to match video dimensions to avoid resolution mismatch
+            status!("   🔥 Burning bitmap subtitles (PGS/DVD) using overlay filter");
+            let style = &plan.subtitle_style;
+            let y = style.bitmap_offset(height);
+            graph.overlay_subtitle(subtitle_input, track.subtitle_index, width, height, y, style.delay_secs);
+        } else {
+            // Text subtitles (SRT, ASS, SSA, etc.) - use subtitles filter
+            status!("   🔥 Burning text subtitles using subtitles filter");
+            // The subtitles filter reads the file itself, so shift a sample's
+            // frames back to their original timestamps while it runs, and
+            // --sub-delay's frames the other way
+            let offset = plan.sample.map_or(0.0, |s| s.start_secs) - plan.subtitle_style.delay_secs;
+            if offset != 0.0 {
+                graph.cpu(shift_pts(offset));
+            }
+            graph.cpu(subtitles_filter(track.input(&info.path), track, &plan.subtitle_style));
+            if offset != 0.0 {
+                graph.cpu("setpts=PTS-STARTPTS");
+            }
+        }
+    }
+    if let Some(watermark) = &plan.watermark {
+        status!("   🏷️  Adding watermark {}", watermark.image.file_name().unwrap_or_default().to_string_lossy());
+        // An eighth of the picture's width, a fortieth of it in from the edges
+        let position = watermark.position.overlay_position(width / 40);
+        graph.watermark(&watermark.image, width / 8, watermark.opacity, position);
+    }
+    // A labeled graph output is only used where mapped, and mapping it keeps
+    // ffmpeg from adding the unfiltered video beside it
+    let filter_args = graph.render();
+    let complex = matches!(filter_args, FilterArgs::Complex(_));
+    let video_map = if complex { COMPLEX_OUTPUT } else { "0:V:0" };
+    ffmpeg_args.extend(filter_args.into_args());
+
+    match &plan.video {
+        VideoEncoder::Hardware(hw) => {
+            status!("   🚀 Using hardware acceleration: {} (H.264)", hw);
+            ffmpeg_args.extend(get_hw_encoding_args(hw, plan.hw_device.as_deref(), &plan.settings));
+        }
+        VideoEncoder::Software => {
+            status!("   ⚠️  Using software encoding (H.264, slower)");
+            ffmpeg_args.extend(get_sw_encoding_args(&plan.settings));
+        }
+        VideoEncoder::Copy => {
+            status!("   📦 Copying the {} stream without re-encoding", info.video_codec);
+            ffmpeg_args.extend(["-c:v".to_string(), "copy".to_string()]);
+            // Apple players only accept HEVC tagged hvc1, not ffmpeg's default hev1
+            if info.video_codec == "hevc" {
+                ffmpeg_args.extend(["-tag:v".to_string(), "hvc1".to_string()]);
+            }
+        }
+    }
+
+    // Encoder threads (libx264 otherwise uses 1.5x the core count)
+    if let Some(threads) = plan.threads {
+        ffmpeg_args.extend(["-threads".to_string(), threads.to_string()]);
+    }
+
+    if let Some(sample) = plan.sample {
+        ffmpeg_args.extend(["-t".to_string(), format!("{:.3}", sample.duration_secs)]);
+    }
+
+    if let Some(fps) = plan.max_frame_rate {
+        status!("   🎚️  Limiting frame rate {:.2} → {} fps", info.frame_rate, fps);
+        ffmpeg_args.extend(["-r".to_string(), fps.to_string()]);
+    }
+
+    match pass {
+        Pass::Single => {}
+        Pass::First(passlog) | Pass::Second(passlog) => {
+            let number = if matches!(pass, Pass::First(_)) { "1" } else { "2" };
+            ffmpeg_args.extend([
+                "-pass".to_string(),
+                number.to_string(),
+                "-passlogfile".to_string(),
+                passlog.to_str().unwrap().to_string(),
+            ]);
+        }
+    }
+
+    // The first pass only gathers video statistics
+    if let Pass::First(_) = pass {
+        if complex {
+            ffmpeg_args.extend(["-map".to_string(), video_map.to_string()]);
+        }
+        ffmpeg_args.extend([
+            "-an".to_string(),
+            "-sn".to_string(),
+            "-f".to_string(),
+            "null".to_string(),
+            "-y".to_string(),
+            "-".to_string(),
+        ]);
+        return ffmpeg_args;
+    }
+
+    // Audio encoding
+    match plan.audio {
+        AudioAction::Aac | AudioAction::Eac3 => {
+            if plan.audio == AudioAction::Eac3 {
+                status!("   🔊 Converting surround audio to {} kbps 5.1 E-AC3", plan.audio_bitrate_kbps);
+            } else {
+                status!("   🔊 Converting audio to {} kbps AAC", plan.audio_bitrate_kbps);
+            }
+            if plan.audio_filters.iter().any(|filter| filter.starts_with("aresample=")) {
+                status!("   🎚️  Resampling audio to {} Hz", AUDIO_SAMPLE_RATE);
+            }
+            if plan.audio_filters.iter().any(|filter| filter == NIGHT_MODE_FILTER) {
+                status!("   🌙 Night mode: compressing the dynamic range");
+            }
+            ffmpeg_args.extend(audio_encode_args(plan));
+        }
+        AudioAction::Copy => {
+            let codec = match plan.audio_tracks.first() {
+                Some(&index) => &info.audio_tracks[index].codec,
+                None => &info.audio_codec,
+            };
+            status!("   🔊 Audio already {}, copying", codec);
+            ffmpeg_args.extend(["-c:a".to_string(), "copy".to_string()]);
+        }
+        AudioAction::Drop => ffmpeg_args.push("-an".to_string()),
+    }
+
+    // Mapping one stream turns off automatic selection for all of them
+    if complex || soft.is_some() || !plan.audio_tracks.is_empty() || audio_input != 0 {
+        ffmpeg_args.extend(["-map".to_string(), video_map.to_string()]);
+        ffmpeg_args.extend(audio_map_args(info, plan, audio_input));
+    }
+    match soft {
+        Some(track) => {
+            status!("   💬 Adding the subtitles as a selectable track");
+            ffmpeg_args.extend([
+                "-map".to_string(),
+                format!("{}:s:{}", subtitle_input, track.subtitle_index),
+                "-c:s".to_string(),
+                "mov_text".to_string(),
+            ]);
+            if let Some(language) = &track.language {
+                ffmpeg_args.extend(["-metadata:s:s:0".to_string(), format!("language={}", language)]);
+            }
+        }
+        // No subtitle streams in output (already burned into video)
+        None => ffmpeg_args.push("-sn".to_string()),
+    }
+
+    if plan.hls {
+        let segments = plan.output.parent().unwrap().join("segment_%04d.m4s");
+        ffmpeg_args.extend([
+            "-force_key_frames".to_string(),
+            format!("expr:gte(t,n_forced*{})", SEGMENT_SECS),
+            "-f".to_string(),
+            "hls".to_string(),
+            "-hls_time".to_string(),
+            SEGMENT_SECS.to_string(),
+            "-hls_playlist_type".to_string(),
+            "vod".to_string(),
+            "-hls_segment_type".to_string(),
+            "fmp4".to_string(),
+            "-hls_fmp4_init_filename".to_string(),
+            "init.mp4".to_string(),
+            "-hls_segment_filename".to_string(),
+            segments.to_str().unwrap().to_string(),
+            "-y".to_string(),
+            plan.output.to_str().unwrap().to_string(),
+        ]);
+        return ffmpeg_args;
+    }
+
+    ffmpeg_args.extend(mp4_output_args(plan));
+    ffmpeg_args
+}
+
+/// Encoder arguments for converting the audio of `plan` to AAC or E-AC3,
+/// through its audio filters.
+fn audio_encode_args(plan: &TranscodePlan) -> Vec<String> {
+    let mut args = Vec::new();
+    if !plan.audio_filters.is_empty() {
+        args.extend(["-af".to_string(), plan.audio_filters.join(",")]);
+    }
+    args.extend([
+        "-c:a".to_string(),
+        if plan.audio == AudioAction::Eac3 { "eac3" } else { "aac" }.to_string(),
+        "-b:a".to_string(),
+        format!("{}k", plan.audio_bitrate_kbps),
+        "-ac".to_string(),
+        plan.audio_channels.to_string(),
+    ]);
+    args
+}
+
+/// `-map`s for the audio of `plan` from input `input`: its chosen tracks in
+/// order, the first marked default, or else the track ffmpeg would pick if
+/// there is one.
+fn audio_map_args(info: &VideoInfo, plan: &TranscodePlan, input: usize) -> Vec<String> {
+    if plan.audio_tracks.is_empty() {
+        let index = info.default_audio_track().map_or(0, |track| track.audio_index);
+        return vec!["-map".to_string(), format!("{}:a:{}?", input, index)];
+    }
+    let languages: Vec<&str> = plan
+        .audio_tracks
+        .iter()
+        .map(|&index| info.audio_tracks[index].language.as_deref().unwrap_or("unknown"))
+        .collect();
+    status!("   🗣️  Keeping audio: {}", languages.join(", "));
+    let mut args = Vec::new();
+    for &index in &plan.audio_tracks {
+        args.extend(["-map".to_string(), format!("{}:a:{}", input, index)]);
+    }
+    for position in 0..plan.audio_tracks.len() {
+        let disposition = if position == 0 { "default" } else { "0" };
+        args.extend([format!("-disposition:a:{}", position), disposition.to_string()]);
+    }
+    args
+}
+
+/// Muxer flags and the output path for an MP4 `plan`. Fragments make a
+/// truncated copy playable up to the last complete fragment; faststart needs
+/// the whole file but seeks better.
+fn mp4_output_args(plan: &TranscodePlan) -> Vec<String> {
+    let movflags = if plan.fragmented {
+        "frag_keyframe+empty_moov"
+    } else {
+        "+faststart"
+    };
+    let mut args = vec![
+        "-metadata".to_string(),
+        format!("comment={} {}", OUTPUT_COMMENT, env!("CARGO_PKG_VERSION")),
+    ];
+    match plan.split_secs {
+        // The segment muxer starts a part at the first keyframe past each cut
+        Some(secs) => args.extend([
+            "-f".to_string(),
+            "segment".to_string(),
+            "-segment_time".to_string(),
+            format!("{:.3}", secs),
+            "-segment_start_number".to_string(),
+            "1".to_string(),
+            "-reset_timestamps".to_string(),
+            "1".to_string(),
+            "-segment_format".to_string(),
+            plan.container.muxer().to_string(),
+            "-segment_format_options".to_string(),
+            format!("movflags={}", movflags),
+            "-y".to_string(),
+            split_part_path(&plan.output, "%d").to_str().unwrap().to_string(),
+        ]),
+        None => args.extend([
+            "-movflags".to_string(),
+            movflags.to_string(),
+            "-f".to_string(),
+            plan.container.muxer().to_string(),
+            "-y".to_string(),
+            plan.output.to_str().unwrap().to_string(),
+        ]),
+    }
+    args
+}
+
+/// Converts (or copies) just the audio of `info` for a `--segments` encode.
+fn build_segment_audio_args(info: &VideoInfo, plan: &TranscodePlan, output: &Path) -> Vec<String> {
+    let mut ffmpeg_args = get_loglevel_args();
+    if plan.audio_delay_secs != 0.0 {
+        ffmpeg_args.extend(["-itsoffset".to_string(), format!("{:.3}", plan.audio_delay_secs)]);
+    }
+    ffmpeg_args.extend(["-i".to_string(), info.path.to_str().unwrap().to_string()]);
+    ffmpeg_args.extend(["-vn".to_string(), "-sn".to_string(), "-dn".to_string()]);
+    if !plan.audio_tracks.is_empty() {
+        ffmpeg_args.extend(audio_map_args(info, plan, 0));
+    }
+    match plan.audio {
+        AudioAction::Aac | AudioAction::Eac3 => ffmpeg_args.extend(audio_encode_args(plan)),
+        AudioAction::Copy | AudioAction::Drop => ffmpeg_args.extend(["-c:a".to_string(), "copy".to_string()]),
+    }
+    ffmpeg_args.extend(["-y".to_string(), output.to_str().unwrap().to_string()]);
+    ffmpeg_args
+}
+
+/// Joins the parts listed in `list` and muxes them with `audio`, if any, into
+/// the final output, without re-encoding.
+fn build_concat_args(plan: &TranscodePlan, list: &Path, audio: Option<&Path>) -> Vec<String> {
+    let mut ffmpeg_args = get_loglevel_args();
+    ffmpeg_args.extend([
+        "-f".to_string(),
+        "concat".to_string(),
+        "-safe".to_string(),
+        "0".to_string(),
+        "-i".to_string(),
+        list.to_str().unwrap().to_string(),
+    ]);
+    if let Some(audio) = audio {
+        ffmpeg_args.extend(["-i".to_string(), audio.to_str().unwrap().to_string()]);
+    }
+    ffmpeg_args.extend(["-map".to_string(), "0:v:0".to_string()]);
+    if audio.is_some() {
+        ffmpeg_args.extend(["-map".to_string(), "1:a".to_string()]);
+    }
+    ffmpeg_args.extend(["-c".to_string(), "copy".to_string()]);
+    ffmpeg_args.extend(mp4_output_args(plan));
+    ffmpeg_args
+}
+
+#[cfg(not(feature = "libav"))]
+fn default_runner() -> Arc<dyn FfmpegRunner> {
+    Arc::new(SystemRunner)
+}
+
+#[cfg(feature = "libav")]
+fn default_runner() -> Arc<dyn FfmpegRunner> {
+    match crate::libav::LibavRunner::new() {
+        Ok(runner) => Arc::new(runner),
+        Err(e) => {
+            eprintln!("   ⚠️  {}; probing with ffprobe", e);
+            Arc::new(SystemRunner)
+        }
+    }
+}
+
+/// ffmpeg logging flags matching the console verbosity.
+fn get_loglevel_args() -> Vec<String> {
+    let args: &[&str] = match verbosity() {
+        Verbosity::Quiet => &["-hide_banner", "-loglevel", "error", "-nostats"],
+        Verbosity::Normal => &["-hide_banner", "-loglevel", "error", "-stats"],
+        Verbosity::Verbose => &["-hide_banner", "-loglevel", "info"],
+        Verbosity::Debug => &["-loglevel", "verbose"],
+    };
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{Watermark, WatermarkPosition};
+    use crate::probe::{AudioTrack, Chapter};
+    use crate::runner::{RecordingRunner, Tool};
+
+    fn two_languages() -> VideoInfo {
+        VideoInfo {
+            container: "matroska,webm".to_string(),
+            audio_tracks: vec![
+                AudioTrack::example(0, "aac", 2, Some("eng")),
+                AudioTrack::example(1, "aac", 2, Some("fra")),
+            ],
+            ..VideoInfo::example("/media/movie.mkv")
+        }
+    }
+
+    fn text_subtitle() -> SubtitleTrack {
+        SubtitleTrack {
+            subtitle_index: 1,
+            codec: "subrip".to_string(),
+            language: Some("eng".to_string()),
+            title: None,
+            is_bitmap: false,
+            source: None,
+            language_detected: false,
+            forced: false,
+        }
+    }
+
+    /// The `-map` targets in `args`, in order.
+    fn maps(args: &[String]) -> Vec<&str> {
+        args.windows(2).filter(|pair| pair[0] == "-map").map(|pair| pair[1].as_str()).collect()
+    }
+
+    #[test]
+    fn without_working_tools_nothing_is_detected() {
+        let runner = RecordingRunner::default();
+        assert!(!crate::check_ffmpeg_installed(&runner));
+        assert!(detect_hardware_acceleration(&runner).is_none());
+        assert!(runner.captures().iter().any(|(tool, _)| *tool == Tool::Ffmpeg));
+    }
+
+    #[test]
+    fn nothing_is_mapped_by_default() {
+        let info = two_languages();
+        let plan = TranscodePlan::new(&info, None, None, &EncodeOptions::default());
+        let args = build_ffmpeg_args(&info, &plan, Pass::Single);
+        assert!(maps(&args).is_empty());
+        assert!(args.contains(&"-sn".to_string()));
+        assert_eq!(args.last().unwrap(), "/media/movie_appletv.mp4");
+    }
+
+    #[test]
+    fn kept_languages_are_mapped_in_order() {
+        let info = two_languages();
+        let options = EncodeOptions {
+            keep_audio_languages: vec!["fra".to_string(), "eng".to_string()],
+            ..EncodeOptions::default()
+        };
+        let plan = TranscodePlan::new(&info, None, None, &options);
+        let args = build_ffmpeg_args(&info, &plan, Pass::Single);
+        assert_eq!(maps(&args), ["0:V:0", "0:a:1", "0:a:0"]);
+        let default = args.iter().position(|arg| arg == "-disposition:a:0").unwrap();
+        assert_eq!(args[default + 1], "default");
+    }
+
+    #[test]
+    fn delayed_audio_is_read_from_a_second_input() {
+        let info = two_languages();
+        let options = EncodeOptions { audio_delay_secs: 0.25, ..EncodeOptions::default() };
+        let plan = TranscodePlan::new(&info, None, None, &options);
+        let args = build_ffmpeg_args(&info, &plan, Pass::Single);
+        assert_eq!(args.iter().filter(|arg| *arg == "-i").count(), 2);
+        let offset = args.iter().position(|arg| arg == "-itsoffset").unwrap();
+        assert_eq!(args[offset + 1], "0.250");
+        assert_eq!(maps(&args), ["0:V:0", "1:a:0?"]);
+    }
+
+    #[test]
+    fn soft_subtitles_are_mapped_as_mov_text() {
+        let info = two_languages();
+        let options = EncodeOptions { soft_subtitles: true, ..EncodeOptions::default() };
+        let plan = TranscodePlan::new(&info, Some(text_subtitle()), None, &options);
+        let args = build_ffmpeg_args(&info, &plan, Pass::Single);
+        assert_eq!(maps(&args), ["0:V:0", "0:a:0?", "0:s:1"]);
+        assert!(args.windows(2).any(|pair| pair == ["-c:s", "mov_text"]));
+        assert!(!args.contains(&"-sn".to_string()));
+    }
+
+    #[test]
+    fn burned_bitmap_subtitles_map_the_graph_output() {
+        let info = two_languages();
+        let subtitle = SubtitleTrack { codec: "hdmv_pgs_subtitle".to_string(), is_bitmap: true, ..text_subtitle() };
+        let options = EncodeOptions {
+            keep_audio_languages: vec!["eng".to_string(), "fra".to_string()],
+            ..EncodeOptions::default()
+        };
+        let plan = TranscodePlan::new(&info, Some(subtitle), None, &options);
+        let args = build_ffmpeg_args(&info, &plan, Pass::Single);
+        let graph = &args[args.iter().position(|arg| arg == "-filter_complex").unwrap() + 1];
+        assert!(graph.ends_with(COMPLEX_OUTPUT));
+        assert_eq!(maps(&args), [COMPLEX_OUTPUT, "0:a:0", "0:a:1"]);
+    }
+
+    #[test]
+    fn a_watermark_alone_maps_the_graph_output_and_the_audio() {
+        let info = two_languages();
+        let watermark = Watermark {
+            image: PathBuf::from("/media/logo.png"),
+            position: WatermarkPosition::default(),
+            opacity: 0.5,
+        };
+        let options = EncodeOptions { watermark: Some(watermark), ..EncodeOptions::default() };
+        let plan = TranscodePlan::new(&info, None, None, &options);
+        assert_eq!(maps(&build_ffmpeg_args(&info, &plan, Pass::Single)), [COMPLEX_OUTPUT, "0:a:0?"]);
+        let first = build_ffmpeg_args(&info, &plan, Pass::First(Path::new("/tmp/passlog")));
+        assert_eq!(maps(&first), [COMPLEX_OUTPUT]);
+    }
+
+    #[test]
+    fn split_parts_are_renamed_from_their_partial_names() {
+        let dir = crate::scratch_dir("rename-parts");
+        let output = dir.join("movie_appletv.mp4");
+        let working = partial_output_path(&output);
+        fs::write(split_part_path(&working, 1), b"1").unwrap();
+        fs::write(split_part_path(&working, 2), b"2").unwrap();
+        rename_output(&working, &output, true).unwrap();
+        assert_eq!(fs::read(split_part_path(&output, 2)).unwrap(), b"2");
+        assert!(!split_part_path(&working, 1).exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn a_finished_folder_replaces_the_old_one() {
+        let dir = crate::scratch_dir("rename-folder");
+        let output = dir.join("movie_appletv_hls");
+        let working = partial_output_path(&output);
+        fs::create_dir_all(&output).unwrap();
+        fs::write(output.join("stale.m4s"), b"").unwrap();
+        fs::create_dir_all(&working).unwrap();
+        fs::write(working.join("master.m3u8"), b"").unwrap();
+        rename_output(&working, &output, false).unwrap();
+        assert!(output.join("master.m3u8").exists());
+        assert!(!output.join("stale.m4s").exists());
+        assert!(!working.exists());
+        assert!(remove_output(&output));
+        assert!(!output.exists() && !remove_output(&output));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn chapters_are_written_into_a_partial_folder() {
+        let dir = crate::scratch_dir("transcode-chapters");
+        let info = VideoInfo {
+            chapters: vec![
+                Chapter { start_secs: 0.0, end_secs: 300.0, title: Some("Opening".to_string()) },
+                Chapter { start_secs: 300.0, end_secs: 600.0, title: None },
+            ],
+            ..VideoInfo::example(dir.join("movie.mkv").to_str().unwrap())
+        };
+        let runner = Arc::new(RecordingRunner::default());
+        let options = EncodeOptions { split_chapters: true, ..EncodeOptions::default() };
+        let transcoder = Transcoder::with_runner(runner.clone(), JobLog::default()).with_options(options);
+        let plan = transcoder.plan(&info, None);
+        transcoder.transcode(&info, &plan).unwrap();
+
+        let partial = partial_output_path(&plan.output);
+        let written: Vec<PathBuf> = runner.runs().into_iter().map(|args| PathBuf::from(args.last().unwrap())).collect();
+        assert_eq!(written, [partial.join("01 - Opening.mp4"), partial.join("02.mp4")]);
+        assert!(plan.output.join("02.mp4").exists());
+        assert!(!partial.exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn first_pass_writes_no_output() {
+        let info = two_languages();
+        let options = EncodeOptions { target_size: Some(300_000_000), ..EncodeOptions::default() };
+        let plan = TranscodePlan::new(&info, None, None, &options);
+        let args = build_ffmpeg_args(&info, &plan, Pass::First(Path::new("/tmp/passlog")));
+        assert!(args.ends_with(&["-an", "-sn", "-f", "null", "-y", "-"].map(String::from)));
+        assert!(args.windows(2).any(|pair| pair == ["-pass", "1"]));
+    }
+
+    #[test]
+    fn matroska_remuxes_run_in_process() {
+        let dir = crate::scratch_dir("transcode-remux");
+        let source = dir.join("movie.mkv");
+        fs::write(&source, crate::remux::fixtures::sample_mkv("V_MPEG4/ISO/AVC")).unwrap();
+        let info = VideoInfo {
+            container: "matroska,webm".to_string(),
+            duration_secs: 0.28,
+            ..VideoInfo::example(source.to_str().unwrap())
+        };
+        let runner = Arc::new(RecordingRunner::default());
+        let transcoder = Transcoder::with_runner(runner.clone(), JobLog::default());
+        let plan = transcoder.plan(&info, None);
+        assert!(plan.is_plain_remux());
+        transcoder.transcode(&info, &plan).unwrap();
+
+        assert!(runner.runs().is_empty());
+        assert_eq!(&fs::read(&plan.output).unwrap()[4..8], b"ftyp");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn transcode_renames_the_finished_output() {
+        let dir = crate::scratch_dir("transcode");
+        let info = VideoInfo::example(dir.join("movie.mkv").to_str().unwrap());
+        let runner = Arc::new(RecordingRunner::default());
+        let transcoder = Transcoder::with_runner(runner.clone(), JobLog::default());
+        let plan = transcoder.plan(&info, None);
+        transcoder.transcode(&info, &plan).unwrap();
+
+        let runs = runner.runs();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].last().unwrap(), partial_output_path(&plan.output).to_str().unwrap());
+        assert!(plan.output.exists());
+        assert!(!partial_output_path(&plan.output).exists());
+        // The sync check probed the encode before it was renamed
+        let partial = partial_output_path(&plan.output).to_string_lossy().into_owned();
+        assert!(runner.captures().iter().any(|(tool, args)| *tool == Tool::Ffprobe && args.contains(&partial)));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
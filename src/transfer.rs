@@ -0,0 +1,272 @@
+//! Pushing finished outputs to another machine, for encoding boxes that are
+//! not the media server.
+//!
+//! Configured with a `[transfer]` table in the config file. After each file
+//! is encoded its outputs (and thumbnails) are copied to the destination
+//! with `rsync` or `scp`, checked there, and only then deleted locally. With
+//! rsync the check is a `--checksum` dry run that must find nothing left to
+//! send; with scp the destination's `cksum` of every file is compared with
+//! one worked out locally. A failed transfer keeps the local files.
+
+use crate::plan::{split_part_path, TranscodePlan};
+use crate::remote::{shell_quote, ssh};
+use crate::thumbnails::thumbnail_paths;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The `[transfer]` table of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransferSettings {
+    /// Folder the outputs are copied into, e.g. `media@nas:/srv/media/Movies`
+    /// (or `nas::module/Movies` for an rsync daemon)
+    pub destination: String,
+    /// `rsync` (the default) or `scp`
+    #[serde(default)]
+    pub method: TransferMethod,
+    /// Extra arguments for rsync or scp, e.g. `["--bwlimit=20m"]`
+    #[serde(default)]
+    pub flags: Vec<String>,
+}
+
+/// The program that copies outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferMethod {
+    #[default]
+    Rsync,
+    Scp,
+}
+
+impl TransferMethod {
+    fn program(self) -> &'static str {
+        match self {
+            TransferMethod::Rsync => "rsync",
+            TransferMethod::Scp => "scp",
+        }
+    }
+}
+
+/// Everything written for the finished `plan` that exists: the output (a
+/// folder for HLS and chapters) or its parts, and its thumbnails.
+pub fn finished_files(plan: &TranscodePlan) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if plan.split_secs.is_some() {
+        files.extend((1..).map(|number| split_part_path(&plan.output, number)).take_while(|part| part.exists()));
+    } else if plan.output.exists() {
+        files.push(plan.output.clone());
+    }
+    files.extend(thumbnail_paths(&plan.output).into_iter().filter(|image| image.exists()));
+    files
+}
+
+impl TransferSettings {
+    /// `destination` with a trailing slash, so rsync and scp copy into it.
+    fn destination_dir(&self) -> String {
+        format!("{}/", self.destination.trim_end_matches('/'))
+    }
+
+    /// Copies `files` to the destination, checks the copies and deletes
+    /// `files`. Nothing is deleted unless every file arrived intact.
+    pub fn push(&self, files: &[PathBuf]) -> Result<(), String> {
+        if files.is_empty() {
+            return Ok(());
+        }
+        let program = self.method.program();
+        let mut args: Vec<String> = match self.method {
+            TransferMethod::Rsync => vec!["-a".to_string(), "--partial".to_string()],
+            TransferMethod::Scp => vec!["-q".to_string(), "-r".to_string(), "-B".to_string()],
+        };
+        args.extend(self.flags.iter().cloned());
+        args.extend(files.iter().map(|file| file.to_string_lossy().into_owned()));
+        args.push(self.destination_dir());
+        let status = Command::new(program)
+            .args(&args)
+            .stdin(Stdio::null())
+            .status()
+            .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+        if !status.success() {
+            return Err(format!("{} to {} failed ({})", program, self.destination, status));
+        }
+
+        match self.method {
+            TransferMethod::Rsync => self.verify_rsync(files)?,
+            TransferMethod::Scp => self.verify_scp(files)?,
+        }
+        for file in files {
+            let removed = if file.is_dir() { fs::remove_dir_all(file) } else { fs::remove_file(file) };
+            removed.map_err(|e| format!("Failed to delete {} after the transfer: {}", file.display(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Fails unless rsync, comparing contents, finds nothing to send.
+    fn verify_rsync(&self, files: &[PathBuf]) -> Result<(), String> {
+        let output = Command::new("rsync")
+            .args(["-a", "--dry-run", "--checksum", "--itemize-changes"])
+            .args(&self.flags)
+            .args(files)
+            .arg(self.destination_dir())
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("Failed to run rsync: {}", e))?;
+        let changes = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() {
+            return Err(format!("Checking the copies at {} failed ({})", self.destination, output.status));
+        }
+        match changes.lines().find(|line| !line.trim().is_empty()) {
+            Some(change) => Err(format!("The copies at {} do not match ({})", self.destination, change.trim())),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails unless `cksum` on the destination host agrees with the local
+    /// files, folders compared file by file.
+    fn verify_scp(&self, files: &[PathBuf]) -> Result<(), String> {
+        let Some((host, dir)) = self.destination.split_once(':') else {
+            return Err(format!("{} is not a host:path destination", self.destination));
+        };
+        let mut local = Vec::new();
+        for file in files {
+            let name = PathBuf::from(file.file_name().unwrap());
+            collect_files(file, &name, &mut local)
+                .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        }
+        let dir = if dir.is_empty() { "." } else { dir.trim_end_matches('/') };
+        let remote_paths: Vec<String> = local
+            .iter()
+            .map(|(_, relative)| shell_quote(&format!("{}/{}", dir, relative.to_string_lossy())))
+            .collect();
+        let sums = ssh(host, &format!("cksum {}", remote_paths.join(" ")))?;
+        let mut lines = sums.lines();
+        for (path, relative) in &local {
+            let (crc, size) = posix_cksum(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            let expected = format!("{} {}", crc, size);
+            let line = lines.next().unwrap_or_default();
+            if !line.starts_with(&format!("{} ", expected)) {
+                return Err(format!(
+                    "The copy of {} on {} does not match (cksum {}, expected {})",
+                    relative.display(),
+                    host,
+                    line.split_whitespace().take(2).collect::<Vec<_>>().join(" "),
+                    expected
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adds `path` to `files` with its path under the destination, or every
+/// file inside it when it is a folder.
+fn collect_files(path: &Path, relative: &Path, files: &mut Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push((path.to_path_buf(), relative.to_path_buf()));
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<_, _>>()?;
+    entries.sort();
+    for entry in entries {
+        collect_files(&entry, &relative.join(entry.file_name().unwrap()), files)?;
+    }
+    Ok(())
+}
+
+/// Lookup table of the CRC `cksum` computes (polynomial 0x04C11DB7, most
+/// significant bit first).
+const CKSUM_TABLE: [u32; 256] = cksum_table();
+
+const fn cksum_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// The checksum and size POSIX `cksum` prints for the file at `path`.
+fn posix_cksum(path: &Path) -> io::Result<(u32, u64)> {
+    let step = |crc: u32, byte: u8| (crc << 8) ^ CKSUM_TABLE[((crc >> 24) ^ byte as u32) as usize];
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 4 * 1024 * 1024];
+    let (mut crc, mut size) = (0u32, 0u64);
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        crc = buffer[..read].iter().fold(crc, |crc, &byte| step(crc, byte));
+        size += read as u64;
+    }
+    // The length goes in too, least significant byte first
+    let mut length = size;
+    while length > 0 {
+        crc = step(crc, length as u8);
+        length >>= 8;
+    }
+    Ok((!crc, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::EncodeOptions;
+    use crate::probe::VideoInfo;
+
+    #[test]
+    fn cksum_matches_posix() {
+        let dir = crate::scratch_dir("transfer-cksum");
+        let (digits, empty) = (dir.join("digits"), dir.join("empty"));
+        fs::write(&digits, b"123456789").unwrap();
+        fs::write(&empty, b"").unwrap();
+        assert_eq!(posix_cksum(&digits).unwrap(), (930_766_865, 9));
+        assert_eq!(posix_cksum(&empty).unwrap(), (4_294_967_295, 0));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn folders_are_listed_file_by_file() {
+        let dir = crate::scratch_dir("transfer-collect");
+        let package = dir.join("movie_appletv_hls");
+        fs::create_dir_all(package.join("1080p")).unwrap();
+        fs::write(package.join("master.m3u8"), b"").unwrap();
+        fs::write(package.join("1080p/index.m3u8"), b"").unwrap();
+        let mut files = Vec::new();
+        collect_files(&package, Path::new("movie_appletv_hls"), &mut files).unwrap();
+        let relative: Vec<&Path> = files.iter().map(|(_, relative)| relative.as_path()).collect();
+        assert_eq!(relative, [Path::new("movie_appletv_hls/1080p/index.m3u8"), Path::new("movie_appletv_hls/master.m3u8")]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn finished_split_outputs_are_their_parts() {
+        let dir = crate::scratch_dir("transfer-parts");
+        let info = VideoInfo::example(dir.join("movie.mkv").to_str().unwrap());
+        let mut plan = TranscodePlan::new(&info, None, None, &EncodeOptions::default());
+        assert!(finished_files(&plan).is_empty());
+        plan.split_secs = Some(300.0);
+        fs::write(split_part_path(&plan.output, 1), b"").unwrap();
+        fs::write(split_part_path(&plan.output, 2), b"").unwrap();
+        assert_eq!(finished_files(&plan), [split_part_path(&plan.output, 1), split_part_path(&plan.output, 2)]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn settings_default_to_rsync() {
+        let settings: TransferSettings = toml::from_str("destination = \"media@nas:/srv/Movies/\"").unwrap();
+        assert_eq!(settings.method, TransferMethod::Rsync);
+        assert_eq!(settings.destination_dir(), "media@nas:/srv/Movies/");
+        assert!(toml::from_str::<TransferSettings>("destination = \"nas:/x\"\nmethod = \"ftp\"").is_err());
+    }
+}
@@ -0,0 +1,383 @@
+//! `--tui`: a full-screen view of a `convert` run.
+//!
+//! Lists every file with its progress, speed and ETA above a pane showing the
+//! run's console output, and takes single-key commands to pause, skip and
+//! reorder files. While it is up, the process's stdout and stderr (and so
+//! ffmpeg's) are redirected into the log pane; the last lines are printed
+//! again when it closes.
+//!
+//! Unix only: the terminal is driven with `stty` and ANSI escapes.
+
+use crate::dashboard::{self, FileView};
+use crate::output::format_duration;
+use crate::pause;
+use crate::queue::FileStatus;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Console lines kept for the log pane.
+const LOG_LINES: usize = 500;
+
+/// Lines printed again once the TUI closes.
+const REPLAY_LINES: usize = 20;
+
+/// Time between redraws.
+const FRAME: Duration = Duration::from_millis(500);
+
+const PROGRESS_WIDTH: usize = 20;
+
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static SELECTED: AtomicUsize = AtomicUsize::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// The open TUI. Dropping it restores the terminal.
+pub struct Tui {
+    render: Option<JoinHandle<()>>,
+}
+
+/// Takes over the terminal until the returned [`Tui`] is dropped.
+pub fn start() -> Result<Tui, String> {
+    sys::take_over()?;
+    dashboard::enable();
+    RUNNING.store(true, Ordering::SeqCst);
+    thread::spawn(read_keys);
+    let render = thread::spawn(|| {
+        while RUNNING.load(Ordering::SeqCst) {
+            sys::draw(&frame(sys::size()));
+            thread::sleep(FRAME);
+        }
+    });
+    Ok(Tui { render: Some(render) })
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        RUNNING.store(false, Ordering::SeqCst);
+        if let Some(render) = self.render.take() {
+            let _ = render.join();
+        }
+        close();
+    }
+}
+
+/// Restores the terminal and prints the tail of the log. Safe to call twice.
+fn close() {
+    if !sys::restore() {
+        return;
+    }
+    let log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    for line in log.iter().skip(log.len().saturating_sub(REPLAY_LINES)) {
+        println!("{}", line);
+    }
+}
+
+/// Adds one line of captured console output to the log pane. ffmpeg's
+/// progress lines are left out; their figures show in the file list.
+fn log_line(line: &str) {
+    let line = line.trim_end();
+    if line.is_empty() || (line.contains("time=") && line.contains("speed=")) {
+        return;
+    }
+    let mut log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    log.push_back(line.to_string());
+    if log.len() > LOG_LINES {
+        log.pop_front();
+    }
+}
+
+fn read_keys() {
+    while let Some(key) = sys::read_key() {
+        let files = dashboard::files();
+        let selected = SELECTED.load(Ordering::Relaxed).min(files.len().saturating_sub(1));
+        match key {
+            Key::Up => SELECTED.store(selected.saturating_sub(1), Ordering::Relaxed),
+            Key::Down => SELECTED.store((selected + 1).min(files.len().saturating_sub(1)), Ordering::Relaxed),
+            Key::Char('+') => SELECTED.store(dashboard::move_file(selected, true), Ordering::Relaxed),
+            Key::Char('-') => SELECTED.store(dashboard::move_file(selected, false), Ordering::Relaxed),
+            Key::Char('s') => {
+                if let Some(file) = files.get(selected) {
+                    if dashboard::skip_file(selected) {
+                        log_line(&format!("   ⏭️  Skipping {}", file.name));
+                    }
+                }
+            }
+            Key::Char('p') => pause::set_paused(!pause::is_paused()),
+            Key::Char('q') | Key::Interrupt => {
+                RUNNING.store(false, Ordering::SeqCst);
+                pause::terminate_children();
+                close();
+                eprintln!("Stopped; run the same command again to resume");
+                std::process::exit(130);
+            }
+            _ => {}
+        }
+    }
+}
+
+enum Key {
+    Up,
+    Down,
+    Char(char),
+    /// Ctrl-C, which arrives as a key because signals are off
+    Interrupt,
+}
+
+/// Renders the screen for a terminal of `rows` x `cols`.
+fn frame((rows, cols): (usize, usize)) -> Vec<String> {
+    let files = dashboard::files();
+    let count = |status| files.iter().filter(|file| file.status == status).count();
+    let mut lines = vec![format!(
+        "📺 tvcode   {} encoding, {} pending, {} done, {} skipped, {} failed{}",
+        count(FileStatus::Encoding),
+        count(FileStatus::Pending),
+        count(FileStatus::Done),
+        count(FileStatus::Skipped),
+        count(FileStatus::Failed),
+        if pause::is_paused() { "   ⏸️  PAUSED" } else { "" }
+    )];
+    lines.push("─".repeat(cols));
+
+    let list_rows = (rows.saturating_sub(4) / 2).max(3);
+    let selected = SELECTED.load(Ordering::Relaxed).min(files.len().saturating_sub(1));
+    let first = selected.saturating_sub(list_rows - 1);
+    for file in files.iter().skip(first).take(list_rows) {
+        lines.push(file_line(file, file.id == selected, cols));
+    }
+    while lines.len() < list_rows + 2 {
+        lines.push(String::new());
+    }
+
+    lines.push(format!("── Log {}", "─".repeat(cols.saturating_sub(7))));
+    let log_rows = rows.saturating_sub(lines.len() + 1);
+    let log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    let tail: Vec<&String> = log.iter().skip(log.len().saturating_sub(log_rows)).collect();
+    for row in 0..log_rows {
+        lines.push(tail.get(row).map_or_else(String::new, |line| line.to_string()));
+    }
+    lines.push("↑/↓ select   +/- move up/down the queue   s skip   p pause/resume   q quit".to_string());
+    lines.into_iter().map(|line| truncate(&line, cols)).collect()
+}
+
+fn file_line(file: &FileView, selected: bool, cols: usize) -> String {
+    let marker = if selected { '>' } else { ' ' };
+    let status = match file.status {
+        FileStatus::Pending => "pending",
+        FileStatus::Encoding => "encoding",
+        FileStatus::Done => "done",
+        FileStatus::Skipped => "skipped",
+        FileStatus::Failed => "failed",
+    };
+    let details = match file.progress {
+        Some(progress) => {
+            let filled = (progress * PROGRESS_WIDTH as f64).round() as usize;
+            format!(
+                "[{}{}] {:5.1}% {:>6} ETA {}",
+                "#".repeat(filled),
+                "-".repeat(PROGRESS_WIDTH - filled),
+                progress * 100.0,
+                file.speed.as_deref().unwrap_or(""),
+                file.eta_secs.map_or_else(|| "?".to_string(), format_duration)
+            )
+        }
+        None => String::new(),
+    };
+    let name_width = cols.saturating_sub(details.chars().count() + 14).max(10);
+    format!(
+        "{} {:<8}  {:<width$} {}",
+        marker,
+        status,
+        truncate(&file.name, name_width),
+        details,
+        width = name_width
+    )
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::Key;
+    use std::fs::File;
+    use std::io::{BufReader, Read, Write};
+    use std::os::raw::c_int;
+    use std::os::unix::io::FromRawFd;
+    use std::process::{Command, Stdio};
+    use std::sync::Mutex;
+    use std::thread::{self, JoinHandle};
+
+    extern "C" {
+        fn dup(fd: c_int) -> c_int;
+        fn dup2(from: c_int, to: c_int) -> c_int;
+        fn pipe(fds: *mut c_int) -> c_int;
+        fn close(fd: c_int) -> c_int;
+    }
+
+    /// What is needed to hand the terminal back.
+    struct Saved {
+        stty: String,
+        stdout: c_int,
+        stderr: c_int,
+        terminal: File,
+        reader: JoinHandle<()>,
+    }
+
+    static SAVED: Mutex<Option<Saved>> = Mutex::new(None);
+
+    fn stty(args: &[&str]) -> Result<String, String> {
+        let output = Command::new("stty")
+            .args(args)
+            .stdin(Stdio::inherit())
+            .output()
+            .map_err(|e| format!("Failed to run stty: {}", e))?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            Err("--tui needs an interactive terminal".to_string())
+        }
+    }
+
+    pub fn take_over() -> Result<(), String> {
+        let stty_state = stty(&["-g"])?;
+        // Single keys without Enter or echo; Ctrl-C is read as a key so the
+        // terminal can be restored before exiting
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+
+        let mut fds = [0 as c_int; 2];
+        // SAFETY: plain fd calls on fds this process owns; every result is checked
+        let (stdout, stderr, pipe_read) = unsafe {
+            let stdout = dup(1);
+            let stderr = dup(2);
+            if stdout < 0 || stderr < 0 || pipe(fds.as_mut_ptr()) != 0 || dup2(fds[1], 1) < 0 || dup2(fds[1], 2) < 0 {
+                let _ = stty(&[&stty_state]);
+                return Err("Failed to redirect console output".to_string());
+            }
+            close(fds[1]);
+            (stdout, stderr, File::from_raw_fd(fds[0]))
+        };
+        // SAFETY: a fresh duplicate owned by the File from here on
+        let mut terminal = unsafe { File::from_raw_fd(dup(stdout)) };
+        // Alternate screen, hidden cursor
+        let _ = terminal.write_all(b"\x1b[?1049h\x1b[?25l");
+
+        let reader = thread::spawn(move || {
+            // ffmpeg redraws its progress line with '\r', so split on both
+            let mut line = Vec::new();
+            for byte in BufReader::new(pipe_read).bytes() {
+                let Ok(byte) = byte else { break };
+                if byte == b'\n' || byte == b'\r' {
+                    super::log_line(&String::from_utf8_lossy(&line));
+                    line.clear();
+                } else {
+                    line.push(byte);
+                }
+            }
+            super::log_line(&String::from_utf8_lossy(&line));
+        });
+
+        *SAVED.lock().unwrap() = Some(Saved {
+            stty: stty_state,
+            stdout,
+            stderr,
+            terminal,
+            reader,
+        });
+        Ok(())
+    }
+
+    /// Hands the terminal back. `false` if it already was.
+    pub fn restore() -> bool {
+        let Some(mut saved) = SAVED.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+            return false;
+        };
+        let _ = std::io::stdout().flush();
+        let _ = saved.terminal.write_all(b"\x1b[?25h\x1b[?1049l");
+        // SAFETY: restores the saved descriptors; this drops the last write
+        // ends of the pipe so the reader sees end-of-file
+        unsafe {
+            dup2(saved.stdout, 1);
+            dup2(saved.stderr, 2);
+            close(saved.stdout);
+            close(saved.stderr);
+        }
+        let _ = saved.reader.join();
+        let _ = stty(&[&saved.stty]);
+        true
+    }
+
+    pub fn draw(lines: &[String]) {
+        let guard = SAVED.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(saved) = guard.as_ref() else {
+            return;
+        };
+        let mut screen = String::from("\x1b[H");
+        for line in lines {
+            screen.push_str(line);
+            screen.push_str("\x1b[K\r\n");
+        }
+        screen.push_str("\x1b[J");
+        let _ = (&saved.terminal).write_all(screen.as_bytes());
+    }
+
+    /// Terminal (rows, columns), 24x80 if unknown.
+    pub fn size() -> (usize, usize) {
+        let size = stty(&["size"]).unwrap_or_default();
+        let mut parts = size.split_whitespace().filter_map(|part| part.parse().ok());
+        match (parts.next(), parts.next()) {
+            (Some(rows), Some(cols)) if rows > 0 && cols > 0 => (rows, cols),
+            _ => (24, 80),
+        }
+    }
+
+    /// The next key press, or `None` once stdin is closed.
+    pub fn read_key() -> Option<Key> {
+        let mut stdin = std::io::stdin().lock();
+        let mut byte = [0u8; 1];
+        loop {
+            stdin.read_exact(&mut byte).ok()?;
+            match byte[0] {
+                3 => return Some(Key::Interrupt),
+                0x1b => {
+                    let mut sequence = [0u8; 2];
+                    stdin.read_exact(&mut sequence).ok()?;
+                    match sequence {
+                        [b'[', b'A'] => return Some(Key::Up),
+                        [b'[', b'B'] => return Some(Key::Down),
+                        _ => continue,
+                    }
+                }
+                b'k' => return Some(Key::Up),
+                b'j' => return Some(Key::Down),
+                other if other.is_ascii() => return Some(Key::Char(other as char)),
+                _ => continue,
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use super::Key;
+
+    pub fn take_over() -> Result<(), String> {
+        Err("--tui is only available on macOS and Linux".to_string())
+    }
+
+    pub fn restore() -> bool {
+        false
+    }
+
+    pub fn draw(_lines: &[String]) {}
+
+    pub fn size() -> (usize, usize) {
+        (24, 80)
+    }
+
+    pub fn read_key() -> Option<Key> {
+        None
+    }
+}